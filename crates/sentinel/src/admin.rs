@@ -0,0 +1,513 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio_util::sync::CancellationToken;
+
+use crate::claim::now_millis;
+use crate::error::SentinelError;
+use crate::mode::{HostMode, ModeControl};
+use crate::registry::{VmDetail, VmRegistry, VmSummary};
+use crate::reload::LiveConfig;
+use crate::shutdown::Shutdown;
+use crate::vm::console;
+
+/// Maximum size of a single admin request line.
+const MAX_REQUEST_SIZE: u64 = 64 * 1024;
+
+/// Requests accepted on the admin socket, one JSON line per connection.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum AdminRequest {
+    ListVms,
+    /// `vm` is a CID or task id.
+    ShowVm {
+        vm: String,
+        console_lines: usize,
+    },
+    Kill {
+        vm: String,
+    },
+    Cordon,
+    Uncordon,
+    Drain {
+        deadline_secs: Option<u64>,
+    },
+    Mode,
+    Config,
+    /// Re-read the config file, as on SIGHUP.
+    Reload,
+}
+
+/// Response to an [`AdminRequest`], one JSON line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AdminResponse {
+    Vms {
+        vms: Vec<VmSummary>,
+    },
+    Vm {
+        vm: VmDetail,
+    },
+    Killed {
+        cid: u32,
+        task_id: String,
+    },
+    Mode {
+        mode: HostMode,
+    },
+    Config {
+        toml: String,
+    },
+    /// Dotted paths of the settings a reload changed.
+    Reloaded {
+        changed: Vec<String>,
+    },
+    Error {
+        error: String,
+    },
+}
+
+/// Serves `sentinelctl` on a Unix socket.
+///
+/// The socket is created mode 0600, so only the sentinel's user (normally
+/// root) can drive it.
+pub struct AdminServer {
+    registry: Arc<VmRegistry>,
+    mode: Arc<ModeControl>,
+    config: Arc<LiveConfig>,
+    shutdown: Arc<Shutdown>,
+}
+
+impl AdminServer {
+    #[must_use]
+    pub fn new(
+        registry: Arc<VmRegistry>,
+        mode: Arc<ModeControl>,
+        config: Arc<LiveConfig>,
+        shutdown: Arc<Shutdown>,
+    ) -> Self {
+        Self {
+            registry,
+            mode,
+            config,
+            shutdown,
+        }
+    }
+
+    /// Accept connections on `listener` (bound at `path` by [`bind`]) until
+    /// `token` is cancelled, then remove the socket.
+    pub async fn serve(
+        self: Arc<Self>,
+        listener: UnixListener,
+        path: PathBuf,
+        token: CancellationToken,
+    ) {
+        tracing::info!(socket = %path.display(), "admin socket listening");
+        loop {
+            tokio::select! {
+                () = token.cancelled() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        let server = Arc::clone(&self);
+                        tokio::spawn(async move {
+                            if let Err(e) = server.serve_connection(stream).await {
+                                tracing::warn!(error = %e, "admin connection failed");
+                            }
+                        });
+                    }
+                    Err(e) => tracing::warn!(error = %e, "admin accept failed"),
+                },
+            }
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    async fn serve_connection(&self, stream: UnixStream) -> Result<(), SentinelError> {
+        let (read, mut write) = stream.into_split();
+        let mut line = String::new();
+        BufReader::new(read.take(MAX_REQUEST_SIZE))
+            .read_line(&mut line)
+            .await?;
+        let response = match serde_json::from_str::<AdminRequest>(&line) {
+            Ok(request) => self.handle(request).await,
+            Err(e) => AdminResponse::Error {
+                error: format!("invalid request: {e}"),
+            },
+        };
+        let mut out = serde_json::to_vec(&response)?;
+        out.push(b'\n');
+        write.write_all(&out).await?;
+        write.shutdown().await?;
+        Ok(())
+    }
+
+    /// Apply one request. A kill answers once the VM is torn down and its
+    /// task failed.
+    pub async fn handle(&self, request: AdminRequest) -> AdminResponse {
+        tracing::debug!(?request, "admin request");
+        match request {
+            AdminRequest::ListVms => AdminResponse::Vms {
+                vms: self.registry.list().iter().map(|e| e.summary()).collect(),
+            },
+            AdminRequest::ShowVm { vm, console_lines } => {
+                let detail = match self.registry.find(&vm) {
+                    Some(entry) => Some(entry.detail(console_lines)),
+                    None => self.registry.journaled_detail(&vm).await.map(|mut detail| {
+                        detail.console =
+                            self.logged_console(&detail.summary.task_id, console_lines);
+                        detail
+                    }),
+                };
+                match detail {
+                    Some(detail) => AdminResponse::Vm { vm: detail },
+                    None => no_such_vm(&vm),
+                }
+            }
+            AdminRequest::Kill { vm } => match self.registry.find(&vm) {
+                Some(entry) => {
+                    tracing::warn!(cid = entry.cid, task_id = %entry.task_id, "vm killed by operator");
+                    match self.shutdown.kill(&entry).await {
+                        Ok(()) => AdminResponse::Killed {
+                            cid: entry.cid,
+                            task_id: entry.task_id.clone(),
+                        },
+                        Err(e) => AdminResponse::Error {
+                            error: format!(
+                                "vm {} killed, but its task was not failed: {e}",
+                                entry.cid
+                            ),
+                        },
+                    }
+                }
+                None => no_such_vm(&vm),
+            },
+            AdminRequest::Cordon => self.set_mode(HostMode::Cordoned),
+            AdminRequest::Uncordon => self.set_mode(HostMode::Active),
+            AdminRequest::Drain { deadline_secs } => self.set_mode(HostMode::Draining {
+                deadline_ms: deadline_secs
+                    .map(|s| now_millis().saturating_add(s.saturating_mul(1000))),
+            }),
+            AdminRequest::Mode => AdminResponse::Mode {
+                mode: self.mode.current(),
+            },
+            AdminRequest::Config => match self.config.snapshot().render() {
+                Ok(toml) => AdminResponse::Config { toml },
+                Err(e) => AdminResponse::Error {
+                    error: e.to_string(),
+                },
+            },
+            AdminRequest::Reload => match self.config.reload() {
+                Ok(changed) => {
+                    tracing::info!(changed = %changed.join(", "), "config reloaded by operator");
+                    AdminResponse::Reloaded { changed }
+                }
+                Err(e) => AdminResponse::Error {
+                    error: e.to_string(),
+                },
+            },
+        }
+    }
+
+    /// Console tail of a VM that is no longer registered, from its log on
+    /// disk. Empty once the log has been swept.
+    fn logged_console(&self, task_id: &str, lines: usize) -> Vec<String> {
+        let config = self.config.snapshot();
+        console::vm_dir(&config.console_dir(), task_id)
+            .and_then(|dir| console::tail_lines(&dir, config.console.tail_bytes, lines))
+            .unwrap_or_else(|e| {
+                tracing::warn!(task_id, error = %e, "console log unreadable");
+                Vec::new()
+            })
+    }
+
+    fn set_mode(&self, mode: HostMode) -> AdminResponse {
+        self.mode.set(mode);
+        AdminResponse::Mode {
+            mode: self.mode.current(),
+        }
+    }
+}
+
+fn no_such_vm(vm: &str) -> AdminResponse {
+    AdminResponse::Error {
+        error: format!("no vm or task {vm:?} on this host or in its journal"),
+    }
+}
+
+/// Bind the admin socket at `path`, replacing a stale socket from a
+/// previous run.
+///
+/// # Errors
+///
+/// Returns `SentinelError::Io` if the socket cannot be bound.
+pub fn bind(path: &Path) -> Result<UnixListener, SentinelError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Send one request to the sentinel listening on `socket`.
+///
+/// # Errors
+///
+/// Returns `SentinelError::Io` if the sentinel is not reachable, or
+/// `SentinelError::Json` on a malformed response.
+pub async fn request(
+    socket: &Path,
+    request: &AdminRequest,
+) -> Result<AdminResponse, SentinelError> {
+    let stream = UnixStream::connect(socket).await?;
+    let (read, mut write) = stream.into_split();
+    let mut out = serde_json::to_vec(request)?;
+    out.push(b'\n');
+    write.write_all(&out).await?;
+    write.shutdown().await?;
+
+    let mut line = String::new();
+    BufReader::new(read).read_line(&mut line).await?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::tests::entry;
+    use crate::shutdown::KILLED;
+    use crate::testing::MemoryStore;
+    use crate::vm::lifecycle::VmState;
+    use crate::vm::teardown::Teardown;
+
+    fn server() -> Arc<AdminServer> {
+        server_with("")
+    }
+
+    fn server_with(extra: &str) -> Arc<AdminServer> {
+        server_and_store(extra).0
+    }
+
+    fn server_and_store(extra: &str) -> (Arc<AdminServer>, Arc<MemoryStore>) {
+        let config = crate::config::SentinelConfig::from_toml(&format!(
+            r#"
+host_id = "h1"
+slots = 1
+image_dir = "/images"
+kernel_path = "/k"
+overlay_dir = "/o"
+firecracker_bin = "/fc"
+task_types = ["shell"]
+
+[profiles.shell]
+vcpus = 1
+mem_mb = 256
+rootfs = "base.ext4"
+{extra}
+"#
+        ))
+        .unwrap();
+        let registry = Arc::new(VmRegistry::new());
+        let store = Arc::new(MemoryStore::default().with(
+            "gbe:state:tasks:shell:t1",
+            &[("state", "running"), ("worker", "h1:3")],
+        ));
+        let shutdown = Shutdown::new(
+            &config,
+            Arc::clone(&store) as _,
+            Arc::clone(&registry),
+            Arc::new(Teardown::new("/fc".into(), "/o".into())),
+        );
+        let server = Arc::new(AdminServer::new(
+            registry,
+            Arc::new(ModeControl::new()),
+            Arc::new(LiveConfig::new(config, None)),
+            Arc::new(shutdown),
+        ));
+        (server, store)
+    }
+
+    #[test]
+    fn request_wire_format() {
+        let json = serde_json::to_value(AdminRequest::ShowVm {
+            vm: "t1".into(),
+            console_lines: 20,
+        })
+        .unwrap();
+        assert_eq!(json["cmd"], "show_vm");
+        let parsed: AdminRequest =
+            serde_json::from_str(r#"{"cmd":"drain","deadline_secs":null}"#).unwrap();
+        assert_eq!(
+            parsed,
+            AdminRequest::Drain {
+                deadline_secs: None
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn list_show_and_kill() {
+        let (server, store) = server_and_store("");
+        let vm = server.registry.insert(entry(3, "t1"));
+        vm.transition(VmState::Provisioning).unwrap();
+        vm.push_console("booted");
+
+        let AdminResponse::Vms { vms } = server.handle(AdminRequest::ListVms).await else {
+            panic!("expected vms");
+        };
+        assert_eq!(vms[0].task_id, "t1");
+
+        let AdminResponse::Vm { vm: detail } = server
+            .handle(AdminRequest::ShowVm {
+                vm: "3".into(),
+                console_lines: 10,
+            })
+            .await
+        else {
+            panic!("expected vm");
+        };
+        assert_eq!(detail.history.len(), 2);
+        assert_eq!(detail.console, ["booted"]);
+
+        let killed = server.handle(AdminRequest::Kill { vm: "t1".into() }).await;
+        assert_eq!(
+            killed,
+            AdminResponse::Killed {
+                cid: 3,
+                task_id: "t1".into()
+            }
+        );
+        assert!(vm.kill_token().is_cancelled());
+        assert_eq!(
+            vm.state(),
+            VmState::Idle,
+            "torn down before the kill is answered"
+        );
+        assert!(server.registry.is_empty());
+        let key = "gbe:state:tasks:shell:t1";
+        assert_eq!(store.field(key, "state").as_deref(), Some("failed"));
+        assert_eq!(store.field(key, "error").as_deref(), Some(KILLED));
+    }
+
+    #[tokio::test]
+    async fn finished_vm_console_read_from_log() {
+        let tmp = tempfile::tempdir().unwrap();
+        let server = server_with(&format!(
+            "[console]\ndir = {:?}\n",
+            tmp.path().join("console")
+        ));
+        let journal = crate::journal::RunJournal::open(
+            &tmp.path().join("run.journal"),
+            &crate::config::JournalConfig::default(),
+        )
+        .unwrap();
+        server.registry.attach_journal(Arc::new(journal));
+        let vm = server.registry.insert(entry(3, "t1"));
+        vm.transition(VmState::Provisioning).unwrap();
+        let mut log = console::ConsoleLog::create(&tmp.path().join("console"), "t1", 4096).unwrap();
+        for line in ["booting", "Kernel panic - not syncing"] {
+            log.append(line).unwrap();
+        }
+        vm.transition(VmState::Failed("boot".into())).unwrap();
+        vm.transition(VmState::Teardown).unwrap();
+        server.registry.remove(3).unwrap();
+
+        let AdminResponse::Vm { vm: detail } = server
+            .handle(AdminRequest::ShowVm {
+                vm: "t1".into(),
+                console_lines: 1,
+            })
+            .await
+        else {
+            panic!("expected vm");
+        };
+        assert_eq!(detail.console, ["Kernel panic - not syncing"]);
+    }
+
+    #[tokio::test]
+    async fn unknown_vm_is_an_error() {
+        let response = server()
+            .handle(AdminRequest::Kill { vm: "nope".into() })
+            .await;
+        assert!(matches!(response, AdminResponse::Error { .. }));
+    }
+
+    #[tokio::test]
+    async fn cordon_drain_uncordon() {
+        let server = server();
+        server.handle(AdminRequest::Cordon).await;
+        assert!(!server.mode.accepting());
+        let AdminResponse::Mode { mode } = server
+            .handle(AdminRequest::Drain {
+                deadline_secs: Some(60),
+            })
+            .await
+        else {
+            panic!("expected mode");
+        };
+        assert!(matches!(mode, HostMode::Draining { deadline_ms: Some(ms) } if ms > now_millis()));
+        let AdminResponse::Mode { mode } = server
+            .handle(AdminRequest::Drain {
+                deadline_secs: Some(u64::MAX),
+            })
+            .await
+        else {
+            panic!("expected mode");
+        };
+        assert_eq!(
+            mode,
+            HostMode::Draining {
+                deadline_ms: Some(u64::MAX)
+            }
+        );
+        server.handle(AdminRequest::Uncordon).await;
+        assert!(server.mode.accepting());
+    }
+
+    #[tokio::test]
+    async fn config_rendered_as_toml() {
+        let AdminResponse::Config { toml } = server().handle(AdminRequest::Config).await else {
+            panic!("expected config");
+        };
+        assert!(toml.contains("host_id = \"h1\""));
+    }
+
+    #[tokio::test]
+    async fn reload_error_returned() {
+        let AdminResponse::Error { error } = server().handle(AdminRequest::Reload).await else {
+            panic!("expected error");
+        };
+        assert!(error.contains("not started from a config file"));
+    }
+
+    #[tokio::test]
+    async fn round_trip_over_socket() {
+        let tmp = tempfile::tempdir().unwrap();
+        let socket = tmp.path().join("run/admin.sock");
+        let server = server();
+        server.registry.insert(entry(7, "t7"));
+        let token = CancellationToken::new();
+        let listener = bind(&socket).unwrap();
+        let serving =
+            tokio::spawn(Arc::clone(&server).serve(listener, socket.clone(), token.clone()));
+
+        let response = request(&socket, &AdminRequest::ListVms).await;
+        let AdminResponse::Vms { vms } = response.unwrap() else {
+            panic!("expected vms");
+        };
+        assert_eq!(vms[0].cid, 7);
+
+        token.cancel();
+        serving.await.unwrap();
+        assert!(!socket.exists());
+    }
+}
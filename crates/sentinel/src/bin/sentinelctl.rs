@@ -0,0 +1,294 @@
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use gbe_sentinel::admin::{self, AdminRequest, AdminResponse};
+use gbe_sentinel::config::DEFAULT_ADMIN_SOCKET;
+use gbe_sentinel::registry::{VmDetail, VmSummary};
+
+/// sysexits(3) `EX_UNAVAILABLE`: the sentinel is not reachable.
+const EXIT_UNAVAILABLE: u8 = 69;
+
+/// Operate a running gbe-sentinel over its admin socket.
+#[derive(Debug, Parser)]
+#[command(name = "sentinelctl", version)]
+struct Args {
+    /// Admin socket of the sentinel to talk to.
+    #[arg(long, env = "SENTINEL_ADMIN_SOCKET", default_value = DEFAULT_ADMIN_SOCKET)]
+    socket: PathBuf,
+
+    /// Print the raw JSON response.
+    #[arg(long)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List VMs running on this host.
+    Vms,
+    /// Show a VM's lifecycle history and console tail.
+    Show {
+        /// VM CID or task id.
+        vm: String,
+        /// Console lines to print.
+        #[arg(short = 'n', long, default_value_t = 50)]
+        lines: usize,
+    },
+    /// Kill a VM, failing its task.
+    Kill {
+        /// VM CID or task id.
+        vm: String,
+    },
+    /// Stop claiming new tasks; running VMs continue.
+    Cordon,
+    /// Resume claiming new tasks.
+    Uncordon,
+    /// Stop claiming and exit once running VMs finish.
+    Drain {
+        /// Seconds running tasks may take before they are preempted.
+        #[arg(long)]
+        deadline: Option<u64>,
+    },
+    /// Print the host mode (active, cordoned, draining).
+    Status,
+    /// Print the effective config.
+    Config,
+    /// Re-read the config file and print what changed.
+    Reload,
+}
+
+impl Command {
+    fn request(&self) -> AdminRequest {
+        match self {
+            Self::Vms => AdminRequest::ListVms,
+            Self::Show { vm, lines } => AdminRequest::ShowVm {
+                vm: vm.clone(),
+                console_lines: *lines,
+            },
+            Self::Kill { vm } => AdminRequest::Kill { vm: vm.clone() },
+            Self::Cordon => AdminRequest::Cordon,
+            Self::Uncordon => AdminRequest::Uncordon,
+            Self::Drain { deadline } => AdminRequest::Drain {
+                deadline_secs: *deadline,
+            },
+            Self::Status => AdminRequest::Mode,
+            Self::Config => AdminRequest::Config,
+            Self::Reload => AdminRequest::Reload,
+        }
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    let response = match admin::request(&args.socket, &args.command.request()).await {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!(
+                "sentinelctl: cannot reach sentinel at {}: {e}",
+                args.socket.display()
+            );
+            return ExitCode::from(EXIT_UNAVAILABLE);
+        }
+    };
+
+    let failed = matches!(response, AdminResponse::Error { .. });
+    let out = if args.json {
+        serde_json::to_string_pretty(&response).unwrap_or_default() + "\n"
+    } else {
+        render(&response)
+    };
+    if failed {
+        eprint!("{out}");
+        ExitCode::FAILURE
+    } else {
+        print!("{out}");
+        ExitCode::SUCCESS
+    }
+}
+
+fn render(response: &AdminResponse) -> String {
+    match response {
+        AdminResponse::Vms { vms } => render_vms(vms),
+        AdminResponse::Vm { vm } => render_vm(vm),
+        AdminResponse::Killed { cid, task_id } => format!("killed vm {cid} (task {task_id})\n"),
+        AdminResponse::Mode { mode } => format!("{mode}\n"),
+        AdminResponse::Config { toml } => toml.clone(),
+        AdminResponse::Reloaded { changed } if changed.is_empty() => {
+            "config reloaded, nothing changed\n".to_string()
+        }
+        AdminResponse::Reloaded { changed } => {
+            let mut out = "config reloaded, changed:\n".to_string();
+            for key in changed {
+                let _ = writeln!(out, "  {key}");
+            }
+            out
+        }
+        AdminResponse::Error { error } => format!("sentinelctl: {error}\n"),
+    }
+}
+
+fn render_vms(vms: &[VmSummary]) -> String {
+    if vms.is_empty() {
+        return "no vms\n".to_string();
+    }
+    let mut out = format!(
+        "{:<6} {:<28} {:<12} {:<12} {}\n",
+        "CID", "TASK", "TYPE", "PROFILE", "STATE"
+    );
+    for vm in vms {
+        let _ = writeln!(
+            out,
+            "{:<6} {:<28} {:<12} {:<12} {}",
+            vm.cid, vm.task_id, vm.task_type, vm.profile, vm.state
+        );
+    }
+    out
+}
+
+fn render_vm(vm: &VmDetail) -> String {
+    let s = &vm.summary;
+    let mut out = format!(
+        "vm {} task {} ({}, profile {})\nstate: {}\n\nhistory:\n",
+        s.cid, s.task_id, s.task_type, s.profile, s.state
+    );
+    for change in &vm.history {
+        let offset = change.at_ms.saturating_sub(s.started_at_ms);
+        let _ = writeln!(out, "  +{offset:>8}ms  {}", change.state);
+    }
+    let phases = [
+        ("provision", vm.durations.provision_ms),
+        ("run", vm.durations.run_ms),
+        ("teardown", vm.durations.teardown_ms),
+    ];
+    let timings: Vec<String> = phases
+        .iter()
+        .filter_map(|(phase, ms)| ms.map(|ms| format!("{phase} {ms}ms")))
+        .collect();
+    if !timings.is_empty() {
+        let _ = writeln!(out, "\ntimings: {}", timings.join(", "));
+    }
+    if let Some(t) = &vm.telemetry {
+        let m = &t.metrics;
+        let _ = writeln!(
+            out,
+            "\nfirecracker: vcpu exits io {}/{} mmio {}/{}, block r {}B w {}B, \
+             net rx {}B tx {}B, vsock rx {}B tx {}B, {} warnings, {} errors",
+            m.vcpu.io_in,
+            m.vcpu.io_out,
+            m.vcpu.mmio_read,
+            m.vcpu.mmio_write,
+            m.block.read_bytes,
+            m.block.write_bytes,
+            m.net.rx_bytes,
+            m.net.tx_bytes,
+            m.vsock.rx_bytes,
+            m.vsock.tx_bytes,
+            t.log.warnings,
+            t.log.errors,
+        );
+    }
+    out.push_str("\nconsole:\n");
+    for line in &vm.console {
+        let _ = writeln!(out, "  {line}");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gbe_sentinel::vm::lifecycle::{PhaseDurations, StateChange, VmState};
+
+    fn summary() -> VmSummary {
+        VmSummary {
+            cid: 3,
+            task_id: "t1".into(),
+            task_type: "shell".into(),
+            profile: "default".into(),
+            state: VmState::Running,
+            started_at_ms: 1_000,
+        }
+    }
+
+    #[test]
+    fn parses_subcommands() {
+        let args = Args::try_parse_from(["sentinelctl", "drain", "--deadline", "300"]).unwrap();
+        assert_eq!(
+            args.command.request(),
+            AdminRequest::Drain {
+                deadline_secs: Some(300)
+            }
+        );
+        let args =
+            Args::try_parse_from(["sentinelctl", "--json", "show", "t1", "-n", "5"]).unwrap();
+        assert!(args.json);
+        assert_eq!(
+            args.command.request(),
+            AdminRequest::ShowVm {
+                vm: "t1".into(),
+                console_lines: 5
+            }
+        );
+    }
+
+    #[test]
+    fn renders_reload() {
+        let args = Args::try_parse_from(["sentinelctl", "reload"]).unwrap();
+        assert_eq!(args.command.request(), AdminRequest::Reload);
+        let out = render(&AdminResponse::Reloaded {
+            changed: vec!["profiles.shell.timeout_sec".into()],
+        });
+        assert_eq!(
+            out,
+            "config reloaded, changed:\n  profiles.shell.timeout_sec\n"
+        );
+        assert_eq!(
+            render(&AdminResponse::Reloaded { changed: vec![] }),
+            "config reloaded, nothing changed\n"
+        );
+    }
+
+    #[test]
+    fn renders_vm_table() {
+        let out = render(&AdminResponse::Vms {
+            vms: vec![summary()],
+        });
+        assert!(out.starts_with("CID"));
+        assert!(out.lines().nth(1).unwrap().contains("t1"));
+        assert_eq!(render(&AdminResponse::Vms { vms: vec![] }), "no vms\n");
+    }
+
+    #[test]
+    fn renders_vm_detail() {
+        let out = render(&AdminResponse::Vm {
+            vm: VmDetail {
+                summary: summary(),
+                history: vec![
+                    StateChange {
+                        state: VmState::Idle,
+                        at_ms: 1_000,
+                    },
+                    StateChange {
+                        state: VmState::Running,
+                        at_ms: 1_250,
+                    },
+                ],
+                durations: PhaseDurations {
+                    run_ms: Some(250),
+                    ..PhaseDurations::default()
+                },
+                console: vec!["booted".into()],
+                telemetry: Some(Box::default()),
+            },
+        });
+        assert!(out.contains("+     250ms  running"));
+        assert!(out.contains("timings: run 250ms\n"));
+        assert!(out.contains("  booted"));
+        assert!(out.contains("block r 0B w 0B"));
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use bytes::Bytes;
@@ -41,18 +42,14 @@ pub async fn claim_task(
         });
     }
 
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_millis()
-        .to_string();
+    let now = now_millis().to_string();
 
     let worker = format!("{host_id}:{vm_cid}");
 
     store
         .set_fields(
             state_key,
-            std::collections::HashMap::from([
+            HashMap::from([
                 ("worker".to_string(), Bytes::from(worker)),
                 ("updated_at".to_string(), Bytes::from(now.clone())),
                 (
@@ -66,12 +63,191 @@ pub async fn claim_task(
     Ok(())
 }
 
+/// Put a task this worker holds back to `pending` so another host can run
+/// it, recording why in `reason` (e.g. `preempted`).
+///
+/// Flow: check `worker` is still `{host_id}:{vm_cid}`, then
+/// `compare_and_swap(key, "state", "running" | "claimed", "pending")`,
+/// then clear `worker` and set `reason`, `updated_at` and any `extra`
+/// fields. Returns `false` if the task is no longer ours to requeue (it
+/// finished, or the watcher already reclaimed it).
+///
+/// # Errors
+///
+/// Returns a store error on I/O failure.
+pub async fn requeue_task(
+    store: &Arc<dyn StateStore>,
+    state_key: &str,
+    host_id: &str,
+    vm_cid: u32,
+    reason: &str,
+    extra: Vec<(String, Bytes)>,
+) -> Result<bool, SentinelError> {
+    let mut fields = extra;
+    fields.push(("reason".to_string(), Bytes::from(reason.to_string())));
+    release_task(store, state_key, host_id, vm_cid, "pending", fields).await
+}
+
+/// Mark a task this worker holds `failed` with `error`, e.g. when it was
+/// orphaned by a sentinel crash. Same ownership checks as [`requeue_task`].
+///
+/// # Errors
+///
+/// Returns a store error on I/O failure.
+pub async fn fail_task(
+    store: &Arc<dyn StateStore>,
+    state_key: &str,
+    host_id: &str,
+    vm_cid: u32,
+    error: &str,
+) -> Result<bool, SentinelError> {
+    let fields = vec![
+        ("error".to_string(), Bytes::from(error.to_string())),
+        (
+            "completed_at".to_string(),
+            Bytes::from(now_millis().to_string()),
+        ),
+    ];
+    release_task(store, state_key, host_id, vm_cid, "failed", fields).await
+}
+
+/// Mark a task this worker holds `completed` once its operative reported a
+/// result. Same ownership checks as [`requeue_task`].
+///
+/// # Errors
+///
+/// Returns a store error on I/O failure.
+pub async fn complete_task(
+    store: &Arc<dyn StateStore>,
+    state_key: &str,
+    host_id: &str,
+    vm_cid: u32,
+) -> Result<bool, SentinelError> {
+    let fields = vec![(
+        "completed_at".to_string(),
+        Bytes::from(now_millis().to_string()),
+    )];
+    release_task(store, state_key, host_id, vm_cid, "completed", fields).await
+}
+
+/// Move `timeout_at` for a task this worker still holds, after granting an
+/// operative's extension request, so the watcher does not reclaim it.
+/// Returns `false` if the task is no longer held by `{host_id}:{vm_cid}`
+/// or has left `running`/`claimed`.
+///
+/// Flow: read `timeout_at`, check `worker` and `state`, then
+/// `compare_and_swap(key, "timeout_at", old, new)`. Every claim writes its
+/// own `timeout_at`, so if the task is reclaimed after the checks the swap
+/// fails rather than extending someone else's claim.
+///
+/// # Errors
+///
+/// Returns a store error on I/O failure.
+pub async fn extend_timeout(
+    store: &Arc<dyn StateStore>,
+    state_key: &str,
+    host_id: &str,
+    vm_cid: u32,
+    timeout_at: u64,
+) -> Result<bool, SentinelError> {
+    let Some(current) = store.get_field(state_key, "timeout_at").await? else {
+        return Ok(false);
+    };
+    let worker = format!("{host_id}:{vm_cid}");
+    if store.get_field(state_key, "worker").await? != Some(Bytes::from(worker)) {
+        return Ok(false);
+    }
+    let state = store.get_field(state_key, "state").await?;
+    if !matches!(state.as_deref(), Some(b"running" | b"claimed")) {
+        return Ok(false);
+    }
+    if !store
+        .compare_and_swap(
+            state_key,
+            "timeout_at",
+            current,
+            Bytes::from(timeout_at.to_string()),
+        )
+        .await?
+    {
+        return Ok(false);
+    }
+    store
+        .set_field(
+            state_key,
+            "updated_at",
+            Bytes::from(now_millis().to_string()),
+        )
+        .await?;
+    Ok(true)
+}
+
+/// CAS a held task from `running`/`claimed` to `to`, then clear `worker`
+/// and write `fields`.
+async fn release_task(
+    store: &Arc<dyn StateStore>,
+    state_key: &str,
+    host_id: &str,
+    vm_cid: u32,
+    to: &str,
+    fields: Vec<(String, Bytes)>,
+) -> Result<bool, SentinelError> {
+    let worker = format!("{host_id}:{vm_cid}");
+    if store.get_field(state_key, "worker").await? != Some(Bytes::from(worker)) {
+        return Ok(false);
+    }
+
+    let mut swapped = false;
+    for held in ["running", "claimed"] {
+        if store
+            .compare_and_swap(
+                state_key,
+                "state",
+                Bytes::from(held),
+                Bytes::from(to.to_string()),
+            )
+            .await?
+        {
+            swapped = true;
+            break;
+        }
+    }
+    if !swapped {
+        return Ok(false);
+    }
+
+    let mut fields: HashMap<_, _> = fields.into_iter().collect();
+    fields.extend([
+        ("worker".to_string(), Bytes::new()),
+        (
+            "updated_at".to_string(),
+            Bytes::from(now_millis().to_string()),
+        ),
+    ]);
+    store.set_fields(state_key, fields).await?;
+    Ok(true)
+}
+
+/// Current time as unix millis, the format of every state store timestamp.
+///
+/// # Panics
+///
+/// Panics if the system clock is before the Unix epoch.
+#[must_use]
+pub fn now_millis() -> u64 {
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    u64::try_from(millis).unwrap_or(u64::MAX)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::MemoryStore;
     use async_trait::async_trait;
     use gbe_state_store::{Record, ScanFilter, StateStoreError};
-    use std::collections::HashMap;
     use std::sync::Mutex;
     use std::time::Duration;
 
@@ -196,4 +372,179 @@ mod tests {
         let fields = mock.get_stored_fields("k");
         assert_eq!(fields["worker"], "node-x:42");
     }
+
+    #[tokio::test]
+    async fn requeue_returns_running_task_to_pending() {
+        let mem =
+            Arc::new(MemoryStore::default().with("k", &[("state", "running"), ("worker", "h1:3")]));
+        let store: Arc<dyn gbe_state_store::StateStore> = Arc::clone(&mem) as _;
+        let extra = vec![("snapshot".to_string(), Bytes::from("/snap/t1"))];
+        assert!(
+            requeue_task(&store, "k", "h1", 3, "preempted", extra)
+                .await
+                .unwrap()
+        );
+        assert_eq!(mem.field("k", "state").as_deref(), Some("pending"));
+        assert_eq!(mem.field("k", "reason").as_deref(), Some("preempted"));
+        assert_eq!(mem.field("k", "worker").as_deref(), Some(""));
+        assert_eq!(mem.field("k", "snapshot").as_deref(), Some("/snap/t1"));
+    }
+
+    #[tokio::test]
+    async fn requeue_skips_tasks_we_no_longer_hold() {
+        let mem = Arc::new(
+            MemoryStore::default()
+                .with("other", &[("state", "running"), ("worker", "h2:3")])
+                .with("done", &[("state", "completed"), ("worker", "h1:3")]),
+        );
+        let store: Arc<dyn gbe_state_store::StateStore> = Arc::clone(&mem) as _;
+        for key in ["other", "done"] {
+            let requeued = requeue_task(&store, key, "h1", 3, "preempted", vec![])
+                .await
+                .unwrap();
+            assert!(!requeued);
+        }
+        assert_eq!(mem.field("done", "state").as_deref(), Some("completed"));
+        assert!(mem.field("other", "reason").is_none());
+    }
+
+    #[tokio::test]
+    async fn fail_marks_held_task_failed() {
+        let mem =
+            Arc::new(MemoryStore::default().with("k", &[("state", "claimed"), ("worker", "h1:3")]));
+        let store: Arc<dyn gbe_state_store::StateStore> = Arc::clone(&mem) as _;
+        assert!(fail_task(&store, "k", "h1", 3, "orphaned").await.unwrap());
+        assert_eq!(mem.field("k", "state").as_deref(), Some("failed"));
+        assert_eq!(mem.field("k", "error").as_deref(), Some("orphaned"));
+        assert!(!fail_task(&store, "k", "h1", 3, "orphaned").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn complete_marks_held_task_completed() {
+        let mem =
+            Arc::new(MemoryStore::default().with("k", &[("state", "running"), ("worker", "h1:3")]));
+        let store: Arc<dyn gbe_state_store::StateStore> = Arc::clone(&mem) as _;
+        assert!(!complete_task(&store, "k", "h1", 4).await.unwrap());
+        assert!(complete_task(&store, "k", "h1", 3).await.unwrap());
+        assert_eq!(mem.field("k", "state").as_deref(), Some("completed"));
+        assert_eq!(mem.field("k", "worker").as_deref(), Some(""));
+        assert!(mem.field("k", "completed_at").is_some());
+    }
+    #[tokio::test]
+    async fn extend_moves_timeout_of_held_task() {
+        let mem = Arc::new(
+            MemoryStore::default()
+                .with(
+                    "k",
+                    &[
+                        ("state", "running"),
+                        ("worker", "h1:3"),
+                        ("timeout_at", "1000"),
+                    ],
+                )
+                .with(
+                    "done",
+                    &[
+                        ("state", "completed"),
+                        ("worker", "h1:3"),
+                        ("timeout_at", "1000"),
+                    ],
+                ),
+        );
+        let store: Arc<dyn gbe_state_store::StateStore> = Arc::clone(&mem) as _;
+        assert!(extend_timeout(&store, "k", "h1", 3, 5000).await.unwrap());
+        assert_eq!(mem.field("k", "timeout_at").as_deref(), Some("5000"));
+        assert!(!extend_timeout(&store, "k", "h1", 4, 9000).await.unwrap());
+        assert!(!extend_timeout(&store, "done", "h1", 3, 9000).await.unwrap());
+        assert_eq!(mem.field("done", "timeout_at").as_deref(), Some("1000"));
+    }
+
+    /// Reclaims the task for `h2:1` right after `timeout_at` is first read.
+    struct Reclaimed(MemoryStore);
+
+    #[async_trait]
+    impl gbe_state_store::StateStore for Reclaimed {
+        async fn get(&self, key: &str) -> Result<Option<Record>, StateStoreError> {
+            self.0.get(key).await
+        }
+        async fn put(
+            &self,
+            key: &str,
+            record: Record,
+            ttl: Option<Duration>,
+        ) -> Result<(), StateStoreError> {
+            self.0.put(key, record, ttl).await
+        }
+        async fn delete(&self, key: &str) -> Result<(), StateStoreError> {
+            self.0.delete(key).await
+        }
+        async fn get_field(
+            &self,
+            key: &str,
+            field: &str,
+        ) -> Result<Option<Bytes>, StateStoreError> {
+            let value = self.0.get_field(key, field).await?;
+            if field == "timeout_at" {
+                self.0
+                    .set_field(key, "timeout_at", Bytes::from("7000"))
+                    .await?;
+            }
+            Ok(value)
+        }
+        async fn set_field(
+            &self,
+            key: &str,
+            field: &str,
+            value: Bytes,
+        ) -> Result<(), StateStoreError> {
+            self.0.set_field(key, field, value).await
+        }
+        async fn set_fields(
+            &self,
+            key: &str,
+            fields: HashMap<String, Bytes>,
+        ) -> Result<(), StateStoreError> {
+            self.0.set_fields(key, fields).await
+        }
+        async fn compare_and_swap(
+            &self,
+            key: &str,
+            field: &str,
+            expected: Bytes,
+            new: Bytes,
+        ) -> Result<bool, StateStoreError> {
+            self.0.compare_and_swap(key, field, expected, new).await
+        }
+        async fn scan(
+            &self,
+            prefix: &str,
+            filter: Option<ScanFilter>,
+        ) -> Result<Vec<(String, Record)>, StateStoreError> {
+            self.0.scan(prefix, filter).await
+        }
+        async fn ping(&self) -> Result<bool, StateStoreError> {
+            self.0.ping().await
+        }
+        async fn close(&self) -> Result<(), StateStoreError> {
+            self.0.close().await
+        }
+    }
+
+    #[tokio::test]
+    async fn extend_loses_to_a_reclaim() {
+        let mem = MemoryStore::default().with(
+            "k",
+            &[
+                ("state", "running"),
+                ("worker", "h1:3"),
+                ("timeout_at", "1000"),
+            ],
+        );
+        let store: Arc<dyn gbe_state_store::StateStore> = Arc::new(Reclaimed(mem));
+        assert!(!extend_timeout(&store, "k", "h1", 3, 5000).await.unwrap());
+        assert_eq!(
+            store.get_field("k", "timeout_at").await.unwrap().as_deref(),
+            Some(&b"7000"[..])
+        );
+    }
 }
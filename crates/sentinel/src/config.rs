@@ -1,42 +1,955 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 use crate::error::SentinelError;
+use crate::routing::RoutingTable;
+use crate::vm::cmdline::KernelCmdline;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SentinelConfig {
     pub host_id: String,
     pub slots: u32,
     pub image_dir: PathBuf,
     pub kernel_path: PathBuf,
+    /// Kernel catalog: profiles pick a `kernel`/`initrd` by name from here.
+    #[serde(default)]
+    pub kernel_dir: Option<PathBuf>,
     pub overlay_dir: PathBuf,
     pub firecracker_bin: PathBuf,
     pub profiles: HashMap<String, VmProfile>,
     pub task_types: Vec<String>,
     #[serde(default = "default_heartbeat")]
     pub heartbeat_interval_secs: u64,
+    #[serde(default)]
+    pub routing: RoutingTable,
+    #[serde(default)]
+    pub bus: TransportConfig,
+    #[serde(default)]
+    pub state: StateStoreConfig,
+    /// Unix socket `sentinelctl` connects to.
+    #[serde(default = "default_admin_socket")]
+    pub admin_socket: PathBuf,
+    /// Shared key authenticating commands on the control subject. Control
+    /// over the bus is disabled when unset. Never rendered.
+    #[serde(default, skip_serializing)]
+    pub control_key: Option<String>,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    /// Durable host state: the run journal used for crash recovery.
+    #[serde(default = "default_state_dir")]
+    pub state_dir: PathBuf,
+    #[serde(default)]
+    pub recovery: RecoveryConfig,
+    #[serde(default)]
+    pub journal: JournalConfig,
+    #[serde(default)]
+    pub console: ConsoleConfig,
+    #[serde(default)]
+    pub logs: LogConfig,
+    #[serde(default)]
+    pub jailer: JailerConfig,
+    #[serde(default)]
+    pub cgroup: CgroupConfig,
+    /// Where the keys of inheriting profiles were set, for validation
+    /// errors.
+    #[serde(skip)]
+    origins: KeyOrigins,
+}
+
+/// What happens to running tasks when the sentinel stops.
+///
+/// New claims stop immediately. Running tasks get `drain_timeout_secs` to
+/// finish; any still running are then preempted per `on_timeout` and put
+/// back to `pending` for another host.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShutdownConfig {
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout_secs: u64,
+    #[serde(default)]
+    pub on_timeout: PreemptAction,
+    /// Where VM snapshots are written; defaults to `{overlay_dir}/snapshots`.
+    #[serde(default)]
+    pub snapshot_dir: Option<PathBuf>,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout_secs: default_drain_timeout(),
+            on_timeout: PreemptAction::default(),
+            snapshot_dir: None,
+        }
+    }
+}
+
+fn default_drain_timeout() -> u64 {
+    30
+}
+
+/// How a task still running at the shutdown deadline is preempted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreemptAction {
+    /// Kill the VM and requeue the task from scratch.
+    #[default]
+    Requeue,
+    /// Snapshot the VM before killing it, recording the snapshot on the
+    /// requeued task. Falls back to `requeue` if the snapshot fails.
+    Snapshot,
+}
+
+/// What happens to tasks left `running` by a sentinel that died.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryConfig {
+    #[serde(default)]
+    pub orphaned_tasks: OrphanAction,
+}
+
+/// How an orphaned task is settled during startup recovery.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrphanAction {
+    /// Put the task back to `pending` for another attempt.
+    #[default]
+    Requeue,
+    /// Mark the task `failed`.
+    Fail,
+}
+
+/// Batching and compaction of the VM lifecycle journal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalConfig {
+    /// Longest a transition waits to be synced.
+    #[serde(default = "default_sync_interval")]
+    pub sync_interval_ms: u64,
+    /// Unsynced transitions that force an immediate sync.
+    #[serde(default = "default_sync_batch")]
+    pub sync_batch: usize,
+    /// Entries appended between compactions.
+    #[serde(default = "default_compact_after")]
+    pub compact_after: usize,
+    /// How long finished VMs stay in the journal for postmortems.
+    #[serde(default = "default_retain_finished")]
+    pub retain_finished_secs: u64,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            sync_interval_ms: default_sync_interval(),
+            sync_batch: default_sync_batch(),
+            compact_after: default_compact_after(),
+            retain_finished_secs: default_retain_finished(),
+        }
+    }
+}
+
+fn default_sync_interval() -> u64 {
+    100
+}
+
+fn default_sync_batch() -> usize {
+    64
+}
+
+fn default_compact_after() -> usize {
+    10_000
+}
+
+fn default_retain_finished() -> u64 {
+    86_400
+}
+
+/// Capture of each VM's serial console.
+///
+/// Console output goes to `{dir}/{task_id}/console.log`, capped at
+/// `max_bytes` per VM. The last `tail_bytes` are attached to failure
+/// terminal events. Logs are kept after teardown for postmortems and
+/// removed once untouched for `retention_secs`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsoleConfig {
+    /// Defaults to `{state_dir}/console`.
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+    #[serde(default = "default_console_max_bytes")]
+    pub max_bytes: u64,
+    #[serde(default = "default_console_tail_bytes")]
+    pub tail_bytes: u64,
+    #[serde(default = "default_console_retention")]
+    pub retention_secs: u64,
+}
+
+impl Default for ConsoleConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_bytes: default_console_max_bytes(),
+            tail_bytes: default_console_tail_bytes(),
+            retention_secs: default_console_retention(),
+        }
+    }
+}
+
+fn default_console_max_bytes() -> u64 {
+    1024 * 1024
+}
+
+fn default_console_tail_bytes() -> u64 {
+    16 * 1024
+}
+
+fn default_console_retention() -> u64 {
+    7 * 86_400
+}
+
+/// Live streaming of task output to `gbe.tasks.{task_type}.logs`.
+///
+/// Lines are published in batches of up to `batch_lines`, at least every
+/// `flush_interval_ms`. Lines beyond `max_lines_per_sec` are dropped and
+/// counted; once a task has sent `max_bytes_per_task`, a truncation marker
+/// is published and the rest of its output is dropped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogConfig {
+    /// Also stream the guest's serial console as `console` lines.
+    #[serde(default)]
+    pub relay_console: bool,
+    #[serde(default = "default_log_flush_interval")]
+    pub flush_interval_ms: u64,
+    #[serde(default = "default_log_batch_lines")]
+    pub batch_lines: usize,
+    #[serde(default = "default_log_lines_per_sec")]
+    pub max_lines_per_sec: u32,
+    #[serde(default = "default_log_bytes_per_task")]
+    pub max_bytes_per_task: u64,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            relay_console: false,
+            flush_interval_ms: default_log_flush_interval(),
+            batch_lines: default_log_batch_lines(),
+            max_lines_per_sec: default_log_lines_per_sec(),
+            max_bytes_per_task: default_log_bytes_per_task(),
+        }
+    }
+}
+
+fn default_log_flush_interval() -> u64 {
+    250
+}
+
+fn default_log_batch_lines() -> usize {
+    100
+}
+
+fn default_log_lines_per_sec() -> u32 {
+    500
+}
+
+fn default_log_bytes_per_task() -> u64 {
+    4 * 1024 * 1024
+}
+
+/// Launching Firecracker through the `jailer` binary.
+///
+/// When enabled, each VM runs chrooted under
+/// `{chroot_base}/{firecracker exec name}/vm-{cid}/root` as uid
+/// `uid_base + cid` and gid `gid_base + cid`, optionally in its own network
+/// namespace. The kernel, initrd and overlay are hard-linked into the
+/// chroot, so `chroot_base` must be on the same filesystem as them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JailerConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_jailer_bin")]
+    pub bin: PathBuf,
+    #[serde(default = "default_chroot_base")]
+    pub chroot_base: PathBuf,
+    #[serde(default = "default_jail_id_base")]
+    pub uid_base: u32,
+    #[serde(default = "default_jail_id_base")]
+    pub gid_base: u32,
+    /// Give each VM its own network namespace, `gbe-vm-{cid}`.
+    #[serde(default = "default_true")]
+    pub netns: bool,
+}
+
+impl Default for JailerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bin: default_jailer_bin(),
+            chroot_base: default_chroot_base(),
+            uid_base: default_jail_id_base(),
+            gid_base: default_jail_id_base(),
+            netns: true,
+        }
+    }
+}
+
+fn default_jailer_bin() -> PathBuf {
+    PathBuf::from("/usr/bin/jailer")
 }
 
+fn default_chroot_base() -> PathBuf {
+    PathBuf::from("/srv/jailer")
+}
+
+fn default_jail_id_base() -> u32 {
+    100_000
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Per-VM cgroup v2 limits on the Firecracker process.
+///
+/// Each VM gets `{root}/{parent}/vm-{cid}` with `cpu.max` of its vCPUs
+/// plus `cpu_overhead_pct`, `memory.max` of its guest memory plus
+/// `mem_overhead_mb`, and, for profiles with an `io_limit`, `io.max` on
+/// `io_device`. `parent` must be delegated to the sentinel with the cpu,
+/// memory and io controllers available.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CgroupConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_cgroup_root")]
+    pub root: PathBuf,
+    #[serde(default = "default_cgroup_parent")]
+    pub parent: String,
+    /// CPU on top of the vCPUs, for Firecracker's emulation threads.
+    #[serde(default = "default_cpu_overhead_pct")]
+    pub cpu_overhead_pct: u32,
+    /// Memory on top of the guest's, for the VMM itself.
+    #[serde(default = "default_mem_overhead_mb")]
+    pub mem_overhead_mb: u32,
+    /// Block device (`major:minor`) backing `overlay_dir`, for `io.max`.
+    #[serde(default)]
+    pub io_device: Option<String>,
+}
+
+impl Default for CgroupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            root: default_cgroup_root(),
+            parent: default_cgroup_parent(),
+            cpu_overhead_pct: default_cpu_overhead_pct(),
+            mem_overhead_mb: default_mem_overhead_mb(),
+            io_device: None,
+        }
+    }
+}
+
+fn default_cgroup_root() -> PathBuf {
+    PathBuf::from("/sys/fs/cgroup")
+}
+
+fn default_cgroup_parent() -> String {
+    "gbe-sentinel".to_string()
+}
+
+fn default_cpu_overhead_pct() -> u32 {
+    10
+}
+
+fn default_mem_overhead_mb() -> u32 {
+    64
+}
+
+/// Smallest allowed `console.max_bytes`.
+pub const MIN_CONSOLE_BYTES: u64 = 4096;
+
+/// Connection settings for the nexus bus.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransportConfig {
+    #[serde(default = "default_redis_url")]
+    pub url: String,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            url: default_redis_url(),
+        }
+    }
+}
+
+/// Connection settings for the task state store.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateStoreConfig {
+    #[serde(default = "default_redis_url")]
+    pub url: String,
+}
+
+impl Default for StateStoreConfig {
+    fn default() -> Self {
+        Self {
+            url: default_redis_url(),
+        }
+    }
+}
+
+fn default_redis_url() -> String {
+    "redis://127.0.0.1:6379".to_string()
+}
+
+/// Default admin socket path.
+pub const DEFAULT_ADMIN_SOCKET: &str = "/run/gbe-sentinel/admin.sock";
+
+fn default_admin_socket() -> PathBuf {
+    PathBuf::from(DEFAULT_ADMIN_SOCKET)
+}
+
+/// Default durable state directory.
+pub const DEFAULT_STATE_DIR: &str = "/var/lib/gbe-sentinel";
+
+fn default_state_dir() -> PathBuf {
+    PathBuf::from(DEFAULT_STATE_DIR)
+}
+
+/// Environment variables that override deployment-specific fields.
+const ENV_HOST_ID: &str = "SENTINEL_HOST_ID";
+const ENV_SLOTS: &str = "SENTINEL_SLOTS";
+const ENV_IMAGE_DIR: &str = "SENTINEL_IMAGE_DIR";
+const ENV_KERNEL_PATH: &str = "SENTINEL_KERNEL_PATH";
+const ENV_KERNEL_DIR: &str = "SENTINEL_KERNEL_DIR";
+const ENV_OVERLAY_DIR: &str = "SENTINEL_OVERLAY_DIR";
+const ENV_FIRECRACKER_BIN: &str = "SENTINEL_FIRECRACKER_BIN";
+const ENV_BUS_URL: &str = "SENTINEL_BUS_URL";
+const ENV_STATE_URL: &str = "SENTINEL_STATE_URL";
+const ENV_ADMIN_SOCKET: &str = "SENTINEL_ADMIN_SOCKET";
+const ENV_CONTROL_KEY: &str = "SENTINEL_CONTROL_KEY";
+const ENV_STATE_DIR: &str = "SENTINEL_STATE_DIR";
+
+/// Minimum control key length, in bytes.
+pub const MIN_CONTROL_KEY_LEN: usize = 32;
+
 impl SentinelConfig {
-    /// Validate all paths and identifiers after deserialization.
+    /// Load config from a TOML file, apply `SENTINEL_*` environment
+    /// overrides, resolve profile rootfs names against `image_dir`, and
+    /// validate. Profile sizes are not checked against this machine; the
+    /// daemon does that with [`validate_for_host`](Self::validate_for_host).
     ///
     /// # Errors
     ///
-    /// Returns `SentinelError::Config` if any path or identifier is invalid.
-    pub fn validate(&self) -> Result<(), SentinelError> {
-        Self::validate_host_id(&self.host_id)?;
-        Self::require_dir(&self.image_dir, "image_dir")?;
-        Self::require_file(&self.kernel_path, "kernel_path")?;
-        Self::require_dir(&self.overlay_dir, "overlay_dir")?;
-        Self::require_file(&self.firecracker_bin, "firecracker_bin")?;
-        Self::reject_traversal(&self.image_dir, "image_dir")?;
-        Self::reject_traversal(&self.kernel_path, "kernel_path")?;
-        Self::reject_traversal(&self.overlay_dir, "overlay_dir")?;
-        Self::reject_traversal(&self.firecracker_bin, "firecracker_bin")?;
+    /// Returns `SentinelError::Config` prefixed with the file path. Parse
+    /// errors include the offending line and column.
+    pub fn load(path: &Path) -> Result<Self, SentinelError> {
+        Self::load_with_env(path, |key| std::env::var(key).ok())
+    }
+
+    /// Like [`load`](Self::load), reading overrides from `env` instead of
+    /// the process environment.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` prefixed with the file path.
+    pub fn load_with_env(
+        path: &Path,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, SentinelError> {
+        let in_file = |msg: &dyn std::fmt::Display| {
+            SentinelError::Config(format!("{}: {msg}", path.display()))
+        };
+        let raw = std::fs::read_to_string(path).map_err(|e| in_file(&e))?;
+        let mut config = Self::from_toml(&raw).map_err(|e| match e {
+            SentinelError::Config(msg) => in_file(&msg),
+            other => other,
+        })?;
+        config.origins.file = Some(path.to_path_buf());
+        config.apply_env_overrides(env)?;
+        config.resolve_profiles();
+        config.validate().map_err(|e| match e {
+            SentinelError::Config(msg) => in_file(&msg),
+            other => other,
+        })?;
+        Ok(config)
+    }
+
+    /// Parse TOML source, resolving `extends` between profiles. Validation
+    /// errors on a merged profile name the profile and line each key came
+    /// from.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` on syntax or type errors, unknown
+    /// parents, or inheritance cycles.
+    pub fn from_toml(raw: &str) -> Result<Self, SentinelError> {
+        let parse_err = |e: toml::de::Error| SentinelError::Config(e.to_string());
+        let mut table: toml::Table = toml::from_str(raw).map_err(parse_err)?;
+        let Some(toml::Value::Table(profiles)) = table.get_mut("profiles") else {
+            return toml::from_str(raw).map_err(parse_err);
+        };
+        if !profiles.values().any(|p| p.get(EXTENDS_KEY).is_some()) {
+            // Nothing to merge: deserialize from the source so type errors
+            // keep their line numbers.
+            return toml::from_str(raw).map_err(parse_err);
+        }
+        resolve_profile_inheritance(profiles)?;
+        let mut config: Self = table.try_into().map_err(parse_err)?;
+        config.origins = KeyOrigins::of_inherited(raw);
+        Ok(config)
+    }
+
+    /// The fully resolved profile `name` (after inheritance) as TOML.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` if the profile does not exist.
+    pub fn render_profile(&self, name: &str) -> Result<String, SentinelError> {
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| SentinelError::Config(format!("profiles.{name}: no such profile")))?;
+        toml::to_string_pretty(profile)
+            .map_err(|e| SentinelError::Config(format!("profiles.{name}: {e}")))
+    }
+
+    /// The effective config (after inheritance and env overrides) as TOML.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` if serialization fails.
+    pub fn render(&self) -> Result<String, SentinelError> {
+        toml::to_string_pretty(self).map_err(|e| SentinelError::Config(format!("render: {e}")))
+    }
+
+    /// Override deployment-specific fields from `SENTINEL_*` variables.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` if `SENTINEL_SLOTS` is not a number.
+    pub fn apply_env_overrides(
+        &mut self,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<(), SentinelError> {
+        if let Some(v) = env(ENV_HOST_ID) {
+            self.host_id = v;
+        }
+        if let Some(v) = env(ENV_SLOTS) {
+            self.slots = v.parse().map_err(|e| {
+                SentinelError::Config(format!("{ENV_SLOTS}: invalid value {v:?}: {e}"))
+            })?;
+        }
+        if let Some(v) = env(ENV_IMAGE_DIR) {
+            self.image_dir = PathBuf::from(v);
+        }
+        if let Some(v) = env(ENV_KERNEL_PATH) {
+            self.kernel_path = PathBuf::from(v);
+        }
+        if let Some(v) = env(ENV_KERNEL_DIR) {
+            self.kernel_dir = Some(PathBuf::from(v));
+        }
+        if let Some(v) = env(ENV_OVERLAY_DIR) {
+            self.overlay_dir = PathBuf::from(v);
+        }
+        if let Some(v) = env(ENV_FIRECRACKER_BIN) {
+            self.firecracker_bin = PathBuf::from(v);
+        }
+        if let Some(v) = env(ENV_BUS_URL) {
+            self.bus.url = v;
+        }
+        if let Some(v) = env(ENV_STATE_URL) {
+            self.state.url = v;
+        }
+        if let Some(v) = env(ENV_ADMIN_SOCKET) {
+            self.admin_socket = PathBuf::from(v);
+        }
+        if let Some(v) = env(ENV_CONTROL_KEY) {
+            self.control_key = Some(v);
+        }
+        if let Some(v) = env(ENV_STATE_DIR) {
+            self.state_dir = PathBuf::from(v);
+        }
         Ok(())
     }
 
+    /// Resolve each profile's `rootfs` name to a path under `image_dir`.
+    pub fn resolve_profiles(&mut self) {
+        for profile in self.profiles.values_mut() {
+            profile.rootfs_path = self.image_dir.join(&profile.rootfs);
+        }
+    }
+
+    /// Validate all paths, identifiers and profiles after deserialization,
+    /// independently of the host the config is loaded on.
+    ///
+    /// Every problem is collected and reported together rather than
+    /// stopping at the first one.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` listing every invalid field.
+    pub fn validate(&self) -> Result<(), SentinelError> {
+        self.check(None)
+    }
+
+    /// Like [`validate`](Self::validate), also checking that every profile
+    /// fits on `host`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` listing every invalid field.
+    pub fn validate_for_host(&self, host: &HostResources) -> Result<(), SentinelError> {
+        self.check(Some(host))
+    }
+
+    fn check(&self, host: Option<&HostResources>) -> Result<(), SentinelError> {
+        let mut problems = Vec::new();
+        note(&mut problems, Self::validate_host_id(&self.host_id));
+        for (path, field, is_dir) in [
+            (&self.image_dir, "image_dir", true),
+            (&self.kernel_path, "kernel_path", false),
+            (&self.overlay_dir, "overlay_dir", true),
+            (&self.firecracker_bin, "firecracker_bin", false),
+        ] {
+            let exists = if is_dir {
+                Self::require_dir(path, field)
+            } else {
+                Self::require_file(path, field)
+            };
+            if note(&mut problems, exists) {
+                note(&mut problems, Self::reject_traversal(path, field));
+            }
+        }
+        if let Some(kernel_dir) = &self.kernel_dir
+            && note(&mut problems, Self::require_dir(kernel_dir, "kernel_dir"))
+        {
+            note(
+                &mut problems,
+                Self::reject_traversal(kernel_dir, "kernel_dir"),
+            );
+        }
+        if let Some(dir) = &self.shutdown.snapshot_dir {
+            note(
+                &mut problems,
+                Self::require_dir(dir, "shutdown.snapshot_dir"),
+            );
+        }
+        if self.journal.compact_after == 0 {
+            problems.push("journal.compact_after: must be at least 1".to_string());
+        }
+        if self.console.max_bytes < MIN_CONSOLE_BYTES {
+            problems.push(format!(
+                "console.max_bytes: must be at least {MIN_CONSOLE_BYTES}"
+            ));
+        }
+        if self.console.tail_bytes == 0 || self.console.tail_bytes > self.console.max_bytes {
+            problems.push("console.tail_bytes: must be 1 to console.max_bytes".to_string());
+        }
+        for (name, value) in [
+            ("flush_interval_ms", self.logs.flush_interval_ms),
+            ("batch_lines", self.logs.batch_lines as u64),
+            ("max_lines_per_sec", u64::from(self.logs.max_lines_per_sec)),
+            ("max_bytes_per_task", self.logs.max_bytes_per_task),
+        ] {
+            if value == 0 {
+                problems.push(format!("logs.{name}: must be at least 1"));
+            }
+        }
+        if self.jailer.enabled {
+            note(
+                &mut problems,
+                Self::require_file(&self.jailer.bin, "jailer.bin"),
+            );
+            note(
+                &mut problems,
+                Self::require_dir(&self.jailer.chroot_base, "jailer.chroot_base"),
+            );
+            for (name, base) in [
+                ("uid_base", self.jailer.uid_base),
+                ("gid_base", self.jailer.gid_base),
+            ] {
+                if base == 0 {
+                    problems.push(format!("jailer.{name}: must not be 0 (root)"));
+                }
+            }
+            // The tap device is created in the host namespace and never
+            // moved into the VM's, so a jailed NAT VM would have no network.
+            if self.jailer.netns {
+                let mut nat: Vec<&String> = self
+                    .profiles
+                    .iter()
+                    .filter(|(_, profile)| matches!(profile.network, NetworkMode::Nat))
+                    .map(|(name, _)| name)
+                    .collect();
+                nat.sort();
+                for name in nat {
+                    problems.push(format!(
+                        "jailer.netns: profile {name:?} uses network = \"nat\", which \
+                         needs the host namespace; set jailer.netns = false"
+                    ));
+                }
+            }
+        }
+        if self.cgroup.enabled {
+            note(
+                &mut problems,
+                Self::require_dir(&self.cgroup.root, "cgroup.root"),
+            );
+            let parent = Path::new(&self.cgroup.parent);
+            if self.cgroup.parent.is_empty()
+                || !parent
+                    .components()
+                    .all(|c| matches!(c, std::path::Component::Normal(_)))
+            {
+                problems.push("cgroup.parent: must be a relative path without `..`".to_string());
+            }
+            if let Some(device) = &self.cgroup.io_device
+                && !is_device_number(device)
+            {
+                problems.push(format!(
+                    "cgroup.io_device: expected major:minor, got {device:?}"
+                ));
+            }
+        }
+        if let Some(key) = &self.control_key
+            && key.len() < MIN_CONTROL_KEY_LEN
+        {
+            problems.push(format!(
+                "control_key: must be at least {MIN_CONTROL_KEY_LEN} bytes"
+            ));
+        }
+
+        let mut names: Vec<&String> = self.profiles.keys().collect();
+        names.sort();
+        for name in names {
+            self.validate_profile(name, &self.profiles[name], host, &mut problems);
+        }
+
+        self.validate_routing(&mut problems);
+        for task_type in &self.task_types {
+            let routed = self.default_profile_name(task_type).is_some()
+                || self
+                    .routing
+                    .rules
+                    .iter()
+                    .any(|r| r.covers_task_type(task_type));
+            if !routed {
+                problems.push(format!(
+                    "task_types: {task_type:?} has no profile (add profiles.{task_type} or profiles.{DEFAULT_PROFILE})"
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            let problems: Vec<String> = problems
+                .into_iter()
+                .map(|p| self.origins.annotate(p))
+                .collect();
+            Err(SentinelError::Config(problems.join("; ")))
+        }
+    }
+
+    /// Directory preemption snapshots are written to.
+    #[must_use]
+    pub fn snapshot_dir(&self) -> PathBuf {
+        self.shutdown
+            .snapshot_dir
+            .clone()
+            .unwrap_or_else(|| self.overlay_dir.join("snapshots"))
+    }
+
+    /// Journal of VM transitions and resources, replayed on startup.
+    #[must_use]
+    pub fn journal_path(&self) -> PathBuf {
+        self.state_dir.join("run.journal")
+    }
+
+    /// Host path of the vsock socket of the VM with `cid`. Its API socket
+    /// and telemetry FIFOs sit beside it.
+    #[must_use]
+    pub fn vsock_path(&self, cid: u32) -> PathBuf {
+        self.state_dir.join("vms").join(format!("fc-{cid}.sock"))
+    }
+
+    /// Root of the per-VM console log directories.
+    #[must_use]
+    pub fn console_dir(&self) -> PathBuf {
+        self.console
+            .dir
+            .clone()
+            .unwrap_or_else(|| self.state_dir.join("console"))
+    }
+
+    /// Kernel image for `profile`: its catalog `kernel`, or `kernel_path`.
+    #[must_use]
+    pub fn kernel_for(&self, profile: &VmProfile) -> PathBuf {
+        match (&profile.kernel, &self.kernel_dir) {
+            (Some(name), Some(dir)) => dir.join(name),
+            _ => self.kernel_path.clone(),
+        }
+    }
+
+    /// Initrd for `profile`, resolved in the kernel catalog.
+    #[must_use]
+    pub fn initrd_for(&self, profile: &VmProfile) -> Option<PathBuf> {
+        let dir = self.kernel_dir.as_ref()?;
+        profile.initrd.as_ref().map(|name| dir.join(name))
+    }
+
+    /// Profile used for a task type when no routing rule matches.
+    #[must_use]
+    pub fn profile_for_task_type(&self, task_type: &str) -> Option<&VmProfile> {
+        self.default_profile_name(task_type)
+            .map(|name| &self.profiles[name])
+    }
+
+    /// Name of the fallback profile for a task type: the profile of the
+    /// same name, then `routing.default_profile`, then `default`.
+    #[must_use]
+    pub fn default_profile_name(&self, task_type: &str) -> Option<&str> {
+        [
+            Some(task_type),
+            self.routing.default_profile.as_deref(),
+            Some(DEFAULT_PROFILE),
+        ]
+        .into_iter()
+        .flatten()
+        .find_map(|name| self.profiles.get_key_value(name))
+        .map(|(name, _)| name.as_str())
+    }
+
+    fn validate_routing(&self, problems: &mut Vec<String>) {
+        if let Some(name) = &self.routing.default_profile
+            && !self.profiles.contains_key(name)
+        {
+            problems.push(format!("routing.default_profile: unknown profile {name:?}"));
+        }
+        for (i, rule) in self.routing.rules.iter().enumerate() {
+            if rule.profiles.is_empty() {
+                problems.push(format!("routing.rules[{i}].profiles: must not be empty"));
+            }
+            for name in &rule.profiles {
+                if !self.profiles.contains_key(name) {
+                    problems.push(format!(
+                        "routing.rules[{i}].profiles: unknown profile {name:?}"
+                    ));
+                }
+            }
+        }
+    }
+
+    fn validate_profile(
+        &self,
+        name: &str,
+        profile: &VmProfile,
+        host: Option<&HostResources>,
+        problems: &mut Vec<String>,
+    ) {
+        let field = format!("profiles.{name}");
+
+        if profile.vcpus == 0 || profile.vcpus > FIRECRACKER_MAX_VCPUS {
+            problems.push(format!(
+                "{field}.vcpus: must be 1-{FIRECRACKER_MAX_VCPUS}, got {}",
+                profile.vcpus
+            ));
+        } else if let Some(host) = host
+            && profile.vcpus > host.cpus
+        {
+            problems.push(format!(
+                "{field}.vcpus: {} exceeds host cpus ({})",
+                profile.vcpus, host.cpus
+            ));
+        }
+
+        if profile.mem_mb < MIN_MEM_MB {
+            problems.push(format!(
+                "{field}.mem_mb: must be at least {MIN_MEM_MB}, got {}",
+                profile.mem_mb
+            ));
+        } else if let Some(host_mem) = host.and_then(|h| h.mem_mb)
+            && u64::from(profile.mem_mb) > host_mem
+        {
+            problems.push(format!(
+                "{field}.mem_mb: {} exceeds host memory ({host_mem} MB)",
+                profile.mem_mb
+            ));
+        }
+
+        for (name, secs) in [
+            ("timeout_sec", profile.timeout_sec),
+            ("provision_timeout_sec", profile.provision_timeout_sec),
+            ("collect_timeout_sec", profile.collect_timeout_sec),
+            ("teardown_timeout_sec", profile.teardown_timeout_sec),
+        ] {
+            if secs == 0 {
+                problems.push(format!("{field}.{name}: must be at least 1"));
+            }
+        }
+        if profile.max_silence_sec == Some(0) {
+            problems.push(format!("{field}.max_silence_sec: must be at least 1"));
+        }
+
+        let rootfs_field = format!("{field}.rootfs");
+        let rootfs = self.image_dir.join(&profile.rootfs);
+        if note(problems, Self::require_file(&rootfs, &rootfs_field)) {
+            note(
+                problems,
+                Self::require_within(&rootfs, &self.image_dir, &rootfs_field),
+            );
+        }
+
+        for (kind, name) in [("kernel", &profile.kernel), ("initrd", &profile.initrd)] {
+            let Some(name) = name else { continue };
+            let kind_field = format!("{field}.{kind}");
+            let Some(kernel_dir) = &self.kernel_dir else {
+                problems.push(format!("{kind_field}: requires kernel_dir"));
+                continue;
+            };
+            let path = kernel_dir.join(name);
+            if note(problems, Self::require_file(&path, &kind_field)) {
+                note(
+                    problems,
+                    Self::require_within(&path, kernel_dir, &kind_field),
+                );
+            }
+        }
+        if let Err(e) = profile.kernel_cmdline().and_then(|c| c.render()) {
+            note(problems, Err(e));
+            if let Some(last) = problems.last_mut() {
+                *last = format!("{field}.boot_args: {last}");
+            }
+        }
+
+        if let Some(limit) = &profile.io_limit {
+            for (name, value) in [
+                ("read_bps", limit.read_bps),
+                ("write_bps", limit.write_bps),
+                ("read_iops", limit.read_iops),
+                ("write_iops", limit.write_iops),
+            ] {
+                if value == Some(0) {
+                    problems.push(format!("{field}.io_limit.{name}: must be at least 1"));
+                }
+            }
+            if self.cgroup.enabled && self.cgroup.io_device.is_none() {
+                problems.push(format!("{field}.io_limit: requires cgroup.io_device"));
+            }
+        }
+
+        if let Some(policy) = &profile.network_policy {
+            if policy.mode != profile.network.as_str() {
+                problems.push(format!(
+                    "{field}.network_policy.mode: {:?} does not match network = {:?}",
+                    policy.mode,
+                    profile.network.as_str()
+                ));
+            }
+            for entry in &policy.allow {
+                if let Err(e) = AllowRule::parse(entry) {
+                    problems.push(format!("{field}.network_policy.allow: {e}"));
+                }
+            }
+        }
+    }
+
     fn validate_host_id(id: &str) -> Result<(), SentinelError> {
         if id.is_empty() || id.len() > 128 {
             return Err(SentinelError::Config(format!(
@@ -87,6 +1000,23 @@ impl SentinelConfig {
         Ok(())
     }
 
+    /// Reject paths that resolve outside `root` (via `..`, an absolute
+    /// path, or a symlink).
+    fn require_within(path: &Path, root: &Path, field: &str) -> Result<(), SentinelError> {
+        let resolve = |p: &Path| {
+            p.canonicalize()
+                .map_err(|e| SentinelError::Config(format!("{field}: cannot resolve path: {e}")))
+        };
+        if !resolve(path)?.starts_with(resolve(root)?) {
+            return Err(SentinelError::Config(format!(
+                "{field}: path escapes {}: {}",
+                root.display(),
+                path.display()
+            )));
+        }
+        Ok(())
+    }
+
     fn reject_traversal(path: &Path, field: &str) -> Result<(), SentinelError> {
         let canonical = path
             .canonicalize()
@@ -104,28 +1034,335 @@ impl SentinelConfig {
     }
 }
 
+/// Key a profile uses to inherit from another profile.
+const EXTENDS_KEY: &str = "extends";
+
+/// Replace every profile in `profiles` with its parent chain merged in.
+///
+/// Nested tables (`network_policy`, `tool_policy`, ...) merge key by key;
+/// any other value, including arrays, is replaced by the child's.
+///
+/// # Errors
+///
+/// Returns `SentinelError::Config` on an unknown parent, a non-string
+/// `extends`, or a cycle.
+pub fn resolve_profile_inheritance(profiles: &mut toml::Table) -> Result<(), SentinelError> {
+    let mut resolved = HashMap::new();
+    for name in profiles.keys() {
+        resolve_profile(name, profiles, &mut resolved, &mut Vec::new())?;
+    }
+    for (name, table) in resolved {
+        profiles.insert(name, toml::Value::Table(table));
+    }
+    Ok(())
+}
+
+fn resolve_profile(
+    name: &str,
+    raw: &toml::Table,
+    resolved: &mut HashMap<String, toml::Table>,
+    chain: &mut Vec<String>,
+) -> Result<toml::Table, SentinelError> {
+    if let Some(done) = resolved.get(name) {
+        return Ok(done.clone());
+    }
+    if chain.iter().any(|n| n == name) {
+        let start = chain.last().map_or(name, String::as_str);
+        return Err(SentinelError::Config(format!(
+            "profiles.{start}.extends: inheritance cycle {} -> {name}",
+            chain.join(" -> ")
+        )));
+    }
+    let Some(toml::Value::Table(own)) = raw.get(name) else {
+        return Err(SentinelError::Config(format!(
+            "profiles.{name}: expected a table"
+        )));
+    };
+    let mut own = own.clone();
+    let merged = match own.remove(EXTENDS_KEY) {
+        None => own,
+        Some(toml::Value::String(parent)) => {
+            if !raw.contains_key(&parent) {
+                return Err(SentinelError::Config(format!(
+                    "profiles.{name}.extends: unknown profile {parent:?}"
+                )));
+            }
+            chain.push(name.to_string());
+            let mut base = resolve_profile(&parent, raw, resolved, chain)?;
+            chain.pop();
+            deep_merge(&mut base, own);
+            base
+        }
+        Some(_) => {
+            return Err(SentinelError::Config(format!(
+                "profiles.{name}.extends: expected a profile name"
+            )));
+        }
+    };
+    resolved.insert(name.to_string(), merged.clone());
+    Ok(merged)
+}
+
+/// Where each key of a profile using `extends` was set: the profile in its
+/// chain that set it last, and the line.
+#[derive(Debug, Clone, Default)]
+struct KeyOrigins {
+    /// The file the lines refer to; set by `load`.
+    file: Option<PathBuf>,
+    /// `profiles.{name}.{key}` to the setting profile and its line.
+    keys: HashMap<String, (String, usize)>,
+}
+
+impl KeyOrigins {
+    /// Origins of every key of the inheriting profiles in `raw`, which has
+    /// already been resolved without cycles.
+    fn of_inherited(raw: &str) -> Self {
+        let mut origins = Self::default();
+        let Ok(doc) = toml_edit::ImDocument::parse(raw) else {
+            return origins;
+        };
+        let Some(profiles) = doc.get("profiles").and_then(toml_edit::Item::as_table_like) else {
+            return origins;
+        };
+        let parent = |name: &str| {
+            profiles
+                .get(name)
+                .and_then(toml_edit::Item::as_table_like)
+                .and_then(|p| p.get(EXTENDS_KEY))
+                .and_then(toml_edit::Item::as_str)
+        };
+        for (name, _) in profiles.iter() {
+            let mut chain = vec![name];
+            while let Some(next) = chain.last().and_then(|n| parent(n))
+                && chain.len() <= profiles.len()
+            {
+                chain.push(next);
+            }
+            if chain.len() == 1 {
+                continue;
+            }
+            // Root first, so each descendant overrides what it inherits.
+            for ancestor in chain.iter().rev() {
+                let Some(own) = profiles
+                    .get(ancestor)
+                    .and_then(toml_edit::Item::as_table_like)
+                else {
+                    continue;
+                };
+                let mut lines = Vec::new();
+                key_lines(raw, own, "", &mut lines);
+                for (key, line) in lines {
+                    origins.keys.insert(
+                        format!("profiles.{name}.{key}"),
+                        ((*ancestor).to_string(), line),
+                    );
+                }
+            }
+        }
+        origins
+    }
+
+    /// `problem`, followed by where the profile key it names was set. A
+    /// nested key without an origin of its own uses its closest table's.
+    fn annotate(&self, problem: String) -> String {
+        let Some((mut field, _)) = problem.split_once(": ") else {
+            return problem;
+        };
+        loop {
+            if let Some((profile, line)) = self.keys.get(field) {
+                let at = match &self.file {
+                    Some(file) => format!("{}:{line}", file.display()),
+                    None => format!("line {line}"),
+                };
+                return format!("{problem} (set in profiles.{profile} at {at})");
+            }
+            match field.rsplit_once('.') {
+                Some((parent, _)) if parent.matches('.').count() >= 2 => field = parent,
+                _ => return problem,
+            }
+        }
+    }
+}
+
+/// Append the 1-based line of every key in `table`, recursively, as
+/// dotted paths under `prefix`. `extends` itself is skipped.
+fn key_lines(
+    raw: &str,
+    table: &dyn toml_edit::TableLike,
+    prefix: &str,
+    out: &mut Vec<(String, usize)>,
+) {
+    for (name, item) in table.iter() {
+        if prefix.is_empty() && name == EXTENDS_KEY {
+            continue;
+        }
+        let path = format!("{prefix}{name}");
+        if let Some(start) = table
+            .get_key_value(name)
+            .and_then(|(key, _)| key.span())
+            .map(|span| span.start)
+        {
+            out.push((path.clone(), raw[..start].matches('\n').count() + 1));
+        }
+        if let Some(inner) = item.as_table_like() {
+            key_lines(raw, inner, &format!("{path}."), out);
+        }
+    }
+}
+
+/// Merge `overlay` into `base`: tables recursively, everything else replaced.
+fn deep_merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_inner)), toml::Value::Table(inner)) => {
+                deep_merge(base_inner, inner);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// `major:minor` block device numbers, as cgroup `io.max` expects.
+fn is_device_number(device: &str) -> bool {
+    device
+        .split_once(':')
+        .is_some_and(|(major, minor)| major.parse::<u32>().is_ok() && minor.parse::<u32>().is_ok())
+}
+
+/// Record a config problem. Returns true if `result` was `Ok`.
+fn note(problems: &mut Vec<String>, result: Result<(), SentinelError>) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(SentinelError::Config(msg)) => {
+            problems.push(msg);
+            false
+        }
+        Err(other) => {
+            problems.push(other.to_string());
+            false
+        }
+    }
+}
+
 fn default_heartbeat() -> u64 {
     10
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// Profile used for task types without a profile of their own.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Firecracker's upper bound on `vcpu_count`.
+pub const FIRECRACKER_MAX_VCPUS: u32 = 32;
+
+/// Smallest guest memory a profile may request.
+pub const MIN_MEM_MB: u32 = 128;
+
+/// CPU and memory available on this host, used to check that every
+/// profile fits.
+#[derive(Debug, Clone, Copy)]
+pub struct HostResources {
+    pub cpus: u32,
+    /// `None` when total memory cannot be determined; the memory check is
+    /// skipped.
+    pub mem_mb: Option<u64>,
+}
+
+impl HostResources {
+    #[must_use]
+    pub fn detect() -> Self {
+        let cpus = std::thread::available_parallelism()
+            .map_or(1, |n| u32::try_from(n.get()).unwrap_or(u32::MAX));
+        let mem_mb = std::fs::read_to_string("/proc/meminfo")
+            .ok()
+            .and_then(|info| parse_mem_total_mb(&info));
+        Self { cpus, mem_mb }
+    }
+}
+
+/// Extract `MemTotal` (reported in kB) from `/proc/meminfo` as MB.
+fn parse_mem_total_mb(meminfo: &str) -> Option<u64> {
+    let line = meminfo.lines().find(|l| l.starts_with("MemTotal:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb / 1024)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VmProfile {
     pub vcpus: u32,
     pub mem_mb: u32,
     pub rootfs: String,
+    /// `rootfs` resolved against `image_dir`; set by `resolve_profiles`.
+    #[serde(skip)]
+    pub rootfs_path: PathBuf,
     #[serde(default = "default_timeout")]
     pub timeout_sec: u64,
+    /// Boot through the operative accepting its vsock connection.
+    #[serde(default = "default_provision_timeout")]
+    pub provision_timeout_sec: u64,
+    /// Gathering results once the operative has reported.
+    #[serde(default = "default_collect_timeout")]
+    pub collect_timeout_sec: u64,
+    /// Releasing the VM, tap and overlay before the VM is force-killed.
+    #[serde(default = "default_teardown_timeout")]
+    pub teardown_timeout_sec: u64,
+    /// Longest the operative may go without sending anything before it is
+    /// pinged; unanswered, the task fails. Unset disables the check.
+    #[serde(default)]
+    pub max_silence_sec: Option<u64>,
     #[serde(default)]
     pub network: NetworkMode,
     pub network_policy: Option<NetworkPolicy>,
     pub tool_policy: Option<ToolPolicy>,
+    /// Run-time extensions the operative may request. None are granted
+    /// when unset.
+    pub extension_policy: Option<ExtensionPolicy>,
+    /// Kernel image name in `kernel_dir`; defaults to `kernel_path`.
+    pub kernel: Option<String>,
+    /// Initrd image name in `kernel_dir`.
+    pub initrd: Option<String>,
+    /// Extra kernel parameters (`key` or `key=value`) appended to the
+    /// Firecracker defaults. Conflicting keys are rejected.
+    #[serde(default)]
+    pub boot_args: Vec<String>,
+    /// Block I/O limits, applied through `io.max` when cgroups are enabled.
+    pub io_limit: Option<IoLimit>,
+}
+
+impl VmProfile {
+    /// Firecracker default boot args plus this profile's `boot_args`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` if an arg is malformed or conflicts
+    /// with a default.
+    pub fn kernel_cmdline(&self) -> Result<KernelCmdline, SentinelError> {
+        let mut cmdline = KernelCmdline::firecracker_defaults();
+        for arg in &self.boot_args {
+            cmdline.push_arg(arg)?;
+        }
+        Ok(cmdline)
+    }
 }
 
 fn default_timeout() -> u64 {
     300
 }
 
-#[derive(Debug, Clone, Default, Deserialize)]
+fn default_provision_timeout() -> u64 {
+    60
+}
+
+fn default_collect_timeout() -> u64 {
+    30
+}
+
+fn default_teardown_timeout() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum NetworkMode {
     #[default]
@@ -134,30 +1371,117 @@ pub enum NetworkMode {
     None,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl NetworkMode {
+    /// The name used in config files (`nat`, `proxy`, `none`).
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Nat => "nat",
+            Self::Proxy => "proxy",
+            Self::None => "none",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkPolicy {
     pub mode: String,
     #[serde(default)]
     pub allow: Vec<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// A parsed `network_policy.allow` entry: `host:port`, where `host` may
+/// start with a `*.` wildcard label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllowRule {
+    pub host: String,
+    pub port: u16,
+}
+
+impl AllowRule {
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` if the entry is not `host:port` with
+    /// a valid hostname and non-zero port.
+    pub fn parse(entry: &str) -> Result<Self, SentinelError> {
+        let invalid = |why: &str| SentinelError::Config(format!("{entry:?}: {why}"));
+        let (host, port) = entry
+            .rsplit_once(':')
+            .ok_or_else(|| invalid("expected host:port"))?;
+        let port: u16 = port.parse().map_err(|_| invalid("invalid port"))?;
+        if port == 0 {
+            return Err(invalid("port must be non-zero"));
+        }
+        let name = host.strip_prefix("*.").unwrap_or(host);
+        let valid_label = |l: &str| {
+            !l.is_empty()
+                && l.len() <= 63
+                && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && !l.starts_with('-')
+                && !l.ends_with('-')
+        };
+        if name.is_empty() || name.len() > 253 || !name.split('.').all(valid_label) {
+            return Err(invalid("invalid hostname"));
+        }
+        Ok(Self {
+            host: host.to_ascii_lowercase(),
+            port,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolPolicy {
     #[serde(default)]
     pub allowed_tools: Vec<String>,
     pub rate_limit: Option<RateLimit>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RateLimit {
     pub calls_per_minute: u32,
 }
 
+/// Per-VM block I/O limits on `cgroup.io_device`. Unset fields are
+/// unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoLimit {
+    pub read_bps: Option<u64>,
+    pub write_bps: Option<u64>,
+    pub read_iops: Option<u64>,
+    pub write_iops: Option<u64>,
+}
+
+/// Limits on operative-requested deadline extensions, per task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtensionPolicy {
+    /// Total seconds that may be added to `timeout_sec`.
+    pub max_total_sec: u64,
+    /// Number of requests answered before all further ones are denied.
+    pub max_requests: u32,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs;
 
+    fn profile(vcpus: u32, mem_mb: u32, rootfs: &str) -> VmProfile {
+        serde_json::from_value(serde_json::json!({
+            "vcpus": vcpus,
+            "mem_mb": mem_mb,
+            "rootfs": rootfs,
+        }))
+        .unwrap()
+    }
+
+    fn host(cpus: u32, mem_mb: u64) -> HostResources {
+        HostResources {
+            cpus,
+            mem_mb: Some(mem_mb),
+        }
+    }
+
     fn valid_config(tmp: &std::path::Path) -> SentinelConfig {
         let image_dir = tmp.join("images");
         let overlay_dir = tmp.join("overlays");
@@ -167,17 +1491,33 @@ mod tests {
         let fc_bin = tmp.join("firecracker");
         fs::write(&kernel, b"").unwrap();
         fs::write(&fc_bin, b"").unwrap();
+        fs::write(image_dir.join("base.ext4"), b"").unwrap();
 
         SentinelConfig {
             host_id: "host-01".into(),
             slots: 4,
             image_dir,
             kernel_path: kernel,
+            kernel_dir: None,
             overlay_dir,
             firecracker_bin: fc_bin,
-            profiles: HashMap::new(),
+            profiles: HashMap::from([("shell".into(), profile(1, 128, "base.ext4"))]),
             task_types: vec!["shell".into()],
             heartbeat_interval_secs: 10,
+            routing: RoutingTable::default(),
+            bus: TransportConfig::default(),
+            state: StateStoreConfig::default(),
+            admin_socket: default_admin_socket(),
+            control_key: None,
+            shutdown: ShutdownConfig::default(),
+            state_dir: default_state_dir(),
+            recovery: RecoveryConfig::default(),
+            journal: JournalConfig::default(),
+            console: ConsoleConfig::default(),
+            logs: LogConfig::default(),
+            jailer: JailerConfig::default(),
+            cgroup: CgroupConfig::default(),
+            origins: KeyOrigins::default(),
         }
     }
 
@@ -282,9 +1622,811 @@ mod tests {
         let json = r#"{"vcpus": 2, "mem_mb": 256, "rootfs": "base.ext4"}"#;
         let p: VmProfile = serde_json::from_str(json).unwrap();
         assert_eq!(p.timeout_sec, 300);
+        assert_eq!(p.provision_timeout_sec, 60);
+        assert_eq!(p.collect_timeout_sec, 30);
+        assert_eq!(p.teardown_timeout_sec, 30);
+        assert!(p.max_silence_sec.is_none());
         assert!(matches!(p.network, NetworkMode::Nat));
     }
 
+    fn write_toml(tmp: &std::path::Path, body: &str) -> PathBuf {
+        let cfg = valid_config(tmp);
+        let path = tmp.join("sentinel.toml");
+        let header = format!(
+            "host_id = \"host-01\"\nslots = 4\nimage_dir = {:?}\nkernel_path = {:?}\n\
+             overlay_dir = {:?}\nfirecracker_bin = {:?}\ntask_types = [\"shell\"]\n",
+            cfg.image_dir, cfg.kernel_path, cfg.overlay_dir, cfg.firecracker_bin,
+        );
+        fs::write(&path, header + body).unwrap();
+        path
+    }
+
+    const SHELL_PROFILE: &str =
+        "[profiles.shell]\nvcpus = 1\nmem_mb = 128\nrootfs = \"base.ext4\"\n";
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn load_parses_toml_profiles() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = write_toml(
+            tmp.path(),
+            r#"
+[profiles.default]
+vcpus = 2
+mem_mb = 512
+rootfs = "base.ext4"
+network = "proxy"
+
+[profiles.default.network_policy]
+mode = "proxy"
+allow = ["api.anthropic.com:443"]
+
+[profiles.default.tool_policy]
+allowed_tools = ["llm.complete"]
+rate_limit = { calls_per_minute = 60 }
+
+[profiles.default.extension_policy]
+max_total_sec = 600
+max_requests = 3
+"#,
+        );
+        let cfg = SentinelConfig::load_with_env(&path, no_env).unwrap();
+        let profile = &cfg.profiles["default"];
+        assert_eq!(profile.vcpus, 2);
+        assert!(matches!(profile.network, NetworkMode::Proxy));
+        assert_eq!(profile.rootfs_path, cfg.image_dir.join("base.ext4"));
+        let tools = profile.tool_policy.as_ref().unwrap();
+        assert_eq!(tools.rate_limit.as_ref().unwrap().calls_per_minute, 60);
+        assert_eq!(
+            profile.extension_policy,
+            Some(ExtensionPolicy {
+                max_total_sec: 600,
+                max_requests: 3
+            })
+        );
+    }
+
+    #[test]
+    fn load_applies_env_overrides() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = write_toml(tmp.path(), SHELL_PROFILE);
+        let cfg = SentinelConfig::load_with_env(&path, |key| match key {
+            "SENTINEL_HOST_ID" => Some("host-override".into()),
+            "SENTINEL_SLOTS" => Some("16".into()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(cfg.host_id, "host-override");
+        assert_eq!(cfg.slots, 16);
+    }
+
+    #[test]
+    fn render_round_trips() {
+        let cfg = SentinelConfig::from_toml(INHERITING).unwrap();
+        let rendered = cfg.render().unwrap();
+        assert!(!rendered.contains("extends"));
+        let reparsed = SentinelConfig::from_toml(&rendered).unwrap();
+        assert_eq!(reparsed.host_id, cfg.host_id);
+        assert_eq!(reparsed.admin_socket, cfg.admin_socket);
+        assert_eq!(reparsed.profiles["heavy"].vcpus, 4);
+        assert_eq!(reparsed.profiles["heavy"].mem_mb, 256);
+    }
+
+    #[test]
+    fn shutdown_defaults_and_overrides() {
+        let cfg = SentinelConfig::from_toml(INHERITING).unwrap();
+        assert_eq!(cfg.shutdown.drain_timeout_secs, 30);
+        assert_eq!(cfg.shutdown.on_timeout, PreemptAction::Requeue);
+        assert_eq!(cfg.snapshot_dir(), PathBuf::from("/o/snapshots"));
+
+        let cfg = SentinelConfig::from_toml(&format!(
+            "{INHERITING}\n[shutdown]\ndrain_timeout_secs = 120\non_timeout = \"snapshot\"\nsnapshot_dir = \"/snap\"\n"
+        ))
+        .unwrap();
+        assert_eq!(cfg.shutdown.drain_timeout_secs, 120);
+        assert_eq!(cfg.shutdown.on_timeout, PreemptAction::Snapshot);
+        assert_eq!(cfg.snapshot_dir(), PathBuf::from("/snap"));
+    }
+
+    #[test]
+    fn recovery_defaults_and_overrides() {
+        let cfg = SentinelConfig::from_toml(INHERITING).unwrap();
+        assert_eq!(cfg.recovery.orphaned_tasks, OrphanAction::Requeue);
+        assert_eq!(cfg.journal, JournalConfig::default());
+        assert_eq!(
+            cfg.journal_path(),
+            PathBuf::from("/var/lib/gbe-sentinel/run.journal")
+        );
+
+        let cfg = SentinelConfig::from_toml(&format!(
+            "state_dir = \"/state\"\n{INHERITING}\n[recovery]\norphaned_tasks = \"fail\"\n\
+             [journal]\nsync_batch = 8\n"
+        ))
+        .unwrap();
+        assert_eq!(cfg.recovery.orphaned_tasks, OrphanAction::Fail);
+        assert_eq!(cfg.journal.sync_batch, 8);
+        assert_eq!(cfg.journal.sync_interval_ms, 100);
+        assert_eq!(cfg.journal_path(), PathBuf::from("/state/run.journal"));
+    }
+
+    #[test]
+    fn missing_snapshot_dir_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.shutdown.snapshot_dir = Some(tmp.path().join("nope"));
+        let err = cfg.validate_for_host(&host(4, 4096)).unwrap_err();
+        assert!(err.to_string().contains("shutdown.snapshot_dir"));
+    }
+
+    #[test]
+    fn zero_compact_after_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.journal.compact_after = 0;
+        let err = cfg.validate_for_host(&host(4, 4096)).unwrap_err();
+        assert!(err.to_string().contains("journal.compact_after"));
+    }
+
+    #[test]
+    fn console_defaults_and_limits() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        assert_eq!(cfg.console_dir(), cfg.state_dir.join("console"));
+        cfg.console.dir = Some("/var/log/gbe-console".into());
+        assert_eq!(cfg.console_dir(), PathBuf::from("/var/log/gbe-console"));
+
+        cfg.console.max_bytes = 1024;
+        cfg.console.tail_bytes = 2048;
+        let err = cfg.validate_for_host(&host(4, 4096)).unwrap_err();
+        assert!(err.to_string().contains("console.max_bytes"));
+        assert!(err.to_string().contains("console.tail_bytes"));
+    }
+
+    #[test]
+    fn zero_log_limits_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        assert!(!cfg.logs.relay_console);
+        cfg.logs.batch_lines = 0;
+        cfg.logs.max_lines_per_sec = 0;
+        let err = cfg
+            .validate_for_host(&host(4, 4096))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("logs.batch_lines"));
+        assert!(err.contains("logs.max_lines_per_sec"));
+        assert!(!err.contains("logs.flush_interval_ms"));
+    }
+
+    #[test]
+    fn jailer_checked_only_when_enabled() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.jailer.uid_base = 0;
+        assert!(cfg.validate_for_host(&host(4, 4096)).is_ok());
+
+        cfg.jailer.enabled = true;
+        cfg.jailer.bin = tmp.path().join("jailer");
+        cfg.jailer.chroot_base = tmp.path().to_path_buf();
+        let err = cfg
+            .validate_for_host(&host(4, 4096))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("jailer.bin: path does not exist"));
+        assert!(err.contains("jailer.uid_base: must not be 0"));
+        assert!(!err.contains("jailer.gid_base"));
+        assert!(!err.contains("jailer.chroot_base"));
+
+        fs::write(&cfg.jailer.bin, b"").unwrap();
+        cfg.jailer.uid_base = 100_000;
+        cfg.jailer.netns = false;
+        assert!(cfg.validate_for_host(&host(4, 4096)).is_ok());
+    }
+
+    #[test]
+    fn jailer_netns_rejects_nat_profiles() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.jailer.enabled = true;
+        cfg.jailer.bin = tmp.path().join("jailer");
+        cfg.jailer.chroot_base = tmp.path().to_path_buf();
+        fs::write(&cfg.jailer.bin, b"").unwrap();
+        cfg.profiles.get_mut("shell").unwrap().network = NetworkMode::Nat;
+        let err = cfg
+            .validate_for_host(&host(4, 4096))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("jailer.netns: profile \"shell\" uses network = \"nat\""));
+
+        cfg.profiles.get_mut("shell").unwrap().network = NetworkMode::None;
+        assert!(cfg.validate_for_host(&host(4, 4096)).is_ok());
+        cfg.profiles.get_mut("shell").unwrap().network = NetworkMode::Nat;
+        cfg.jailer.netns = false;
+        assert!(cfg.validate_for_host(&host(4, 4096)).is_ok());
+    }
+
+    #[test]
+    fn cgroup_settings_checked_when_enabled() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.cgroup.parent = "../escape".into();
+        cfg.profiles.get_mut("shell").unwrap().io_limit = Some(IoLimit {
+            write_bps: Some(0),
+            ..IoLimit::default()
+        });
+        let err = cfg
+            .validate_for_host(&host(4, 4096))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("profiles.shell.io_limit.write_bps"));
+        assert!(!err.contains("cgroup."));
+
+        cfg.cgroup.enabled = true;
+        cfg.cgroup.root = tmp.path().to_path_buf();
+        cfg.cgroup.io_device = Some("sda".into());
+        let err = cfg
+            .validate_for_host(&host(4, 4096))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("cgroup.parent"));
+        assert!(err.contains("cgroup.io_device: expected major:minor"));
+
+        cfg.cgroup.parent = "system.slice/gbe-sentinel".into();
+        cfg.cgroup.io_device = None;
+        cfg.profiles.get_mut("shell").unwrap().io_limit = Some(IoLimit {
+            write_bps: Some(1 << 20),
+            ..IoLimit::default()
+        });
+        let err = cfg.validate_for_host(&host(4, 4096)).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("profiles.shell.io_limit: requires cgroup.io_device")
+        );
+
+        cfg.cgroup.io_device = Some("259:0".into());
+        assert!(cfg.validate_for_host(&host(4, 4096)).is_ok());
+    }
+
+    #[test]
+    fn short_control_key_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.control_key = Some("short".into());
+        let err = cfg.validate_for_host(&host(4, 4096)).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("control_key: must be at least 32 bytes")
+        );
+        cfg.control_key = Some("k".repeat(MIN_CONTROL_KEY_LEN));
+        assert!(cfg.validate_for_host(&host(4, 4096)).is_ok());
+    }
+
+    #[test]
+    fn control_key_never_rendered() {
+        let mut cfg = SentinelConfig::from_toml(INHERITING).unwrap();
+        cfg.control_key = Some("secret".repeat(8));
+        assert!(!cfg.render().unwrap().contains("secret"));
+    }
+
+    #[test]
+    fn bus_and_state_urls() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = write_toml(
+            tmp.path(),
+            &format!("{SHELL_PROFILE}\n[bus]\nurl = \"redis://bus:6379\"\n"),
+        );
+        let cfg = SentinelConfig::load_with_env(&path, no_env).unwrap();
+        assert_eq!(cfg.bus.url, "redis://bus:6379");
+        assert_eq!(cfg.state.url, "redis://127.0.0.1:6379");
+
+        let cfg = SentinelConfig::load_with_env(&path, |key| {
+            (key == "SENTINEL_STATE_URL").then(|| "redis://state:6380".into())
+        })
+        .unwrap();
+        assert_eq!(cfg.state.url, "redis://state:6380");
+    }
+
+    #[test]
+    fn env_override_paths_resolve_profiles() {
+        let tmp = tempfile::tempdir().unwrap();
+        let alt = tmp.path().join("alt-images");
+        fs::create_dir_all(&alt).unwrap();
+        fs::write(alt.join("base.ext4"), b"").unwrap();
+        let path = write_toml(
+            tmp.path(),
+            "[profiles.default]\nvcpus = 1\nmem_mb = 128\nrootfs = \"base.ext4\"\n",
+        );
+        let alt_str = alt.to_string_lossy().to_string();
+        let cfg = SentinelConfig::load_with_env(&path, |key| {
+            (key == "SENTINEL_IMAGE_DIR").then(|| alt_str.clone())
+        })
+        .unwrap();
+        assert_eq!(cfg.profiles["default"].rootfs_path, alt.join("base.ext4"));
+    }
+
+    #[test]
+    fn invalid_slots_override_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = write_toml(tmp.path(), SHELL_PROFILE);
+        let err = SentinelConfig::load_with_env(&path, |key| {
+            (key == "SENTINEL_SLOTS").then(|| "many".to_string())
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("SENTINEL_SLOTS"));
+    }
+
+    #[test]
+    fn parse_error_points_at_file_and_line() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = write_toml(tmp.path(), "[profiles]\nbroken = = 1\n");
+        let msg = SentinelConfig::load_with_env(&path, no_env)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("sentinel.toml"));
+        assert!(msg.contains("line 9"), "{msg}");
+    }
+
+    #[test]
+    fn validation_error_names_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = write_toml(tmp.path(), SHELL_PROFILE);
+        let msg = SentinelConfig::load_with_env(&path, |key| {
+            (key == "SENTINEL_HOST_ID").then(|| "bad/host".to_string())
+        })
+        .unwrap_err()
+        .to_string();
+        assert!(msg.contains("sentinel.toml"));
+        assert!(msg.contains("host_id"));
+    }
+
+    #[test]
+    fn missing_file_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let err = SentinelConfig::load_with_env(&tmp.path().join("nope.toml"), no_env).unwrap_err();
+        assert!(matches!(err, SentinelError::Config(_)));
+        assert!(err.to_string().contains("nope.toml"));
+    }
+
+    #[test]
+    fn missing_rootfs_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.profiles
+            .insert("shell".into(), profile(1, 128, "missing.ext4"));
+        let err = cfg.validate_for_host(&host(4, 4096)).unwrap_err();
+        assert!(err.to_string().contains("profiles.shell.rootfs"));
+    }
+
+    #[test]
+    fn rootfs_traversal_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        fs::write(tmp.path().join("outside.ext4"), b"").unwrap();
+        cfg.profiles
+            .insert("shell".into(), profile(1, 128, "../outside.ext4"));
+        let err = cfg.validate_for_host(&host(4, 4096)).unwrap_err();
+        assert!(err.to_string().contains("escapes"), "{err}");
+    }
+
+    #[test]
+    fn vcpus_out_of_firecracker_range_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.profiles
+            .insert("shell".into(), profile(0, 128, "base.ext4"));
+        let err = cfg.validate_for_host(&host(64, 4096)).unwrap_err();
+        assert!(err.to_string().contains("vcpus: must be 1-32"));
+
+        cfg.profiles
+            .insert("shell".into(), profile(33, 128, "base.ext4"));
+        assert!(cfg.validate_for_host(&host(64, 4096)).is_err());
+    }
+
+    #[test]
+    fn profile_larger_than_host_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.profiles
+            .insert("shell".into(), profile(8, 8192, "base.ext4"));
+        let msg = cfg
+            .validate_for_host(&host(4, 4096))
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("exceeds host cpus"));
+        assert!(msg.contains("exceeds host memory"));
+        // Sizes are only checked against the host the daemon runs on.
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn unknown_host_memory_skips_memory_check() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.profiles
+            .insert("shell".into(), profile(1, 65536, "base.ext4"));
+        let unknown = HostResources {
+            cpus: 4,
+            mem_mb: None,
+        };
+        assert!(cfg.validate_for_host(&unknown).is_ok());
+    }
+
+    #[test]
+    fn too_little_memory_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.profiles
+            .insert("shell".into(), profile(1, 64, "base.ext4"));
+        let err = cfg.validate_for_host(&host(4, 4096)).unwrap_err();
+        assert!(err.to_string().contains("mem_mb: must be at least 128"));
+    }
+
+    #[test]
+    fn zero_phase_timeout_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        let mut shell = profile(1, 128, "base.ext4");
+        shell.provision_timeout_sec = 0;
+        shell.max_silence_sec = Some(0);
+        cfg.profiles.insert("shell".into(), shell);
+        let err = cfg
+            .validate_for_host(&host(4, 4096))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("profiles.shell.provision_timeout_sec: must be at least 1"));
+        assert!(err.contains("profiles.shell.max_silence_sec: must be at least 1"));
+    }
+
+    #[test]
+    fn task_type_without_profile_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.task_types.push("build".into());
+        let err = cfg.validate_for_host(&host(4, 4096)).unwrap_err();
+        assert!(err.to_string().contains("\"build\" has no profile"));
+    }
+
+    #[test]
+    fn task_type_falls_back_to_default_profile() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.task_types.push("build".into());
+        cfg.profiles
+            .insert(DEFAULT_PROFILE.into(), profile(1, 128, "base.ext4"));
+        assert!(cfg.validate_for_host(&host(4, 4096)).is_ok());
+        assert!(cfg.profile_for_task_type("build").is_some());
+    }
+
+    #[test]
+    fn routing_rule_covers_task_type() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.task_types.push("build".into());
+        cfg.routing.rules.push(crate::routing::RoutingRule {
+            task_type: Some("build".into()),
+            tenant: None,
+            labels: HashMap::new(),
+            profiles: vec!["shell".into()],
+        });
+        assert!(cfg.validate_for_host(&host(4, 4096)).is_ok());
+    }
+
+    #[test]
+    fn routing_unknown_profiles_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.routing.default_profile = Some("missing".into());
+        cfg.routing.rules.push(crate::routing::RoutingRule {
+            task_type: None,
+            tenant: Some("acme".into()),
+            labels: HashMap::new(),
+            profiles: vec!["ghost".into()],
+        });
+        let msg = cfg
+            .validate_for_host(&host(4, 4096))
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("routing.default_profile: unknown profile \"missing\""));
+        assert!(msg.contains("routing.rules[0].profiles: unknown profile \"ghost\""));
+    }
+
+    #[test]
+    fn profile_kernel_and_initrd_from_catalog() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        let kernels = tmp.path().join("kernels");
+        fs::create_dir_all(&kernels).unwrap();
+        fs::write(kernels.join("vmlinux-6.1"), b"").unwrap();
+        fs::write(kernels.join("initrd.img"), b"").unwrap();
+        cfg.kernel_dir = Some(kernels.clone());
+        let mut p = profile(1, 128, "base.ext4");
+        p.kernel = Some("vmlinux-6.1".into());
+        p.initrd = Some("initrd.img".into());
+        p.boot_args = vec!["init=/sbin/operative".into()];
+        cfg.profiles.insert("shell".into(), p);
+
+        assert!(cfg.validate_for_host(&host(4, 4096)).is_ok());
+        let p = &cfg.profiles["shell"];
+        assert_eq!(cfg.kernel_for(p), kernels.join("vmlinux-6.1"));
+        assert_eq!(cfg.initrd_for(p), Some(kernels.join("initrd.img")));
+    }
+
+    #[test]
+    fn profile_kernel_requires_catalog() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        let mut p = profile(1, 128, "base.ext4");
+        p.kernel = Some("vmlinux-6.1".into());
+        cfg.profiles.insert("shell".into(), p);
+        let err = cfg.validate_for_host(&host(4, 4096)).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("profiles.shell.kernel: requires kernel_dir")
+        );
+    }
+
+    #[test]
+    fn profile_kernel_outside_catalog_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        let kernels = tmp.path().join("kernels");
+        fs::create_dir_all(&kernels).unwrap();
+        cfg.kernel_dir = Some(kernels);
+        let mut p = profile(1, 128, "base.ext4");
+        p.kernel = Some("../vmlinux".into());
+        p.initrd = Some("missing.img".into());
+        cfg.profiles.insert("shell".into(), p);
+        let msg = cfg
+            .validate_for_host(&host(4, 4096))
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("profiles.shell.kernel: path escapes"), "{msg}");
+        assert!(msg.contains("profiles.shell.initrd: path does not exist"));
+    }
+
+    #[test]
+    fn conflicting_boot_args_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        let mut p = profile(1, 128, "base.ext4");
+        p.boot_args = vec!["console=ttyS1".into()];
+        cfg.profiles.insert("shell".into(), p);
+        let err = cfg.validate_for_host(&host(4, 4096)).unwrap_err();
+        assert!(
+            err.to_string().contains("profiles.shell.boot_args"),
+            "{err}"
+        );
+        assert!(err.to_string().contains("conflicts"));
+    }
+
+    #[test]
+    fn kernel_dir_env_override() {
+        let mut cfg: SentinelConfig = serde_json::from_str(
+            r#"{"host_id":"h1","slots":1,"image_dir":"/i","kernel_path":"/k",
+                "overlay_dir":"/o","firecracker_bin":"/fc","profiles":{},"task_types":[]}"#,
+        )
+        .unwrap();
+        assert!(cfg.kernel_dir.is_none());
+        cfg.apply_env_overrides(|key| (key == "SENTINEL_KERNEL_DIR").then(|| "/kernels".into()))
+            .unwrap();
+        assert_eq!(cfg.kernel_dir, Some(PathBuf::from("/kernels")));
+    }
+
+    #[test]
+    fn network_policy_mode_must_match_network() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        let mut p = profile(1, 128, "base.ext4");
+        p.network = NetworkMode::Nat;
+        p.network_policy = Some(NetworkPolicy {
+            mode: "proxy".into(),
+            allow: vec![],
+        });
+        cfg.profiles.insert("shell".into(), p);
+        let err = cfg.validate_for_host(&host(4, 4096)).unwrap_err();
+        assert!(err.to_string().contains("network_policy.mode"));
+    }
+
+    #[test]
+    fn invalid_allow_entries_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        let mut p = profile(1, 128, "base.ext4");
+        p.network = NetworkMode::Proxy;
+        p.network_policy = Some(NetworkPolicy {
+            mode: "proxy".into(),
+            allow: vec![
+                "api.anthropic.com:443".into(),
+                "no-port".into(),
+                "bad_host:443".into(),
+                "*.example.com:0".into(),
+            ],
+        });
+        cfg.profiles.insert("shell".into(), p);
+        let msg = cfg
+            .validate_for_host(&host(4, 4096))
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("\"no-port\": expected host:port"));
+        assert!(msg.contains("\"bad_host:443\": invalid hostname"));
+        assert!(msg.contains("port must be non-zero"));
+        assert!(!msg.contains("api.anthropic.com"));
+    }
+
+    #[test]
+    fn all_problems_reported_together() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cfg = valid_config(tmp.path());
+        cfg.host_id = String::new();
+        cfg.kernel_path = tmp.path().join("missing-kernel");
+        cfg.profiles
+            .insert("shell".into(), profile(0, 64, "missing.ext4"));
+        let msg = cfg
+            .validate_for_host(&host(4, 4096))
+            .unwrap_err()
+            .to_string();
+        for field in ["host_id", "kernel_path", "vcpus", "mem_mb", "rootfs"] {
+            assert!(msg.contains(field), "missing {field} in: {msg}");
+        }
+    }
+
+    #[test]
+    fn allow_rule_parses_wildcards() {
+        let rule = AllowRule::parse("*.Example.com:8443").unwrap();
+        assert_eq!(rule.host, "*.example.com");
+        assert_eq!(rule.port, 8443);
+        assert!(AllowRule::parse("*.:443").is_err());
+        assert!(AllowRule::parse("host:99999").is_err());
+    }
+
+    #[test]
+    fn parse_mem_total() {
+        let info = "MemTotal:       16303868 kB\nMemFree:         1234 kB\n";
+        assert_eq!(parse_mem_total_mb(info), Some(15921));
+        assert_eq!(parse_mem_total_mb("garbage"), None);
+    }
+
+    const INHERITING: &str = r#"
+host_id = "h1"
+slots = 2
+image_dir = "/images"
+kernel_path = "/k"
+overlay_dir = "/o"
+firecracker_bin = "/fc"
+task_types = ["shell"]
+
+[profiles.base]
+vcpus = 1
+mem_mb = 256
+rootfs = "base.ext4"
+network = "proxy"
+
+[profiles.base.network_policy]
+mode = "proxy"
+allow = ["api.anthropic.com:443"]
+
+[profiles.base.tool_policy]
+allowed_tools = ["llm.complete"]
+rate_limit = { calls_per_minute = 60 }
+
+[profiles.heavy]
+extends = "base"
+vcpus = 4
+
+[profiles.heavy.tool_policy.rate_limit]
+calls_per_minute = 600
+
+[profiles.heavy-web]
+extends = "heavy"
+
+[profiles.heavy-web.network_policy]
+allow = ["example.com:443"]
+"#;
+
+    #[test]
+    fn extends_deep_merges_nested_policies() {
+        let cfg = SentinelConfig::from_toml(INHERITING).unwrap();
+        let heavy = &cfg.profiles["heavy"];
+        assert_eq!(heavy.vcpus, 4);
+        assert_eq!(heavy.mem_mb, 256);
+        assert!(matches!(heavy.network, NetworkMode::Proxy));
+        let tools = heavy.tool_policy.as_ref().unwrap();
+        assert_eq!(tools.allowed_tools, ["llm.complete"]);
+        assert_eq!(tools.rate_limit.as_ref().unwrap().calls_per_minute, 600);
+    }
+
+    #[test]
+    fn extends_chains_and_replaces_arrays() {
+        let cfg = SentinelConfig::from_toml(INHERITING).unwrap();
+        let web = &cfg.profiles["heavy-web"];
+        assert_eq!(web.vcpus, 4);
+        let net = web.network_policy.as_ref().unwrap();
+        assert_eq!(net.mode, "proxy");
+        assert_eq!(net.allow, ["example.com:443"]);
+        // The parent is untouched by its children.
+        assert_eq!(cfg.profiles["base"].vcpus, 1);
+    }
+
+    #[test]
+    fn extends_unknown_parent_rejected() {
+        let raw = INHERITING.replace(r#"extends = "heavy""#, r#"extends = "nope""#);
+        let err = SentinelConfig::from_toml(&raw).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("profiles.heavy-web.extends: unknown profile \"nope\"")
+        );
+    }
+
+    #[test]
+    fn extends_cycle_rejected() {
+        let raw = INHERITING.replace(
+            "[profiles.base]\n",
+            "[profiles.base]\nextends = \"heavy-web\"\n",
+        );
+        let err = SentinelConfig::from_toml(&raw).unwrap_err();
+        assert!(err.to_string().contains("inheritance cycle"), "{err}");
+    }
+
+    #[test]
+    fn extends_self_rejected() {
+        let raw = INHERITING.replace(r#"extends = "base""#, r#"extends = "heavy""#);
+        let err = SentinelConfig::from_toml(&raw).unwrap_err();
+        assert!(err.to_string().contains("heavy -> heavy"), "{err}");
+    }
+
+    #[test]
+    fn extends_type_errors_name_the_key() {
+        let raw = INHERITING.replace("vcpus = 4", "vcpus = \"four\"");
+        let err = SentinelConfig::from_toml(&raw).unwrap_err();
+        assert!(err.to_string().contains("profiles.heavy.vcpus"), "{err}");
+    }
+
+    #[test]
+    fn extends_validation_errors_point_at_the_setting_line() {
+        let tmp = tempfile::tempdir().unwrap();
+        // After the 7-line header written by `write_toml`.
+        let path = write_toml(
+            tmp.path(),
+            "[profiles.shell]\nvcpus = 0\nmem_mb = 128\nrootfs = \"base.ext4\"\n\
+             [profiles.big]\nextends = \"shell\"\nmem_mb = 64\n",
+        );
+        let msg = SentinelConfig::load_with_env(&path, no_env)
+            .unwrap_err()
+            .to_string();
+        let file = path.display();
+        assert!(
+            msg.contains(&format!(
+                "profiles.big.vcpus: must be 1-32, got 0 (set in profiles.shell at {file}:9)"
+            )),
+            "{msg}"
+        );
+        assert!(
+            msg.contains(&format!(
+                "profiles.big.mem_mb: must be at least 128, got 64 (set in profiles.big at {file}:14)"
+            )),
+            "{msg}"
+        );
+        // A profile without `extends` has nothing merged to explain.
+        assert!(
+            msg.ends_with("profiles.shell.vcpus: must be 1-32, got 0"),
+            "{msg}"
+        );
+    }
+
+    #[test]
+    fn render_profile_shows_resolved_fields() {
+        let cfg = SentinelConfig::from_toml(INHERITING).unwrap();
+        let rendered = cfg.render_profile("heavy").unwrap();
+        assert!(rendered.contains("vcpus = 4"));
+        assert!(rendered.contains("mem_mb = 256"));
+        assert!(rendered.contains("calls_per_minute = 600"));
+        assert!(!rendered.contains("extends"));
+        assert!(cfg.render_profile("missing").is_err());
+    }
+
     #[test]
     fn network_mode_variants() {
         let json_nat = r#"{"vcpus":1,"mem_mb":128,"rootfs":"r","network":"nat"}"#;
@@ -0,0 +1,303 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use async_trait::async_trait;
+use gbe_nexus::{Message, MessageHandler, TransportError};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::claim::now_millis;
+use crate::error::SentinelError;
+use crate::mode::{HostMode, ModeControl};
+
+/// Commands older or newer than this (relative to the host clock) are
+/// rejected.
+pub const MAX_CLOCK_SKEW_MS: u64 = 60_000;
+
+/// Control subject for `host_id`.
+#[must_use]
+pub fn control_subject(host_id: &str) -> String {
+    format!("gbe.events.sentinel.{host_id}.control")
+}
+
+/// Mode change requested over the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlCommand {
+    Cordon,
+    Uncordon,
+    /// Stop claiming and exit once empty; `deadline_secs` bounds the wait.
+    Drain {
+        deadline_secs: Option<u64>,
+    },
+}
+
+impl ControlCommand {
+    fn canonical(&self) -> String {
+        match self {
+            Self::Cordon => "cordon".to_string(),
+            Self::Uncordon => "uncordon".to_string(),
+            Self::Drain { deadline_secs } => {
+                format!(
+                    "drain:{}",
+                    deadline_secs.map(|s| s.to_string()).unwrap_or_default()
+                )
+            }
+        }
+    }
+
+    /// Sign this command for `host_id`, issued at `issued_at_ms`.
+    #[must_use]
+    pub fn sign(self, key: &[u8], host_id: &str, issued_at_ms: u64) -> SignedCommand {
+        SignedCommand {
+            command: self,
+            issued_at_ms,
+            signature: hex::encode(signature(key, host_id, &self, issued_at_ms)),
+        }
+    }
+}
+
+/// Payload of a message on the control subject.
+///
+/// `signature` is hex HMAC-SHA256 over the target host id, the command and
+/// `issued_at_ms`, so a command cannot be redirected to another host or
+/// altered. Commands must be fresh, issued after the sentinel started, and
+/// strictly newer than the last one accepted, so a captured message cannot
+/// be replayed, including after a restart empties the replay state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedCommand {
+    #[serde(flatten)]
+    pub command: ControlCommand,
+    pub issued_at_ms: u64,
+    pub signature: String,
+}
+
+fn signature(key: &[u8], host_id: &str, command: &ControlCommand, issued_at_ms: u64) -> Vec<u8> {
+    mac(key, host_id, command, issued_at_ms)
+        .finalize()
+        .into_bytes()
+        .to_vec()
+}
+
+fn mac(key: &[u8], host_id: &str, command: &ControlCommand, issued_at_ms: u64) -> Hmac<Sha256> {
+    // HMAC accepts keys of any length.
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap_or_else(|_| unreachable!());
+    mac.update(format!("{host_id}\n{}\n{issued_at_ms}", command.canonical()).as_bytes());
+    mac
+}
+
+/// Applies authenticated control commands to the host mode.
+pub struct ControlHandler {
+    host_id: String,
+    key: Vec<u8>,
+    mode: Arc<ModeControl>,
+    /// Commands issued before this are rejected: the replay state below
+    /// does not survive a restart.
+    started_ms: u64,
+    last_issued_ms: AtomicU64,
+}
+
+impl ControlHandler {
+    #[must_use]
+    pub fn new(host_id: &str, key: &[u8], mode: Arc<ModeControl>) -> Self {
+        Self {
+            host_id: host_id.to_string(),
+            key: key.to_vec(),
+            mode,
+            started_ms: now_millis(),
+            last_issued_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Verify and apply a raw control payload, returning the new mode.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Json` on a malformed payload, or
+    /// `SentinelError::Unauthorized` if the signature is wrong, the command
+    /// is stale or predates this sentinel, or it is not newer than the last
+    /// accepted command.
+    pub fn apply(&self, payload: &[u8]) -> Result<HostMode, SentinelError> {
+        let signed: SignedCommand = serde_json::from_slice(payload)?;
+        self.verify(&signed, now_millis())?;
+
+        let mode = match signed.command {
+            ControlCommand::Cordon => HostMode::Cordoned,
+            ControlCommand::Uncordon => HostMode::Active,
+            ControlCommand::Drain { deadline_secs } => HostMode::Draining {
+                deadline_ms: deadline_secs.map(|s| now_millis() + s * 1000),
+            },
+        };
+        tracing::info!(command = ?signed.command, "control command accepted");
+        self.mode.set(mode.clone());
+        Ok(mode)
+    }
+
+    fn verify(&self, signed: &SignedCommand, now_ms: u64) -> Result<(), SentinelError> {
+        let reject = |reason: &str| Err(SentinelError::Unauthorized(format!("control: {reason}")));
+
+        let Ok(sig) = hex::decode(&signed.signature) else {
+            return reject("malformed signature");
+        };
+        if mac(
+            &self.key,
+            &self.host_id,
+            &signed.command,
+            signed.issued_at_ms,
+        )
+        .verify_slice(&sig)
+        .is_err()
+        {
+            return reject("bad signature");
+        }
+        if now_ms.abs_diff(signed.issued_at_ms) > MAX_CLOCK_SKEW_MS {
+            return reject("command expired");
+        }
+        if signed.issued_at_ms < self.started_ms {
+            return reject("command issued before sentinel start");
+        }
+        let newer = self
+            .last_issued_ms
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |last| {
+                (signed.issued_at_ms > last).then_some(signed.issued_at_ms)
+            });
+        if newer.is_err() {
+            return reject("command replayed");
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MessageHandler for ControlHandler {
+    async fn handle(&self, msg: &dyn Message) -> Result<(), TransportError> {
+        // Rejected commands are acked too: redelivery would not make them valid.
+        if let Err(e) = self.apply(&msg.envelope().payload) {
+            tracing::warn!(error = %e, "control command rejected");
+        }
+        msg.ack().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn handler() -> (Arc<ModeControl>, ControlHandler) {
+        let mode = Arc::new(ModeControl::new());
+        let handler = ControlHandler::new("h1", KEY, Arc::clone(&mode));
+        (mode, handler)
+    }
+
+    fn payload(command: ControlCommand, key: &[u8], host_id: &str, at: u64) -> Vec<u8> {
+        serde_json::to_vec(&command.sign(key, host_id, at)).unwrap()
+    }
+
+    #[test]
+    fn wire_format() {
+        let signed = ControlCommand::Drain {
+            deadline_secs: Some(300),
+        }
+        .sign(KEY, "h1", 42);
+        let json = serde_json::to_value(&signed).unwrap();
+        assert_eq!(json["command"], "drain");
+        assert_eq!(json["deadline_secs"], 300);
+        assert_eq!(json["issued_at_ms"], 42);
+        assert_eq!(json["signature"].as_str().unwrap().len(), 64);
+        assert_eq!(control_subject("h1"), "gbe.events.sentinel.h1.control");
+    }
+
+    #[test]
+    fn signed_commands_change_mode() {
+        let (mode, handler) = handler();
+        let now = now_millis();
+        handler
+            .apply(&payload(ControlCommand::Cordon, KEY, "h1", now))
+            .unwrap();
+        assert_eq!(mode.current(), HostMode::Cordoned);
+        let drained = handler
+            .apply(&payload(
+                ControlCommand::Drain {
+                    deadline_secs: Some(60),
+                },
+                KEY,
+                "h1",
+                now + 1,
+            ))
+            .unwrap();
+        assert!(matches!(
+            drained,
+            HostMode::Draining {
+                deadline_ms: Some(_)
+            }
+        ));
+        handler
+            .apply(&payload(ControlCommand::Uncordon, KEY, "h1", now + 2))
+            .unwrap();
+        assert!(mode.accepting());
+    }
+
+    #[test]
+    fn wrong_key_or_host_rejected() {
+        let (mode, handler) = handler();
+        let now = now_millis();
+        let err = handler
+            .apply(&payload(ControlCommand::Cordon, b"wrong-key", "h1", now))
+            .unwrap_err();
+        assert!(err.to_string().contains("bad signature"));
+        let err = handler
+            .apply(&payload(ControlCommand::Cordon, KEY, "h2", now))
+            .unwrap_err();
+        assert!(matches!(err, SentinelError::Unauthorized(_)));
+        assert!(err.to_string().contains("bad signature"));
+        assert!(mode.accepting());
+    }
+
+    #[test]
+    fn tampered_command_rejected() {
+        let (_, handler) = handler();
+        let mut signed = ControlCommand::Cordon.sign(KEY, "h1", now_millis());
+        signed.command = ControlCommand::Drain {
+            deadline_secs: None,
+        };
+        let err = handler
+            .apply(&serde_json::to_vec(&signed).unwrap())
+            .unwrap_err();
+        assert!(err.to_string().contains("bad signature"));
+    }
+
+    #[test]
+    fn stale_and_replayed_commands_rejected() {
+        let (_, handler) = handler();
+        let now = now_millis();
+        let err = handler
+            .apply(&payload(
+                ControlCommand::Cordon,
+                KEY,
+                "h1",
+                now - MAX_CLOCK_SKEW_MS - 1_000,
+            ))
+            .unwrap_err();
+        assert!(err.to_string().contains("expired"));
+
+        let cordon = payload(ControlCommand::Cordon, KEY, "h1", now);
+        handler.apply(&cordon).unwrap();
+        let err = handler.apply(&cordon).unwrap_err();
+        assert!(matches!(err, SentinelError::Unauthorized(_)));
+        assert!(err.to_string().contains("replayed"));
+    }
+
+    #[test]
+    fn command_from_before_restart_rejected() {
+        let captured = payload(ControlCommand::Cordon, KEY, "h1", now_millis() - 1_000);
+        // A restarted sentinel has no replay state.
+        let (mode, handler) = handler();
+        let err = handler.apply(&captured).unwrap_err();
+        assert!(matches!(err, SentinelError::Unauthorized(_)));
+        assert!(err.to_string().contains("before sentinel start"));
+        assert!(mode.accepting());
+    }
+}
@@ -24,15 +24,42 @@ pub enum SentinelError {
     #[error("config error: {0}")]
     Config(String),
 
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+
     #[error("claim failed for task {task_id}: {reason}")]
     ClaimFailed { task_id: String, reason: String },
 
+    #[error("no profile for task {task_id}: {reason}")]
+    Unroutable { task_id: String, reason: String },
+
+    #[error("host is {0}, not claiming new tasks")]
+    NotAccepting(String),
+
     #[error("timeout: task {0} exceeded deadline")]
     Timeout(String),
 
+    #[error("operative unresponsive: silent for {silent_secs}s and did not answer a ping")]
+    Unresponsive { silent_secs: u64 },
+
+    #[error("{phase} deadline of {limit_secs}s exceeded")]
+    DeadlineExceeded {
+        phase: crate::vm::deadline::Phase,
+        limit_secs: u64,
+    },
+
+    #[error("illegal vm transition from {from} to {to}")]
+    IllegalTransition {
+        from: crate::vm::lifecycle::VmState,
+        to: crate::vm::lifecycle::VmState,
+    },
+
     #[error("io error: {0}")]
     Io(#[from] io::Error),
 
     #[error("json error: {0}")]
     Json(#[from] serde_json::Error),
+
+    #[error("background task failed: {0}")]
+    Join(#[from] tokio::task::JoinError),
 }
@@ -0,0 +1,102 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::vm::lifecycle::{PhaseDurations, VmState};
+
+/// Events buffered per subscriber before it starts lagging.
+pub const EVENT_CAPACITY: usize = 256;
+
+/// One VM state change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LifecycleEvent {
+    pub cid: u32,
+    pub task_id: String,
+    pub profile: String,
+    pub from: VmState,
+    pub to: VmState,
+    /// When `to` was entered (Unix millis).
+    pub at_ms: u64,
+    /// Time spent in `from`.
+    pub elapsed_ms: u64,
+    /// Phase durations up to and including this change.
+    pub durations: PhaseDurations,
+}
+
+/// In-process fan-out of VM state changes.
+///
+/// Every subscriber gets its own receiver and every event. Publishing never
+/// blocks: a subscriber more than [`EVENT_CAPACITY`] events behind gets
+/// `RecvError::Lagged` and skips ahead, so consumers should treat events as
+/// a prompt to re-read current state rather than as a complete log. The run
+/// journal is written directly for that reason.
+#[derive(Clone)]
+pub struct LifecycleEvents {
+    tx: broadcast::Sender<LifecycleEvent>,
+}
+
+impl Default for LifecycleEvents {
+    fn default() -> Self {
+        Self::new(EVENT_CAPACITY)
+    }
+}
+
+impl LifecycleEvents {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            tx: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Events published from now on.
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<LifecycleEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Send `event` to every current subscriber. Dropped if there are none.
+    pub fn publish(&self, event: LifecycleEvent) {
+        let _ = self.tx.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::broadcast::error::RecvError;
+
+    fn event(to: VmState) -> LifecycleEvent {
+        LifecycleEvent {
+            cid: 3,
+            task_id: "t1".into(),
+            profile: "shell".into(),
+            from: VmState::Idle,
+            to,
+            at_ms: 0,
+            elapsed_ms: 0,
+            durations: PhaseDurations::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn every_subscriber_sees_every_event() {
+        let events = LifecycleEvents::default();
+        events.publish(event(VmState::Provisioning));
+        let mut a = events.subscribe();
+        let mut b = events.subscribe();
+        events.publish(event(VmState::Running));
+        assert_eq!(a.recv().await.unwrap().to, VmState::Running);
+        assert_eq!(b.recv().await.unwrap().to, VmState::Running);
+    }
+
+    #[tokio::test]
+    async fn slow_subscriber_lags_without_blocking() {
+        let events = LifecycleEvents::new(2);
+        let mut rx = events.subscribe();
+        for _ in 0..3 {
+            events.publish(event(VmState::Provisioning));
+        }
+        assert!(matches!(rx.recv().await, Err(RecvError::Lagged(1))));
+        assert!(rx.recv().await.is_ok());
+    }
+}
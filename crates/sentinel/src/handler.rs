@@ -1,20 +1,298 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use gbe_nexus::{Envelope, Transport};
+use gbe_state_store::StateStore;
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::Instrument;
+
+use crate::claim::{claim_task, now_millis};
+use crate::config::{SentinelConfig, VmProfile};
 use crate::error::SentinelError;
+use crate::logs::LogShipper;
+use crate::mode::ModeControl;
+use crate::relay::TaskRelay;
+use crate::reload::LiveConfig;
+use crate::routing::{self, ResourceRequest};
+use crate::trace::TraceContext;
+use crate::vm::console;
+use crate::vm::lifecycle::VmLifecycle;
+use crate::vsock::protocol::SentinelMessage;
+
+/// Payload of a message on `gbe.tasks.{task_type}.queue`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct QueuedTask {
+    pub task_id: String,
+    pub task_type: String,
+    pub state_key: String,
+    #[serde(default)]
+    pub payload: Value,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Resources the producer needs; routing picks a profile that fits.
+    #[serde(default)]
+    pub resources: ResourceRequest,
+}
+
+/// A task this sentinel has successfully claimed.
+///
+/// Carries the trace context extracted from the queue envelope and the root
+/// task span, so provisioning, lifecycle transitions and bus publishes all
+/// join the same trace. `config` is the snapshot taken at claim time; a
+/// reload does not affect tasks already claimed.
+pub struct ClaimedTask {
+    pub task: QueuedTask,
+    pub trace: TraceContext,
+    pub span: tracing::Span,
+    pub config: Arc<SentinelConfig>,
+    /// Name of the profile the task was routed to.
+    pub profile: String,
+    /// When the run must end, as written at claim time, in unix millis.
+    pub timeout_at: u64,
+}
+
+impl ClaimedTask {
+    #[must_use]
+    pub fn vm_profile(&self) -> &VmProfile {
+        &self.config.profiles[&self.profile]
+    }
+
+    /// Span for VM provisioning, parented to the task span.
+    #[must_use]
+    pub fn provision_span(&self) -> tracing::Span {
+        tracing::info_span!(parent: &self.span, "provision")
+    }
+
+    /// Lifecycle for this task's VM, created inside the task span.
+    #[must_use]
+    pub fn lifecycle(&self) -> VmLifecycle {
+        self.span
+            .in_scope(|| VmLifecycle::for_task(&self.task.task_id, &self.trace))
+    }
+
+    /// Relay for progress/terminal events, echoing the task's trace id and
+    /// attaching the VM's console tail to failures.
+    #[must_use]
+    pub fn relay(&self, transport: Arc<dyn Transport>) -> TaskRelay {
+        let relay = TaskRelay::new(
+            transport,
+            &self.task.task_type,
+            &self.task.task_id,
+            self.trace.clone(),
+        );
+        match self.console_dir() {
+            Ok(dir) => relay.with_console(dir, self.config.console.tail_bytes),
+            Err(_) => relay,
+        }
+    }
+
+    /// Shipper for the task's live output, echoing its trace id.
+    #[must_use]
+    pub fn log_shipper(&self, transport: Arc<dyn Transport>) -> LogShipper {
+        LogShipper::new(
+            transport,
+            &self.task.task_type,
+            &self.task.task_id,
+            self.trace.clone(),
+            &self.config.logs,
+        )
+    }
+
+    /// Directory of this task's console log.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Vm` if the task id cannot name a directory.
+    pub fn console_dir(&self) -> Result<PathBuf, SentinelError> {
+        console::vm_dir(&self.config.console_dir(), &self.task.task_id)
+    }
+
+    /// The `Task` message injected into the VM over vsock.
+    #[must_use]
+    pub fn task_message(&self, tools: Vec<String>) -> SentinelMessage {
+        SentinelMessage::Task {
+            id: self.task.task_id.clone(),
+            payload: self.task.payload.clone(),
+            tools,
+            trace_id: self.trace.trace_id.clone(),
+        }
+    }
+}
 
 /// Handles incoming task queue messages.
 ///
 /// On receipt: extract state key, attempt CAS claim, provision VM on success.
-pub struct TaskHandler;
+pub struct TaskHandler {
+    pub(crate) config: Arc<LiveConfig>,
+    pub(crate) store: Arc<dyn StateStore>,
+    pub(crate) mode: Arc<ModeControl>,
+}
 
 impl TaskHandler {
+    #[must_use]
+    pub fn new(
+        config: Arc<LiveConfig>,
+        store: Arc<dyn StateStore>,
+        mode: Arc<ModeControl>,
+    ) -> Self {
+        Self {
+            config,
+            store,
+            mode,
+        }
+    }
+
+    /// Parse the queue envelope, route the task to a profile, and CAS-claim
+    /// it for `vm_cid`.
+    ///
+    /// Routing happens before the claim so a task this host cannot run is
+    /// never claimed. The envelope's trace id is kept in the returned
+    /// `ClaimedTask`; the claim itself runs in a `claim` span under the
+    /// task span.
+    ///
     /// # Errors
     ///
-    /// Returns `SentinelError` on claim failure or VM provisioning error.
-    pub async fn handle_message(&self, _payload: &[u8]) -> Result<(), SentinelError> {
-        // TODO: implement message handling
-        // 1. Deserialize envelope, extract state key
-        // 2. CAS claim via claim module
-        // 3. On success: provision VM, inject task
-        // 4. On failure: nak message
-        Ok(())
+    /// Returns `SentinelError::NotAccepting` if the host is cordoned or
+    /// draining, `SentinelError::Json` on a malformed payload,
+    /// `SentinelError::Unroutable` if no profile fits,
+    /// `SentinelError::ClaimFailed` if another worker won the claim, or a
+    /// store error on I/O failure. The caller naks the message on error.
+    pub async fn handle_message(
+        &self,
+        envelope: &Envelope,
+        vm_cid: u32,
+    ) -> Result<ClaimedTask, SentinelError> {
+        let mode = self.mode.current();
+        if !mode.accepting() {
+            return Err(SentinelError::NotAccepting(mode.to_string()));
+        }
+        let config = self.config.snapshot();
+        let task: QueuedTask = serde_json::from_slice(&envelope.payload)?;
+        let trace = TraceContext::from_envelope(envelope);
+        let span = trace.task_span(&task.task_id, &task.task_type);
+
+        let (profile, vm_profile) = span.in_scope(|| routing::route(&config, &task))?;
+        span.record("profile", profile);
+        let timeout_at = now_millis().saturating_add(vm_profile.timeout_sec.saturating_mul(1000));
+        let profile = profile.to_string();
+
+        claim_task(
+            &self.store,
+            &task.state_key,
+            &config.host_id,
+            vm_cid,
+            timeout_at,
+        )
+        .instrument(tracing::info_span!(parent: &span, "claim", vm_cid))
+        .await?;
+
+        Ok(ClaimedTask {
+            task,
+            trace,
+            span,
+            config,
+            profile,
+            timeout_at,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn claimed(trace_id: Option<&str>) -> ClaimedTask {
+        let task: QueuedTask = serde_json::from_str(
+            r#"{"task_id":"t1","task_type":"shell","state_key":"gbe:state:tasks:shell:t1",
+                "payload":{"cmd":"echo hi"}}"#,
+        )
+        .unwrap();
+        let config = SentinelConfig::from_toml(
+            r#"
+host_id = "h1"
+slots = 1
+image_dir = "/images"
+kernel_path = "/k"
+overlay_dir = "/o"
+firecracker_bin = "/fc"
+task_types = ["shell"]
+
+[profiles.shell]
+vcpus = 1
+mem_mb = 256
+rootfs = "base.ext4"
+"#,
+        )
+        .unwrap();
+        let trace = TraceContext::new(trace_id.map(String::from));
+        let span = trace.task_span(&task.task_id, &task.task_type);
+        ClaimedTask {
+            task,
+            trace,
+            span,
+            config: Arc::new(config),
+            profile: "shell".into(),
+            timeout_at: now_millis() + 300_000,
+        }
+    }
+
+    #[test]
+    fn queued_task_defaults() {
+        let task: QueuedTask =
+            serde_json::from_str(r#"{"task_id":"t1","task_type":"shell","state_key":"k"}"#)
+                .unwrap();
+        assert!(task.payload.is_null());
+        assert!(task.labels.is_empty());
+        assert!(task.tenant.is_none());
+        assert!(task.resources.is_empty());
+    }
+
+    #[test]
+    fn queued_task_resources() {
+        let task: QueuedTask = serde_json::from_str(
+            r#"{"task_id":"t1","task_type":"shell","state_key":"k",
+                "tenant":"acme","resources":{"mem_mb":4096}}"#,
+        )
+        .unwrap();
+        assert_eq!(task.tenant.as_deref(), Some("acme"));
+        assert_eq!(task.resources.mem_mb, Some(4096));
+        assert_eq!(task.resources.vcpus, None);
+    }
+
+    #[test]
+    fn claimed_task_exposes_routed_profile() {
+        let claimed = claimed(None);
+        assert_eq!(claimed.vm_profile().mem_mb, 256);
+    }
+
+    #[test]
+    fn task_message_carries_trace_id() {
+        let claimed = claimed(Some("trace-abc"));
+        let msg = claimed.task_message(vec!["grep".into()]);
+        if let SentinelMessage::Task {
+            id,
+            payload,
+            trace_id,
+            ..
+        } = msg
+        {
+            assert_eq!(id, "t1");
+            assert_eq!(payload["cmd"], "echo hi");
+            assert_eq!(trace_id.as_deref(), Some("trace-abc"));
+        } else {
+            panic!("expected Task");
+        }
+    }
+
+    #[test]
+    fn lifecycle_inherits_trace_id() {
+        let claimed = claimed(Some("trace-abc"));
+        let vm = claimed.lifecycle();
+        assert_eq!(vm.task_id.as_deref(), Some("t1"));
+        assert_eq!(vm.trace_id.as_deref(), Some("trace-abc"));
     }
 }
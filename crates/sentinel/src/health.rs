@@ -1,25 +1,220 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use gbe_nexus::Transport;
+use serde_json::Value;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::claim::now_millis;
 use crate::error::SentinelError;
+use crate::events::LifecycleEvent;
+use crate::mode::ModeControl;
+use crate::registry::VmRegistry;
+use crate::vm::lifecycle::VmState;
 
 /// Publishes periodic heartbeat beacons and capacity updates.
 ///
 /// Beacon: `gbe.events.sentinel.{host_id}.health`
 /// Capacity: `gbe.events.sentinel.{host_id}.capacity`
-pub struct HealthPublisher;
+///
+/// The beacon carries the host mode so schedulers and dashboards can see
+/// a cordoned or draining host.
+pub struct HealthPublisher {
+    transport: Arc<dyn Transport>,
+    host_id: String,
+    mode: Arc<ModeControl>,
+}
 
 impl HealthPublisher {
+    #[must_use]
+    pub fn new(transport: Arc<dyn Transport>, host_id: &str, mode: Arc<ModeControl>) -> Self {
+        Self {
+            transport,
+            host_id: host_id.to_string(),
+            mode,
+        }
+    }
+
     /// # Errors
     ///
     /// Returns `SentinelError` on transport failure.
     pub async fn publish_beacon(&self) -> Result<(), SentinelError> {
-        // TODO: publish heartbeat to gbe.events.sentinel.{host_id}.health
-        Ok(())
+        let body = serde_json::json!({
+            "host_id": self.host_id,
+            "mode": self.mode.current(),
+            "at_ms": now_millis(),
+        });
+        self.publish("health", &body).await
     }
 
     /// # Errors
     ///
     /// Returns `SentinelError` on transport failure.
-    pub async fn publish_capacity(&self, _total: u32, _used: u32) -> Result<(), SentinelError> {
-        // TODO: publish slot availability to gbe.events.sentinel.{host_id}.capacity
+    pub async fn publish_capacity(&self, total: u32, used: u32) -> Result<(), SentinelError> {
+        let body = serde_json::json!({
+            "host_id": self.host_id,
+            "total": total,
+            "used": used,
+            "accepting": self.mode.accepting(),
+            "at_ms": now_millis(),
+        });
+        self.publish("capacity", &body).await
+    }
+
+    /// Publish a beacon every `interval`, and immediately on every mode
+    /// change, until `token` is cancelled. Publish failures are logged,
+    /// not fatal.
+    pub async fn beacon_loop(self: Arc<Self>, interval: Duration, token: CancellationToken) {
+        let mut ticker = tokio::time::interval(interval);
+        let mut mode = self.mode.subscribe();
+        loop {
+            tokio::select! {
+                () = token.cancelled() => return,
+                _ = ticker.tick() => {}
+                changed = mode.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    ticker.reset();
+                }
+            }
+            if let Err(e) = self.publish_beacon().await {
+                tracing::warn!(error = %e, "beacon publish failed");
+            }
+        }
+    }
+
+    /// Publish capacity whenever a VM takes a slot (starts provisioning)
+    /// or frees one (returns to idle), until `token` is cancelled. `used`
+    /// counts registered VMs that are not idle. After a lag the count is
+    /// re-read and published anyway.
+    pub async fn capacity_loop(
+        self: Arc<Self>,
+        mut events: broadcast::Receiver<LifecycleEvent>,
+        registry: Arc<VmRegistry>,
+        total: u32,
+        token: CancellationToken,
+    ) {
+        loop {
+            let event = tokio::select! {
+                () = token.cancelled() => return,
+                event = events.recv() => event,
+            };
+            match event {
+                Ok(event) if !matches!(event.to, VmState::Provisioning | VmState::Idle) => {
+                    continue;
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            }
+            let used = registry
+                .list()
+                .iter()
+                .filter(|vm| vm.state() != VmState::Idle)
+                .count();
+            let used = u32::try_from(used).unwrap_or(u32::MAX);
+            if let Err(e) = self.publish_capacity(total, used).await {
+                tracing::warn!(error = %e, "capacity publish failed");
+            }
+        }
+    }
+
+    async fn publish(&self, kind: &str, body: &Value) -> Result<(), SentinelError> {
+        let subject = format!("gbe.events.sentinel.{}.{kind}", self.host_id);
+        self.transport
+            .publish(&subject, Bytes::from(serde_json::to_vec(body)?), None)
+            .await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mode::HostMode;
+    use crate::relay::tests::RecordingTransport;
+
+    fn health() -> (Arc<RecordingTransport>, Arc<ModeControl>, HealthPublisher) {
+        let transport = Arc::new(RecordingTransport::default());
+        let mode = Arc::new(ModeControl::new());
+        let health = HealthPublisher::new(Arc::clone(&transport) as _, "h1", Arc::clone(&mode));
+        (transport, mode, health)
+    }
+
+    #[tokio::test]
+    async fn beacon_carries_mode() {
+        let (transport, mode, health) = health();
+        health.publish_beacon().await.unwrap();
+        mode.set(HostMode::Cordoned);
+        health.publish_beacon().await.unwrap();
+
+        let published = transport.published.lock().unwrap();
+        assert_eq!(published[0].0, "gbe.events.sentinel.h1.health");
+        assert_eq!(published[0].1["mode"]["mode"], "active");
+        assert_eq!(published[1].1["mode"]["mode"], "cordoned");
+    }
+
+    #[tokio::test]
+    async fn capacity_reports_slots() {
+        let (transport, _, health) = health();
+        health.publish_capacity(4, 1).await.unwrap();
+        let published = transport.published.lock().unwrap();
+        assert_eq!(published[0].0, "gbe.events.sentinel.h1.capacity");
+        assert_eq!(published[0].1["total"], 4);
+        assert_eq!(published[0].1["used"], 1);
+        assert_eq!(published[0].1["accepting"], true);
+    }
+
+    #[tokio::test]
+    async fn capacity_follows_lifecycle_events() {
+        let (transport, _, health) = health();
+        let registry = Arc::new(VmRegistry::new());
+        let token = CancellationToken::new();
+        let task = tokio::spawn(Arc::new(health).capacity_loop(
+            registry.subscribe(),
+            Arc::clone(&registry),
+            4,
+            token.clone(),
+        ));
+        let vm = registry.insert(crate::registry::tests::entry(3, "t1"));
+        for state in [
+            VmState::Provisioning,
+            VmState::Running,
+            VmState::Timeout,
+            VmState::Teardown,
+            VmState::Idle,
+        ] {
+            vm.transition(state).unwrap();
+            tokio::task::yield_now().await;
+        }
+        while transport.published.lock().unwrap().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        token.cancel();
+        task.await.unwrap();
+
+        let published = transport.published.lock().unwrap();
+        assert_eq!(published.len(), 2);
+        assert_eq!(published[0].1["used"], 1);
+        assert_eq!(published[1].1["used"], 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn mode_change_publishes_immediately() {
+        let (transport, mode, health) = health();
+        let token = CancellationToken::new();
+        let task =
+            tokio::spawn(Arc::new(health).beacon_loop(Duration::from_secs(10), token.clone()));
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        mode.set(HostMode::Draining { deadline_ms: None });
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        token.cancel();
+        task.await.unwrap();
+
+        let published = transport.published.lock().unwrap();
+        assert_eq!(published.len(), 2);
+        assert_eq!(published[1].1["mode"]["mode"], "draining");
+    }
+}
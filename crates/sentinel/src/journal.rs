@@ -0,0 +1,549 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+
+use crate::claim::now_millis;
+use crate::config::JournalConfig;
+use crate::error::SentinelError;
+use crate::registry::VmResources;
+use crate::vm::cgroup::VmCgroup;
+use crate::vm::jailer::Jail;
+use crate::vm::lifecycle::{StateChange, VmState};
+use crate::vm::manager::VmHandle;
+use crate::vm::network::TapDevice;
+use crate::vm::telemetry::Fifos;
+
+/// A VM's state and host resources at one point in time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VmRecord {
+    pub cid: u32,
+    pub task_id: String,
+    #[serde(default)]
+    pub task_type: String,
+    #[serde(default)]
+    pub profile: String,
+    pub state_key: String,
+    pub state: VmState,
+    pub at_ms: u64,
+    #[serde(default)]
+    pub handle: Option<VmHandle>,
+    #[serde(default)]
+    pub overlay: Option<PathBuf>,
+    #[serde(default)]
+    pub tap: Option<TapDevice>,
+    #[serde(default)]
+    pub fifos: Option<Fifos>,
+    #[serde(default)]
+    pub jail: Option<Jail>,
+    #[serde(default)]
+    pub cgroup: Option<VmCgroup>,
+}
+
+impl VmRecord {
+    /// The host resources to release for this VM.
+    #[must_use]
+    pub fn resources(&self) -> VmResources {
+        VmResources {
+            handle: self.handle.clone(),
+            overlay: self.overlay.clone(),
+            tap: self.tap.clone(),
+            fifos: self.fifos.clone(),
+            jail: self.jail.clone(),
+            cgroup: self.cgroup.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum JournalEntry {
+    /// A VM changed state or acquired a resource. The latest record for a
+    /// CID wins.
+    Vm(Box<VmRecord>),
+    /// The VM was torn down and its task settled.
+    Finished {
+        cid: u32,
+        task_id: String,
+        at_ms: u64,
+    },
+}
+
+impl JournalEntry {
+    fn task_id(&self) -> &str {
+        match self {
+            Self::Vm(vm) => &vm.task_id,
+            Self::Finished { task_id, .. } => task_id,
+        }
+    }
+}
+
+enum Command {
+    Append(Vec<u8>),
+    /// Flush entries appended so far, fsyncing them if `durable`.
+    Sync {
+        durable: bool,
+        done: oneshot::Sender<Result<(), SentinelError>>,
+    },
+}
+
+/// Owns the journal file on the writer thread.
+struct Writer {
+    path: PathBuf,
+    config: JournalConfig,
+    out: BufWriter<File>,
+    /// Entries written but not yet synced.
+    unsynced: usize,
+    /// Entries appended since the last compaction.
+    since_compaction: usize,
+}
+
+impl Writer {
+    /// Handle commands until every sender is dropped, then sync once more.
+    fn run(mut self, commands: &mpsc::Receiver<Command>) {
+        while let Ok(command) = commands.recv() {
+            match command {
+                Command::Append(line) => {
+                    if let Err(e) = self.append(&line) {
+                        tracing::warn!(path = %self.path.display(), error = %e, "journal write failed");
+                    }
+                }
+                Command::Sync { durable, done } => {
+                    let result = if durable {
+                        self.sync()
+                    } else {
+                        self.out.flush().map_err(SentinelError::from)
+                    };
+                    // The caller may have stopped waiting.
+                    let _ = done.send(result);
+                }
+            }
+        }
+        if let Err(e) = self.sync() {
+            tracing::warn!(path = %self.path.display(), error = %e, "journal sync failed");
+        }
+    }
+
+    fn append(&mut self, line: &[u8]) -> Result<(), SentinelError> {
+        self.out.write_all(line)?;
+        self.unsynced += 1;
+        self.since_compaction += 1;
+        if self.unsynced >= self.config.sync_batch {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Flush and fsync pending entries, compacting if due.
+    fn sync(&mut self) -> Result<(), SentinelError> {
+        if self.unsynced > 0 {
+            self.out.flush()?;
+            self.out.get_ref().sync_data()?;
+            self.unsynced = 0;
+        }
+        if self.since_compaction >= self.config.compact_after {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the journal keeping unfinished VMs and those that finished
+    /// within the retention window, then swap it in atomically.
+    fn compact(&mut self) -> Result<(), SentinelError> {
+        let entries = read_entries(&self.path)?;
+        let cutoff = now_millis().saturating_sub(self.config.retain_finished_secs * 1000);
+        // A task requeued to this host again keeps its later run.
+        let mut expired: HashMap<&str, usize> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            if let JournalEntry::Finished { task_id, at_ms, .. } = entry
+                && *at_ms < cutoff
+            {
+                expired.insert(task_id, i);
+            }
+        }
+
+        let tmp = self.path.with_extension("compact");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        let mut kept = 0;
+        for (i, entry) in entries.iter().enumerate() {
+            if expired.get(entry.task_id()).is_some_and(|&last| i <= last) {
+                continue;
+            }
+            serde_json::to_writer(&mut out, entry)?;
+            out.write_all(b"\n")?;
+            kept += 1;
+        }
+        out.flush()?;
+        out.get_ref().sync_all()?;
+        std::fs::rename(&tmp, &self.path)?;
+
+        self.out = BufWriter::new(append(&self.path)?);
+        self.since_compaction = 0;
+        tracing::debug!(path = %self.path.display(), before = entries.len(), kept, "journal compacted");
+        Ok(())
+    }
+}
+
+/// Append-only JSON-lines journal of every VM transition and resource on
+/// this host, used for crash recovery, postmortems and `sentinelctl show`
+/// on finished VMs.
+///
+/// A dedicated writer thread owns the file, so recording never blocks on
+/// disk. Entries are synced in batches (every `sync_interval_ms` or
+/// `sync_batch` entries); callers that need an entry durable, such as a
+/// newly acquired resource, await [`RunJournal::sync`]. Once
+/// `compact_after` entries have accumulated, the file is rewritten without
+/// VMs that finished more than `retain_finished_secs` ago.
+pub struct RunJournal {
+    path: PathBuf,
+    sync_interval: Duration,
+    commands: mpsc::Sender<Command>,
+}
+
+impl RunJournal {
+    /// Open the journal at `path` for appending, creating it if needed,
+    /// and start its writer thread.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if the file cannot be opened or the
+    /// thread cannot be started.
+    pub fn open(path: &Path, config: &JournalConfig) -> Result<Self, SentinelError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let writer = Writer {
+            path: path.to_path_buf(),
+            config: config.clone(),
+            out: BufWriter::new(append(path)?),
+            unsynced: 0,
+            since_compaction: 0,
+        };
+        let (commands, received) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("run-journal".into())
+            .spawn(move || writer.run(&received))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            sync_interval: Duration::from_millis(config.sync_interval_ms.max(1)),
+            commands,
+        })
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record a state or resource change. Synced with the next batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if the writer thread has stopped.
+    pub fn record(&self, vm: &VmRecord) -> Result<(), SentinelError> {
+        self.append(&JournalEntry::Vm(Box::new(vm.clone())))
+    }
+
+    /// Record that a VM's resources were released. Synced with the next
+    /// batch; replaying a lost entry only repeats an idempotent cleanup.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if the writer thread has stopped.
+    pub fn finished(&self, cid: u32, task_id: &str) -> Result<(), SentinelError> {
+        let entry = JournalEntry::Finished {
+            cid,
+            task_id: task_id.to_string(),
+            at_ms: now_millis(),
+        };
+        self.append(&entry)
+    }
+
+    fn append(&self, entry: &JournalEntry) -> Result<(), SentinelError> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.commands
+            .send(Command::Append(line))
+            .map_err(|_| writer_stopped())
+    }
+
+    /// Wait until every entry recorded so far is fsynced, compacting if
+    /// due.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if the journal cannot be written.
+    pub async fn sync(&self) -> Result<(), SentinelError> {
+        self.request_sync(true).await
+    }
+
+    async fn request_sync(&self, durable: bool) -> Result<(), SentinelError> {
+        let (done, synced) = oneshot::channel();
+        self.commands
+            .send(Command::Sync { durable, done })
+            .map_err(|_| writer_stopped())?;
+        synced.await.map_err(|_| writer_stopped())?
+    }
+
+    /// Sync every `sync_interval_ms` until `token` is cancelled, then once
+    /// more. Failures are logged, not fatal.
+    pub async fn sync_loop(self: Arc<Self>, token: CancellationToken) {
+        let mut ticker = tokio::time::interval(self.sync_interval);
+        loop {
+            let stop = tokio::select! {
+                () = token.cancelled() => true,
+                _ = ticker.tick() => false,
+            };
+            if let Err(e) = self.sync().await {
+                tracing::warn!(path = %self.path.display(), error = %e, "journal sync failed");
+            }
+            if stop {
+                return;
+            }
+        }
+    }
+
+    /// VMs the journal lists as started but never finished, ordered by CID.
+    /// A missing journal means a clean first start. A torn final line from
+    /// a crash mid-write is skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if the journal exists but cannot be read.
+    pub fn unfinished(path: &Path) -> Result<Vec<VmRecord>, SentinelError> {
+        let mut vms = BTreeMap::new();
+        for entry in read_entries(path)? {
+            match entry {
+                JournalEntry::Vm(vm) => {
+                    vms.insert(vm.cid, *vm);
+                }
+                JournalEntry::Finished { cid, .. } => {
+                    vms.remove(&cid);
+                }
+            }
+        }
+        Ok(vms.into_values().collect())
+    }
+
+    /// Last record and state history of the most recent VM matching
+    /// `target` (CID or task id), unsynced entries included.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if the journal cannot be read.
+    pub async fn history(
+        &self,
+        target: &str,
+    ) -> Result<Option<(VmRecord, Vec<StateChange>)>, SentinelError> {
+        self.request_sync(false).await?;
+        let cid = target.parse::<u32>().ok();
+        let mut latest: Option<String> = None;
+        let mut by_task: HashMap<String, Vec<VmRecord>> = HashMap::new();
+        for entry in read_entries(&self.path)? {
+            if let JournalEntry::Vm(vm) = entry {
+                if vm.task_id == target || Some(vm.cid) == cid {
+                    latest = Some(vm.task_id.clone());
+                }
+                by_task.entry(vm.task_id.clone()).or_default().push(*vm);
+            }
+        }
+        let Some(records) = latest.and_then(|task_id| by_task.remove(&task_id)) else {
+            return Ok(None);
+        };
+        let mut history: Vec<StateChange> = Vec::new();
+        for vm in &records {
+            if history.last().is_none_or(|last| last.state != vm.state) {
+                history.push(StateChange {
+                    state: vm.state.clone(),
+                    at_ms: vm.at_ms,
+                });
+            }
+        }
+        Ok(records.last().cloned().map(|last| (last, history)))
+    }
+}
+
+fn writer_stopped() -> SentinelError {
+    std::io::Error::other("journal writer thread stopped").into()
+}
+
+fn append(path: &Path) -> Result<File, SentinelError> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+fn read_entries(path: &Path) -> Result<Vec<JournalEntry>, SentinelError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = Vec::new();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        match serde_json::from_str(&line?) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => {
+                tracing::warn!(path = %path.display(), line = n + 1, error = %e, "skipping unreadable journal entry");
+            }
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(cid: u32, state: VmState) -> VmRecord {
+        VmRecord {
+            cid,
+            task_id: format!("t{cid}"),
+            task_type: "shell".into(),
+            profile: "shell".into(),
+            state_key: format!("gbe:state:tasks:shell:t{cid}"),
+            state,
+            at_ms: now_millis(),
+            handle: None,
+            overlay: Some(PathBuf::from(format!("/o/{cid}.ext4"))),
+            tap: None,
+            fifos: None,
+            jail: None,
+            cgroup: None,
+        }
+    }
+
+    fn journal(dir: &Path, config: &JournalConfig) -> RunJournal {
+        RunJournal::open(&dir.join("state/run.journal"), config).unwrap()
+    }
+
+    #[test]
+    fn missing_journal_is_clean_start() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(
+            RunJournal::unfinished(&tmp.path().join("run.journal"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_returns_latest_record_of_unfinished_vms() {
+        let tmp = tempfile::tempdir().unwrap();
+        let journal = journal(tmp.path(), &JournalConfig::default());
+        journal.record(&record(3, VmState::Provisioning)).unwrap();
+        journal.record(&record(4, VmState::Provisioning)).unwrap();
+        let mut with_tap = record(3, VmState::Running);
+        with_tap.tap = Some(TapDevice {
+            name: "tap3".into(),
+            ip: "10.0.0.3".into(),
+        });
+        journal.record(&with_tap).unwrap();
+        journal.finished(4, "t4").unwrap();
+        journal.sync().await.unwrap();
+
+        assert_eq!(RunJournal::unfinished(journal.path()).unwrap(), [with_tap]);
+    }
+
+    #[tokio::test]
+    async fn transitions_batched_until_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let journal = journal(
+            tmp.path(),
+            &JournalConfig {
+                sync_batch: 3,
+                ..JournalConfig::default()
+            },
+        );
+        journal.record(&record(3, VmState::Provisioning)).unwrap();
+        journal.record(&record(3, VmState::Running)).unwrap();
+        assert!(RunJournal::unfinished(journal.path()).unwrap().is_empty());
+        journal.record(&record(3, VmState::Collecting)).unwrap();
+        // The writer thread syncs the full batch on its own.
+        let mut unfinished = Vec::new();
+        for _ in 0..500 {
+            unfinished = RunJournal::unfinished(journal.path()).unwrap();
+            if !unfinished.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        assert_eq!(unfinished[0].state, VmState::Collecting);
+    }
+
+    #[tokio::test]
+    async fn torn_line_skipped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let journal = journal(tmp.path(), &JournalConfig::default());
+        journal.record(&record(3, VmState::Running)).unwrap();
+        journal.sync().await.unwrap();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(journal.path())
+            .unwrap();
+        file.write_all(br#"{"event":"finished","ci"#).unwrap();
+        assert_eq!(RunJournal::unfinished(journal.path()).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn history_of_finished_vm() {
+        let tmp = tempfile::tempdir().unwrap();
+        let journal = journal(tmp.path(), &JournalConfig::default());
+        for state in [
+            VmState::Provisioning,
+            VmState::Running,
+            VmState::Running,
+            VmState::Teardown,
+        ] {
+            journal.record(&record(3, state)).unwrap();
+        }
+        journal.finished(3, "t3").unwrap();
+
+        let (last, history) = journal.history("t3").await.unwrap().unwrap();
+        assert_eq!(last.state, VmState::Teardown);
+        let states: Vec<_> = history.into_iter().map(|c| c.state).collect();
+        assert_eq!(
+            states,
+            [VmState::Provisioning, VmState::Running, VmState::Teardown]
+        );
+        assert!(journal.history("3").await.unwrap().is_some());
+        assert!(journal.history("t9").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn compaction_drops_expired_finished_vms() {
+        let tmp = tempfile::tempdir().unwrap();
+        let journal = journal(
+            tmp.path(),
+            &JournalConfig {
+                compact_after: 5,
+                retain_finished_secs: 0,
+                ..JournalConfig::default()
+            },
+        );
+        journal.record(&record(3, VmState::Running)).unwrap();
+        journal.record(&record(4, VmState::Running)).unwrap();
+        journal.finished(3, "t3").unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        journal.record(&record(4, VmState::Collecting)).unwrap();
+        journal.record(&record(5, VmState::Running)).unwrap();
+        journal.sync().await.unwrap();
+
+        let entries = read_entries(journal.path()).unwrap();
+        assert!(entries.iter().all(|e| e.task_id() != "t3"));
+        assert_eq!(entries.len(), 3);
+
+        journal.record(&record(6, VmState::Running)).unwrap();
+        journal.sync().await.unwrap();
+        let cids: Vec<_> = RunJournal::unfinished(journal.path())
+            .unwrap()
+            .iter()
+            .map(|vm| vm.cid)
+            .collect();
+        assert_eq!(cids, [4, 5, 6]);
+    }
+}
@@ -1,11 +1,29 @@
 #![allow(clippy::unused_async)] // stub implementations will need async when completed
 
+pub mod admin;
 pub mod claim;
 pub mod config;
+pub mod control;
 pub mod error;
+pub mod events;
 pub mod handler;
 pub mod health;
+pub mod journal;
+pub mod local;
+pub mod logs;
+pub mod mode;
+pub mod recovery;
+pub mod registry;
+pub mod relay;
+pub mod reload;
+pub mod routing;
+pub mod runner;
 pub mod sentinel;
+pub mod shutdown;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod trace;
+pub mod usage;
 pub mod vm;
 pub mod vsock;
 
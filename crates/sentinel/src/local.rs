@@ -0,0 +1,597 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::UnixStream;
+
+use crate::claim::now_millis;
+use crate::config::{NetworkMode, SentinelConfig};
+use crate::error::SentinelError;
+use crate::registry::VmResources;
+use crate::vm::cgroup::VmCgroup;
+use crate::vm::config::FirecrackerConfig;
+use crate::vm::console::ConsoleLog;
+use crate::vm::deadline::{Phase, PhaseDeadlines, RunDeadline};
+use crate::vm::jailer::Jail;
+use crate::vm::manager::VmOutput;
+use crate::vm::teardown::Teardown;
+use crate::vsock::channel::{OPERATIVE_PORT, OperativeChannel, connect_guest};
+use crate::vsock::liveness::{Liveness, Silence};
+use crate::vsock::protocol::{OperativeMessage, SentinelMessage};
+
+/// CID of the single VM booted by a local run.
+const LOCAL_CID: u32 = 3;
+
+/// Canned tool results for local runs, loaded from a JSON object mapping
+/// tool name to the result returned for every call.
+#[derive(Debug, Default)]
+pub struct ToolStubs(BTreeMap<String, Value>);
+
+impl ToolStubs {
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` if the file cannot be read or is not
+    /// a JSON object.
+    pub fn load(path: &Path) -> Result<Self, SentinelError> {
+        let raw = std::fs::read(path)
+            .map_err(|e| SentinelError::Config(format!("{}: {e}", path.display())))?;
+        let stubs = serde_json::from_slice(&raw)
+            .map_err(|e| SentinelError::Config(format!("{}: {e}", path.display())))?;
+        Ok(Self(stubs))
+    }
+
+    /// Tool names with a stub, sorted.
+    #[must_use]
+    pub fn tools(&self) -> Vec<String> {
+        self.0.keys().cloned().collect()
+    }
+
+    /// Result for a call to `tool`; unknown tools get an `error` result.
+    #[must_use]
+    pub fn answer(&self, tool: &str) -> Value {
+        self.0.get(tool).cloned().unwrap_or_else(
+            || serde_json::json!({ "error": format!("no local stub for tool {tool}") }),
+        )
+    }
+}
+
+/// Runs one task in one VM without the bus or state store, for testing
+/// operatives and images.
+///
+/// Boots through the same `OverlayManager` / `VmManager` path as the
+/// daemon, sends `SentinelMessage::Task`, prints progress and the result,
+/// answers tool calls from [`ToolStubs`], and always tears the VM down.
+pub struct LocalRun {
+    config: SentinelConfig,
+    profile: String,
+    payload: Value,
+    stubs: ToolStubs,
+    task_id: String,
+}
+
+impl LocalRun {
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` if `profile` is not configured.
+    pub fn new(
+        config: SentinelConfig,
+        profile: &str,
+        payload: Value,
+        stubs: ToolStubs,
+    ) -> Result<Self, SentinelError> {
+        if !config.profiles.contains_key(profile) {
+            return Err(SentinelError::Config(format!("unknown profile {profile}")));
+        }
+        Ok(Self {
+            config,
+            profile: profile.to_string(),
+            payload,
+            stubs,
+            task_id: format!("local-{}", std::process::id()),
+        })
+    }
+
+    /// Boot the VM, run the task and tear down, returning the operative's
+    /// exit code. Provisioning, the run and teardown are each bounded by
+    /// the profile's deadlines.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError` if the VM cannot be provisioned or the
+    /// operative does not answer, or `SentinelError::DeadlineExceeded` if
+    /// a phase overruns.
+    pub async fn run(&self, out: &mut (dyn Write + Send)) -> Result<i32, SentinelError> {
+        let teardown = Teardown::new(
+            self.config.firecracker_bin.clone(),
+            self.config.overlay_dir.clone(),
+        );
+        let deadlines = PhaseDeadlines::for_profile(&self.config.profiles[&self.profile]);
+        let mut resources = VmResources::default();
+        let result = self
+            .boot_and_run(&teardown, &deadlines, &mut resources, out)
+            .await;
+        if let Some(cgroup) = &resources.cgroup {
+            match cgroup.usage() {
+                Ok(usage) => tracing::info!(?usage, "vm cgroup usage"),
+                Err(e) => tracing::debug!(error = %e, "vm cgroup usage unreadable"),
+            }
+        }
+        if let Err(e) = teardown
+            .release_within(&resources, deadlines.teardown)
+            .await
+        {
+            tracing::warn!(error = %e, "local teardown incomplete");
+        }
+        result
+    }
+
+    async fn boot_and_run(
+        &self,
+        teardown: &Teardown,
+        deadlines: &PhaseDeadlines,
+        resources: &mut VmResources,
+        out: &mut (dyn Write + Send),
+    ) -> Result<i32, SentinelError> {
+        let profile = &self.config.profiles[&self.profile];
+        let vsock_path = local_vsock_path(&self.task_id);
+        let stream = deadlines
+            .within(
+                Phase::Provision,
+                self.provision(teardown, resources, &vsock_path, deadlines.provision),
+            )
+            .await?;
+
+        let tools = match &profile.tool_policy {
+            Some(policy) => policy.allowed_tools.clone(),
+            None => self.stubs.tools(),
+        };
+        let task = SentinelMessage::Task {
+            id: self.task_id.clone(),
+            payload: self.payload.clone(),
+            tools,
+            trace_id: None,
+        };
+        let timeout_at = now_millis().saturating_add(profile.timeout_sec.saturating_mul(1000));
+        let mut deadline = RunDeadline::for_profile(profile, timeout_at);
+        let mut channel = OperativeChannel::new(stream);
+        let exit_code = drive(
+            &mut channel,
+            &task,
+            &self.stubs,
+            &mut deadline,
+            profile.max_silence_sec.map(Duration::from_secs),
+            out,
+        )
+        .await?;
+        deadlines
+            .within(Phase::Collect, collect(&mut channel, out))
+            .await?;
+        Ok(exit_code)
+    }
+
+    /// Overlay, tap, cgroup and jail (when enabled) and VM, through the
+    /// operative accepting the vsock connection. Each resource is recorded
+    /// as soon as it exists, so it is released even if a later step hangs
+    /// past the deadline.
+    async fn provision(
+        &self,
+        teardown: &Teardown,
+        resources: &mut VmResources,
+        vsock_path: &Path,
+        connect_timeout: Duration,
+    ) -> Result<UnixStream, SentinelError> {
+        let profile = &self.config.profiles[&self.profile];
+        let overlay = teardown
+            .overlays
+            .create(&profile.rootfs_path, &self.task_id)
+            .await?;
+        resources.overlay = Some(overlay.clone());
+        if matches!(profile.network, NetworkMode::Nat) {
+            resources.tap = Some(teardown.network.create_tap(&self.task_id).await?);
+        }
+
+        let mut fc = FirecrackerConfig::for_profile(
+            &self.config,
+            profile,
+            overlay,
+            LOCAL_CID,
+            vsock_path.to_path_buf(),
+        )?;
+        if self.config.cgroup.enabled {
+            let cgroup = VmCgroup::for_vm(&self.config.cgroup, profile, LOCAL_CID);
+            if !self.config.jailer.enabled {
+                cgroup.create().await?;
+            }
+            resources.cgroup = Some(cgroup.clone());
+            fc = fc.with_cgroup(cgroup);
+        }
+        if self.config.jailer.enabled {
+            let jail = Jail::for_vm(&self.config.jailer, &self.config.firecracker_bin, LOCAL_CID)?;
+            resources.jail = Some(jail.clone());
+            fc = jail.confine(fc).await?;
+        }
+        let console = ConsoleLog::create(
+            &self.config.console_dir(),
+            &self.task_id,
+            self.config.console.max_bytes,
+        )?;
+        let output = VmOutput::new(console)
+            .on_console_line(|line| tracing::debug!(target: "console", "{line}"));
+        resources.handle = Some(teardown.vms.create_vm(&fc, output).await?);
+        tracing::info!(profile = %self.profile, task_id = %self.task_id, "vm booted");
+
+        connect_guest(&fc.host_socket_path(), OPERATIVE_PORT, connect_timeout).await
+    }
+}
+
+fn local_vsock_path(task_id: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("gbe-sentinel-{task_id}.vsock"))
+}
+
+/// Send `task`, then print operative messages to `out`, answer tool calls
+/// and extension requests until a result or error arrives or `deadline`
+/// passes. An operative silent for `max_silence` is pinged, and fails the
+/// run if it does not answer.
+async fn drive<S: AsyncRead + AsyncWrite>(
+    channel: &mut OperativeChannel<S>,
+    task: &SentinelMessage,
+    stubs: &ToolStubs,
+    deadline: &mut RunDeadline,
+    max_silence: Option<Duration>,
+    out: &mut (dyn Write + Send),
+) -> Result<i32, SentinelError> {
+    channel.send(task).await?;
+    let mut liveness = Liveness::new(max_silence);
+    loop {
+        let msg = tokio::select! {
+            msg = channel.recv() => msg?,
+            () = tokio::time::sleep(deadline.remaining()) => return Err(deadline.exceeded()),
+            () = liveness.wait() => match liveness.expired() {
+                Silence::Ping => {
+                    writeln!(out, "[ping] operative silent")?;
+                    channel.send(&SentinelMessage::Ping { id: task.id().to_string() }).await?;
+                    continue;
+                }
+                Silence::Unresponsive => return Err(liveness.unresponsive()),
+            },
+        };
+        let Some(msg) = msg else { break };
+        liveness.heard();
+        match msg {
+            OperativeMessage::Pong { .. } => {}
+            OperativeMessage::Log { stream, line, .. } => {
+                writeln!(out, "[{}] {line}", stream.as_str())?;
+            }
+            OperativeMessage::Progress {
+                step, status, data, ..
+            } => match data {
+                Some(data) => writeln!(out, "[{step}] {status} {data}")?,
+                None => writeln!(out, "[{step}] {status}")?,
+            },
+            OperativeMessage::ToolCall {
+                id, call_id, tool, ..
+            } => {
+                let result = stubs.answer(&tool);
+                writeln!(out, "[tool {tool}] {result}")?;
+                channel
+                    .send(&SentinelMessage::ToolResult {
+                        id,
+                        call_id,
+                        result,
+                    })
+                    .await?;
+            }
+            OperativeMessage::ExtendDeadline {
+                id,
+                additional_sec,
+                reason,
+            } => {
+                let extension = deadline.request(additional_sec);
+                writeln!(out, "[extend {additional_sec}s] {reason}: {extension}")?;
+                channel.send(&deadline.reply(&id, extension)).await?;
+            }
+            OperativeMessage::Result {
+                output, exit_code, ..
+            } => {
+                writeln!(out, "{}", serde_json::to_string_pretty(&output)?)?;
+                writeln!(out, "[exit {exit_code}]")?;
+                return Ok(exit_code);
+            }
+            OperativeMessage::Error {
+                error, exit_code, ..
+            } => {
+                writeln!(out, "error: {error}")?;
+                writeln!(out, "[exit {exit_code}]")?;
+                return Ok(exit_code);
+            }
+        }
+    }
+    Err(SentinelError::Vsock(
+        "operative disconnected before sending a result".into(),
+    ))
+}
+
+/// Print what the operative sends after its result or error, until it
+/// closes the connection as the guest shuts down.
+async fn collect<S: AsyncRead + AsyncWrite>(
+    channel: &mut OperativeChannel<S>,
+    out: &mut (dyn Write + Send),
+) -> Result<(), SentinelError> {
+    while let Some(msg) = channel.recv().await? {
+        if let OperativeMessage::Log { stream, line, .. } = msg {
+            writeln!(out, "[{}] {line}", stream.as_str())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExtensionPolicy;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    fn stubs() -> ToolStubs {
+        ToolStubs(BTreeMap::from([(
+            "grep".to_string(),
+            serde_json::json!({"matches": 2}),
+        )]))
+    }
+
+    fn deadline(policy: Option<ExtensionPolicy>, secs: u64) -> RunDeadline {
+        let mut profile = crate::handler::tests::claimed(None).vm_profile().clone();
+        profile.timeout_sec = secs;
+        profile.extension_policy = policy;
+        RunDeadline::for_profile(&profile, now_millis() + secs * 1000)
+    }
+
+    fn task() -> SentinelMessage {
+        SentinelMessage::Task {
+            id: "t1".into(),
+            payload: serde_json::json!({"cmd": "echo hi"}),
+            tools: vec!["grep".into()],
+            trace_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn drive_streams_progress_answers_tools_and_returns_exit_code() {
+        let (host, guest) = tokio::io::duplex(4096);
+        let operative = tokio::spawn(async move {
+            let mut guest = BufReader::new(guest);
+            let mut line = String::new();
+            guest.read_line(&mut line).await.unwrap();
+            assert!(line.contains("echo hi"));
+            guest
+                .write_all(concat!(
+                    r#"{"type":"progress","id":"t1","step":"build","status":"running"}"#,
+                    "\n",
+                    r#"{"type":"log","id":"t1","stream":"stderr","line":"cc: warning","ts":1}"#,
+                    "\n",
+                    r#"{"type":"tool_call","id":"t1","call_id":"c1","tool":"grep","params":{}}"#,
+                    "\n",
+                ).as_bytes())
+                .await
+                .unwrap();
+            line.clear();
+            guest.read_line(&mut line).await.unwrap();
+            let reply: Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(reply["call_id"], "c1");
+            assert_eq!(reply["result"]["matches"], 2);
+            guest
+                .write_all(b"{\"type\":\"result\",\"id\":\"t1\",\"output\":{\"ok\":true},\"exit_code\":3}\n")
+                .await
+                .unwrap();
+        });
+
+        let mut out = Vec::new();
+        let code = drive(
+            &mut OperativeChannel::new(host),
+            &task(),
+            &stubs(),
+            &mut deadline(None, 300),
+            None,
+            &mut out,
+        )
+        .await
+        .unwrap();
+        operative.await.unwrap();
+        assert_eq!(code, 3);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("[build] running"));
+        assert!(out.contains("[stderr] cc: warning"));
+        assert!(out.contains("[tool grep]"));
+        assert!(out.contains("\"ok\": true"));
+        assert!(out.ends_with("[exit 3]\n"));
+    }
+
+    #[tokio::test]
+    async fn drive_fails_if_operative_hangs_up() {
+        let (host, guest) = tokio::io::duplex(4096);
+        drop(guest);
+        let err = drive(
+            &mut OperativeChannel::new(host),
+            &task(),
+            &stubs(),
+            &mut deadline(None, 300),
+            None,
+            &mut Vec::new(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            SentinelError::Io(_) | SentinelError::Vsock(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn drive_answers_extension_requests() {
+        let (host, guest) = tokio::io::duplex(4096);
+        let operative = tokio::spawn(async move {
+            let mut guest = BufReader::new(guest);
+            let mut line = String::new();
+            guest.read_line(&mut line).await.unwrap();
+            guest
+                .write_all(b"{\"type\":\"extend_deadline\",\"id\":\"t1\",\"additional_sec\":120,\"reason\":\"slow\"}\n")
+                .await
+                .unwrap();
+            line.clear();
+            guest.read_line(&mut line).await.unwrap();
+            let reply: Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(reply["type"], "extend_deadline_result");
+            assert_eq!(reply["granted"], true);
+            assert_eq!(reply["additional_sec"], 60);
+            guest
+                .write_all(b"{\"type\":\"error\",\"id\":\"t1\",\"error\":\"e\",\"exit_code\":1}\n")
+                .await
+                .unwrap();
+        });
+
+        let mut deadline = deadline(
+            Some(ExtensionPolicy {
+                max_total_sec: 60,
+                max_requests: 1,
+            }),
+            300,
+        );
+        let mut out = Vec::new();
+        let code = drive(
+            &mut OperativeChannel::new(host),
+            &task(),
+            &stubs(),
+            &mut deadline,
+            None,
+            &mut out,
+        )
+        .await
+        .unwrap();
+        operative.await.unwrap();
+        assert_eq!(code, 1);
+        assert_eq!(deadline.limit_secs(), 360);
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .contains("[extend 120s] slow: granted 60s")
+        );
+    }
+
+    #[tokio::test]
+    async fn drive_fails_when_run_deadline_passes() {
+        let (host, _guest) = tokio::io::duplex(4096);
+        let mut deadline = deadline(None, 0);
+        let err = drive(
+            &mut OperativeChannel::new(host),
+            &task(),
+            &stubs(),
+            &mut deadline,
+            None,
+            &mut Vec::new(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            SentinelError::DeadlineExceeded {
+                phase: Phase::Run,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_operative_is_pinged_then_failed() {
+        let (host, guest) = tokio::io::duplex(4096);
+        let operative = tokio::spawn(async move {
+            let mut guest = BufReader::new(guest);
+            let mut line = String::new();
+            guest.read_line(&mut line).await.unwrap();
+            // Answer the first ping, ignore the second.
+            line.clear();
+            guest.read_line(&mut line).await.unwrap();
+            assert!(line.contains("\"ping\""));
+            guest
+                .write_all(b"{\"type\":\"pong\",\"id\":\"t1\"}\n")
+                .await
+                .unwrap();
+            line.clear();
+            guest.read_line(&mut line).await.unwrap();
+            assert!(line.contains("\"ping\""));
+            guest
+        });
+
+        let mut out = Vec::new();
+        let err = drive(
+            &mut OperativeChannel::new(host),
+            &task(),
+            &stubs(),
+            &mut deadline(None, 3_600),
+            Some(Duration::from_secs(30)),
+            &mut out,
+        )
+        .await
+        .unwrap_err();
+        let _guest = operative.await.unwrap();
+        assert!(matches!(err, SentinelError::Unresponsive { .. }));
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("[ping]").count(), 2);
+    }
+
+    #[test]
+    fn unknown_tool_gets_error_result() {
+        let stubs = stubs();
+        assert_eq!(stubs.tools(), ["grep"]);
+        assert!(
+            stubs.answer("curl")["error"]
+                .as_str()
+                .unwrap()
+                .contains("curl")
+        );
+    }
+
+    #[test]
+    fn unknown_profile_rejected() {
+        let config = crate::handler::tests::claimed(None).config.as_ref().clone();
+        let err = LocalRun::new(config, "gpu", Value::Null, ToolStubs::default())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("unknown profile gpu"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn collect_prints_trailing_logs_within_deadline() {
+        let (host, guest) = tokio::io::duplex(4096);
+        let mut channel = OperativeChannel::new(host);
+        let mut guest = guest;
+        guest
+            .write_all(b"{\"type\":\"log\",\"id\":\"t1\",\"stream\":\"stdout\",\"line\":\"bye\",\"ts\":1}\n")
+            .await
+            .unwrap();
+        let profile = crate::handler::tests::claimed(None).vm_profile().clone();
+        let deadlines = PhaseDeadlines::for_profile(&profile);
+
+        // The operative has not hung up, so collection runs out of time.
+        let mut out = Vec::new();
+        let err = deadlines
+            .within(Phase::Collect, collect(&mut channel, &mut out))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SentinelError::DeadlineExceeded {
+                phase: Phase::Collect,
+                ..
+            }
+        ));
+        assert_eq!(String::from_utf8(out).unwrap(), "[stdout] bye\n");
+
+        drop(guest);
+        deadlines
+            .within(Phase::Collect, collect(&mut channel, &mut Vec::new()))
+            .await
+            .unwrap();
+    }
+}
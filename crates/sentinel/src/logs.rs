@@ -0,0 +1,355 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use bytes::Bytes;
+use gbe_nexus::Transport;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::claim::now_millis;
+use crate::config::LogConfig;
+use crate::error::SentinelError;
+use crate::trace::TraceContext;
+use crate::vsock::protocol::LogStream;
+
+/// Lines queued between producers and the shipper before new ones are
+/// dropped.
+pub const LOG_QUEUE: usize = 1024;
+
+/// One line of task output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogLine {
+    pub stream: LogStream,
+    pub line: String,
+    /// Unix millis.
+    pub ts: u64,
+}
+
+impl LogLine {
+    /// A serial console line, stamped now.
+    #[must_use]
+    pub fn console(line: &str) -> Self {
+        Self {
+            stream: LogStream::Console,
+            line: line.to_string(),
+            ts: now_millis(),
+        }
+    }
+}
+
+/// What one task's log stream amounted to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LogStats {
+    /// Lines published.
+    pub lines: u64,
+    /// Bytes of line text published.
+    pub bytes: u64,
+    /// Lines dropped by the rate limit, a full queue or truncation.
+    pub dropped: u64,
+    pub batches: u64,
+    /// The per-task byte cap was reached.
+    pub truncated: bool,
+}
+
+/// Producer side of a task's log stream: the vsock reader and, with
+/// `logs.relay_console`, the console capture.
+///
+/// Never blocks. A line that does not fit in the queue is counted as
+/// dropped, so a chatty guest cannot stall its own vsock channel.
+#[derive(Clone)]
+pub struct LogSender {
+    tx: mpsc::Sender<LogLine>,
+    overflow: Arc<AtomicU64>,
+}
+
+impl LogSender {
+    pub fn send(&self, line: LogLine) {
+        if self.tx.try_send(line).is_err() {
+            self.overflow.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Publishes a task's output to `gbe.tasks.{task_type}.logs` in batches.
+///
+/// Each batch is `{task_id, seq, lines, dropped, truncated}`, published
+/// with the task's trace id. `dropped` counts lines lost since the previous
+/// batch. Once `max_bytes_per_task` is reached, a marker line is added,
+/// `truncated` is set, and every later line is dropped.
+pub struct LogShipper {
+    transport: Arc<dyn Transport>,
+    subject: String,
+    task_id: String,
+    trace: TraceContext,
+    config: LogConfig,
+    batch: Vec<LogLine>,
+    /// Lines dropped since the last batch.
+    dropped: u64,
+    overflow: Arc<AtomicU64>,
+    tokens: f64,
+    refilled: Instant,
+    seq: u64,
+    stats: LogStats,
+}
+
+impl LogShipper {
+    #[must_use]
+    pub fn new(
+        transport: Arc<dyn Transport>,
+        task_type: &str,
+        task_id: &str,
+        trace: TraceContext,
+        config: &LogConfig,
+    ) -> Self {
+        Self {
+            transport,
+            subject: format!("gbe.tasks.{task_type}.logs"),
+            task_id: task_id.to_string(),
+            trace,
+            config: config.clone(),
+            batch: Vec::new(),
+            dropped: 0,
+            overflow: Arc::new(AtomicU64::new(0)),
+            tokens: f64::from(config.max_lines_per_sec),
+            refilled: Instant::now(),
+            seq: 0,
+            stats: LogStats::default(),
+        }
+    }
+
+    /// Spawn the shipper, returning the sender that feeds it and a handle
+    /// resolving to its totals once every sender is dropped and the last
+    /// batch is out.
+    #[must_use]
+    pub fn start(self) -> (LogSender, JoinHandle<LogStats>) {
+        let (tx, rx) = mpsc::channel(LOG_QUEUE);
+        let sender = LogSender {
+            tx,
+            overflow: Arc::clone(&self.overflow),
+        };
+        (sender, tokio::spawn(self.run(rx)))
+    }
+
+    /// Ship lines from `lines` until it closes, flushing whenever a batch
+    /// fills and every `flush_interval_ms`. Publish failures are logged and
+    /// the batch is lost; log streaming never fails a task.
+    pub async fn run(mut self, mut lines: mpsc::Receiver<LogLine>) -> LogStats {
+        let period = Duration::from_millis(self.config.flush_interval_ms.max(1));
+        let mut ticker = tokio::time::interval_at(Instant::now() + period, period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                line = lines.recv() => match line {
+                    Some(line) => {
+                        self.push(line);
+                        if self.batch.len() >= self.config.batch_lines {
+                            self.flush_logged().await;
+                        }
+                    }
+                    None => break,
+                },
+                _ = ticker.tick() => self.flush_logged().await,
+            }
+        }
+        self.flush_logged().await;
+        self.stats
+    }
+
+    /// Queue `line` for the next batch. Returns `false` if it was dropped
+    /// by the rate limit or the byte cap.
+    pub fn push(&mut self, line: LogLine) -> bool {
+        if self.stats.truncated || !self.take_token() {
+            self.drop_lines(1);
+            return false;
+        }
+        let len = line.line.len() as u64;
+        if self.stats.bytes + len > self.config.max_bytes_per_task {
+            self.stats.truncated = true;
+            self.drop_lines(1);
+            self.batch.push(LogLine {
+                stream: line.stream,
+                line: format!(
+                    "[log truncated: {} byte limit reached]",
+                    self.config.max_bytes_per_task
+                ),
+                ts: line.ts,
+            });
+            return false;
+        }
+        self.stats.lines += 1;
+        self.stats.bytes += len;
+        self.batch.push(line);
+        true
+    }
+
+    fn drop_lines(&mut self, n: u64) {
+        self.dropped += n;
+        self.stats.dropped += n;
+    }
+
+    /// Token bucket refilled at `max_lines_per_sec`, holding at most one
+    /// second's worth.
+    fn take_token(&mut self) -> bool {
+        let rate = f64::from(self.config.max_lines_per_sec);
+        let now = Instant::now();
+        self.tokens = rate.min(self.tokens + (now - self.refilled).as_secs_f64() * rate);
+        self.refilled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Publish pending lines, if any, and the drop count since the last
+    /// batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Transport` on publish failure; the batch is
+    /// not retried.
+    pub async fn flush(&mut self) -> Result<(), SentinelError> {
+        let overflow = self.overflow.swap(0, Ordering::Relaxed);
+        self.drop_lines(overflow);
+        if self.batch.is_empty() && self.dropped == 0 {
+            return Ok(());
+        }
+        let body = serde_json::json!({
+            "task_id": self.task_id,
+            "seq": self.seq,
+            "lines": std::mem::take(&mut self.batch),
+            "dropped": std::mem::take(&mut self.dropped),
+            "truncated": self.stats.truncated,
+        });
+        self.seq += 1;
+        self.stats.batches += 1;
+        self.transport
+            .publish(
+                &self.subject,
+                Bytes::from(serde_json::to_vec(&body)?),
+                Some(self.trace.publish_opts()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn flush_logged(&mut self) {
+        if let Err(e) = self.flush().await {
+            tracing::warn!(task_id = %self.task_id, error = %e, "log batch publish failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::relay::tests::RecordingTransport;
+
+    fn config() -> LogConfig {
+        LogConfig {
+            relay_console: false,
+            flush_interval_ms: 250,
+            batch_lines: 3,
+            max_lines_per_sec: 10,
+            max_bytes_per_task: 1024,
+        }
+    }
+
+    fn shipper(config: &LogConfig) -> (Arc<RecordingTransport>, LogShipper) {
+        let transport = Arc::new(RecordingTransport::default());
+        let shipper = LogShipper::new(
+            Arc::clone(&transport) as _,
+            "shell",
+            "t1",
+            TraceContext::new(Some("trace-abc".into())),
+            config,
+        );
+        (transport, shipper)
+    }
+
+    fn stdout(line: &str) -> LogLine {
+        LogLine {
+            stream: LogStream::Stdout,
+            line: line.to_string(),
+            ts: 1,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn full_batches_flush_immediately() {
+        let (transport, shipper) = shipper(&config());
+        let (logs, shipping) = shipper.start();
+        for i in 0..4 {
+            logs.send(stdout(&format!("line {i}")));
+        }
+        drop(logs);
+        let stats = shipping.await.unwrap();
+
+        let published = transport.published.lock().unwrap();
+        assert_eq!(published.len(), 2);
+        let (subject, first, trace_id) = &published[0];
+        assert_eq!(subject, "gbe.tasks.shell.logs");
+        assert_eq!(trace_id.as_deref(), Some("trace-abc"));
+        assert_eq!(first["seq"], 0);
+        assert_eq!(first["lines"].as_array().unwrap().len(), 3);
+        assert_eq!(first["lines"][0]["stream"], "stdout");
+        assert_eq!(published[1].1["lines"][0]["line"], "line 3");
+        assert_eq!(stats.lines, 4);
+        assert_eq!(stats.batches, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn partial_batch_flushed_on_interval() {
+        let (transport, shipper) = shipper(&config());
+        let (logs, shipping) = shipper.start();
+        logs.send(stdout("one"));
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(transport.published.lock().unwrap().len(), 1);
+        drop(logs);
+        shipping.await.unwrap();
+        assert_eq!(transport.published.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_drops_and_counts() {
+        let (_, mut shipper) = shipper(&config());
+        let accepted = (0..15).filter(|_| shipper.push(stdout("x"))).count();
+        assert_eq!(accepted, 10);
+        tokio::time::advance(Duration::from_millis(500)).await;
+        let accepted = (0..15).filter(|_| shipper.push(stdout("x"))).count();
+        assert_eq!(accepted, 5);
+        assert_eq!(shipper.stats.dropped, 15);
+        assert_eq!(shipper.dropped, 15);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn byte_cap_truncates_with_marker() {
+        let mut config = config();
+        config.max_bytes_per_task = 10;
+        let (transport, mut shipper) = shipper(&config);
+        assert!(shipper.push(stdout("12345678")));
+        assert!(!shipper.push(stdout("abc")));
+        assert!(!shipper.push(stdout("d")));
+        shipper.flush().await.unwrap();
+
+        let published = transport.published.lock().unwrap();
+        let body = &published[0].1;
+        assert_eq!(body["truncated"], true);
+        assert_eq!(body["dropped"], 2);
+        let lines = body["lines"].as_array().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1]["line"].as_str().unwrap().contains("truncated"));
+        assert_eq!(shipper.stats.bytes, 8);
+    }
+
+    #[tokio::test]
+    async fn nothing_to_flush_publishes_nothing() {
+        let (transport, mut shipper) = shipper(&config());
+        shipper.flush().await.unwrap();
+        assert!(transport.published.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,308 @@
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use gbe_nexus::Transport;
+use gbe_nexus_redis::{RedisTransport, RedisTransportConfig};
+use gbe_sentinel::config::HostResources;
+use gbe_sentinel::local::{LocalRun, ToolStubs};
+use gbe_sentinel::reload::LiveConfig;
+use gbe_sentinel::{Sentinel, SentinelConfig, SentinelError};
+use gbe_state_store::StateStore;
+use gbe_state_store_redis::{RedisStateStore, RedisStateStoreConfig};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::EnvFilter;
+
+/// Exit codes from sysexits(3), so a unit can stop restarting on a bad
+/// config (`RestartPreventExitStatus=78`) but keep restarting on failures.
+const EXIT_CONFIG: u8 = 78;
+const EXIT_UNAVAILABLE: u8 = 69;
+const EXIT_SOFTWARE: u8 = 70;
+/// `run-local` exits with the operative's own exit code, so its sentinel
+/// failures get one code of their own instead of the daemon's, as
+/// `docker run` does.
+const EXIT_LOCAL_FAILED: u8 = 125;
+
+/// Per-host VM lifecycle manager.
+#[derive(Debug, Parser)]
+#[command(name = "gbe-sentinel", version)]
+struct Args {
+    /// Sentinel config file; re-read on SIGHUP.
+    #[arg(
+        short,
+        long,
+        env = "SENTINEL_CONFIG",
+        default_value = "/etc/gbe/sentinel.toml"
+    )]
+    config: PathBuf,
+
+    /// Log output format. Filter with `RUST_LOG` (default `info`).
+    #[arg(long, env = "SENTINEL_LOG_FORMAT", value_enum, default_value_t = LogFormat::Pretty)]
+    log_format: LogFormat,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Boot one VM and run a single task without the bus or state store.
+    /// Exits with the operative's exit code, or 125 if the run itself fails.
+    RunLocal {
+        /// Profile to boot.
+        #[arg(long)]
+        profile: String,
+        /// JSON file with the task payload.
+        #[arg(long)]
+        payload: PathBuf,
+        /// JSON object mapping tool name to the result returned for it.
+        #[arg(long)]
+        tools: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    Pretty,
+    Json,
+}
+
+/// Why the daemon stopped, mapped to its exit code.
+#[derive(Debug)]
+enum Failure {
+    /// Config failed to load or validate. Restarting will not help.
+    Config(SentinelError),
+    /// The bus or state store could not be reached at startup.
+    Unavailable(SentinelError),
+    /// The sentinel failed while running.
+    Runtime(SentinelError),
+}
+
+impl Failure {
+    fn exit_code(&self) -> u8 {
+        match self {
+            Self::Config(_) => EXIT_CONFIG,
+            Self::Unavailable(_) => EXIT_UNAVAILABLE,
+            Self::Runtime(_) => EXIT_SOFTWARE,
+        }
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Config(e) => write!(f, "config error: {e}"),
+            Self::Unavailable(e) => write!(f, "dependency unavailable: {e}"),
+            Self::Runtime(e) => write!(f, "runtime failure: {e}"),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    init_tracing(args.log_format);
+
+    let result = match &args.command {
+        Some(Command::RunLocal {
+            profile,
+            payload,
+            tools,
+        }) => run_local(&args.config, profile, payload, tools.as_deref())
+            .await
+            .map_err(|failure| (EXIT_LOCAL_FAILED, failure)),
+        None => run(&args)
+            .await
+            .map(|()| 0)
+            .map_err(|failure| (failure.exit_code(), failure)),
+    };
+    match result {
+        Ok(code) => ExitCode::from(code),
+        Err((code, failure)) => {
+            tracing::error!("{failure}");
+            ExitCode::from(code)
+        }
+    }
+}
+
+fn init_tracing(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Pretty => builder.pretty().init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+/// Load `path` and check that its profiles fit on this machine.
+fn load_config(path: &Path) -> Result<SentinelConfig, Failure> {
+    let config = SentinelConfig::load(path).map_err(Failure::Config)?;
+    config
+        .validate_for_host(&HostResources::detect())
+        .map_err(Failure::Config)?;
+    Ok(config)
+}
+
+async fn run(args: &Args) -> Result<(), Failure> {
+    let config = load_config(&args.config)?;
+    tracing::info!(
+        host_id = %config.host_id,
+        config = %args.config.display(),
+        "starting sentinel"
+    );
+
+    let transport: Arc<dyn Transport> = Arc::new(
+        RedisTransport::connect(RedisTransportConfig {
+            url: config.bus.url.clone(),
+        })
+        .await
+        .map_err(|e| Failure::Unavailable(e.into()))?,
+    );
+    let store: Arc<dyn StateStore> = Arc::new(
+        RedisStateStore::connect(RedisStateStoreConfig {
+            url: config.state.url.clone(),
+        })
+        .await
+        .map_err(|e| Failure::Unavailable(e.into()))?,
+    );
+
+    let sentinel = Sentinel::from_live(
+        LiveConfig::new(config, Some(args.config.clone())),
+        Arc::clone(&transport),
+        Arc::clone(&store),
+    );
+
+    let token = CancellationToken::new();
+    let signals = tokio::spawn(cancel_on_shutdown_signal(token.clone()));
+    let result = sentinel.run(token.clone()).await;
+    token.cancel();
+    signals
+        .await
+        .map_err(|e| Failure::Runtime(e.into()))?
+        .map_err(Failure::Runtime)?;
+
+    if let Err(e) = transport.close().await {
+        tracing::warn!(error = %e, "closing transport");
+    }
+    if let Err(e) = store.close().await {
+        tracing::warn!(error = %e, "closing state store");
+    }
+    result.map_err(Failure::Runtime)?;
+    tracing::info!("sentinel stopped");
+    Ok(())
+}
+
+/// Run one task locally. The operative's exit code is printed with its
+/// result and becomes the process's, taken modulo 256 as a shell would.
+async fn run_local(
+    config: &Path,
+    profile: &str,
+    payload: &Path,
+    tools: Option<&Path>,
+) -> Result<u8, Failure> {
+    let config = load_config(config)?;
+    let payload = std::fs::read(payload)
+        .map_err(|e| SentinelError::Config(format!("{}: {e}", payload.display())))
+        .and_then(|raw| Ok(serde_json::from_slice(&raw)?))
+        .map_err(Failure::Config)?;
+    let stubs = tools
+        .map(ToolStubs::load)
+        .transpose()
+        .map_err(Failure::Config)?
+        .unwrap_or_default();
+    let local = LocalRun::new(config, profile, payload, stubs).map_err(Failure::Config)?;
+
+    let code = local
+        .run(&mut std::io::stdout())
+        .await
+        .map_err(Failure::Runtime)?;
+    Ok(task_exit_code(code))
+}
+
+fn task_exit_code(operative: i32) -> u8 {
+    u8::try_from(operative.rem_euclid(256)).unwrap_or(u8::MAX)
+}
+
+/// Cancel `token` on the first SIGTERM or SIGINT.
+async fn cancel_on_shutdown_signal(token: CancellationToken) -> Result<(), SentinelError> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        () = token.cancelled() => return Ok(()),
+        _ = terminate.recv() => tracing::info!("SIGTERM received, shutting down"),
+        _ = interrupt.recv() => tracing::info!("SIGINT received, shutting down"),
+    }
+    token.cancel();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_defaults() {
+        let args = Args::try_parse_from(["gbe-sentinel"]).unwrap();
+        assert_eq!(args.config, PathBuf::from("/etc/gbe/sentinel.toml"));
+        assert_eq!(args.log_format, LogFormat::Pretty);
+    }
+
+    #[test]
+    fn args_override() {
+        let args = Args::try_parse_from([
+            "gbe-sentinel",
+            "--config",
+            "/tmp/s.toml",
+            "--log-format",
+            "json",
+        ])
+        .unwrap();
+        assert_eq!(args.config, PathBuf::from("/tmp/s.toml"));
+        assert_eq!(args.log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn args_run_local() {
+        let args = Args::try_parse_from([
+            "gbe-sentinel",
+            "run-local",
+            "--profile",
+            "shell",
+            "--payload",
+            "task.json",
+        ])
+        .unwrap();
+        let Some(Command::RunLocal { profile, tools, .. }) = args.command else {
+            panic!("expected run-local");
+        };
+        assert_eq!(profile, "shell");
+        assert!(tools.is_none());
+    }
+
+    #[test]
+    fn exit_codes_distinguish_config_from_runtime() {
+        let config = Failure::Config(SentinelError::Config("bad".into()));
+        let runtime = Failure::Runtime(SentinelError::Vm("boom".into()));
+        let unavailable = Failure::Unavailable(SentinelError::Timeout("redis".into()));
+        assert_eq!(config.exit_code(), 78);
+        assert_eq!(unavailable.exit_code(), 69);
+        assert_eq!(runtime.exit_code(), 70);
+    }
+
+    #[test]
+    fn operative_exit_code_passes_through() {
+        for (operative, code) in [(0, 0), (1, 1), (3, 3), (78, 78), (256 + 3, 3), (-1, 255)] {
+            assert_eq!(task_exit_code(operative), code);
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_config_is_config_failure() {
+        let args =
+            Args::try_parse_from(["gbe-sentinel", "-c", "/nonexistent/sentinel.toml"]).unwrap();
+        let failure = run(&args).await.unwrap_err();
+        assert_eq!(failure.exit_code(), EXIT_CONFIG);
+    }
+}
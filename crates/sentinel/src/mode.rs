@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+/// Whether this host is taking new work.
+///
+/// Cordoned and draining hosts claim nothing new; running VMs are left to
+/// finish. A drain additionally ends the sentinel once the host is empty.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum HostMode {
+    Active,
+    Cordoned,
+    /// `deadline_ms` (Unix millis) bounds how long running tasks may take.
+    Draining {
+        deadline_ms: Option<u64>,
+    },
+}
+
+impl HostMode {
+    #[must_use]
+    pub fn accepting(&self) -> bool {
+        matches!(self, Self::Active)
+    }
+}
+
+impl std::fmt::Display for HostMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Active => f.write_str("active"),
+            Self::Cordoned => f.write_str("cordoned"),
+            Self::Draining { .. } => f.write_str("draining"),
+        }
+    }
+}
+
+/// Shared, observable host mode. Set by `sentinelctl`; read by the claim
+/// path before every claim.
+pub struct ModeControl {
+    tx: watch::Sender<HostMode>,
+}
+
+impl Default for ModeControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ModeControl {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            tx: watch::Sender::new(HostMode::Active),
+        }
+    }
+
+    #[must_use]
+    pub fn current(&self) -> HostMode {
+        self.tx.borrow().clone()
+    }
+
+    /// Whether new tasks may be claimed right now.
+    #[must_use]
+    pub fn accepting(&self) -> bool {
+        self.tx.borrow().accepting()
+    }
+
+    /// Switch mode, logging the change. Returns the previous mode.
+    pub fn set(&self, mode: HostMode) -> HostMode {
+        let previous = self.tx.send_replace(mode);
+        let current = self.tx.borrow();
+        if previous != *current {
+            tracing::info!(from = %previous, to = %*current, "host mode changed");
+        }
+        previous
+    }
+
+    /// Receiver notified on every mode change.
+    #[must_use]
+    pub fn subscribe(&self) -> watch::Receiver<HostMode> {
+        self.tx.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_active_and_accepting() {
+        let mode = ModeControl::new();
+        assert_eq!(mode.current(), HostMode::Active);
+        assert!(mode.accepting());
+    }
+
+    #[test]
+    fn cordon_and_drain_stop_accepting() {
+        let mode = ModeControl::new();
+        assert_eq!(mode.set(HostMode::Cordoned), HostMode::Active);
+        assert!(!mode.accepting());
+        mode.set(HostMode::Draining { deadline_ms: None });
+        assert!(!mode.accepting());
+        mode.set(HostMode::Active);
+        assert!(mode.accepting());
+    }
+
+    #[test]
+    fn subscribers_see_changes() {
+        let mode = ModeControl::new();
+        let mut rx = mode.subscribe();
+        mode.set(HostMode::Cordoned);
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(*rx.borrow_and_update(), HostMode::Cordoned);
+    }
+
+    #[test]
+    fn serialized_form() {
+        let json = serde_json::to_value(HostMode::Draining {
+            deadline_ms: Some(5),
+        })
+        .unwrap();
+        assert_eq!(json["mode"], "draining");
+        assert_eq!(json["deadline_ms"], 5);
+    }
+}
@@ -0,0 +1,294 @@
+use std::sync::Arc;
+
+use gbe_state_store::StateStore;
+use serde::Serialize;
+
+use crate::claim::{fail_task, requeue_task};
+use crate::config::{OrphanAction, SentinelConfig};
+use crate::journal::VmRecord;
+use crate::vm::teardown::Teardown;
+
+/// Reason or error recorded on tasks orphaned by a sentinel crash.
+pub const ORPHANED: &str = "orphaned";
+
+/// What startup recovery cleaned up.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RecoveryReport {
+    pub vms: usize,
+    pub processes: usize,
+    pub overlays: usize,
+    pub taps: usize,
+    pub requeued: usize,
+    pub failed: usize,
+    /// VMs whose teardown or task update did not complete.
+    pub errors: usize,
+    /// One per orphan, in the order given.
+    pub outcomes: Vec<OrphanOutcome>,
+}
+
+/// How one orphan's cleanup went.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OrphanOutcome {
+    pub cid: u32,
+    pub task_id: String,
+    /// Every host resource was released.
+    pub released: bool,
+    /// The task was requeued or failed, or was no longer held by the VM.
+    pub settled: bool,
+}
+
+impl OrphanOutcome {
+    /// Nothing is left to retry: the VM can be journaled finished.
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.released && self.settled
+    }
+}
+
+impl std::fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} orphaned vms: {} processes killed, {} overlays and {} taps removed, \
+             {} tasks requeued, {} failed, {} errors",
+            self.vms,
+            self.processes,
+            self.overlays,
+            self.taps,
+            self.requeued,
+            self.failed,
+            self.errors
+        )
+    }
+}
+
+/// Reconciles leftovers from a previous run that did not shut down cleanly.
+///
+/// For every VM the journal lists as unfinished:
+/// 1. Kill its Firecracker process and remove its API socket
+/// 2. Delete its overlay and tap device
+/// 3. If the task is still held by that VM, requeue it or mark it failed
+///    per `recovery.orphaned_tasks`
+///
+/// Orphaned processes are killed rather than adopted: the vsock session
+/// with the operative died with the old process and cannot be resumed.
+pub struct Recovery {
+    store: Arc<dyn StateStore>,
+    teardown: Arc<Teardown>,
+    host_id: String,
+    orphaned_tasks: OrphanAction,
+}
+
+impl Recovery {
+    #[must_use]
+    pub fn new(
+        config: &SentinelConfig,
+        store: Arc<dyn StateStore>,
+        teardown: Arc<Teardown>,
+    ) -> Self {
+        Self {
+            store,
+            teardown,
+            host_id: config.host_id.clone(),
+            orphaned_tasks: config.recovery.orphaned_tasks,
+        }
+    }
+
+    /// Clean up `orphans` and settle their tasks. Failures are counted,
+    /// logged and reported per VM in `outcomes`; recovery never stops the
+    /// sentinel from starting.
+    pub async fn reconcile(&self, orphans: &[VmRecord]) -> RecoveryReport {
+        let mut report = RecoveryReport::default();
+        for vm in orphans {
+            report.vms += 1;
+            tracing::warn!(cid = vm.cid, task_id = %vm.task_id, "cleaning up orphaned vm");
+
+            let released = self.teardown.release(&vm.resources()).await;
+            match &released {
+                Ok(()) => {
+                    report.processes += usize::from(vm.handle.is_some());
+                    report.overlays += usize::from(vm.overlay.is_some());
+                    report.taps += usize::from(vm.tap.is_some());
+                }
+                Err(e) => {
+                    tracing::error!(cid = vm.cid, error = %e, "orphan teardown incomplete");
+                    report.errors += 1;
+                }
+            }
+
+            let settled = match self.orphaned_tasks {
+                OrphanAction::Requeue => {
+                    requeue_task(
+                        &self.store,
+                        &vm.state_key,
+                        &self.host_id,
+                        vm.cid,
+                        ORPHANED,
+                        Vec::new(),
+                    )
+                    .await
+                }
+                OrphanAction::Fail => {
+                    fail_task(&self.store, &vm.state_key, &self.host_id, vm.cid, ORPHANED).await
+                }
+            };
+            let outcome = OrphanOutcome {
+                cid: vm.cid,
+                task_id: vm.task_id.clone(),
+                released: released.is_ok(),
+                settled: settled.is_ok(),
+            };
+            match settled {
+                Ok(true) if self.orphaned_tasks == OrphanAction::Requeue => report.requeued += 1,
+                Ok(true) => report.failed += 1,
+                Ok(false) => {
+                    tracing::info!(task_id = %vm.task_id, "orphaned task no longer held, left as is");
+                }
+                Err(e) => {
+                    tracing::error!(task_id = %vm.task_id, error = %e, "could not settle orphaned task");
+                    report.errors += 1;
+                }
+            }
+            report.outcomes.push(outcome);
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MemoryStore;
+    use crate::vm::lifecycle::VmState;
+    use crate::vm::manager::VmHandle;
+    use crate::vm::network::TapDevice;
+
+    fn orphan(cid: u32, task_id: &str) -> VmRecord {
+        VmRecord {
+            cid,
+            task_id: task_id.to_string(),
+            task_type: "shell".into(),
+            profile: "shell".into(),
+            state: VmState::Running,
+            at_ms: 0,
+            state_key: format!("gbe:state:tasks:shell:{task_id}"),
+            handle: Some(VmHandle {
+                cid,
+                pid: 4242,
+                socket_path: format!("/run/fc-{cid}.sock").into(),
+            }),
+            overlay: Some(format!("/o/{cid}.ext4").into()),
+            tap: Some(TapDevice {
+                name: format!("tap{cid}"),
+                ip: String::new(),
+            }),
+            fifos: None,
+            jail: None,
+            cgroup: None,
+        }
+    }
+
+    fn recovery(action: OrphanAction) -> (Arc<MemoryStore>, Recovery) {
+        let mem = Arc::new(
+            MemoryStore::default()
+                .with(
+                    "gbe:state:tasks:shell:t1",
+                    &[("state", "running"), ("worker", "h1:3")],
+                )
+                .with(
+                    "gbe:state:tasks:shell:t2",
+                    &[("state", "completed"), ("worker", "h1:4")],
+                ),
+        );
+        let mut config = crate::handler::tests::claimed(None).config.as_ref().clone();
+        config.recovery.orphaned_tasks = action;
+        let recovery = Recovery::new(
+            &config,
+            Arc::clone(&mem) as _,
+            Arc::new(Teardown::new("/fc".into(), "/o".into())),
+        );
+        (mem, recovery)
+    }
+
+    #[tokio::test]
+    async fn orphans_cleaned_and_requeued() {
+        let (mem, recovery) = recovery(OrphanAction::Requeue);
+        let report = recovery
+            .reconcile(&[orphan(3, "t1"), orphan(4, "t2")])
+            .await;
+        assert_eq!(
+            report,
+            RecoveryReport {
+                vms: 2,
+                processes: 2,
+                overlays: 2,
+                taps: 2,
+                requeued: 1,
+                failed: 0,
+                errors: 0,
+                outcomes: vec![
+                    OrphanOutcome {
+                        cid: 3,
+                        task_id: "t1".into(),
+                        released: true,
+                        settled: true,
+                    },
+                    OrphanOutcome {
+                        cid: 4,
+                        task_id: "t2".into(),
+                        released: true,
+                        settled: true,
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            mem.field("gbe:state:tasks:shell:t1", "reason").as_deref(),
+            Some(ORPHANED)
+        );
+        assert_eq!(
+            mem.field("gbe:state:tasks:shell:t2", "state").as_deref(),
+            Some("completed")
+        );
+        assert!(report.to_string().starts_with("2 orphaned vms"));
+    }
+
+    #[tokio::test]
+    async fn orphans_failed_when_configured() {
+        let (mem, recovery) = recovery(OrphanAction::Fail);
+        let report = recovery.reconcile(&[orphan(3, "t1")]).await;
+        assert_eq!(report.failed, 1);
+        assert!(report.outcomes[0].is_clean());
+        assert_eq!(
+            mem.field("gbe:state:tasks:shell:t1", "state").as_deref(),
+            Some("failed")
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_release_leaves_orphan_unclean() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = crate::config::CgroupConfig {
+            root: tmp.path().to_path_buf(),
+            ..crate::config::CgroupConfig::default()
+        };
+        let profile = crate::handler::tests::claimed(None).vm_profile().clone();
+        let cgroup = crate::vm::cgroup::VmCgroup::for_vm(&config, &profile, 3);
+        // Still holding a file, so the cgroup cannot be removed.
+        std::fs::create_dir_all(cgroup.path().join("busy")).unwrap();
+        let mut vm = orphan(3, "t1");
+        vm.cgroup = Some(cgroup);
+
+        let (mem, recovery) = recovery(OrphanAction::Requeue);
+        let report = recovery.reconcile(&[vm, orphan(4, "t2")]).await;
+        assert_eq!(report.errors, 1);
+        assert!(!report.outcomes[0].released);
+        assert!(report.outcomes[0].settled);
+        assert!(!report.outcomes[0].is_clean());
+        assert!(report.outcomes[1].is_clean());
+        assert_eq!(
+            mem.field("gbe:state:tasks:shell:t1", "reason").as_deref(),
+            Some(ORPHANED)
+        );
+    }
+}
@@ -0,0 +1,637 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+use crate::claim::now_millis;
+use crate::error::SentinelError;
+use crate::events::{LifecycleEvent, LifecycleEvents};
+use crate::handler::ClaimedTask;
+use crate::journal::{RunJournal, VmRecord};
+use crate::vm::cgroup::VmCgroup;
+use crate::vm::jailer::Jail;
+use crate::vm::lifecycle::{PhaseDurations, StateChange, VmLifecycle, VmState};
+use crate::vm::manager::VmHandle;
+use crate::vm::network::TapDevice;
+use crate::vm::telemetry::{Fifos, VmTelemetry};
+
+/// Console lines kept per VM for `sentinelctl show`.
+pub const CONSOLE_TAIL_LINES: usize = 200;
+
+/// Host resources backing one VM. Released exactly once, by whoever takes
+/// them first: the task runner on completion, or shutdown on preemption.
+#[derive(Default)]
+pub struct VmResources {
+    pub handle: Option<VmHandle>,
+    pub overlay: Option<PathBuf>,
+    pub tap: Option<TapDevice>,
+    pub fifos: Option<Fifos>,
+    pub jail: Option<Jail>,
+    pub cgroup: Option<VmCgroup>,
+}
+
+/// One running VM, shared between its task runner and the admin socket.
+pub struct VmEntry {
+    pub cid: u32,
+    pub task_id: String,
+    pub task_type: String,
+    /// State store key of the task, for requeueing on preemption.
+    pub state_key: String,
+    pub profile: String,
+    pub started_at_ms: u64,
+    lifecycle: Mutex<VmLifecycle>,
+    console: Mutex<VecDeque<String>>,
+    telemetry: Mutex<VmTelemetry>,
+    resources: Mutex<VmResources>,
+    kill: CancellationToken,
+    /// Set when registered with a journaling registry.
+    journal: Option<Arc<RunJournal>>,
+    /// Set when registered; transitions are published here.
+    events: Option<LifecycleEvents>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl VmEntry {
+    #[must_use]
+    pub fn new(cid: u32, task: &ClaimedTask) -> Self {
+        Self {
+            cid,
+            task_id: task.task.task_id.clone(),
+            task_type: task.task.task_type.clone(),
+            state_key: task.task.state_key.clone(),
+            profile: task.profile.clone(),
+            started_at_ms: now_millis(),
+            lifecycle: Mutex::new(task.lifecycle()),
+            console: Mutex::new(VecDeque::new()),
+            telemetry: Mutex::new(VmTelemetry::default()),
+            resources: Mutex::new(VmResources::default()),
+            kill: CancellationToken::new(),
+            journal: None,
+            events: None,
+        }
+    }
+
+    /// Move the VM's lifecycle to `next`, journaling the transition and
+    /// publishing it to the registry's lifecycle subscribers.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::IllegalTransition` if the move is not in
+    /// the transition table; nothing is journaled or published.
+    pub fn transition(&self, next: VmState) -> Result<(), SentinelError> {
+        let event = {
+            let mut lifecycle = lock(&self.lifecycle);
+            let from = lifecycle.state.clone();
+            let since = lifecycle.history.last().map_or(0, |c| c.at_ms);
+            lifecycle.transition(next)?;
+            // Journaled under the lock, so concurrent transitions of this VM
+            // reach the journal in the order they were made.
+            self.journal(&lifecycle, &lock(&self.resources));
+            let at_ms = lifecycle.history.last().map_or(since, |c| c.at_ms);
+            LifecycleEvent {
+                cid: self.cid,
+                task_id: self.task_id.clone(),
+                profile: self.profile.clone(),
+                from,
+                to: lifecycle.state.clone(),
+                at_ms,
+                elapsed_ms: at_ms.saturating_sub(since),
+                durations: PhaseDurations::from_history(&lifecycle.history, at_ms),
+            }
+        };
+        if let Some(events) = &self.events {
+            events.publish(event);
+        }
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn state(&self) -> VmState {
+        lock(&self.lifecycle).state.clone()
+    }
+
+    /// Span for the current state; see [`VmLifecycle::span`].
+    #[must_use]
+    pub fn span(&self) -> tracing::Span {
+        lock(&self.lifecycle).span().clone()
+    }
+
+    #[must_use]
+    pub fn history(&self) -> Vec<StateChange> {
+        lock(&self.lifecycle).history.clone()
+    }
+
+    /// Append a guest console line, dropping the oldest past the tail size.
+    pub fn push_console(&self, line: &str) {
+        let mut console = lock(&self.console);
+        if console.len() == CONSOLE_TAIL_LINES {
+            console.pop_front();
+        }
+        console.push_back(line.to_string());
+    }
+
+    /// The last `lines` console lines, oldest first.
+    #[must_use]
+    pub fn console_tail(&self, lines: usize) -> Vec<String> {
+        let console = lock(&self.console);
+        console
+            .iter()
+            .skip(console.len().saturating_sub(lines))
+            .cloned()
+            .collect()
+    }
+
+    /// Update the VM's Firecracker telemetry as its FIFOs are read.
+    pub fn record_telemetry(&self, f: impl FnOnce(&mut VmTelemetry)) {
+        f(&mut lock(&self.telemetry));
+    }
+
+    /// Firecracker's metrics and log summary so far.
+    #[must_use]
+    pub fn telemetry(&self) -> VmTelemetry {
+        lock(&self.telemetry).clone()
+    }
+
+    /// Record resources as they are provisioned. Returns once the journal
+    /// entry is synced, so a crash cannot leak the resource.
+    pub async fn with_resources(&self, f: impl FnOnce(&mut VmResources)) {
+        {
+            let lifecycle = lock(&self.lifecycle);
+            let mut resources = lock(&self.resources);
+            f(&mut resources);
+            self.journal(&lifecycle, &resources);
+        }
+        if let Some(journal) = &self.journal
+            && let Err(e) = journal.sync().await
+        {
+            tracing::warn!(cid = self.cid, error = %e, "journal sync failed");
+        }
+    }
+
+    /// Journal the VM's state and resources. Callers hold the lifecycle
+    /// lock, then the resources lock.
+    fn journal(&self, lifecycle: &VmLifecycle, resources: &VmResources) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        if let Err(e) = journal.record(&self.record(lifecycle, resources)) {
+            tracing::warn!(cid = self.cid, error = %e, "journal write failed");
+        }
+    }
+
+    /// This VM's current state and resources, for the journal.
+    #[must_use]
+    pub fn journal_record(&self) -> VmRecord {
+        let lifecycle = lock(&self.lifecycle);
+        self.record(&lifecycle, &lock(&self.resources))
+    }
+
+    fn record(&self, lifecycle: &VmLifecycle, resources: &VmResources) -> VmRecord {
+        let change = lifecycle.history.last();
+        VmRecord {
+            cid: self.cid,
+            task_id: self.task_id.clone(),
+            task_type: self.task_type.clone(),
+            profile: self.profile.clone(),
+            state_key: self.state_key.clone(),
+            state: change.map_or(VmState::Idle, |c| c.state.clone()),
+            at_ms: change.map_or_else(now_millis, |c| c.at_ms),
+            handle: resources.handle.clone(),
+            overlay: resources.overlay.clone(),
+            tap: resources.tap.clone(),
+            fifos: resources.fifos.clone(),
+            jail: resources.jail.clone(),
+            cgroup: resources.cgroup.clone(),
+        }
+    }
+
+    /// Take the VM's resources for release, leaving nothing behind for a
+    /// second caller.
+    #[must_use]
+    pub fn take_resources(&self) -> VmResources {
+        std::mem::take(&mut lock(&self.resources))
+    }
+
+    /// Ask the task runner to kill this VM.
+    pub fn kill(&self) {
+        self.kill.cancel();
+    }
+
+    /// Cancelled when an operator kills the VM.
+    #[must_use]
+    pub fn kill_token(&self) -> &CancellationToken {
+        &self.kill
+    }
+
+    #[must_use]
+    pub fn summary(&self) -> VmSummary {
+        VmSummary {
+            cid: self.cid,
+            task_id: self.task_id.clone(),
+            task_type: self.task_type.clone(),
+            profile: self.profile.clone(),
+            state: self.state(),
+            started_at_ms: self.started_at_ms,
+        }
+    }
+
+    #[must_use]
+    pub fn detail(&self, console_lines: usize) -> VmDetail {
+        VmDetail {
+            summary: self.summary(),
+            history: self.history(),
+            durations: lock(&self.lifecycle).durations(),
+            console: self.console_tail(console_lines),
+            telemetry: Some(Box::new(self.telemetry())),
+        }
+    }
+}
+
+/// One line of `sentinelctl vms`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VmSummary {
+    pub cid: u32,
+    pub task_id: String,
+    pub task_type: String,
+    pub profile: String,
+    pub state: VmState,
+    pub started_at_ms: u64,
+}
+
+/// `sentinelctl show`: lifecycle history, phase durations, console tail and,
+/// for a running VM, Firecracker's telemetry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VmDetail {
+    #[serde(flatten)]
+    pub summary: VmSummary,
+    pub history: Vec<StateChange>,
+    #[serde(default)]
+    pub durations: PhaseDurations,
+    pub console: Vec<String>,
+    #[serde(default)]
+    pub telemetry: Option<Box<VmTelemetry>>,
+}
+
+/// VMs currently running on this host, keyed by vsock CID.
+#[derive(Default)]
+pub struct VmRegistry {
+    vms: Mutex<HashMap<u32, Arc<VmEntry>>>,
+    changed: Notify,
+    journal: OnceLock<Arc<RunJournal>>,
+    events: LifecycleEvents,
+}
+
+impl VmRegistry {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// State changes of every registered VM, from now on.
+    #[must_use]
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<LifecycleEvent> {
+        self.events.subscribe()
+    }
+
+    /// Journal every VM registered from now on: its transitions, its
+    /// resources and its removal. Only the first journal attached is used.
+    pub fn attach_journal(&self, journal: Arc<RunJournal>) {
+        if self.journal.set(journal).is_err() {
+            tracing::warn!("registry journal already attached");
+        }
+    }
+
+    pub fn insert(&self, mut entry: VmEntry) -> Arc<VmEntry> {
+        entry.journal = self.journal.get().cloned();
+        entry.events = Some(self.events.clone());
+        let entry = Arc::new(entry);
+        entry.journal(&lock(&entry.lifecycle), &lock(&entry.resources));
+        lock(&self.vms).insert(entry.cid, Arc::clone(&entry));
+        self.changed.notify_waiters();
+        entry
+    }
+
+    /// Deregister a VM whose resources have been released.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Vm` if the VM is not in teardown or idle:
+    /// it may still hold resources. It stays registered.
+    pub fn remove(&self, cid: u32) -> Result<Option<Arc<VmEntry>>, SentinelError> {
+        let removed = {
+            let mut vms = lock(&self.vms);
+            if let Some(entry) = vms.get(&cid) {
+                let state = entry.state();
+                if !matches!(state, VmState::Teardown | VmState::Idle) {
+                    return Err(SentinelError::Vm(format!(
+                        "vm {cid} is {state}, not torn down"
+                    )));
+                }
+            }
+            vms.remove(&cid)
+        };
+        if let (Some(journal), Some(entry)) = (self.journal.get(), &removed)
+            && let Err(e) = journal.finished(cid, &entry.task_id)
+        {
+            tracing::warn!(cid, error = %e, "journal write failed");
+        }
+        self.changed.notify_waiters();
+        Ok(removed)
+    }
+
+    /// Deregister a VM whose release failed. Its journal entry is left
+    /// unfinished, so crash recovery releases it on the next start.
+    pub fn abandon(&self, cid: u32) -> Option<Arc<VmEntry>> {
+        let removed = lock(&self.vms).remove(&cid);
+        self.changed.notify_waiters();
+        removed
+    }
+
+    /// Detail for a VM that is no longer running, from the journal.
+    #[must_use]
+    pub async fn journaled_detail(&self, target: &str) -> Option<VmDetail> {
+        let journal = self.journal.get()?;
+        let (last, history) = match journal.history(target).await {
+            Ok(found) => found?,
+            Err(e) => {
+                tracing::warn!(error = %e, "journal read failed");
+                return None;
+            }
+        };
+        Some(VmDetail {
+            summary: VmSummary {
+                cid: last.cid,
+                task_id: last.task_id,
+                task_type: last.task_type,
+                profile: last.profile,
+                state: last.state,
+                started_at_ms: history.first().map_or(last.at_ms, |c| c.at_ms),
+            },
+            durations: PhaseDurations::from_history(&history, now_millis()),
+            history,
+            console: Vec::new(),
+            telemetry: None,
+        })
+    }
+
+    /// Resolves once no VMs are registered.
+    pub async fn wait_until_empty(&self) {
+        loop {
+            let changed = self.changed.notified();
+            if self.is_empty() {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    /// Resolves once no VM is registered under `cid`.
+    pub async fn wait_until_gone(&self, cid: u32) {
+        loop {
+            let changed = self.changed.notified();
+            if !lock(&self.vms).contains_key(&cid) {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    /// Find a VM by CID or task id.
+    #[must_use]
+    pub fn find(&self, target: &str) -> Option<Arc<VmEntry>> {
+        let vms = lock(&self.vms);
+        if let Ok(cid) = target.parse::<u32>()
+            && let Some(entry) = vms.get(&cid)
+        {
+            return Some(Arc::clone(entry));
+        }
+        vms.values().find(|e| e.task_id == target).cloned()
+    }
+
+    /// All VMs, ordered by CID.
+    #[must_use]
+    pub fn list(&self) -> Vec<Arc<VmEntry>> {
+        let mut vms: Vec<_> = lock(&self.vms).values().cloned().collect();
+        vms.sort_by_key(|e| e.cid);
+        vms
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        lock(&self.vms).len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::JournalConfig;
+    use crate::handler::tests::claimed;
+
+    pub(crate) fn entry(cid: u32, task_id: &str) -> VmEntry {
+        let mut task = claimed(None);
+        task.task.task_id = task_id.to_string();
+        VmEntry::new(cid, &task)
+    }
+
+    #[test]
+    fn find_by_cid_or_task_id() {
+        let registry = VmRegistry::new();
+        registry.insert(entry(3, "t-a"));
+        registry.insert(entry(4, "t-b"));
+        assert_eq!(registry.find("3").unwrap().task_id, "t-a");
+        assert_eq!(registry.find("t-b").unwrap().cid, 4);
+        assert!(registry.find("t-c").is_none());
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[test]
+    fn list_ordered_by_cid() {
+        let registry = VmRegistry::new();
+        for cid in [9, 3, 5] {
+            registry.insert(entry(cid, &format!("t{cid}")));
+        }
+        let cids: Vec<_> = registry.list().iter().map(|e| e.cid).collect();
+        assert_eq!(cids, [3, 5, 9]);
+        registry.remove(5).unwrap();
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[test]
+    fn remove_rejects_vm_not_torn_down() {
+        let registry = VmRegistry::new();
+        let vm = registry.insert(entry(3, "t3"));
+        vm.transition(VmState::Provisioning).unwrap();
+        let Err(err) = registry.remove(3) else {
+            panic!("removed a provisioning vm");
+        };
+        assert!(err.to_string().contains("provisioning"), "{err}");
+        assert_eq!(registry.len(), 1);
+
+        vm.transition(VmState::Timeout).unwrap();
+        vm.transition(VmState::Teardown).unwrap();
+        assert!(registry.remove(3).unwrap().is_some());
+        assert!(registry.remove(3).unwrap().is_none());
+    }
+
+    #[test]
+    fn console_tail_bounded() {
+        let vm = entry(3, "t");
+        for i in 0..CONSOLE_TAIL_LINES + 5 {
+            vm.push_console(&format!("line {i}"));
+        }
+        let tail = vm.console_tail(2);
+        assert_eq!(
+            tail,
+            [
+                format!("line {}", CONSOLE_TAIL_LINES + 3),
+                format!("line {}", CONSOLE_TAIL_LINES + 4)
+            ]
+        );
+        assert_eq!(vm.console_tail(usize::MAX).len(), CONSOLE_TAIL_LINES);
+    }
+
+    #[test]
+    fn detail_includes_history() {
+        let vm = entry(3, "t");
+        vm.transition(VmState::Provisioning).unwrap();
+        vm.transition(VmState::Running).unwrap();
+        let detail = vm.detail(10);
+        assert_eq!(detail.summary.state, VmState::Running);
+        assert_eq!(detail.history.len(), 3);
+        assert!(detail.durations.provision_ms.is_some());
+        assert!(detail.durations.run_ms.is_some());
+        assert!(detail.durations.teardown_ms.is_none());
+        assert_eq!(detail.summary.profile, "shell");
+    }
+
+    #[tokio::test]
+    async fn wait_until_empty_wakes_on_last_removal() {
+        let registry = Arc::new(VmRegistry::new());
+        registry.wait_until_empty().await;
+        registry.insert(entry(3, "t3"));
+        registry.insert(entry(4, "t4"));
+
+        let waiter = tokio::spawn({
+            let registry = Arc::clone(&registry);
+            async move { registry.wait_until_empty().await }
+        });
+        tokio::task::yield_now().await;
+        registry.remove(3).unwrap();
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+        registry.remove(4).unwrap();
+        waiter.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resources_taken_once() {
+        let vm = entry(3, "t");
+        assert_eq!(vm.state_key, "gbe:state:tasks:shell:t1");
+        vm.with_resources(|r| r.overlay = Some("/o/3.ext4".into()))
+            .await;
+        assert_eq!(vm.journal_record().overlay, Some("/o/3.ext4".into()));
+        assert!(vm.take_resources().overlay.is_some());
+        assert!(vm.take_resources().overlay.is_none());
+    }
+
+    #[tokio::test]
+    async fn journaled_registry_records_lifecycle() {
+        let tmp = tempfile::tempdir().unwrap();
+        let journal = Arc::new(
+            RunJournal::open(&tmp.path().join("run.journal"), &JournalConfig::default()).unwrap(),
+        );
+        let registry = VmRegistry::new();
+        registry.attach_journal(Arc::clone(&journal));
+        let vm = registry.insert(entry(3, "t3"));
+        vm.transition(VmState::Provisioning).unwrap();
+        vm.with_resources(|r| r.overlay = Some("/o/3.ext4".into()))
+            .await;
+        let unfinished = RunJournal::unfinished(journal.path()).unwrap();
+        assert_eq!(unfinished[0].state, VmState::Provisioning);
+        assert_eq!(unfinished[0].overlay, Some("/o/3.ext4".into()));
+
+        for state in [VmState::Running, VmState::Collecting, VmState::Teardown] {
+            vm.transition(state).unwrap();
+        }
+        registry.remove(3).unwrap();
+        journal.sync().await.unwrap();
+        assert!(RunJournal::unfinished(journal.path()).unwrap().is_empty());
+
+        let detail = registry.journaled_detail("t3").await.unwrap();
+        assert_eq!(detail.summary.state, VmState::Teardown);
+        assert_eq!(detail.history.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn racing_transitions_journaled_in_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        let journal = Arc::new(
+            RunJournal::open(&tmp.path().join("run.journal"), &JournalConfig::default()).unwrap(),
+        );
+        let registry = VmRegistry::new();
+        registry.attach_journal(Arc::clone(&journal));
+        let vm = registry.insert(entry(3, "t3"));
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for state in [
+                        VmState::Provisioning,
+                        VmState::Running,
+                        VmState::Collecting,
+                        VmState::Teardown,
+                    ] {
+                        let _ = vm.transition(state);
+                    }
+                });
+            }
+        });
+        journal.sync().await.unwrap();
+
+        let unfinished = RunJournal::unfinished(journal.path()).unwrap();
+        assert_eq!(unfinished[0].state, vm.state());
+        assert_eq!(vm.state(), VmState::Teardown);
+    }
+
+    #[tokio::test]
+    async fn transitions_published_to_subscribers() {
+        let registry = VmRegistry::new();
+        let mut events = registry.subscribe();
+        let vm = registry.insert(entry(3, "t3"));
+        vm.transition(VmState::Provisioning).unwrap();
+        vm.transition(VmState::Collecting).unwrap_err();
+        vm.transition(VmState::Running).unwrap();
+
+        let provisioning = events.recv().await.unwrap();
+        assert_eq!((provisioning.cid, provisioning.task_id.as_str()), (3, "t3"));
+        assert_eq!(provisioning.profile, "shell");
+        assert_eq!(
+            (provisioning.from, provisioning.to),
+            (VmState::Idle, VmState::Provisioning)
+        );
+        let running = events.recv().await.unwrap();
+        assert_eq!(running.from, VmState::Provisioning);
+        assert_eq!(running.to, VmState::Running);
+        assert_eq!(running.durations.provision_ms, Some(running.elapsed_ms));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn kill_cancels_token() {
+        let vm = entry(3, "t");
+        assert!(!vm.kill_token().is_cancelled());
+        vm.kill();
+        assert!(vm.kill_token().is_cancelled());
+    }
+}
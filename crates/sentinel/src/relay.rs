@@ -0,0 +1,351 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use gbe_nexus::Transport;
+use serde_json::Value;
+
+use crate::error::SentinelError;
+use crate::trace::TraceContext;
+use crate::usage::TaskUsage;
+use crate::vm::console;
+use crate::vm::supervisor::{ExitKind, ProcessExit};
+
+/// Relays task progress and terminal events from a VM to the bus.
+///
+/// Progress: `gbe.tasks.{task_type}.progress`
+/// Terminal: `gbe.tasks.{task_type}.terminal`
+/// Usage: `gbe.tasks.{task_type}.usage`
+///
+/// Every publish carries the task's trace id in `PublishOpts`. Failure
+/// terminal events carry the end of the VM's console log as
+/// `console_tail`, if one was attached with [`with_console`](Self::with_console).
+pub struct TaskRelay {
+    transport: Arc<dyn Transport>,
+    task_type: String,
+    task_id: String,
+    trace: TraceContext,
+    console: Option<(PathBuf, u64)>,
+}
+
+impl TaskRelay {
+    #[must_use]
+    pub fn new(
+        transport: Arc<dyn Transport>,
+        task_type: &str,
+        task_id: &str,
+        trace: TraceContext,
+    ) -> Self {
+        Self {
+            transport,
+            task_type: task_type.to_string(),
+            task_id: task_id.to_string(),
+            trace,
+            console: None,
+        }
+    }
+
+    /// Attach the last `tail_bytes` of the console log in `dir` to failure
+    /// terminal events.
+    #[must_use]
+    pub fn with_console(mut self, dir: PathBuf, tail_bytes: u64) -> Self {
+        self.console = Some((dir, tail_bytes));
+        self
+    }
+
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Transport` on publish failure.
+    pub async fn progress(
+        &self,
+        step: &str,
+        status: &str,
+        data: Option<&Value>,
+    ) -> Result<(), SentinelError> {
+        let body = serde_json::json!({
+            "task_id": self.task_id,
+            "step": step,
+            "status": status,
+            "data": data,
+        });
+        self.publish("progress", &body).await
+    }
+
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Transport` on publish failure.
+    pub async fn completed(&self, output: &Value, exit_code: i32) -> Result<(), SentinelError> {
+        let body = serde_json::json!({
+            "task_id": self.task_id,
+            "state": "completed",
+            "exit_code": exit_code,
+            "output": output,
+        });
+        self.publish("terminal", &body).await
+    }
+
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Transport` on publish failure.
+    pub async fn failed(&self, error: &str, exit_code: Option<i32>) -> Result<(), SentinelError> {
+        let mut body = serde_json::json!({
+            "task_id": self.task_id,
+            "state": "failed",
+            "exit_code": exit_code,
+            "error": error,
+        });
+        self.attach_console(&mut body);
+        self.publish("terminal", &body).await
+    }
+
+    /// Fail the task because its VM exited, with the exit classification
+    /// as the error. A clean exit publishes nothing: the operative's result
+    /// already went out.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Transport` on publish failure.
+    pub async fn vm_exited(&self, kind: ExitKind, exit: &ProcessExit) -> Result<(), SentinelError> {
+        let Some(reason) = kind.failure_reason() else {
+            return Ok(());
+        };
+        let mut body = serde_json::json!({
+            "task_id": self.task_id,
+            "state": "failed",
+            "exit_code": exit.code,
+            "error": reason,
+            "vm_exit": {
+                "exit": kind,
+                "code": exit.code,
+                "signal": exit.signal,
+                "at_ms": exit.at_ms,
+            },
+        });
+        self.attach_console(&mut body);
+        self.publish("terminal", &body).await
+    }
+
+    /// Publish what the task's VM consumed, after teardown.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Transport` on publish failure.
+    pub async fn usage(&self, usage: &TaskUsage) -> Result<(), SentinelError> {
+        self.publish("usage", &serde_json::to_value(usage)?).await
+    }
+
+    /// Add `console_tail` to a failure body. A log that cannot be read is
+    /// left out rather than holding up the terminal event.
+    fn attach_console(&self, body: &mut Value) {
+        let Some((dir, tail_bytes)) = &self.console else {
+            return;
+        };
+        match console::read_tail(dir, *tail_bytes) {
+            Ok(tail) if !tail.is_empty() => body["console_tail"] = tail.into(),
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!(task_id = %self.task_id, error = %e, "console tail unreadable");
+            }
+        }
+    }
+
+    async fn publish(&self, kind: &str, body: &Value) -> Result<(), SentinelError> {
+        let subject = format!("gbe.tasks.{}.{kind}", self.task_type);
+        self.transport
+            .publish(
+                &subject,
+                Bytes::from(serde_json::to_vec(body)?),
+                Some(self.trace.publish_opts()),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use gbe_nexus::{MessageHandler, PublishOpts, SubscribeOpts, Subscription, TransportError};
+    use std::sync::Mutex;
+
+    /// Transport that records every publish.
+    #[derive(Default)]
+    pub(crate) struct RecordingTransport {
+        pub(crate) published: Mutex<Vec<(String, Value, Option<String>)>>,
+    }
+
+    /// Subscription that never delivers anything.
+    struct NoSubscription;
+
+    #[async_trait]
+    impl Subscription for NoSubscription {
+        async fn unsubscribe(&self) -> Result<(), TransportError> {
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Transport for RecordingTransport {
+        async fn publish(
+            &self,
+            subject: &str,
+            payload: Bytes,
+            opts: Option<PublishOpts>,
+        ) -> Result<String, TransportError> {
+            self.published.lock().unwrap().push((
+                subject.to_string(),
+                serde_json::from_slice(&payload).unwrap(),
+                opts.and_then(|o| o.trace_id),
+            ));
+            Ok("1-0".into())
+        }
+        async fn subscribe(
+            &self,
+            _subject: &str,
+            _group: &str,
+            _handler: Box<dyn MessageHandler>,
+            _opts: Option<SubscribeOpts>,
+        ) -> Result<Box<dyn Subscription>, TransportError> {
+            Ok(Box::new(NoSubscription))
+        }
+        async fn ping(&self) -> Result<bool, TransportError> {
+            Ok(true)
+        }
+        async fn close(&self) -> Result<(), TransportError> {
+            Ok(())
+        }
+    }
+
+    fn relay(trace_id: Option<&str>) -> (Arc<RecordingTransport>, TaskRelay) {
+        let transport = Arc::new(RecordingTransport::default());
+        let relay = TaskRelay::new(
+            Arc::clone(&transport) as _,
+            "shell",
+            "task-1",
+            TraceContext::new(trace_id.map(String::from)),
+        );
+        (transport, relay)
+    }
+
+    #[tokio::test]
+    async fn progress_published_with_trace_id() {
+        let (transport, relay) = relay(Some("trace-abc"));
+        relay.progress("compile", "running", None).await.unwrap();
+
+        let published = transport.published.lock().unwrap();
+        let (subject, body, trace_id) = &published[0];
+        assert_eq!(subject, "gbe.tasks.shell.progress");
+        assert_eq!(body["task_id"], "task-1");
+        assert_eq!(body["step"], "compile");
+        assert_eq!(trace_id.as_deref(), Some("trace-abc"));
+    }
+
+    #[tokio::test]
+    async fn terminal_events_published_with_trace_id() {
+        let (transport, relay) = relay(Some("trace-abc"));
+        relay
+            .completed(&serde_json::json!({"ok": true}), 0)
+            .await
+            .unwrap();
+        relay.failed("boom", Some(1)).await.unwrap();
+
+        let published = transport.published.lock().unwrap();
+        assert_eq!(published.len(), 2);
+        assert!(
+            published
+                .iter()
+                .all(|(s, _, t)| s == "gbe.tasks.shell.terminal"
+                    && t.as_deref() == Some("trace-abc"))
+        );
+        assert_eq!(published[0].1["state"], "completed");
+        assert_eq!(published[1].1["state"], "failed");
+        assert_eq!(published[1].1["error"], "boom");
+    }
+
+    #[tokio::test]
+    async fn vm_exit_fails_task_with_reason() {
+        let (transport, relay) = relay(Some("trace-abc"));
+        let exit = ProcessExit {
+            code: None,
+            signal: Some(9),
+            killed_by_sentinel: false,
+            at_ms: 7,
+        };
+        relay.vm_exited(ExitKind::OomKilled, &exit).await.unwrap();
+        relay.vm_exited(ExitKind::Clean, &exit).await.unwrap();
+
+        let published = transport.published.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        let body = &published[0].1;
+        assert_eq!(body["state"], "failed");
+        assert_eq!(body["error"], "oom_killed");
+        assert_eq!(body["vm_exit"]["exit"]["kind"], "oom_killed");
+        assert_eq!(body["vm_exit"]["signal"], 9);
+    }
+
+    #[tokio::test]
+    async fn failures_carry_console_tail() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut log = console::ConsoleLog::create(tmp.path(), "task-1", 4096).unwrap();
+        log.append("Kernel panic - not syncing: VFS").unwrap();
+        let (transport, relay) = relay(None);
+        let relay = relay.with_console(log.dir().to_path_buf(), 1024);
+        relay
+            .completed(&serde_json::json!({"ok": true}), 0)
+            .await
+            .unwrap();
+        relay.failed("boom", Some(1)).await.unwrap();
+
+        let published = transport.published.lock().unwrap();
+        assert!(published[0].1.get("console_tail").is_none());
+        assert_eq!(
+            published[1].1["console_tail"],
+            "Kernel panic - not syncing: VFS\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn usage_carries_vm_telemetry() {
+        let vm = crate::registry::tests::entry(3, "task-1");
+        vm.record_telemetry(|t| {
+            t.metrics.flushes = 2;
+            t.metrics.block.write_bytes = 512;
+            t.log.errors = 1;
+        });
+        let logs = crate::logs::LogStats {
+            lines: 12,
+            bytes: 340,
+            batches: 1,
+            ..Default::default()
+        };
+        let (transport, relay) = relay(Some("trace-abc"));
+        relay
+            .usage(&TaskUsage::for_vm(&vm, Some(logs)).with_cgroup(
+                crate::vm::cgroup::CgroupUsage {
+                    memory_peak_bytes: 1 << 27,
+                    ..Default::default()
+                },
+            ))
+            .await
+            .unwrap();
+
+        let published = transport.published.lock().unwrap();
+        let (subject, body, trace_id) = &published[0];
+        assert_eq!(subject, "gbe.tasks.shell.usage");
+        assert_eq!(trace_id.as_deref(), Some("trace-abc"));
+        assert_eq!(body["cid"], 3);
+        assert_eq!(body["vm"]["metrics"]["block"]["write_bytes"], 512);
+        assert_eq!(body["vm"]["log"]["errors"], 1);
+        assert_eq!(body["logs"]["lines"], 12);
+        assert_eq!(body["logs"]["bytes"], 340);
+        assert_eq!(body["cgroup"]["memory_peak_bytes"], 1 << 27);
+    }
+
+    #[tokio::test]
+    async fn missing_trace_id_publishes_without_one() {
+        let (transport, relay) = relay(None);
+        relay.progress("s", "ok", None).await.unwrap();
+        assert!(transport.published.lock().unwrap()[0].2.is_none());
+    }
+}
@@ -0,0 +1,304 @@
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use serde_json::Value;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{HostResources, SentinelConfig};
+use crate::error::SentinelError;
+
+/// Config shared by the run loop, swapped atomically on reload.
+///
+/// New tasks take a [`snapshot`](Self::snapshot) when claimed and keep it
+/// for their whole lifetime, so a reload only affects tasks claimed after
+/// it. Profiles, policies, `image_dir` and `kernel_path` can change live;
+/// fields the running sentinel is built around (see
+/// [`reject_static_changes`]) cannot.
+pub struct LiveConfig {
+    current: RwLock<Arc<SentinelConfig>>,
+    source: Option<PathBuf>,
+}
+
+impl LiveConfig {
+    /// `source` is the file re-read on reload; `None` disables reloading.
+    #[must_use]
+    pub fn new(config: SentinelConfig, source: Option<PathBuf>) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(config)),
+            source,
+        }
+    }
+
+    /// The config new tasks should use.
+    #[must_use]
+    pub fn snapshot(&self) -> Arc<SentinelConfig> {
+        let current = self
+            .current
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        Arc::clone(&current)
+    }
+
+    /// Re-read and validate the config file, then swap it in. Returns the
+    /// settings that changed, as [`changed_keys`] lists them.
+    ///
+    /// On any error the current config stays in place.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` if there is no config file, the new
+    /// config fails to load or validate, a profile does not fit on this
+    /// host, or it changes a field that cannot change live.
+    pub fn reload(&self) -> Result<Vec<String>, SentinelError> {
+        let path = self.source.as_deref().ok_or_else(|| {
+            SentinelError::Config("reload: sentinel was not started from a config file".into())
+        })?;
+        let next = SentinelConfig::load(path)?;
+        next.validate_for_host(&HostResources::detect())?;
+
+        let mut current = self
+            .current
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        reject_static_changes(&current, &next)?;
+        let changed = changed_keys(&current, &next);
+        *current = Arc::new(next);
+        Ok(changed)
+    }
+
+    /// Reload on every SIGHUP until `token` is cancelled. A rejected reload
+    /// is logged and the current config kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if the signal handler cannot be installed.
+    pub async fn reload_on_sighup(
+        self: Arc<Self>,
+        token: CancellationToken,
+    ) -> Result<(), SentinelError> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        loop {
+            tokio::select! {
+                () = token.cancelled() => return Ok(()),
+                _ = hangup.recv() => match self.reload() {
+                    Ok(changed) => tracing::info!(
+                        changed = %changed.join(", "),
+                        "config reloaded"
+                    ),
+                    Err(e) => tracing::warn!(
+                        error = %e,
+                        "config reload rejected, keeping current config"
+                    ),
+                },
+            }
+        }
+    }
+}
+
+/// Reject changes to fields that require a restart: `host_id` (bus
+/// subjects and worker ids), `slots` (slot tracker), `task_types` (queue
+/// subscriptions), `overlay_dir`, `firecracker_bin`, `jailer` and `cgroup`
+/// (running VMs are torn down with them), the bus and state connections,
+/// and everything read once when the sentinel starts: `shutdown`,
+/// `journal`, `recovery`, `console`, `logs` and `heartbeat_interval_secs`.
+///
+/// # Errors
+///
+/// Returns `SentinelError::Config` naming every changed field.
+pub fn reject_static_changes(
+    current: &SentinelConfig,
+    next: &SentinelConfig,
+) -> Result<(), SentinelError> {
+    let changed: Vec<&str> = [
+        ("host_id", current.host_id != next.host_id),
+        ("slots", current.slots != next.slots),
+        ("task_types", current.task_types != next.task_types),
+        ("overlay_dir", current.overlay_dir != next.overlay_dir),
+        (
+            "firecracker_bin",
+            current.firecracker_bin != next.firecracker_bin,
+        ),
+        ("bus", current.bus != next.bus),
+        ("state", current.state != next.state),
+        ("admin_socket", current.admin_socket != next.admin_socket),
+        ("control_key", current.control_key != next.control_key),
+        ("state_dir", current.state_dir != next.state_dir),
+        (
+            "heartbeat_interval_secs",
+            current.heartbeat_interval_secs != next.heartbeat_interval_secs,
+        ),
+        ("shutdown", current.shutdown != next.shutdown),
+        ("journal", current.journal != next.journal),
+        ("recovery", current.recovery != next.recovery),
+        ("console", current.console != next.console),
+        ("logs", current.logs != next.logs),
+        ("jailer", current.jailer != next.jailer),
+        ("cgroup", current.cgroup != next.cgroup),
+    ]
+    .into_iter()
+    .filter_map(|(field, differs)| differs.then_some(field))
+    .collect();
+
+    if changed.is_empty() {
+        Ok(())
+    } else {
+        Err(SentinelError::Config(format!(
+            "reload: cannot change {} without a restart",
+            changed.join(", ")
+        )))
+    }
+}
+
+/// Dotted paths of the settings that differ between `current` and `next`,
+/// sorted. Values are left out, since some of them are secrets.
+#[must_use]
+pub fn changed_keys(current: &SentinelConfig, next: &SentinelConfig) -> Vec<String> {
+    let mut changed = Vec::new();
+    diff(
+        "",
+        &serde_json::to_value(current).unwrap_or_default(),
+        &serde_json::to_value(next).unwrap_or_default(),
+        &mut changed,
+    );
+    changed.sort();
+    changed
+}
+
+fn diff(path: &str, current: &Value, next: &Value, changed: &mut Vec<String>) {
+    match (current, next) {
+        (Value::Object(current), Value::Object(next)) => {
+            let added = next.keys().filter(|key| !current.contains_key(*key));
+            for key in current.keys().chain(added) {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                diff(
+                    &path,
+                    current.get(key).unwrap_or(&Value::Null),
+                    next.get(key).unwrap_or(&Value::Null),
+                    changed,
+                );
+            }
+        }
+        _ if current != next => changed.push(path.to_string()),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn write_config(tmp: &Path, host_id: &str, timeout_sec: u64) -> PathBuf {
+        for dir in ["images", "overlays"] {
+            fs::create_dir_all(tmp.join(dir)).unwrap();
+        }
+        for file in ["vmlinux", "firecracker", "images/base.ext4"] {
+            fs::write(tmp.join(file), b"").unwrap();
+        }
+        let path = tmp.join("sentinel.toml");
+        fs::write(
+            &path,
+            format!(
+                "host_id = {host_id:?}\nslots = 2\nimage_dir = {:?}\nkernel_path = {:?}\n\
+                 overlay_dir = {:?}\nfirecracker_bin = {:?}\ntask_types = [\"shell\"]\n\
+                 [profiles.shell]\nvcpus = 1\nmem_mb = 128\nrootfs = \"base.ext4\"\n\
+                 timeout_sec = {timeout_sec}\n",
+                tmp.join("images"),
+                tmp.join("vmlinux"),
+                tmp.join("overlays"),
+                tmp.join("firecracker"),
+            ),
+        )
+        .unwrap();
+        path
+    }
+
+    fn live(tmp: &Path) -> LiveConfig {
+        let path = write_config(tmp, "host-01", 300);
+        LiveConfig::new(SentinelConfig::load(&path).unwrap(), Some(path))
+    }
+
+    #[test]
+    fn reload_swaps_profiles_for_new_snapshots() {
+        let tmp = tempfile::tempdir().unwrap();
+        let live = live(tmp.path());
+        let before = live.snapshot();
+
+        write_config(tmp.path(), "host-01", 900);
+        assert_eq!(live.reload().unwrap(), ["profiles.shell.timeout_sec"]);
+        assert!(live.reload().unwrap().is_empty());
+
+        assert_eq!(live.snapshot().profiles["shell"].timeout_sec, 900);
+        // A task holding the old snapshot keeps its original profile.
+        assert_eq!(before.profiles["shell"].timeout_sec, 300);
+    }
+
+    #[test]
+    fn reload_rejects_host_id_change() {
+        let tmp = tempfile::tempdir().unwrap();
+        let live = live(tmp.path());
+
+        write_config(tmp.path(), "host-02", 900);
+        let err = live.reload().unwrap_err();
+
+        assert!(err.to_string().contains("cannot change host_id"));
+        assert_eq!(live.snapshot().host_id, "host-01");
+        assert_eq!(live.snapshot().profiles["shell"].timeout_sec, 300);
+    }
+
+    #[test]
+    fn reload_rejects_invalid_config() {
+        let tmp = tempfile::tempdir().unwrap();
+        let live = live(tmp.path());
+
+        fs::write(tmp.path().join("sentinel.toml"), "not = [valid").unwrap();
+        assert!(live.reload().is_err());
+        assert_eq!(live.snapshot().profiles["shell"].timeout_sec, 300);
+    }
+
+    #[test]
+    fn reload_without_source_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = write_config(tmp.path(), "host-01", 300);
+        let live = LiveConfig::new(SentinelConfig::load(&path).unwrap(), None);
+        let err = live.reload().unwrap_err();
+        assert!(err.to_string().contains("not started from a config file"));
+    }
+
+    #[test]
+    fn static_changes_listed_together() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = write_config(tmp.path(), "host-01", 300);
+        let current = SentinelConfig::load(&path).unwrap();
+        let mut next = current.clone();
+        next.slots = 8;
+        next.task_types.push("build".into());
+        let msg = reject_static_changes(&current, &next)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("slots, task_types"));
+
+        let mut next = current.clone();
+        next.bus.url = "redis://elsewhere:6379".into();
+        let msg = reject_static_changes(&current, &next)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("cannot change bus"));
+
+        let mut next = current.clone();
+        next.shutdown.drain_timeout_secs += 1;
+        next.heartbeat_interval_secs += 1;
+        next.console.retention_secs += 1;
+        next.cgroup.enabled = !next.cgroup.enabled;
+        let msg = reject_static_changes(&current, &next)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("heartbeat_interval_secs, shutdown, console, cgroup"));
+    }
+}
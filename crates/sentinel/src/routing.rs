@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{SentinelConfig, VmProfile};
+use crate::error::SentinelError;
+use crate::handler::QueuedTask;
+
+/// Label that pins a task to a named profile, bypassing the rules.
+pub const PROFILE_LABEL: &str = "profile";
+
+/// Rule-based mapping from task attributes to a `VmProfile`.
+///
+/// ```toml
+/// [routing]
+/// default_profile = "default"
+///
+/// [[routing.rules]]
+/// tenant = "acme"
+/// labels = { tier = "batch" }
+/// profiles = ["small", "heavy"]
+/// ```
+///
+/// Rules are tried in order; the first whose conditions all match supplies
+/// the candidate profiles, and the smallest candidate that satisfies the
+/// task's requested resources wins.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RoutingTable {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub rules: Vec<RoutingRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingRule {
+    pub task_type: Option<String>,
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    pub profiles: Vec<String>,
+}
+
+impl RoutingRule {
+    #[must_use]
+    pub fn matches(&self, task: &QueuedTask) -> bool {
+        self.task_type.as_ref().is_none_or(|t| *t == task.task_type)
+            && self
+                .tenant
+                .as_ref()
+                .is_none_or(|t| task.tenant.as_ref() == Some(t))
+            && self
+                .labels
+                .iter()
+                .all(|(k, v)| task.labels.get(k) == Some(v))
+    }
+
+    /// True if this rule matches every task of `task_type`.
+    #[must_use]
+    pub fn covers_task_type(&self, task_type: &str) -> bool {
+        self.task_type.as_deref().is_none_or(|t| t == task_type)
+            && self.tenant.is_none()
+            && self.labels.is_empty()
+    }
+}
+
+/// Resources a task asks for. Unset fields accept any profile.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceRequest {
+    pub vcpus: Option<u32>,
+    pub mem_mb: Option<u32>,
+}
+
+impl ResourceRequest {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.vcpus.is_none() && self.mem_mb.is_none()
+    }
+
+    #[must_use]
+    pub fn fits(&self, profile: &VmProfile) -> bool {
+        self.vcpus.is_none_or(|v| v <= profile.vcpus)
+            && self.mem_mb.is_none_or(|m| m <= profile.mem_mb)
+    }
+}
+
+impl fmt::Display for ResourceRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.vcpus, self.mem_mb) {
+            (Some(v), Some(m)) => write!(f, "vcpus={v} mem_mb={m}"),
+            (Some(v), None) => write!(f, "vcpus={v}"),
+            (None, Some(m)) => write!(f, "mem_mb={m}"),
+            (None, None) => write!(f, "no resources"),
+        }
+    }
+}
+
+/// Pick a profile for `task`.
+///
+/// 1. A `profile` label names the profile directly.
+/// 2. Otherwise the first matching rule supplies the candidates.
+/// 3. Otherwise the task type's default profile is used, sized up to the
+///    smallest profile that fits if the task requests more. A profile named
+///    after a task type belongs to that type: a task type with its own
+///    profile is never sized up, and no other type is sized up into it.
+///
+/// # Errors
+///
+/// Returns `SentinelError::Unroutable` if the task asks for resources
+/// beyond any profile, names an unknown profile, or matches no profile.
+pub fn route<'a>(
+    config: &'a SentinelConfig,
+    task: &QueuedTask,
+) -> Result<(&'a str, &'a VmProfile), SentinelError> {
+    let unroutable = |reason: String| SentinelError::Unroutable {
+        task_id: task.task_id.clone(),
+        reason,
+    };
+    let request = task.resources;
+
+    if !config.profiles.values().any(|p| request.fits(p)) {
+        return Err(unroutable(format!(
+            "requests {request} beyond any profile on this host"
+        )));
+    }
+
+    let candidates: Vec<&str> = if let Some(name) = task.labels.get(PROFILE_LABEL) {
+        if !config.profiles.contains_key(name) {
+            return Err(unroutable(format!("unknown profile {name:?}")));
+        }
+        vec![name.as_str()]
+    } else if let Some(rule) = config.routing.rules.iter().find(|r| r.matches(task)) {
+        rule.profiles.iter().map(String::as_str).collect()
+    } else {
+        let default = config
+            .default_profile_name(&task.task_type)
+            .ok_or_else(|| unroutable(format!("no profile for task type {:?}", task.task_type)))?;
+        if request.fits(&config.profiles[default]) {
+            vec![default]
+        } else {
+            size_up_candidates(config, &task.task_type)
+        }
+    };
+
+    smallest_fit(config, &candidates, request).ok_or_else(|| {
+        unroutable(format!(
+            "no profile in [{}] fits {request}",
+            candidates.join(", ")
+        ))
+    })
+}
+
+/// Profiles a task of `task_type` may be sized up to, by name.
+fn size_up_candidates<'a>(config: &'a SentinelConfig, task_type: &str) -> Vec<&'a str> {
+    if let Some((own, _)) = config.profiles.get_key_value(task_type) {
+        return vec![own.as_str()];
+    }
+    let mut names: Vec<&str> = config
+        .profiles
+        .keys()
+        .filter(|name| !config.task_types.contains(name))
+        .map(String::as_str)
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+fn smallest_fit<'a>(
+    config: &'a SentinelConfig,
+    candidates: &[&str],
+    request: ResourceRequest,
+) -> Option<(&'a str, &'a VmProfile)> {
+    candidates
+        .iter()
+        .filter_map(|name| config.profiles.get_key_value(*name))
+        .filter(|(_, profile)| request.fits(profile))
+        .min_by_key(|(name, profile)| (profile.mem_mb, profile.vcpus, name.as_str()))
+        .map(|(name, profile)| (name.as_str(), profile))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+host_id = "h1"
+slots = 2
+image_dir = "/images"
+kernel_path = "/k"
+overlay_dir = "/o"
+firecracker_bin = "/fc"
+task_types = ["shell", "build"]
+
+[profiles.default]
+vcpus = 1
+mem_mb = 512
+rootfs = "base.ext4"
+
+[profiles.medium]
+vcpus = 2
+mem_mb = 2048
+rootfs = "base.ext4"
+
+[profiles.heavy]
+vcpus = 4
+mem_mb = 8192
+rootfs = "heavy.ext4"
+
+[profiles.build]
+vcpus = 2
+mem_mb = 1024
+rootfs = "build.ext4"
+
+[[routing.rules]]
+tenant = "acme"
+profiles = ["default", "medium"]
+
+[[routing.rules]]
+labels = { tier = "gpu" }
+profiles = ["heavy"]
+"#;
+
+    fn config() -> SentinelConfig {
+        SentinelConfig::from_toml(CONFIG).unwrap()
+    }
+
+    fn task(json: &str) -> QueuedTask {
+        let mut value: serde_json::Value = serde_json::from_str(json).unwrap();
+        let obj = value.as_object_mut().unwrap();
+        obj.entry("task_id").or_insert("t1".into());
+        obj.entry("task_type").or_insert("shell".into());
+        obj.entry("state_key").or_insert("k".into());
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn routed(json: &str) -> Result<String, SentinelError> {
+        let cfg = config();
+        route(&cfg, &task(json)).map(|(name, _)| name.to_string())
+    }
+
+    #[test]
+    fn task_type_profile_used_by_default() {
+        assert_eq!(routed(r#"{"task_type":"build"}"#).unwrap(), "build");
+        assert_eq!(routed("{}").unwrap(), "default");
+    }
+
+    #[test]
+    fn routing_default_profile_used_when_no_task_type_profile() {
+        let mut cfg = config();
+        cfg.routing.default_profile = Some("medium".into());
+        let (name, _) = route(&cfg, &task("{}")).unwrap();
+        assert_eq!(name, "medium");
+    }
+
+    #[test]
+    fn resources_size_up_from_default() {
+        assert_eq!(routed(r#"{"resources":{"mem_mb":4096}}"#).unwrap(), "heavy");
+        assert_eq!(
+            routed(r#"{"resources":{"mem_mb":1500}}"#).unwrap(),
+            "medium"
+        );
+    }
+
+    #[test]
+    fn size_up_skips_other_task_types_profiles() {
+        // `build` (2 vcpus, 1024 MB) is smaller but belongs to build tasks.
+        assert_eq!(
+            routed(r#"{"resources":{"vcpus":2,"mem_mb":1024}}"#).unwrap(),
+            "medium"
+        );
+        let err = routed(r#"{"task_type":"build","resources":{"mem_mb":2048}}"#).unwrap_err();
+        assert!(matches!(err, SentinelError::Unroutable { .. }));
+        assert!(
+            err.to_string()
+                .contains("no profile in [build] fits mem_mb=2048")
+        );
+    }
+
+    #[test]
+    fn resources_beyond_any_profile_rejected() {
+        let err = routed(r#"{"resources":{"mem_mb":65536}}"#).unwrap_err();
+        assert!(matches!(err, SentinelError::Unroutable { .. }));
+        assert!(err.to_string().contains("beyond any profile"));
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        assert_eq!(routed(r#"{"tenant":"acme"}"#).unwrap(), "default");
+        assert_eq!(
+            routed(r#"{"tenant":"acme","labels":{"tier":"gpu"}}"#).unwrap(),
+            "default"
+        );
+        assert_eq!(routed(r#"{"labels":{"tier":"gpu"}}"#).unwrap(), "heavy");
+    }
+
+    #[test]
+    fn rule_picks_smallest_fitting_candidate() {
+        assert_eq!(
+            routed(r#"{"tenant":"acme","resources":{"vcpus":2}}"#).unwrap(),
+            "medium"
+        );
+    }
+
+    #[test]
+    fn rule_candidates_limit_size_up() {
+        let err = routed(r#"{"tenant":"acme","resources":{"mem_mb":4096}}"#).unwrap_err();
+        assert!(err.to_string().contains("no profile in [default, medium]"));
+    }
+
+    #[test]
+    fn profile_label_pins_profile() {
+        assert_eq!(
+            routed(r#"{"labels":{"profile":"heavy"}}"#).unwrap(),
+            "heavy"
+        );
+        let err = routed(r#"{"labels":{"profile":"nope"}}"#).unwrap_err();
+        assert!(err.to_string().contains("unknown profile"));
+        let err =
+            routed(r#"{"labels":{"profile":"default"},"resources":{"vcpus":2}}"#).unwrap_err();
+        assert!(err.to_string().contains("fits vcpus=2"));
+    }
+
+    #[test]
+    fn no_default_profile_rejected() {
+        let mut cfg = config();
+        cfg.profiles.remove("default");
+        let err = route(&cfg, &task("{}")).unwrap_err();
+        assert!(err.to_string().contains("no profile for task type"));
+    }
+
+    #[test]
+    fn rule_coverage() {
+        let rule = RoutingRule {
+            task_type: Some("shell".into()),
+            tenant: None,
+            labels: HashMap::new(),
+            profiles: vec!["default".into()],
+        };
+        assert!(rule.covers_task_type("shell"));
+        assert!(!rule.covers_task_type("build"));
+    }
+}
@@ -0,0 +1,1046 @@
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use gbe_nexus::{Message, MessageHandler, Transport, TransportError};
+use gbe_state_store::StateStore;
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::UnixStream;
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+use crate::claim::{complete_task, fail_task};
+use crate::config::NetworkMode;
+use crate::error::SentinelError;
+use crate::handler::{ClaimedTask, TaskHandler};
+use crate::logs::{LogLine, LogSender, LogStats};
+use crate::registry::{VmEntry, VmRegistry};
+use crate::relay::TaskRelay;
+use crate::sentinel::SlotTracker;
+use crate::usage::TaskUsage;
+use crate::vm::cgroup::VmCgroup;
+use crate::vm::config::FirecrackerConfig;
+use crate::vm::console::ConsoleLog;
+use crate::vm::deadline::{Extension, Phase, PhaseDeadlines, RunDeadline};
+use crate::vm::jailer::Jail;
+use crate::vm::lifecycle::VmState;
+use crate::vm::manager::{VmHandle, VmOutput};
+use crate::vm::supervisor::{ExitEvidence, ExitKind, ProcessExit};
+use crate::vm::teardown::Teardown;
+use crate::vm::telemetry::Fifos;
+use crate::vsock::channel::{OPERATIVE_PORT, OperativeChannel, connect_guest};
+use crate::vsock::liveness::{Liveness, OPERATIVE_UNRESPONSIVE, Silence};
+use crate::vsock::protocol::{OperativeMessage, SentinelMessage};
+use crate::vsock::proxy::ToolProxy;
+
+/// Lowest CID handed to a VM; 0-2 are reserved by vsock.
+pub const FIRST_CID: u32 = 3;
+
+/// Console lines searched for a kernel panic when a VM exits early.
+const EXIT_CONSOLE_LINES: usize = 50;
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Vsock CIDs for this host's VMs, one per slot.
+pub struct CidPool {
+    free: Mutex<BTreeSet<u32>>,
+}
+
+impl CidPool {
+    #[must_use]
+    pub fn new(slots: u32) -> Self {
+        Self {
+            free: Mutex::new((FIRST_CID..FIRST_CID.saturating_add(slots)).collect()),
+        }
+    }
+
+    /// Take the lowest free CID.
+    pub fn take(&self) -> Option<u32> {
+        lock(&self.free).pop_first()
+    }
+
+    pub fn put(&self, cid: u32) {
+        lock(&self.free).insert(cid);
+    }
+}
+
+/// A slot and a CID held for one task, handed back when dropped.
+pub struct SlotLease {
+    cid: u32,
+    slots: Arc<SlotTracker>,
+    cids: Arc<CidPool>,
+}
+
+impl SlotLease {
+    /// Claim a slot and a CID, or `None` if the host is full.
+    #[must_use]
+    pub fn take(slots: &Arc<SlotTracker>, cids: &Arc<CidPool>) -> Option<Self> {
+        if !slots.try_claim() {
+            return None;
+        }
+        let Some(cid) = cids.take() else {
+            slots.release();
+            return None;
+        };
+        Some(Self {
+            cid,
+            slots: Arc::clone(slots),
+            cids: Arc::clone(cids),
+        })
+    }
+
+    #[must_use]
+    pub fn cid(&self) -> u32 {
+        self.cid
+    }
+}
+
+impl Drop for SlotLease {
+    fn drop(&mut self) {
+        self.cids.put(self.cid);
+        self.slots.release();
+    }
+}
+
+/// Claims tasks from `gbe.tasks.{task_type}.queue` and runs each on a
+/// spawned [`TaskRunner`].
+///
+/// A message is acked once its task is claimed, or when it can never be
+/// claimed: it is malformed or another worker holds the task. Otherwise it
+/// stays pending for redelivery, e.g. while the host is full or cordoned.
+pub struct QueueHandler {
+    tasks: TaskHandler,
+    transport: Arc<dyn Transport>,
+    registry: Arc<VmRegistry>,
+    teardown: Arc<Teardown>,
+    slots: Arc<SlotTracker>,
+    cids: Arc<CidPool>,
+}
+
+impl QueueHandler {
+    #[must_use]
+    pub fn new(
+        tasks: TaskHandler,
+        transport: Arc<dyn Transport>,
+        registry: Arc<VmRegistry>,
+        teardown: Arc<Teardown>,
+        slots: Arc<SlotTracker>,
+        cids: Arc<CidPool>,
+    ) -> Self {
+        Self {
+            tasks,
+            transport,
+            registry,
+            teardown,
+            slots,
+            cids,
+        }
+    }
+}
+
+#[async_trait]
+impl MessageHandler for QueueHandler {
+    async fn handle(&self, msg: &dyn Message) -> Result<(), TransportError> {
+        let Some(lease) = SlotLease::take(&self.slots, &self.cids) else {
+            tracing::debug!("no free slot, leaving task queued");
+            return Ok(());
+        };
+        match self.tasks.handle_message(msg.envelope(), lease.cid()).await {
+            Ok(task) => {
+                let vm = self.registry.insert(VmEntry::new(lease.cid(), &task));
+                let runner = TaskRunner::new(
+                    task,
+                    vm,
+                    Arc::clone(&self.transport),
+                    Arc::clone(&self.tasks.store),
+                    Arc::clone(&self.registry),
+                    Arc::clone(&self.teardown),
+                );
+                tokio::spawn(runner.run(lease));
+                msg.ack().await
+            }
+            Err(e @ (SentinelError::ClaimFailed { .. } | SentinelError::Json(_))) => {
+                tracing::info!(error = %e, "task cannot be claimed, dropping message");
+                msg.ack().await
+            }
+            Err(e) => {
+                tracing::info!(error = %e, "task not claimed, leaving it queued");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How the operative's run ended.
+#[derive(Debug)]
+enum Ended {
+    /// The operative sent `result`.
+    Completed { output: Value, exit_code: i32 },
+    /// The operative sent `error`.
+    Failed { error: String, exit_code: i32 },
+    /// Firecracker exited before the operative reported.
+    VmExited(ProcessExit),
+}
+
+/// Runs one claimed task in its VM: provisions it, sends the task, relays
+/// what the operative reports and streams its output, then tears the VM
+/// down and publishes its usage.
+///
+/// The runner settles the task unless the VM is killed first. Whoever
+/// killed it (shutdown or `sentinelctl kill`) settles the task and releases
+/// the VM; the runner only releases what it recorded after that.
+pub struct TaskRunner {
+    task: ClaimedTask,
+    vm: Arc<VmEntry>,
+    transport: Arc<dyn Transport>,
+    store: Arc<dyn StateStore>,
+    registry: Arc<VmRegistry>,
+    teardown: Arc<Teardown>,
+    tools: ToolProxy,
+}
+
+impl TaskRunner {
+    #[must_use]
+    pub fn new(
+        task: ClaimedTask,
+        vm: Arc<VmEntry>,
+        transport: Arc<dyn Transport>,
+        store: Arc<dyn StateStore>,
+        registry: Arc<VmRegistry>,
+        teardown: Arc<Teardown>,
+    ) -> Self {
+        Self {
+            task,
+            vm,
+            transport,
+            store,
+            registry,
+            teardown,
+            tools: ToolProxy,
+        }
+    }
+
+    /// Run the task to the end, then hand `lease` back once the VM has
+    /// left the registry, whoever removed it.
+    pub async fn run(self, lease: SlotLease) {
+        let span = self.task.span.clone();
+        self.run_task().instrument(span).await;
+        self.registry.wait_until_gone(self.vm.cid).await;
+        drop(lease);
+    }
+
+    async fn run_task(&self) {
+        let deadlines = PhaseDeadlines::for_profile(self.task.vm_profile());
+        let relay = self.task.relay(Arc::clone(&self.transport));
+        let (logs, shipped) = self.task.log_shipper(Arc::clone(&self.transport)).start();
+        let ended = self.operate(&deadlines, &relay, &logs).await;
+        if self.vm.kill_token().is_cancelled() {
+            tracing::info!(
+                cid = self.vm.cid,
+                "vm killed, task left to whoever killed it"
+            );
+            let leftovers = self.vm.take_resources();
+            if let Err(e) = self.teardown.release(&leftovers).await {
+                tracing::warn!(
+                    cid = self.vm.cid,
+                    error = %e,
+                    "release after kill incomplete"
+                );
+            }
+            return;
+        }
+        match ended {
+            Ok(Ended::Completed { .. } | Ended::Failed { .. }) => {}
+            Ok(Ended::VmExited(exit)) => self.vm_exited(&exit, &relay).await,
+            Err(e) => self.fail(&e, &relay).await,
+        }
+        self.release(&deadlines, &relay, logs, shipped).await;
+    }
+
+    /// Provision, run and collect. A report from the operative is settled
+    /// here, before collection; other endings are left to the caller.
+    async fn operate(
+        &self,
+        deadlines: &PhaseDeadlines,
+        relay: &TaskRelay,
+        logs: &LogSender,
+    ) -> Result<Ended, SentinelError> {
+        self.vm.transition(VmState::Provisioning)?;
+        let (stream, handle) = deadlines
+            .within(Phase::Provision, self.provision(deadlines.provision, logs))
+            .instrument(self.task.provision_span())
+            .await?;
+        self.vm.transition(VmState::Running)?;
+
+        let mut deadline = RunDeadline::for_profile(self.task.vm_profile(), self.task.timeout_at);
+        let mut channel = OperativeChannel::new(stream);
+        let vm_exit = async {
+            match self.teardown.vms.exited(&handle).await {
+                Some(exit) => exit,
+                None => std::future::pending().await,
+            }
+        };
+        let ended = self
+            .drive(&mut channel, &mut deadline, relay, logs, vm_exit)
+            .await?;
+        if matches!(ended, Ended::VmExited(_)) {
+            return Ok(ended);
+        }
+        self.report(&ended, relay).await;
+        self.vm.transition(VmState::Collecting)?;
+        if let Err(e) = deadlines
+            .within(Phase::Collect, collect(&mut channel, logs))
+            .await
+        {
+            tracing::warn!(cid = self.vm.cid, error = %e, "collection cut short");
+        }
+        Ok(ended)
+    }
+
+    /// Overlay, tap, telemetry FIFOs, cgroup and jail (when enabled) and
+    /// VM, through the operative accepting the vsock connection. Each
+    /// resource is recorded on the VM as soon as it exists, so teardown,
+    /// shutdown or crash recovery can release it. Console lines go to `logs`
+    /// too with `logs.relay_console`.
+    async fn provision(
+        &self,
+        connect_timeout: Duration,
+        logs: &LogSender,
+    ) -> Result<(UnixStream, VmHandle), SentinelError> {
+        let config = &self.task.config;
+        let profile = self.task.vm_profile();
+        let task_id = &self.task.task.task_id;
+        let cid = self.vm.cid;
+
+        let overlay = self
+            .teardown
+            .overlays
+            .create(&profile.rootfs_path, task_id)
+            .await?;
+        self.vm
+            .with_resources(|r| r.overlay = Some(overlay.clone()))
+            .await;
+        if matches!(profile.network, NetworkMode::Nat) {
+            let tap = self.teardown.network.create_tap(task_id).await?;
+            self.vm.with_resources(|r| r.tap = Some(tap)).await;
+        }
+
+        let socket = config.vsock_path(cid);
+        let mut fc = FirecrackerConfig::for_profile(config, profile, overlay, cid, socket.clone())?
+            .with_telemetry(Fifos::beside(&socket));
+        if config.cgroup.enabled {
+            let cgroup = VmCgroup::for_vm(&config.cgroup, profile, cid);
+            if !config.jailer.enabled {
+                cgroup.create().await?;
+            }
+            self.vm
+                .with_resources(|r| r.cgroup = Some(cgroup.clone()))
+                .await;
+            fc = fc.with_cgroup(cgroup);
+        }
+        if config.jailer.enabled {
+            let jail = Jail::for_vm(&config.jailer, &config.firecracker_bin, cid)?;
+            self.vm
+                .with_resources(|r| r.jail = Some(jail.clone()))
+                .await;
+            fc = jail.confine(fc).await?;
+            let fifos = fc.host_fifos();
+            self.vm.with_resources(|r| r.fifos = fifos).await;
+        } else {
+            if let Some(dir) = socket.parent() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+            let fifos = fc.host_fifos();
+            self.vm.with_resources(|r| r.fifos = fifos.clone()).await;
+            if let Some(fifos) = fifos {
+                fifos.create().await?;
+            }
+        }
+
+        let console = ConsoleLog::create(&config.console_dir(), task_id, config.console.max_bytes)?;
+        let on_line = Arc::clone(&self.vm);
+        let console_logs = config.logs.relay_console.then(|| logs.clone());
+        let on_telemetry = Arc::clone(&self.vm);
+        let output = VmOutput::new(console)
+            .on_console_line(move |line| {
+                on_line.push_console(line);
+                if let Some(logs) = &console_logs {
+                    logs.send(LogLine::console(line));
+                }
+            })
+            .on_telemetry(move |f| on_telemetry.record_telemetry(|t| f(t)));
+        let handle = self.teardown.vms.create_vm(&fc, output).await?;
+        self.vm
+            .with_resources(|r| r.handle = Some(handle.clone()))
+            .await;
+        tracing::info!(cid, profile = %self.task.profile, "vm booted");
+
+        let stream = connect_guest(&fc.host_socket_path(), OPERATIVE_PORT, connect_timeout).await?;
+        Ok((stream, handle))
+    }
+
+    /// Send the task, then relay progress, ship log lines to `logs`, and
+    /// answer tool calls and extension requests until the operative
+    /// reports, `vm_exit` resolves, or the
+    /// deadline passes. An operative silent for the profile's
+    /// `max_silence_sec` is pinged, and fails the run if it does not answer.
+    async fn drive<S: AsyncRead + AsyncWrite>(
+        &self,
+        channel: &mut OperativeChannel<S>,
+        deadline: &mut RunDeadline,
+        relay: &TaskRelay,
+        logs: &LogSender,
+        vm_exit: impl Future<Output = ProcessExit>,
+    ) -> Result<Ended, SentinelError> {
+        let profile = self.task.vm_profile();
+        let tools = profile
+            .tool_policy
+            .as_ref()
+            .map(|policy| policy.allowed_tools.clone())
+            .unwrap_or_default();
+        let task = self.task.task_message(tools);
+        channel.send(&task).await?;
+
+        let kill = self.vm.kill_token();
+        let mut liveness = Liveness::new(profile.max_silence_sec.map(Duration::from_secs));
+        tokio::pin!(vm_exit);
+        loop {
+            let msg = tokio::select! {
+                msg = channel.recv() => msg?,
+                exit = &mut vm_exit => return Ok(Ended::VmExited(exit)),
+                () = kill.cancelled() => return Err(SentinelError::Vm("vm killed".into())),
+                () = tokio::time::sleep(deadline.remaining()) => return Err(deadline.exceeded()),
+                () = liveness.wait() => match liveness.expired() {
+                    Silence::Ping => {
+                        channel.send(&SentinelMessage::Ping { id: task.id().to_string() }).await?;
+                        continue;
+                    }
+                    Silence::Unresponsive => return Err(liveness.unresponsive()),
+                },
+            };
+            let Some(msg) = msg else {
+                return Err(SentinelError::Vsock(
+                    "operative disconnected before sending a result".into(),
+                ));
+            };
+            liveness.heard();
+            match msg {
+                OperativeMessage::Pong { .. } => {}
+                OperativeMessage::Log {
+                    stream, line, ts, ..
+                } => logs.send(LogLine { stream, line, ts }),
+                OperativeMessage::Progress {
+                    step, status, data, ..
+                } => {
+                    if let Err(e) = relay.progress(&step, &status, data.as_ref()).await {
+                        tracing::warn!(step, error = %e, "progress not relayed");
+                    }
+                }
+                OperativeMessage::ToolCall {
+                    id,
+                    call_id,
+                    tool,
+                    params,
+                } => {
+                    let result = match self.tools.handle_tool_call(&tool, &params).await {
+                        Ok(result) => result,
+                        Err(e) => serde_json::json!({ "error": e.to_string() }),
+                    };
+                    channel
+                        .send(&SentinelMessage::ToolResult {
+                            id,
+                            call_id,
+                            result,
+                        })
+                        .await?;
+                }
+                OperativeMessage::ExtendDeadline {
+                    id,
+                    additional_sec,
+                    reason,
+                } => {
+                    let extension = self.extend(deadline, additional_sec).await;
+                    tracing::info!(
+                        additional_sec,
+                        reason,
+                        %extension,
+                        "deadline extension requested"
+                    );
+                    channel.send(&deadline.reply(&id, extension)).await?;
+                }
+                OperativeMessage::Result {
+                    output, exit_code, ..
+                } => return Ok(Ended::Completed { output, exit_code }),
+                OperativeMessage::Error {
+                    error, exit_code, ..
+                } => return Ok(Ended::Failed { error, exit_code }),
+            }
+        }
+    }
+
+    /// Decide an extension request, recording a grant as the task's
+    /// `timeout_at`. A grant that cannot be recorded is denied.
+    async fn extend(&self, deadline: &mut RunDeadline, additional_sec: u64) -> Extension {
+        let granted = deadline
+            .request_for_task(
+                additional_sec,
+                &self.store,
+                &self.task.task.state_key,
+                &self.task.config.host_id,
+                self.vm.cid,
+            )
+            .await;
+        granted.unwrap_or_else(|e| {
+            tracing::warn!(cid = self.vm.cid, error = %e, "extension not recorded");
+            Extension::Denied("extension could not be recorded")
+        })
+    }
+
+    /// Record the operative's report in the state store and publish it.
+    async fn report(&self, ended: &Ended, relay: &TaskRelay) {
+        let (settled, published) = match ended {
+            Ended::Completed { output, exit_code } => (
+                complete_task(
+                    &self.store,
+                    &self.task.task.state_key,
+                    &self.task.config.host_id,
+                    self.vm.cid,
+                )
+                .await,
+                relay.completed(output, *exit_code).await,
+            ),
+            Ended::Failed { error, exit_code } => (
+                self.settle_failed(error).await,
+                relay.failed(error, Some(*exit_code)).await,
+            ),
+            Ended::VmExited(_) => return,
+        };
+        self.log_settled(settled, published);
+    }
+
+    /// Fail the task because its VM exited before the operative reported.
+    async fn vm_exited(&self, exit: &ProcessExit, relay: &TaskRelay) {
+        let console_tail = self.vm.console_tail(EXIT_CONSOLE_LINES).join("\n");
+        let oom_killed = self
+            .vm
+            .journal_record()
+            .cgroup
+            .and_then(|cgroup| cgroup.usage().ok())
+            .is_some_and(|usage| usage.oom_killed());
+        let kind = ExitKind::classify(
+            exit,
+            &ExitEvidence {
+                result_received: false,
+                console_tail: &console_tail,
+                oom_killed,
+            },
+        );
+        let reason = kind
+            .failure_reason()
+            .unwrap_or("guest_exited_without_result");
+        tracing::warn!(
+            cid = self.vm.cid,
+            ?kind,
+            "vm exited before the operative reported"
+        );
+        if let Err(e) = self.vm.transition(VmState::Failed(reason.to_string())) {
+            tracing::warn!(cid = self.vm.cid, error = %e, "failure not recorded on vm");
+        }
+        let settled = self.settle_failed(reason).await;
+        self.log_settled(settled, relay.vm_exited(kind, exit).await);
+    }
+
+    /// Fail the task because the run could not finish: a phase overran,
+    /// the operative went silent, or provisioning or the channel failed.
+    async fn fail(&self, error: &SentinelError, relay: &TaskRelay) {
+        let (state, reason) = match error {
+            SentinelError::DeadlineExceeded { phase, .. } => {
+                (phase.timeout_state(), phase.timeout_reason().to_string())
+            }
+            SentinelError::Unresponsive { .. } => (
+                Some(VmState::Failed(OPERATIVE_UNRESPONSIVE.to_string())),
+                OPERATIVE_UNRESPONSIVE.to_string(),
+            ),
+            e => (Some(VmState::Failed(e.to_string())), e.to_string()),
+        };
+        tracing::warn!(cid = self.vm.cid, error = %error, "task failed");
+        if let Some(state) = state
+            && let Err(e) = self.vm.transition(state)
+        {
+            tracing::warn!(cid = self.vm.cid, error = %e, "failure not recorded on vm");
+        }
+        let settled = self.settle_failed(&reason).await;
+        self.log_settled(settled, relay.failed(&reason, None).await);
+    }
+
+    async fn settle_failed(&self, error: &str) -> Result<bool, SentinelError> {
+        fail_task(
+            &self.store,
+            &self.task.task.state_key,
+            &self.task.config.host_id,
+            self.vm.cid,
+            error,
+        )
+        .await
+    }
+
+    fn log_settled(
+        &self,
+        settled: Result<bool, SentinelError>,
+        published: Result<(), SentinelError>,
+    ) {
+        match settled {
+            Ok(true) => {}
+            Ok(false) => tracing::info!(cid = self.vm.cid, "task no longer held, left as is"),
+            Err(e) => tracing::warn!(cid = self.vm.cid, error = %e, "task state not updated"),
+        }
+        if let Err(e) = published {
+            tracing::warn!(cid = self.vm.cid, error = %e, "terminal event not published");
+        }
+    }
+
+    /// Tear the VM down within the profile's teardown deadline, deregister
+    /// it and publish its usage, with the totals of the log stream once
+    /// `shipped` has sent its last batch. A VM whose release fails is
+    /// abandoned, so crash recovery retries it on the next start.
+    async fn release(
+        &self,
+        deadlines: &PhaseDeadlines,
+        relay: &TaskRelay,
+        logs: LogSender,
+        shipped: JoinHandle<LogStats>,
+    ) {
+        let cid = self.vm.cid;
+        let resources = self.vm.take_resources();
+        let cgroup_usage = resources.cgroup.as_ref().and_then(|cgroup| {
+            cgroup
+                .usage()
+                .inspect_err(|e| tracing::debug!(cid, error = %e, "vm cgroup usage unreadable"))
+                .ok()
+        });
+        if let Err(e) = self.vm.transition(VmState::Teardown) {
+            tracing::warn!(cid, error = %e, "teardown not recorded on vm");
+        }
+        match self
+            .teardown
+            .release_within(&resources, deadlines.teardown)
+            .await
+        {
+            Ok(()) => {
+                if let Err(e) = self.vm.transition(VmState::Idle) {
+                    tracing::warn!(cid, error = %e, "idle not recorded on vm");
+                }
+                if let Err(e) = self.registry.remove(cid) {
+                    tracing::warn!(cid, error = %e, "vm left registered");
+                    self.registry.abandon(cid);
+                }
+            }
+            Err(e) => {
+                tracing::warn!(cid, error = %e, "teardown incomplete, left for recovery");
+                self.vm.with_resources(|r| *r = resources).await;
+                self.registry.abandon(cid);
+            }
+        }
+
+        // The console capture holds the other sender until the VM is gone.
+        drop(logs);
+        let stats = shipped
+            .await
+            .inspect_err(|e| tracing::warn!(cid, error = %e, "log shipper failed"))
+            .ok();
+        let mut usage = TaskUsage::for_vm(&self.vm, stats);
+        if let Some(cgroup_usage) = cgroup_usage {
+            usage = usage.with_cgroup(cgroup_usage);
+        }
+        if let Err(e) = relay.usage(&usage).await {
+            tracing::warn!(cid, error = %e, "usage not published");
+        }
+    }
+}
+
+/// Ship what the operative logs after its report, until it closes the
+/// connection as the guest shuts down.
+async fn collect<S: AsyncRead + AsyncWrite>(
+    channel: &mut OperativeChannel<S>,
+    logs: &LogSender,
+) -> Result<(), SentinelError> {
+    while let Some(msg) = channel.recv().await? {
+        if let OperativeMessage::Log {
+            stream, line, ts, ..
+        } = msg
+        {
+            logs.send(LogLine { stream, line, ts });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExtensionPolicy;
+    use crate::handler::tests::claimed;
+    use crate::mode::ModeControl;
+    use crate::relay::tests::RecordingTransport;
+    use crate::reload::LiveConfig;
+    use crate::testing::MemoryStore;
+    use bytes::Bytes;
+    use gbe_nexus::Envelope;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf, WriteHalf};
+
+    const KEY: &str = "gbe:state:tasks:shell:t1";
+
+    fn runner(
+        policy: Option<ExtensionPolicy>,
+        worker: &str,
+    ) -> (Arc<RecordingTransport>, Arc<MemoryStore>, TaskRunner) {
+        let mut task = claimed(Some("trace-1"));
+        let mut config = task.config.as_ref().clone();
+        config.profiles.get_mut("shell").unwrap().extension_policy = policy;
+        task.config = Arc::new(config);
+        let store = Arc::new(MemoryStore::default().with(
+            KEY,
+            &[
+                ("state", "running"),
+                ("worker", worker),
+                ("timeout_at", &task.timeout_at.to_string()),
+            ],
+        ));
+        let transport = Arc::new(RecordingTransport::default());
+        let registry = Arc::new(VmRegistry::new());
+        let vm = registry.insert(VmEntry::new(3, &task));
+        let runner = TaskRunner::new(
+            task,
+            vm,
+            Arc::clone(&transport) as _,
+            Arc::clone(&store) as _,
+            registry,
+            Arc::new(Teardown::new("/fc".into(), "/o".into())),
+        );
+        (transport, store, runner)
+    }
+
+    /// The operative's end of the channel.
+    struct Guest {
+        lines: tokio::io::Lines<BufReader<ReadHalf<DuplexStream>>>,
+        writer: WriteHalf<DuplexStream>,
+    }
+
+    impl Guest {
+        fn connect() -> (OperativeChannel<DuplexStream>, Self) {
+            let (host, guest) = tokio::io::duplex(4096);
+            let (reader, writer) = tokio::io::split(guest);
+            let guest = Self {
+                lines: BufReader::new(reader).lines(),
+                writer,
+            };
+            (OperativeChannel::new(host), guest)
+        }
+
+        async fn send(&mut self, json: Value) {
+            let mut line = serde_json::to_vec(&json).unwrap();
+            line.push(b'\n');
+            self.writer.write_all(&line).await.unwrap();
+        }
+
+        async fn recv(&mut self) -> Value {
+            serde_json::from_str(&self.lines.next_line().await.unwrap().unwrap()).unwrap()
+        }
+    }
+
+    async fn extend(runner: &TaskRunner, additional_sec: u64) -> (Value, Ended) {
+        let relay = runner.task.relay(Arc::new(RecordingTransport::default()));
+        let (logs, _shipped) = runner
+            .task
+            .log_shipper(Arc::new(RecordingTransport::default()))
+            .start();
+        let mut deadline =
+            RunDeadline::for_profile(runner.task.vm_profile(), runner.task.timeout_at);
+        let (mut channel, mut guest) = Guest::connect();
+        let operative = async {
+            assert_eq!(guest.recv().await["type"], "task");
+            guest
+                .send(serde_json::json!({
+                    "type": "extend_deadline", "id": "t1",
+                    "additional_sec": additional_sec, "reason": "slow",
+                }))
+                .await;
+            let reply = guest.recv().await;
+            guest
+                .send(serde_json::json!({
+                    "type": "result", "id": "t1", "output": {"ok": true}, "exit_code": 0,
+                }))
+                .await;
+            reply
+        };
+        let drive = runner.drive(
+            &mut channel,
+            &mut deadline,
+            &relay,
+            &logs,
+            std::future::pending(),
+        );
+        let (ended, reply) = tokio::join!(drive, operative);
+        (reply, ended.unwrap())
+    }
+
+    #[tokio::test]
+    async fn extension_grant_moves_task_timeout() {
+        let policy = ExtensionPolicy {
+            max_total_sec: 60,
+            max_requests: 2,
+        };
+        let (_, store, runner) = runner(Some(policy), "h1:3");
+        let (reply, ended) = extend(&runner, 90).await;
+
+        assert!(matches!(ended, Ended::Completed { exit_code: 0, .. }));
+        assert_eq!(reply["type"], "extend_deadline_result");
+        assert_eq!(reply["granted"], true);
+        assert_eq!(reply["additional_sec"], 60);
+        let timeout_at = runner.task.timeout_at + 60_000;
+        assert_eq!(reply["timeout_at"], timeout_at);
+        assert_eq!(store.field(KEY, "timeout_at"), Some(timeout_at.to_string()));
+    }
+
+    #[tokio::test]
+    async fn extension_for_a_task_no_longer_held_is_denied() {
+        let policy = ExtensionPolicy {
+            max_total_sec: 60,
+            max_requests: 2,
+        };
+        let (_, store, runner) = runner(Some(policy), "h2:7");
+        let (reply, _) = extend(&runner, 30).await;
+
+        assert_eq!(reply["granted"], false);
+        assert_eq!(reply["reason"], "task no longer held");
+        assert_eq!(reply["timeout_at"], runner.task.timeout_at);
+        assert_eq!(
+            store.field(KEY, "timeout_at"),
+            Some(runner.task.timeout_at.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn progress_is_relayed_and_failed_tool_calls_get_an_error() {
+        let (transport, _, runner) = runner(None, "h1:3");
+        let relay = runner.task.relay(Arc::clone(&transport) as _);
+        let (logs, _shipped) = runner.task.log_shipper(Arc::clone(&transport) as _).start();
+        let mut deadline =
+            RunDeadline::for_profile(runner.task.vm_profile(), runner.task.timeout_at);
+        let (mut channel, mut guest) = Guest::connect();
+        let operative = async {
+            guest.recv().await;
+            guest
+                .send(serde_json::json!({
+                    "type": "progress", "id": "t1", "step": "fetch", "status": "running",
+                }))
+                .await;
+            guest
+                .send(serde_json::json!({
+                    "type": "tool_call", "id": "t1", "call_id": "c1",
+                    "tool": "grep", "params": {},
+                }))
+                .await;
+            let answer = guest.recv().await;
+            guest
+                .send(serde_json::json!({
+                    "type": "error", "id": "t1", "error": "no tools", "exit_code": 2,
+                }))
+                .await;
+            answer
+        };
+        let drive = runner.drive(
+            &mut channel,
+            &mut deadline,
+            &relay,
+            &logs,
+            std::future::pending(),
+        );
+        let (ended, answer) = tokio::join!(drive, operative);
+
+        assert!(matches!(
+            ended.unwrap(),
+            Ended::Failed { ref error, exit_code: 2 } if error == "no tools"
+        ));
+        assert_eq!(answer["type"], "tool_result");
+        assert_eq!(answer["call_id"], "c1");
+        assert!(answer["result"]["error"].is_string());
+        let published = transport.published.lock().unwrap();
+        assert_eq!(published[0].0, "gbe.tasks.shell.progress");
+        assert_eq!(published[0].1["step"], "fetch");
+        assert_eq!(published[0].2.as_deref(), Some("trace-1"));
+    }
+
+    #[tokio::test]
+    async fn vm_exit_ends_the_run() {
+        let (_, _, runner) = runner(None, "h1:3");
+        let relay = runner.task.relay(Arc::new(RecordingTransport::default()));
+        let (logs, _shipped) = runner
+            .task
+            .log_shipper(Arc::new(RecordingTransport::default()))
+            .start();
+        let mut deadline =
+            RunDeadline::for_profile(runner.task.vm_profile(), runner.task.timeout_at);
+        let (mut channel, _guest) = Guest::connect();
+        let exit = ProcessExit {
+            code: Some(0),
+            signal: None,
+            killed_by_sentinel: false,
+            at_ms: 1,
+        };
+        let ended = runner
+            .drive(&mut channel, &mut deadline, &relay, &logs, async { exit })
+            .await
+            .unwrap();
+        assert!(matches!(ended, Ended::VmExited(exit) if exit.code == Some(0)));
+    }
+
+    #[tokio::test]
+    async fn usage_carries_shipped_log_stats() {
+        let (transport, _, runner) = runner(None, "h1:3");
+        let relay = runner.task.relay(Arc::clone(&transport) as _);
+        let (logs, shipped) = runner.task.log_shipper(Arc::clone(&transport) as _).start();
+        let mut deadline =
+            RunDeadline::for_profile(runner.task.vm_profile(), runner.task.timeout_at);
+        let (mut channel, mut guest) = Guest::connect();
+        let operative = async {
+            guest.recv().await;
+            for line in ["building", "done"] {
+                guest
+                    .send(serde_json::json!({
+                        "type": "log", "id": "t1", "stream": "stdout", "line": line, "ts": 1,
+                    }))
+                    .await;
+            }
+            guest
+                .send(serde_json::json!({
+                    "type": "result", "id": "t1", "output": {}, "exit_code": 0,
+                }))
+                .await;
+            guest
+                .send(serde_json::json!({
+                    "type": "log", "id": "t1", "stream": "stderr", "line": "bye", "ts": 2,
+                }))
+                .await;
+            drop(guest);
+        };
+        let run = async {
+            let ended = runner
+                .drive(
+                    &mut channel,
+                    &mut deadline,
+                    &relay,
+                    &logs,
+                    std::future::pending(),
+                )
+                .await;
+            collect(&mut channel, &logs).await.unwrap();
+            ended
+        };
+        let (ended, ()) = tokio::join!(run, operative);
+        assert!(matches!(ended.unwrap(), Ended::Completed { .. }));
+
+        let deadlines = PhaseDeadlines::for_profile(runner.task.vm_profile());
+        runner.release(&deadlines, &relay, logs, shipped).await;
+
+        let published = transport.published.lock().unwrap();
+        let lines: Vec<_> = published
+            .iter()
+            .filter(|(subject, ..)| subject == "gbe.tasks.shell.logs")
+            .flat_map(|(_, body, _)| body["lines"].as_array().unwrap().clone())
+            .map(|line| line["line"].clone())
+            .collect();
+        assert_eq!(lines, ["building", "done", "bye"]);
+        let (subject, usage, trace_id) = published.last().unwrap();
+        assert_eq!(subject, "gbe.tasks.shell.usage");
+        assert_eq!(trace_id.as_deref(), Some("trace-1"));
+        assert_eq!(usage["logs"]["lines"], 3);
+        assert_eq!(usage["logs"]["bytes"], 15);
+        assert_eq!(usage["logs"]["dropped"], 0);
+    }
+
+    #[test]
+    fn lease_returns_slot_and_cid_when_dropped() {
+        let slots = Arc::new(SlotTracker::new(2));
+        let cids = Arc::new(CidPool::new(2));
+        let first = SlotLease::take(&slots, &cids).unwrap();
+        let second = SlotLease::take(&slots, &cids).unwrap();
+        assert_eq!((first.cid(), second.cid()), (FIRST_CID, FIRST_CID + 1));
+        assert!(SlotLease::take(&slots, &cids).is_none());
+
+        drop(first);
+        assert_eq!(slots.available(), 1);
+        assert_eq!(SlotLease::take(&slots, &cids).unwrap().cid(), FIRST_CID);
+    }
+
+    struct Delivered {
+        envelope: Envelope,
+        acked: AtomicBool,
+    }
+
+    #[async_trait]
+    impl Message for Delivered {
+        fn envelope(&self) -> &Envelope {
+            &self.envelope
+        }
+        async fn ack(&self) -> Result<(), TransportError> {
+            self.acked.store(true, Ordering::Release);
+            Ok(())
+        }
+    }
+
+    fn queue(slots: u32) -> (Arc<VmRegistry>, QueueHandler) {
+        let task = claimed(None);
+        let store = Arc::new(MemoryStore::default().with(KEY, &[("state", "pending")]));
+        let registry = Arc::new(VmRegistry::new());
+        let handler = QueueHandler::new(
+            TaskHandler::new(
+                Arc::new(LiveConfig::new(task.config.as_ref().clone(), None)),
+                store as _,
+                Arc::new(ModeControl::new()),
+            ),
+            Arc::new(RecordingTransport::default()),
+            Arc::clone(&registry),
+            Arc::new(Teardown::new("/fc".into(), "/o".into())),
+            Arc::new(SlotTracker::new(slots)),
+            Arc::new(CidPool::new(slots)),
+        );
+        (registry, handler)
+    }
+
+    fn delivered(payload: &str) -> Delivered {
+        Delivered {
+            envelope: Envelope {
+                payload: Bytes::from(payload.to_string()),
+                ..Envelope::default()
+            },
+            acked: AtomicBool::new(false),
+        }
+    }
+
+    #[tokio::test]
+    async fn full_host_leaves_tasks_queued() {
+        let (registry, handler) = queue(0);
+        let msg = delivered(
+            r#"{"task_id":"t1","task_type":"shell","state_key":"gbe:state:tasks:shell:t1"}"#,
+        );
+        handler.handle(&msg).await.unwrap();
+        assert!(!msg.acked.load(Ordering::Acquire));
+        assert!(registry.is_empty());
+    }
+
+    #[tokio::test]
+    async fn malformed_tasks_are_dropped() {
+        let (registry, handler) = queue(1);
+        let msg = delivered("not json");
+        handler.handle(&msg).await.unwrap();
+        assert!(msg.acked.load(Ordering::Acquire));
+        assert!(registry.is_empty());
+    }
+}
@@ -1,12 +1,28 @@
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
 
 use gbe_nexus::Transport;
 use gbe_state_store::StateStore;
 use tokio_util::sync::CancellationToken;
 
-use crate::config::SentinelConfig;
+use crate::admin::{self, AdminServer};
+use crate::claim::now_millis;
+use crate::config::{HostResources, SentinelConfig};
+use crate::control::{ControlHandler, control_subject};
 use crate::error::SentinelError;
+use crate::handler::TaskHandler;
+use crate::health::HealthPublisher;
+use crate::journal::RunJournal;
+use crate::mode::{HostMode, ModeControl};
+use crate::recovery::Recovery;
+use crate::registry::VmRegistry;
+use crate::reload::LiveConfig;
+use crate::runner::{CidPool, QueueHandler};
+use crate::shutdown::Shutdown;
+use crate::vm::console;
+use crate::vm::teardown::Teardown;
 
 /// Tracks VM slot usage with atomic operations. Safe to share across
 /// concurrent task handlers without external locking.
@@ -47,50 +63,308 @@ impl SlotTracker {
     }
 }
 
-#[allow(dead_code)]
 pub struct Sentinel {
-    pub(crate) config: SentinelConfig,
+    pub(crate) config: Arc<LiveConfig>,
     pub(crate) transport: Arc<dyn Transport>,
     pub(crate) store: Arc<dyn StateStore>,
-    pub(crate) slots: SlotTracker,
+    pub(crate) slots: Arc<SlotTracker>,
+    pub(crate) cids: Arc<CidPool>,
+    pub(crate) registry: Arc<VmRegistry>,
+    pub(crate) mode: Arc<ModeControl>,
+    pub(crate) teardown: Arc<Teardown>,
 }
 
 impl Sentinel {
     /// # Errors
     ///
-    /// Returns `SentinelError::Config` if config validation fails.
+    /// Returns `SentinelError::Config` if config validation fails or a
+    /// profile does not fit on this host.
     pub async fn new(
         config: SentinelConfig,
         transport: Arc<dyn Transport>,
         store: Arc<dyn StateStore>,
     ) -> Result<Self, SentinelError> {
-        config.validate()?;
-        let slots = SlotTracker::new(config.slots);
-        Ok(Self {
-            config,
+        config.validate_for_host(&HostResources::detect())?;
+        Ok(Self::from_live(
+            LiveConfig::new(config, None),
+            transport,
+            store,
+        ))
+    }
+
+    /// Like [`new`](Self::new), loading config from a TOML file. The file
+    /// is re-read on SIGHUP.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` if the file fails to load or validate,
+    /// or a profile does not fit on this host.
+    pub async fn load(
+        path: &Path,
+        transport: Arc<dyn Transport>,
+        store: Arc<dyn StateStore>,
+    ) -> Result<Self, SentinelError> {
+        let config = SentinelConfig::load(path)?;
+        config.validate_for_host(&HostResources::detect())?;
+        Ok(Self::from_live(
+            LiveConfig::new(config, Some(path.to_path_buf())),
+            transport,
+            store,
+        ))
+    }
+
+    /// Build from an already loaded and validated config, e.g. when the
+    /// caller needed the config to connect `transport` and `store` first.
+    #[must_use]
+    pub fn from_live(
+        config: LiveConfig,
+        transport: Arc<dyn Transport>,
+        store: Arc<dyn StateStore>,
+    ) -> Self {
+        let snapshot = config.snapshot();
+        let slots = Arc::new(SlotTracker::new(snapshot.slots));
+        let cids = Arc::new(CidPool::new(snapshot.slots));
+        let teardown = Teardown::new(
+            snapshot.firecracker_bin.clone(),
+            snapshot.overlay_dir.clone(),
+        );
+        Self {
+            config: Arc::new(config),
             transport,
             store,
             slots,
-        })
+            cids,
+            registry: Arc::new(VmRegistry::new()),
+            mode: Arc::new(ModeControl::new()),
+            teardown: Arc::new(teardown),
+        }
+    }
+
+    /// Live config, for admin-triggered reloads and snapshots.
+    #[must_use]
+    pub fn config(&self) -> &Arc<LiveConfig> {
+        &self.config
     }
 
+    /// VMs running on this host.
+    #[must_use]
+    pub fn registry(&self) -> &Arc<VmRegistry> {
+        &self.registry
+    }
+
+    /// Cordon/drain state, consulted before every claim.
+    #[must_use]
+    pub fn mode(&self) -> &Arc<ModeControl> {
+        &self.mode
+    }
+
+    /// Run until `token` is cancelled or a drain completes. On
+    /// cancellation, running tasks get `shutdown.drain_timeout_secs` to
+    /// finish before they are preempted and requeued.
+    ///
     /// # Errors
     ///
-    /// Returns `SentinelError` on transport or state store failures.
-    pub async fn run(&self, _token: CancellationToken) -> Result<(), SentinelError> {
-        // TODO: implement run loop
-        // 1. Subscribe to each configured task type queue
-        // 2. Start beacon (heartbeat + capacity publisher)
-        // 3. Start vsock listener for all VMs
-        // 4. Wait for cancellation
-        // 5. Graceful shutdown: stop accepting, drain running VMs, unsubscribe
+    /// Returns `SentinelError` on transport or state store failures, or
+    /// `SentinelError::Io` if the run journal cannot be created or the
+    /// admin socket cannot be bound.
+    pub async fn run(&self, token: CancellationToken) -> Result<(), SentinelError> {
+        // A completed drain cancels only this sentinel, not the caller's token.
+        let token = token.child_token();
+        let config = self.config.snapshot();
+        let journal = Arc::new(self.recover(&config).await?);
+        self.registry.attach_journal(Arc::clone(&journal));
+        let journal_sync = tokio::spawn(Arc::clone(&journal).sync_loop(token.clone()));
+
+        let shutdown = Arc::new(Shutdown::new(
+            &config,
+            Arc::clone(&self.store),
+            Arc::clone(&self.registry),
+            Arc::clone(&self.teardown),
+        ));
+        let listener = admin::bind(&config.admin_socket)?;
+        let admin = Arc::new(AdminServer::new(
+            Arc::clone(&self.registry),
+            Arc::clone(&self.mode),
+            Arc::clone(&self.config),
+            Arc::clone(&shutdown),
+        ));
+        let admin = tokio::spawn(admin.serve(listener, config.admin_socket.clone(), token.clone()));
+        let reload = tokio::spawn(Arc::clone(&self.config).reload_on_sighup(token.clone()));
+        let console_sweep = tokio::spawn(console::sweep_loop(
+            config.console_dir(),
+            Duration::from_secs(config.console.retention_secs),
+            Arc::clone(&self.registry),
+            token.clone(),
+        ));
+
+        let control = if let Some(key) = &config.control_key {
+            let handler =
+                ControlHandler::new(&config.host_id, key.as_bytes(), Arc::clone(&self.mode));
+            let subject = control_subject(&config.host_id);
+            let group = format!("sentinel-{}", config.host_id);
+            Some(
+                self.transport
+                    .subscribe(&subject, &group, Box::new(handler), None)
+                    .await?,
+            )
+        } else {
+            tracing::warn!("control_key not set; bus control commands disabled");
+            None
+        };
+
+        let beacon = Arc::new(HealthPublisher::new(
+            Arc::clone(&self.transport),
+            &config.host_id,
+            Arc::clone(&self.mode),
+        ));
+        let capacity = tokio::spawn(Arc::clone(&beacon).capacity_loop(
+            self.registry.subscribe(),
+            Arc::clone(&self.registry),
+            config.slots,
+            token.clone(),
+        ));
+        let beacon = tokio::spawn(beacon.beacon_loop(
+            Duration::from_secs(config.heartbeat_interval_secs),
+            token.clone(),
+        ));
+        let drain = tokio::spawn(watch_drain(
+            Arc::clone(&self.mode),
+            Arc::clone(&self.registry),
+            Arc::clone(&shutdown),
+            token.clone(),
+        ));
+
+        let mut queues = Vec::new();
+        for task_type in &config.task_types {
+            let handler = QueueHandler::new(
+                TaskHandler::new(
+                    Arc::clone(&self.config),
+                    Arc::clone(&self.store),
+                    Arc::clone(&self.mode),
+                ),
+                Arc::clone(&self.transport),
+                Arc::clone(&self.registry),
+                Arc::clone(&self.teardown),
+                Arc::clone(&self.slots),
+                Arc::clone(&self.cids),
+            );
+            let subject = format!("gbe.tasks.{task_type}.queue");
+            let group = format!("{task_type}-workers");
+            queues.push(
+                self.transport
+                    .subscribe(&subject, &group, Box::new(handler), None)
+                    .await?,
+            );
+        }
+
+        token.cancelled().await;
+        shutdown.run(&self.mode).await;
+        for queue in queues {
+            queue.unsubscribe().await?;
+        }
+        if let Some(control) = control {
+            control.unsubscribe().await?;
+        }
+        drain.await?;
+        beacon.await?;
+        capacity.await?;
+        reload.await??;
+        console_sweep.await?;
+        admin.await?;
+        journal_sync.await?;
         Ok(())
     }
+
+    /// Open the journal and clean up after a previous run that did not
+    /// shut down cleanly. Only VMs fully cleaned up, with their task
+    /// settled, are marked finished; the rest stay unfinished and are
+    /// retried on the next start.
+    async fn recover(&self, config: &SentinelConfig) -> Result<RunJournal, SentinelError> {
+        let path = config.journal_path();
+        let orphans = RunJournal::unfinished(&path)?;
+        let journal = RunJournal::open(&path, &config.journal)?;
+        if !orphans.is_empty() {
+            let recovery =
+                Recovery::new(config, Arc::clone(&self.store), Arc::clone(&self.teardown));
+            let report = recovery.reconcile(&orphans).await;
+            tracing::warn!("recovered from unclean shutdown: {report}");
+            for outcome in &report.outcomes {
+                if outcome.is_clean() {
+                    journal.finished(outcome.cid, &outcome.task_id)?;
+                } else {
+                    tracing::warn!(
+                        cid = outcome.cid,
+                        task_id = %outcome.task_id,
+                        "orphan left unfinished, retried on next start"
+                    );
+                }
+            }
+            journal.sync().await?;
+        }
+        Ok(journal)
+    }
+}
+
+/// While the host is draining, wait for every VM to be torn down, then
+/// cancel `token` so the sentinel exits cleanly. VMs still running at the
+/// drain deadline are preempted and their tasks requeued.
+async fn watch_drain(
+    mode: Arc<ModeControl>,
+    registry: Arc<VmRegistry>,
+    shutdown: Arc<Shutdown>,
+    token: CancellationToken,
+) {
+    let mut mode_rx = mode.subscribe();
+    loop {
+        let current = mode_rx.borrow_and_update().clone();
+        if let HostMode::Draining { deadline_ms } = current {
+            tracing::info!(vms = registry.len(), "draining");
+            let deadline = async {
+                match deadline_ms {
+                    Some(ms) => {
+                        let left = ms.saturating_sub(now_millis());
+                        tokio::time::sleep(Duration::from_millis(left)).await;
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                () = token.cancelled() => return,
+                () = registry.wait_until_empty() => {
+                    tracing::info!("drain complete, shutting down");
+                    token.cancel();
+                    return;
+                }
+                () = deadline => {
+                    tracing::warn!(vms = registry.len(), "drain deadline passed, preempting");
+                    shutdown.preempt_all().await;
+                    tokio::select! {
+                        () = token.cancelled() => return,
+                        () = registry.wait_until_empty() => {
+                            tracing::info!("drain complete, shutting down");
+                            token.cancel();
+                            return;
+                        }
+                        result = mode_rx.changed() => if result.is_err() { return },
+                    }
+                }
+                result = mode_rx.changed() => if result.is_err() { return },
+            }
+        } else {
+            tokio::select! {
+                () = token.cancelled() => return,
+                result = mode_rx.changed() => if result.is_err() { return },
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::PreemptAction;
+    use crate::registry::tests::entry;
+    use crate::shutdown::tests::shutdown;
 
     #[test]
     fn new_tracker_has_full_capacity() {
@@ -122,6 +396,79 @@ mod tests {
         assert!(t.try_claim());
     }
 
+    #[tokio::test]
+    async fn drain_exits_once_empty() {
+        let mode = Arc::new(ModeControl::new());
+        let (_, registry, shutdown, _dir) = shutdown(30, PreemptAction::Requeue);
+        registry.insert(entry(3, "t3"));
+        let token = CancellationToken::new();
+        let watcher = tokio::spawn(watch_drain(
+            Arc::clone(&mode),
+            Arc::clone(&registry),
+            shutdown,
+            token.clone(),
+        ));
+
+        mode.set(HostMode::Cordoned);
+        tokio::task::yield_now().await;
+        assert!(!token.is_cancelled());
+
+        mode.set(HostMode::Draining { deadline_ms: None });
+        tokio::task::yield_now().await;
+        assert!(!token.is_cancelled());
+        registry.remove(3).unwrap();
+        watcher.await.unwrap();
+        assert!(token.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn uncordon_cancels_drain() {
+        let mode = Arc::new(ModeControl::new());
+        let (_, registry, shutdown, _dir) = shutdown(30, PreemptAction::Requeue);
+        registry.insert(entry(3, "t3"));
+        let token = CancellationToken::new();
+        let watcher = tokio::spawn(watch_drain(
+            Arc::clone(&mode),
+            Arc::clone(&registry),
+            shutdown,
+            token.clone(),
+        ));
+        mode.set(HostMode::Draining { deadline_ms: None });
+        tokio::task::yield_now().await;
+        mode.set(HostMode::Active);
+        tokio::task::yield_now().await;
+        registry.remove(3).unwrap();
+        tokio::task::yield_now().await;
+        assert!(!token.is_cancelled());
+        token.cancel();
+        watcher.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drain_deadline_preempts_remaining_vms() {
+        let mode = Arc::new(ModeControl::new());
+        let (mem, registry, shutdown, _dir) = shutdown(30, PreemptAction::Requeue);
+        let vm = registry.insert(entry(3, "t1"));
+        let token = CancellationToken::new();
+        let watcher = tokio::spawn(watch_drain(
+            Arc::clone(&mode),
+            Arc::clone(&registry),
+            shutdown,
+            token.clone(),
+        ));
+        mode.set(HostMode::Draining {
+            deadline_ms: Some(now_millis() + 5_000),
+        });
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        assert!(vm.kill_token().is_cancelled());
+        watcher.await.unwrap();
+        assert!(token.is_cancelled());
+        assert_eq!(
+            mem.field("gbe:state:tasks:shell:t1", "state").as_deref(),
+            Some("pending")
+        );
+    }
+
     #[test]
     fn zero_slots_never_claims() {
         let t = SlotTracker::new(0);
@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use gbe_state_store::StateStore;
+use tokio::task::JoinSet;
+
+use crate::claim::{fail_task, now_millis, requeue_task};
+use crate::config::{PreemptAction, SentinelConfig, ShutdownConfig};
+use crate::error::SentinelError;
+use crate::mode::{HostMode, ModeControl};
+use crate::registry::{VmEntry, VmRegistry};
+use crate::vm::deadline::PhaseDeadlines;
+use crate::vm::lifecycle::VmState;
+use crate::vm::teardown::Teardown;
+
+/// Reason recorded on tasks handed back to the queue.
+pub const PREEMPTED: &str = "preempted";
+
+/// Error recorded on tasks whose VM an operator killed.
+pub const KILLED: &str = "killed by operator";
+
+/// Graceful shutdown policy.
+///
+/// 1. Stop claiming (mode becomes draining)
+/// 2. Wait up to `drain_timeout_secs` for running VMs to finish
+/// 3. Preempt the rest: snapshot or kill, release overlay and tap, and CAS
+///    the task back to `pending` with reason `preempted`
+///
+/// `sentinelctl kill` stops a VM the same way but fails its task.
+pub struct Shutdown {
+    store: Arc<dyn StateStore>,
+    registry: Arc<VmRegistry>,
+    teardown: Arc<Teardown>,
+    host_id: String,
+    config: ShutdownConfig,
+    snapshot_dir: PathBuf,
+    /// Teardown deadline per profile.
+    teardown_limits: HashMap<String, Duration>,
+}
+
+impl Shutdown {
+    #[must_use]
+    pub fn new(
+        config: &SentinelConfig,
+        store: Arc<dyn StateStore>,
+        registry: Arc<VmRegistry>,
+        teardown: Arc<Teardown>,
+    ) -> Self {
+        Self {
+            store,
+            registry,
+            teardown,
+            host_id: config.host_id.clone(),
+            config: config.shutdown.clone(),
+            snapshot_dir: config.snapshot_dir(),
+            teardown_limits: config
+                .profiles
+                .iter()
+                .map(|(name, profile)| {
+                    (name.clone(), PhaseDeadlines::for_profile(profile).teardown)
+                })
+                .collect(),
+        }
+    }
+
+    /// Teardown deadline for `profile`. A profile dropped by a reload gets
+    /// the longest configured deadline.
+    fn teardown_limit(&self, profile: &str) -> Duration {
+        self.teardown_limits
+            .get(profile)
+            .copied()
+            .unwrap_or_else(|| {
+                self.teardown_limits
+                    .values()
+                    .copied()
+                    .max()
+                    .unwrap_or_default()
+            })
+    }
+
+    /// Drain the host, preempting whatever is still running at the deadline.
+    /// An earlier deadline from an operator drain is kept.
+    pub async fn run(self: &Arc<Self>, mode: &ModeControl) {
+        let timeout_ms = self.config.drain_timeout_secs.saturating_mul(1000);
+        let mut deadline_ms = now_millis().saturating_add(timeout_ms);
+        if let HostMode::Draining {
+            deadline_ms: Some(earlier),
+        } = mode.current()
+        {
+            deadline_ms = deadline_ms.min(earlier);
+        }
+        mode.set(HostMode::Draining {
+            deadline_ms: Some(deadline_ms),
+        });
+
+        let left = Duration::from_millis(deadline_ms.saturating_sub(now_millis()));
+        if tokio::time::timeout(left, self.registry.wait_until_empty())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                vms = self.registry.len(),
+                "shutdown deadline passed, preempting"
+            );
+            self.preempt_all().await;
+        }
+    }
+
+    /// Preempt every VM still registered, all at once, so the drain takes
+    /// one teardown deadline rather than one per VM.
+    pub async fn preempt_all(self: &Arc<Self>) {
+        let mut preempts = JoinSet::new();
+        for vm in self.registry.list() {
+            let shutdown = Arc::clone(self);
+            preempts.spawn(async move {
+                if let Err(e) = shutdown.preempt(&vm).await {
+                    tracing::error!(cid = vm.cid, task_id = %vm.task_id, error = %e, "preempt failed");
+                }
+            });
+        }
+        while let Some(joined) = preempts.join_next().await {
+            if let Err(e) = joined {
+                tracing::error!(error = %e, "preempt task panicked");
+            }
+        }
+    }
+
+    /// Stop one VM and hand its task back to the queue. If its release
+    /// fails, the VM is left unfinished in the journal for crash recovery.
+    ///
+    /// # Errors
+    ///
+    /// Returns a store error if the task could not be requeued.
+    pub async fn preempt(&self, vm: &VmEntry) -> Result<(), SentinelError> {
+        self.stop(vm, Settle::Requeue).await
+    }
+
+    /// Stop one VM on an operator's request and fail its task with
+    /// [`KILLED`]. As with [`preempt`](Self::preempt), a failed release
+    /// leaves the VM unfinished in the journal.
+    ///
+    /// # Errors
+    ///
+    /// Returns a store error if the task could not be marked failed.
+    pub async fn kill(&self, vm: &VmEntry) -> Result<(), SentinelError> {
+        self.stop(vm, Settle::Fail).await
+    }
+
+    /// Kill the VM, release its resources and settle its task. The VM is
+    /// deregistered even if settling fails. If its release fails, the
+    /// resources are journaled again and the VM is left unfinished, so
+    /// crash recovery retries the release on the next start.
+    ///
+    /// A VM still active fails first, one that already failed or timed out
+    /// goes straight to teardown, and one that never started provisioning
+    /// stays idle. Any other state, such as a VM already in teardown, is an
+    /// illegal transition and is returned without touching the VM.
+    async fn stop(&self, vm: &VmEntry, settle: Settle) -> Result<(), SentinelError> {
+        let reason = settle.reason();
+        let provisioned = match vm.state() {
+            VmState::Idle => false,
+            VmState::Failed(_) | VmState::Timeout => true,
+            _ => {
+                vm.transition(VmState::Failed(reason.to_string()))?;
+                true
+            }
+        };
+        vm.kill();
+        let resources = vm.take_resources();
+
+        let mut extra = Vec::new();
+        if settle == Settle::Requeue
+            && self.config.on_timeout == PreemptAction::Snapshot
+            && let Some(handle) = &resources.handle
+        {
+            let dest = self.snapshot_dir.join(&vm.task_id);
+            match self.teardown.vms.snapshot_vm(handle, &dest).await {
+                Ok(path) => {
+                    extra.push((
+                        "snapshot".to_string(),
+                        Bytes::from(path.display().to_string()),
+                    ));
+                }
+                Err(e) => {
+                    tracing::warn!(cid = vm.cid, error = %e, "snapshot failed, requeueing from scratch");
+                }
+            }
+        }
+
+        if provisioned && let Err(e) = vm.transition(VmState::Teardown) {
+            vm.with_resources(|r| *r = resources).await;
+            return Err(e);
+        }
+        let limit = self.teardown_limit(&vm.profile);
+        let released = self.teardown.release_within(&resources, limit).await;
+        let idle = match &released {
+            Ok(()) if provisioned => vm.transition(VmState::Idle),
+            Ok(()) => Ok(()),
+            Err(e) => {
+                tracing::warn!(cid = vm.cid, reason, error = %e, "teardown after stop incomplete");
+                vm.with_resources(|r| *r = resources).await;
+                Ok(())
+            }
+        };
+        let settled = match settle {
+            Settle::Requeue => {
+                requeue_task(
+                    &self.store,
+                    &vm.state_key,
+                    &self.host_id,
+                    vm.cid,
+                    reason,
+                    extra,
+                )
+                .await
+            }
+            Settle::Fail => {
+                fail_task(&self.store, &vm.state_key, &self.host_id, vm.cid, reason).await
+            }
+        };
+        if released.is_ok() {
+            self.registry.remove(vm.cid)?;
+        } else {
+            self.registry.abandon(vm.cid);
+        }
+        idle?;
+
+        if settled? {
+            tracing::info!(cid = vm.cid, task_id = %vm.task_id, reason, "vm stopped, task settled");
+        } else {
+            tracing::info!(cid = vm.cid, task_id = %vm.task_id, reason, "task no longer held, left as is");
+        }
+        Ok(())
+    }
+}
+
+/// What happens to the task of a stopped VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Settle {
+    /// Back to `pending` for another host, as [`PREEMPTED`].
+    Requeue,
+    /// `failed`, as [`KILLED`].
+    Fail,
+}
+
+impl Settle {
+    fn reason(self) -> &'static str {
+        match self {
+            Self::Requeue => PREEMPTED,
+            Self::Fail => KILLED,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::JournalConfig;
+    use crate::journal::RunJournal;
+    use crate::registry::tests::entry;
+    use crate::testing::MemoryStore;
+    use tempfile::TempDir;
+
+    pub(crate) fn shutdown(
+        drain_timeout_secs: u64,
+        on_timeout: PreemptAction,
+    ) -> (Arc<MemoryStore>, Arc<VmRegistry>, Arc<Shutdown>, TempDir) {
+        let mem = Arc::new(MemoryStore::default().with(
+            "gbe:state:tasks:shell:t1",
+            &[("state", "running"), ("worker", "h1:3")],
+        ));
+        let dir = tempfile::tempdir().unwrap();
+        let registry = Arc::new(VmRegistry::new());
+        registry.attach_journal(Arc::new(
+            RunJournal::open(&dir.path().join("run.journal"), &JournalConfig::default()).unwrap(),
+        ));
+        let mut config = crate::handler::tests::claimed(None).config.as_ref().clone();
+        config.shutdown = ShutdownConfig {
+            drain_timeout_secs,
+            on_timeout,
+            snapshot_dir: None,
+        };
+        let shutdown = Arc::new(Shutdown::new(
+            &config,
+            Arc::clone(&mem) as _,
+            Arc::clone(&registry),
+            Arc::new(Teardown::new("/fc".into(), "/o".into())),
+        ));
+        (mem, registry, shutdown, dir)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn finished_vms_are_not_preempted() {
+        let (mem, registry, shutdown, _dir) = shutdown(30, PreemptAction::Requeue);
+        registry.insert(entry(3, "t1"));
+        let mode = ModeControl::new();
+        let waiter = async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            registry.remove(3).unwrap();
+        };
+        tokio::join!(shutdown.run(&mode), waiter);
+        assert!(!mode.accepting());
+        assert_eq!(
+            mem.field("gbe:state:tasks:shell:t1", "state").as_deref(),
+            Some("running")
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deadline_requeues_running_tasks() {
+        let (mem, registry, shutdown, _dir) = shutdown(30, PreemptAction::Requeue);
+        let vm = registry.insert(entry(3, "t1"));
+        vm.transition(VmState::Provisioning).unwrap();
+        vm.with_resources(|r| r.overlay = Some("/o/3.ext4".into()))
+            .await;
+        vm.transition(VmState::Running).unwrap();
+        shutdown.run(&ModeControl::new()).await;
+        let detail = registry.journaled_detail("t1").await.unwrap();
+        assert_eq!(detail.summary.state, VmState::Idle);
+        let states: Vec<_> = detail.history.into_iter().map(|c| c.state).collect();
+        assert_eq!(
+            states[2..],
+            [
+                VmState::Running,
+                VmState::Failed(PREEMPTED.to_string()),
+                VmState::Teardown,
+                VmState::Idle
+            ]
+        );
+
+        assert!(vm.kill_token().is_cancelled());
+        assert!(vm.take_resources().overlay.is_none());
+        assert!(registry.is_empty());
+        let key = "gbe:state:tasks:shell:t1";
+        assert_eq!(mem.field(key, "state").as_deref(), Some("pending"));
+        assert_eq!(mem.field(key, "reason").as_deref(), Some(PREEMPTED));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn huge_drain_timeout_saturates() {
+        let (_, _, shutdown, _dir) = shutdown(u64::MAX, PreemptAction::Requeue);
+        let mode = ModeControl::new();
+        shutdown.run(&mode).await;
+        assert_eq!(
+            mode.current(),
+            HostMode::Draining {
+                deadline_ms: Some(u64::MAX)
+            }
+        );
+    }
+
+    #[test]
+    fn teardown_limit_per_profile() {
+        let (_, _, shutdown, _dir) = shutdown(30, PreemptAction::Requeue);
+        assert_eq!(shutdown.teardown_limit("shell"), Duration::from_secs(30));
+        assert_eq!(shutdown.teardown_limit("gone"), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn failed_snapshot_falls_back_to_requeue() {
+        let (mem, registry, shutdown, _dir) = shutdown(0, PreemptAction::Snapshot);
+        let vm = registry.insert(entry(3, "t1"));
+        vm.transition(VmState::Provisioning).unwrap();
+        vm.with_resources(|r| {
+            r.handle = Some(crate::vm::manager::VmHandle {
+                cid: 3,
+                pid: 1,
+                socket_path: "/run/fc-3.sock".into(),
+            });
+        })
+        .await;
+        shutdown.preempt_all().await;
+
+        let key = "gbe:state:tasks:shell:t1";
+        assert_eq!(mem.field(key, "state").as_deref(), Some("pending"));
+        assert!(mem.field(key, "snapshot").is_none());
+        assert_eq!(vm.state(), VmState::Idle);
+        assert!(
+            vm.history()
+                .iter()
+                .any(|c| c.state == VmState::Failed(PREEMPTED.to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_release_leaves_vm_unfinished() {
+        let (mem, registry, shutdown, dir) = shutdown(0, PreemptAction::Requeue);
+        let config = crate::config::CgroupConfig {
+            root: dir.path().to_path_buf(),
+            ..crate::config::CgroupConfig::default()
+        };
+        let profile = crate::handler::tests::claimed(None).vm_profile().clone();
+        let cgroup = crate::vm::cgroup::VmCgroup::for_vm(&config, &profile, 3);
+        // Still holding a file, so the cgroup cannot be removed.
+        std::fs::create_dir_all(cgroup.path().join("busy")).unwrap();
+        let vm = registry.insert(entry(3, "t1"));
+        vm.transition(VmState::Provisioning).unwrap();
+        vm.with_resources(|r| r.cgroup = Some(cgroup)).await;
+        registry.insert(entry(4, "t2"));
+        shutdown.preempt_all().await;
+
+        assert!(registry.is_empty());
+        assert_eq!(vm.state(), VmState::Teardown);
+        let unfinished = RunJournal::unfinished(&dir.path().join("run.journal")).unwrap();
+        assert_eq!(unfinished.len(), 1);
+        assert_eq!(unfinished[0].cid, 3);
+        assert!(unfinished[0].cgroup.is_some());
+        assert_eq!(
+            mem.field("gbe:state:tasks:shell:t1", "state").as_deref(),
+            Some("pending")
+        );
+    }
+
+    #[tokio::test]
+    async fn vm_already_in_teardown_is_not_stopped_again() {
+        let (mem, registry, shutdown, _dir) = shutdown(0, PreemptAction::Requeue);
+        let vm = registry.insert(entry(3, "t1"));
+        for state in [VmState::Provisioning, VmState::Timeout, VmState::Teardown] {
+            vm.transition(state).unwrap();
+        }
+        let err = shutdown.kill(&vm).await.unwrap_err();
+        assert!(matches!(err, SentinelError::IllegalTransition { .. }));
+        assert!(!vm.kill_token().is_cancelled());
+        assert_eq!(registry.len(), 1);
+        assert_eq!(
+            mem.field("gbe:state:tasks:shell:t1", "state").as_deref(),
+            Some("running")
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! Shared test doubles.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use gbe_state_store::{Record, ScanFilter, StateStore, StateStoreError};
+
+/// In-memory `StateStore` with real CAS semantics.
+#[derive(Default)]
+pub(crate) struct MemoryStore {
+    records: Mutex<HashMap<String, HashMap<String, Bytes>>>,
+}
+
+impl MemoryStore {
+    /// Store `fields` (as UTF-8 strings) under `key`.
+    pub(crate) fn with(self, key: &str, fields: &[(&str, &str)]) -> Self {
+        self.records.lock().unwrap().insert(
+            key.to_string(),
+            fields
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), Bytes::from((*v).to_string())))
+                .collect(),
+        );
+        self
+    }
+
+    pub(crate) fn field(&self, key: &str, field: &str) -> Option<String> {
+        self.records
+            .lock()
+            .unwrap()
+            .get(key)?
+            .get(field)
+            .map(|v| String::from_utf8_lossy(v).to_string())
+    }
+}
+
+#[async_trait]
+impl StateStore for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<Record>, StateStoreError> {
+        Ok(self.records.lock().unwrap().get(key).map(|fields| Record {
+            fields: fields.clone(),
+            ttl: None,
+        }))
+    }
+    async fn put(
+        &self,
+        key: &str,
+        record: Record,
+        _ttl: Option<Duration>,
+    ) -> Result<(), StateStoreError> {
+        self.records
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), record.fields);
+        Ok(())
+    }
+    async fn delete(&self, key: &str) -> Result<(), StateStoreError> {
+        self.records.lock().unwrap().remove(key);
+        Ok(())
+    }
+    async fn get_field(&self, key: &str, field: &str) -> Result<Option<Bytes>, StateStoreError> {
+        Ok(self
+            .records
+            .lock()
+            .unwrap()
+            .get(key)
+            .and_then(|f| f.get(field).cloned()))
+    }
+    async fn set_field(&self, key: &str, field: &str, value: Bytes) -> Result<(), StateStoreError> {
+        self.records
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .insert(field.to_string(), value);
+        Ok(())
+    }
+    async fn set_fields(
+        &self,
+        key: &str,
+        fields: HashMap<String, Bytes>,
+    ) -> Result<(), StateStoreError> {
+        self.records
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .extend(fields);
+        Ok(())
+    }
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        field: &str,
+        expected: Bytes,
+        new: Bytes,
+    ) -> Result<bool, StateStoreError> {
+        let mut records = self.records.lock().unwrap();
+        let fields = records.entry(key.to_string()).or_default();
+        if fields.get(field) == Some(&expected) {
+            fields.insert(field.to_string(), new);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+    async fn scan(
+        &self,
+        prefix: &str,
+        _filter: Option<ScanFilter>,
+    ) -> Result<Vec<(String, Record)>, StateStoreError> {
+        Ok(self
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(k, _)| k.starts_with(prefix))
+            .map(|(k, fields)| {
+                (
+                    k.clone(),
+                    Record {
+                        fields: fields.clone(),
+                        ttl: None,
+                    },
+                )
+            })
+            .collect())
+    }
+    async fn ping(&self) -> Result<bool, StateStoreError> {
+        Ok(true)
+    }
+    async fn close(&self) -> Result<(), StateStoreError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use gbe_nexus::{Envelope, PublishOpts};
+
+/// Trace context carried from the queue envelope through the task lifecycle.
+///
+/// Extracted once when a task message arrives, then threaded through claim,
+/// provisioning, every lifecycle transition, the `Task` message sent to the
+/// operative, and every progress/terminal publish. One trace id covers
+/// queue → VM → result.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: Option<String>,
+}
+
+impl TraceContext {
+    #[must_use]
+    pub fn new(trace_id: Option<String>) -> Self {
+        Self { trace_id }
+    }
+
+    #[must_use]
+    pub fn from_envelope(envelope: &Envelope) -> Self {
+        Self::new(envelope.trace_id.clone())
+    }
+
+    /// Publish options that echo the trace id onto outgoing bus messages.
+    #[must_use]
+    pub fn publish_opts(&self) -> PublishOpts {
+        PublishOpts {
+            trace_id: self.trace_id.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Root span for a single task. Child spans (claim, provision, vm)
+    /// inherit the `trace_id` field from it. `profile` is recorded once the
+    /// task has been routed.
+    #[must_use]
+    pub fn task_span(&self, task_id: &str, task_type: &str) -> tracing::Span {
+        tracing::info_span!(
+            "task",
+            trace_id = self.trace_id.as_deref().unwrap_or_default(),
+            task_id,
+            task_type,
+            profile = tracing::field::Empty,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_has_no_trace_id() {
+        assert!(TraceContext::default().trace_id.is_none());
+    }
+
+    #[test]
+    fn publish_opts_echo_trace_id() {
+        let ctx = TraceContext::new(Some("trace-abc".into()));
+        assert_eq!(ctx.publish_opts().trace_id.as_deref(), Some("trace-abc"));
+        assert!(TraceContext::default().publish_opts().trace_id.is_none());
+    }
+}
@@ -0,0 +1,46 @@
+use serde::Serialize;
+
+use crate::logs::LogStats;
+use crate::registry::VmEntry;
+use crate::vm::cgroup::CgroupUsage;
+use crate::vm::lifecycle::PhaseDurations;
+use crate::vm::telemetry::VmTelemetry;
+
+/// What one task's VM consumed, published once it has been torn down.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TaskUsage {
+    pub task_id: String,
+    pub profile: String,
+    pub cid: u32,
+    pub durations: PhaseDurations,
+    /// Firecracker's metrics and log summary.
+    pub vm: VmTelemetry,
+    /// Present if the task's output was streamed.
+    pub logs: Option<LogStats>,
+    /// Present if the VM ran in its own cgroup.
+    pub cgroup: Option<CgroupUsage>,
+}
+
+impl TaskUsage {
+    /// Usage of `entry`'s VM as recorded so far.
+    #[must_use]
+    pub fn for_vm(entry: &VmEntry, logs: Option<LogStats>) -> Self {
+        let detail = entry.detail(0);
+        Self {
+            task_id: entry.task_id.clone(),
+            profile: entry.profile.clone(),
+            cid: entry.cid,
+            durations: detail.durations,
+            vm: entry.telemetry(),
+            logs,
+            cgroup: None,
+        }
+    }
+
+    /// Add the VM cgroup's usage, read before teardown removed it.
+    #[must_use]
+    pub fn with_cgroup(mut self, usage: CgroupUsage) -> Self {
+        self.cgroup = Some(usage);
+        self
+    }
+}
@@ -0,0 +1,327 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{CgroupConfig, VmProfile};
+use crate::error::SentinelError;
+
+/// `cpu.max` period, in microseconds.
+pub const CPU_PERIOD_USEC: u64 = 100_000;
+
+/// Controllers the per-VM cgroups need from their parent.
+const CONTROLLERS: &str = "+cpu +memory +io";
+
+/// Limits written to a VM's cgroup, derived from its profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CgroupLimits {
+    /// `{quota} {period}`.
+    pub cpu_max: String,
+    /// Bytes.
+    pub memory_max: u64,
+    /// `{major:minor} rbps=.. wbps=..`; only the profile's set limits.
+    pub io_max: Option<String>,
+}
+
+impl CgroupLimits {
+    #[must_use]
+    pub fn for_profile(config: &CgroupConfig, profile: &VmProfile) -> Self {
+        let quota =
+            u64::from(profile.vcpus) * CPU_PERIOD_USEC * u64::from(100 + config.cpu_overhead_pct)
+                / 100;
+        let memory_mb = u64::from(profile.mem_mb) + u64::from(config.mem_overhead_mb);
+        let io_max = config
+            .io_device
+            .as_ref()
+            .zip(profile.io_limit.as_ref())
+            .and_then(|(device, limit)| {
+                let keys: Vec<String> = [
+                    ("rbps", limit.read_bps),
+                    ("wbps", limit.write_bps),
+                    ("riops", limit.read_iops),
+                    ("wiops", limit.write_iops),
+                ]
+                .iter()
+                .filter_map(|(key, value)| value.map(|v| format!("{key}={v}")))
+                .collect();
+                (!keys.is_empty()).then(|| format!("{device} {}", keys.join(" ")))
+            });
+        Self {
+            cpu_max: format!("{quota} {CPU_PERIOD_USEC}"),
+            memory_max: memory_mb * 1024 * 1024,
+            io_max,
+        }
+    }
+
+    /// Interface files and the values written to them.
+    #[must_use]
+    pub fn files(&self) -> Vec<(&'static str, String)> {
+        let mut files = vec![
+            ("cpu.max", self.cpu_max.clone()),
+            ("memory.max", self.memory_max.to_string()),
+        ];
+        if let Some(io_max) = &self.io_max {
+            files.push(("io.max", io_max.clone()));
+        }
+        files
+    }
+}
+
+/// One VM's cgroup: `{root}/{parent}/{name}`.
+///
+/// Without the jailer, the sentinel creates it and moves the Firecracker
+/// process in. The jailer creates the same cgroup itself from
+/// `--parent-cgroup` and `--cgroup`, since its id is also `vm-{cid}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VmCgroup {
+    pub root: PathBuf,
+    pub parent: String,
+    pub name: String,
+    pub limits: CgroupLimits,
+}
+
+impl VmCgroup {
+    #[must_use]
+    pub fn for_vm(config: &CgroupConfig, profile: &VmProfile, cid: u32) -> Self {
+        Self {
+            root: config.root.clone(),
+            parent: config.parent.clone(),
+            name: format!("vm-{cid}"),
+            limits: CgroupLimits::for_profile(config, profile),
+        }
+    }
+
+    #[must_use]
+    pub fn path(&self) -> PathBuf {
+        self.root.join(&self.parent).join(&self.name)
+    }
+
+    /// Create the cgroup with its limits, enabling the controllers it needs
+    /// in the parent. A leftover cgroup of the same name is reused.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Vm` if the cgroup cannot be created or a
+    /// limit cannot be written.
+    pub async fn create(&self) -> Result<(), SentinelError> {
+        let parent = self.root.join(&self.parent);
+        tokio::fs::create_dir_all(&parent)
+            .await
+            .map_err(|e| self.error("create", &parent, &e))?;
+        tokio::fs::write(parent.join("cgroup.subtree_control"), CONTROLLERS)
+            .await
+            .map_err(|e| self.error("enable controllers in", &parent, &e))?;
+        let path = self.path();
+        match tokio::fs::create_dir(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => {
+                return Err(self.error("create", &path, &e));
+            }
+            _ => {}
+        }
+        for (file, value) in self.limits.files() {
+            let file = path.join(file);
+            tokio::fs::write(&file, value)
+                .await
+                .map_err(|e| self.error("write", &file, &e))?;
+        }
+        Ok(())
+    }
+
+    /// Move process `pid`, with all its threads, into the cgroup.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Vm` if `cgroup.procs` cannot be written.
+    pub async fn attach(&self, pid: u32) -> Result<(), SentinelError> {
+        let procs = self.path().join("cgroup.procs");
+        tokio::fs::write(&procs, pid.to_string())
+            .await
+            .map_err(|e| self.error("write", &procs, &e))
+    }
+
+    /// Peak and cumulative usage, read before the cgroup is removed.
+    /// Interface files the kernel does not provide read as zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if the cgroup does not exist.
+    pub fn usage(&self) -> Result<CgroupUsage, SentinelError> {
+        let path = self.path();
+        std::fs::metadata(&path)?;
+        let read = |file: &str| std::fs::read_to_string(path.join(file)).unwrap_or_default();
+        let mut usage = CgroupUsage {
+            cpu_usec: keyed(&read("cpu.stat"), "usage_usec"),
+            memory_peak_bytes: read("memory.peak").trim().parse().unwrap_or(0),
+            oom_kills: keyed(&read("memory.events"), "oom_kill"),
+            ..CgroupUsage::default()
+        };
+        for field in read("io.stat").split_whitespace() {
+            match field.split_once('=') {
+                Some(("rbytes", n)) => usage.io_read_bytes += n.parse().unwrap_or(0),
+                Some(("wbytes", n)) => usage.io_write_bytes += n.parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Remove the cgroup once its process has exited. A missing cgroup is
+    /// not an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if it cannot be removed, for example
+    /// because a process is still in it.
+    pub async fn remove(&self) -> Result<(), SentinelError> {
+        match tokio::fs::remove_dir(self.path()).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn error(&self, action: &str, path: &Path, e: &std::io::Error) -> SentinelError {
+        SentinelError::Vm(format!(
+            "cgroup {}: {action} {}: {e}",
+            self.name,
+            path.display()
+        ))
+    }
+}
+
+/// The value of `key` in a flat-keyed cgroup file (`key value` per line).
+fn keyed(contents: &str, key: &str) -> u64 {
+    contents
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, v)| v.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// What a VM's process used over its life, from its cgroup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CgroupUsage {
+    pub cpu_usec: u64,
+    pub memory_peak_bytes: u64,
+    pub io_read_bytes: u64,
+    pub io_write_bytes: u64,
+    pub oom_kills: u64,
+}
+
+impl CgroupUsage {
+    /// Evidence for [`ExitEvidence::oom_killed`](super::supervisor::ExitEvidence).
+    #[must_use]
+    pub fn oom_killed(&self) -> bool {
+        self.oom_kills > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IoLimit;
+
+    fn profile(vcpus: u32, mem_mb: u32, io_limit: Option<IoLimit>) -> VmProfile {
+        let mut profile: VmProfile = serde_json::from_value(serde_json::json!({
+            "vcpus": vcpus,
+            "mem_mb": mem_mb,
+            "rootfs": "base.ext4",
+        }))
+        .unwrap();
+        profile.io_limit = io_limit;
+        profile
+    }
+
+    #[test]
+    fn limits_derived_from_profile() {
+        let mut config = CgroupConfig::default();
+        let limits = CgroupLimits::for_profile(&config, &profile(2, 512, None));
+        assert_eq!(limits.cpu_max, "220000 100000");
+        assert_eq!(limits.memory_max, (512 + 64) * 1024 * 1024);
+        assert_eq!(limits.io_max, None);
+        assert_eq!(limits.files().len(), 2);
+
+        let io = Some(IoLimit {
+            read_bps: Some(1_048_576),
+            write_iops: Some(500),
+            ..IoLimit::default()
+        });
+        assert_eq!(
+            CgroupLimits::for_profile(&config, &profile(1, 128, io)).io_max,
+            None
+        );
+        config.io_device = Some("259:0".into());
+        let limits = CgroupLimits::for_profile(&config, &profile(1, 128, io));
+        assert_eq!(
+            limits.io_max.as_deref(),
+            Some("259:0 rbps=1048576 wiops=500")
+        );
+        assert_eq!(limits.files()[2].0, "io.max");
+    }
+
+    #[tokio::test]
+    async fn create_usage_and_remove() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = CgroupConfig {
+            enabled: true,
+            root: tmp.path().to_path_buf(),
+            ..CgroupConfig::default()
+        };
+        let cgroup = VmCgroup::for_vm(&config, &profile(1, 128, None), 3);
+        assert_eq!(cgroup.path(), tmp.path().join("gbe-sentinel/vm-3"));
+        assert!(cgroup.usage().is_err());
+
+        cgroup.create().await.unwrap();
+        let path = cgroup.path();
+        assert_eq!(
+            std::fs::read_to_string(path.join("cpu.max")).unwrap(),
+            "110000 100000"
+        );
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("gbe-sentinel/cgroup.subtree_control"))
+                .unwrap(),
+            "+cpu +memory +io"
+        );
+        cgroup.attach(4242).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path.join("cgroup.procs")).unwrap(),
+            "4242"
+        );
+
+        std::fs::write(path.join("memory.peak"), "201326592\n").unwrap();
+        std::fs::write(
+            path.join("cpu.stat"),
+            "usage_usec 1500000\nuser_usec 1000000\n",
+        )
+        .unwrap();
+        std::fs::write(
+            path.join("memory.events"),
+            "low 0\nhigh 0\nmax 4\noom 1\noom_kill 1\n",
+        )
+        .unwrap();
+        std::fs::write(
+            path.join("io.stat"),
+            "259:0 rbytes=4096 wbytes=8192 rios=1 wios=2\n8:0 rbytes=1 wbytes=0\n",
+        )
+        .unwrap();
+        let usage = cgroup.usage().unwrap();
+        assert_eq!(
+            usage,
+            CgroupUsage {
+                cpu_usec: 1_500_000,
+                memory_peak_bytes: 201_326_592,
+                io_read_bytes: 4097,
+                io_write_bytes: 8192,
+                oom_kills: 1,
+            }
+        );
+        assert!(usage.oom_killed());
+
+        // A real cgroup directory has only kernel files; rmdir works on it.
+        for entry in std::fs::read_dir(&path).unwrap() {
+            std::fs::remove_file(entry.unwrap().path()).unwrap();
+        }
+        cgroup.remove().await.unwrap();
+        assert!(!path.exists());
+        cgroup.remove().await.unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+use std::fmt;
+
+use crate::error::SentinelError;
+
+/// Longest command line the x86 boot protocol accepts (`COMMAND_LINE_SIZE`).
+pub const MAX_CMDLINE_LEN: usize = 2048;
+
+/// Typed Linux kernel command line.
+///
+/// Parameters are `key` flags or `key=value` pairs. Values containing
+/// spaces are double-quoted on render; values that cannot be represented
+/// (embedded quotes, control characters) are rejected. Setting a key twice
+/// with different values is an error rather than "last one wins", so a
+/// profile cannot silently override the sentinel's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KernelCmdline {
+    params: Vec<(String, Option<String>)>,
+}
+
+impl KernelCmdline {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defaults every Firecracker guest boots with: serial console on
+    /// `ttyS0`, reboot via keyboard controller, reboot on panic, no PCI.
+    #[must_use]
+    pub fn firecracker_defaults() -> Self {
+        let params = [
+            ("console", "ttyS0"),
+            ("reboot", "k"),
+            ("panic", "1"),
+            ("pci", "off"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), Some(value.to_string())))
+        .collect();
+        Self { params }
+    }
+
+    /// Add a `key` flag or `key=value` parameter.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` if the key or value is malformed, or
+    /// the key is already set to something else.
+    pub fn push(&mut self, key: &str, value: Option<&str>) -> Result<&mut Self, SentinelError> {
+        validate_key(key)?;
+        if let Some(v) = value {
+            validate_value(key, v)?;
+        }
+        if let Some((_, existing)) = self.params.iter().find(|(k, _)| k == key) {
+            if existing.as_deref() == value {
+                return Ok(self);
+            }
+            return Err(SentinelError::Config(format!(
+                "kernel parameter {key:?} conflicts: already set to {}",
+                existing
+                    .as_deref()
+                    .map_or_else(|| "a flag".into(), |v| format!("{v:?}"))
+            )));
+        }
+        self.params.push((key.to_string(), value.map(String::from)));
+        Ok(self)
+    }
+
+    /// Add a parameter written as `key` or `key=value` (as in config files).
+    ///
+    /// # Errors
+    ///
+    /// Same as [`push`](Self::push).
+    pub fn push_arg(&mut self, arg: &str) -> Result<&mut Self, SentinelError> {
+        match arg.split_once('=') {
+            Some((key, value)) => self.push(key, Some(value)),
+            None => self.push(arg, None),
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<Option<&str>> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_deref())
+    }
+
+    /// Render the command line, checking the boot protocol length limit.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` if the result exceeds
+    /// [`MAX_CMDLINE_LEN`].
+    pub fn render(&self) -> Result<String, SentinelError> {
+        let rendered = self.to_string();
+        if rendered.len() > MAX_CMDLINE_LEN {
+            return Err(SentinelError::Config(format!(
+                "kernel command line is {} bytes (max {MAX_CMDLINE_LEN})",
+                rendered.len()
+            )));
+        }
+        Ok(rendered)
+    }
+}
+
+impl fmt::Display for KernelCmdline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (key, value)) in self.params.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            match value {
+                None => f.write_str(key)?,
+                Some(v) if v.is_empty() || v.contains(' ') => write!(f, "{key}=\"{v}\"")?,
+                Some(v) => write!(f, "{key}={v}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_key(key: &str) -> Result<(), SentinelError> {
+    if key.is_empty()
+        || key == "--"
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_graphic() && c != '=' && c != '"')
+    {
+        return Err(SentinelError::Config(format!(
+            "invalid kernel parameter name {key:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// The kernel has no escape for `"` inside a quoted value, so those (and
+/// control characters) cannot be passed through safely.
+fn validate_value(key: &str, value: &str) -> Result<(), SentinelError> {
+    if value.chars().any(|c| c == '"' || c.is_control()) {
+        return Err(SentinelError::Config(format!(
+            "kernel parameter {key:?}: value contains a quote or control character"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_render_firecracker_args() {
+        assert_eq!(
+            KernelCmdline::firecracker_defaults().render().unwrap(),
+            "console=ttyS0 reboot=k panic=1 pci=off"
+        );
+    }
+
+    #[test]
+    fn flags_and_values() {
+        let mut cmdline = KernelCmdline::new();
+        cmdline
+            .push_arg("quiet")
+            .unwrap()
+            .push_arg("init=/sbin/operative")
+            .unwrap();
+        assert_eq!(cmdline.to_string(), "quiet init=/sbin/operative");
+        assert_eq!(cmdline.get("quiet"), Some(None));
+        assert_eq!(cmdline.get("init"), Some(Some("/sbin/operative")));
+        assert_eq!(cmdline.get("missing"), None);
+    }
+
+    #[test]
+    fn values_with_spaces_are_quoted() {
+        let mut cmdline = KernelCmdline::new();
+        cmdline.push("operative.args", Some("--mode fast")).unwrap();
+        cmdline.push("empty", Some("")).unwrap();
+        assert_eq!(
+            cmdline.to_string(),
+            r#"operative.args="--mode fast" empty="""#
+        );
+    }
+
+    #[test]
+    fn quotes_and_control_chars_rejected() {
+        let mut cmdline = KernelCmdline::new();
+        assert!(cmdline.push("a", Some("say \"hi\"")).is_err());
+        assert!(cmdline.push("a", Some("line\nbreak")).is_err());
+        assert!(cmdline.push_arg("bad key=1").is_err());
+        assert!(cmdline.push_arg("=1").is_err());
+        assert!(cmdline.push_arg("--").is_err());
+    }
+
+    #[test]
+    fn conflicting_keys_rejected() {
+        let mut cmdline = KernelCmdline::firecracker_defaults();
+        let err = cmdline.push_arg("console=ttyS1").unwrap_err();
+        assert!(err.to_string().contains("\"console\" conflicts"));
+        let err = cmdline.push_arg("pci").unwrap_err();
+        assert!(err.to_string().contains("conflicts"));
+    }
+
+    #[test]
+    fn identical_duplicates_allowed() {
+        let mut cmdline = KernelCmdline::firecracker_defaults();
+        cmdline.push_arg("panic=1").unwrap();
+        assert_eq!(cmdline.to_string().matches("panic=1").count(), 1);
+    }
+
+    #[test]
+    fn overlong_cmdline_rejected() {
+        let mut cmdline = KernelCmdline::new();
+        cmdline
+            .push("big", Some(&"x".repeat(MAX_CMDLINE_LEN)))
+            .unwrap();
+        assert!(cmdline.render().is_err());
+    }
+}
@@ -1,19 +1,107 @@
 use std::path::PathBuf;
 
+use super::cgroup::VmCgroup;
+use super::cmdline::KernelCmdline;
+use super::jailer::Jail;
+use super::telemetry::{self, Fifos};
+use crate::config::{SentinelConfig, VmProfile};
+use crate::error::SentinelError;
+
 /// Firecracker boot configuration builder.
 ///
 /// Produces the JSON payloads for Firecracker's API:
-/// /machine-config, /boot-source, /drives/rootfs, /vsock
+/// /machine-config, /boot-source, /drives/rootfs, /vsock, and /logger and
+/// /metrics when telemetry FIFOs are attached.
 pub struct FirecrackerConfig {
     pub vcpus: u32,
     pub mem_mb: u32,
     pub kernel_path: PathBuf,
+    pub initrd_path: Option<PathBuf>,
+    pub boot_args: KernelCmdline,
     pub rootfs_path: PathBuf,
     pub vsock_cid: u32,
     pub socket_path: PathBuf,
+    pub telemetry: Option<Fifos>,
+    /// Set by [`Jail::confine`]; the paths above are then inside its chroot.
+    pub jail: Option<Jail>,
+    /// The cgroup the Firecracker process is placed in.
+    pub cgroup: Option<VmCgroup>,
 }
 
 impl FirecrackerConfig {
+    /// Boot config for a VM running `profile`, with the profile's kernel,
+    /// initrd and extra boot args layered over the Firecracker defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` if the profile's boot args are
+    /// invalid or conflict with the defaults.
+    pub fn for_profile(
+        config: &SentinelConfig,
+        profile: &VmProfile,
+        rootfs_path: PathBuf,
+        vsock_cid: u32,
+        socket_path: PathBuf,
+    ) -> Result<Self, SentinelError> {
+        Ok(Self {
+            vcpus: profile.vcpus,
+            mem_mb: profile.mem_mb,
+            kernel_path: config.kernel_for(profile),
+            initrd_path: config.initrd_for(profile),
+            boot_args: profile.kernel_cmdline()?,
+            rootfs_path,
+            vsock_cid,
+            socket_path,
+            telemetry: None,
+            jail: None,
+            cgroup: None,
+        })
+    }
+
+    /// Have Firecracker write its log and metrics to `fifos`, which must
+    /// exist before the VM is created.
+    #[must_use]
+    pub fn with_telemetry(mut self, fifos: Fifos) -> Self {
+        self.telemetry = Some(fifos);
+        self
+    }
+
+    /// Host path of Firecracker's API socket: the jail's when jailed,
+    /// otherwise beside the vsock socket (`fc-3.sock` gets `fc-3.api.sock`).
+    #[must_use]
+    pub fn api_socket(&self) -> PathBuf {
+        match &self.jail {
+            Some(jail) => jail.api_socket(),
+            None => self.socket_path.with_extension("api.sock"),
+        }
+    }
+
+    /// Host path of the vsock socket, which is inside the jail when jailed.
+    #[must_use]
+    pub fn host_socket_path(&self) -> PathBuf {
+        match &self.jail {
+            Some(jail) => jail.host_path(&self.socket_path),
+            None => self.socket_path.clone(),
+        }
+    }
+
+    /// Host paths of the telemetry FIFOs, if attached.
+    #[must_use]
+    pub fn host_fifos(&self) -> Option<Fifos> {
+        let fifos = self.telemetry.as_ref()?;
+        Some(match &self.jail {
+            Some(jail) => jail.host_fifos(fifos),
+            None => fifos.clone(),
+        })
+    }
+
+    /// Run the Firecracker process in `cgroup`.
+    #[must_use]
+    pub fn with_cgroup(mut self, cgroup: VmCgroup) -> Self {
+        self.cgroup = Some(cgroup);
+        self
+    }
+
     #[must_use]
     pub fn machine_config_json(&self) -> serde_json::Value {
         serde_json::json!({
@@ -22,12 +110,19 @@ impl FirecrackerConfig {
         })
     }
 
-    #[must_use]
-    pub fn boot_source_json(&self) -> serde_json::Value {
-        serde_json::json!({
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` if the boot args exceed the kernel's
+    /// command line limit.
+    pub fn boot_source_json(&self) -> Result<serde_json::Value, SentinelError> {
+        let mut json = serde_json::json!({
             "kernel_image_path": self.kernel_path.to_string_lossy(),
-            "boot_args": "console=ttyS0 reboot=k panic=1 pci=off",
-        })
+            "boot_args": self.boot_args.render()?,
+        });
+        if let Some(initrd) = &self.initrd_path {
+            json["initrd_path"] = initrd.to_string_lossy().into();
+        }
+        Ok(json)
     }
 
     #[must_use]
@@ -47,6 +142,27 @@ impl FirecrackerConfig {
             "uds_path": self.socket_path.to_string_lossy(),
         })
     }
+
+    #[must_use]
+    pub fn logger_json(&self) -> Option<serde_json::Value> {
+        self.telemetry.as_ref().map(|fifos| {
+            serde_json::json!({
+                "log_path": fifos.log.to_string_lossy(),
+                "level": telemetry::LOG_LEVEL,
+                "show_level": true,
+                "show_log_origin": false,
+            })
+        })
+    }
+
+    #[must_use]
+    pub fn metrics_json(&self) -> Option<serde_json::Value> {
+        self.telemetry.as_ref().map(|fifos| {
+            serde_json::json!({
+                "metrics_path": fifos.metrics.to_string_lossy(),
+            })
+        })
+    }
 }
 
 #[cfg(test)]
@@ -58,9 +174,14 @@ mod tests {
             vcpus: 2,
             mem_mb: 512,
             kernel_path: PathBuf::from("/opt/vmlinux"),
+            initrd_path: None,
+            boot_args: KernelCmdline::firecracker_defaults(),
             rootfs_path: PathBuf::from("/images/base.ext4"),
             vsock_cid: 3,
             socket_path: PathBuf::from("/tmp/fc.sock"),
+            telemetry: None,
+            jail: None,
+            cgroup: None,
         }
     }
 
@@ -75,7 +196,7 @@ mod tests {
     #[test]
     fn boot_source_has_kernel_and_args() {
         let cfg = test_config();
-        let json = cfg.boot_source_json();
+        let json = cfg.boot_source_json().unwrap();
         assert_eq!(json["kernel_image_path"], "/opt/vmlinux");
         assert!(
             json["boot_args"]
@@ -84,6 +205,76 @@ mod tests {
                 .contains("console=ttyS0")
         );
         assert!(json["boot_args"].as_str().unwrap().contains("pci=off"));
+        assert!(json.get("initrd_path").is_none());
+    }
+
+    #[test]
+    fn boot_source_includes_initrd() {
+        let mut cfg = test_config();
+        cfg.initrd_path = Some(PathBuf::from("/kernels/initrd.img"));
+        let json = cfg.boot_source_json().unwrap();
+        assert_eq!(json["initrd_path"], "/kernels/initrd.img");
+    }
+
+    #[test]
+    fn for_profile_uses_profile_kernel_and_args() {
+        let config = SentinelConfig::from_toml(
+            r#"
+host_id = "h1"
+slots = 1
+image_dir = "/images"
+kernel_path = "/opt/vmlinux"
+kernel_dir = "/kernels"
+overlay_dir = "/o"
+firecracker_bin = "/fc"
+task_types = ["shell"]
+
+[profiles.shell]
+vcpus = 2
+mem_mb = 256
+rootfs = "base.ext4"
+
+[profiles.custom]
+vcpus = 1
+mem_mb = 128
+rootfs = "base.ext4"
+kernel = "vmlinux-6.1"
+initrd = "initrd-6.1.img"
+boot_args = ["init=/sbin/operative", "quiet"]
+"#,
+        )
+        .unwrap();
+
+        let shell = FirecrackerConfig::for_profile(
+            &config,
+            &config.profiles["shell"],
+            PathBuf::from("/o/vm.ext4"),
+            3,
+            PathBuf::from("/tmp/fc.sock"),
+        )
+        .unwrap();
+        assert_eq!(shell.kernel_path, PathBuf::from("/opt/vmlinux"));
+        assert!(shell.initrd_path.is_none());
+        assert_eq!(shell.vcpus, 2);
+
+        let custom = FirecrackerConfig::for_profile(
+            &config,
+            &config.profiles["custom"],
+            PathBuf::from("/o/vm.ext4"),
+            4,
+            PathBuf::from("/tmp/fc.sock"),
+        )
+        .unwrap();
+        assert_eq!(custom.kernel_path, PathBuf::from("/kernels/vmlinux-6.1"));
+        assert_eq!(
+            custom.initrd_path,
+            Some(PathBuf::from("/kernels/initrd-6.1.img"))
+        );
+        let json = custom.boot_source_json().unwrap();
+        assert_eq!(
+            json["boot_args"],
+            "console=ttyS0 reboot=k panic=1 pci=off init=/sbin/operative quiet"
+        );
     }
 
     #[test]
@@ -103,4 +294,21 @@ mod tests {
         assert_eq!(json["guest_cid"], 3);
         assert_eq!(json["uds_path"], "/tmp/fc.sock");
     }
+
+    #[test]
+    fn logger_and_metrics_only_with_telemetry() {
+        let cfg = test_config();
+        assert!(cfg.logger_json().is_none());
+        assert!(cfg.metrics_json().is_none());
+
+        let cfg = cfg.with_telemetry(Fifos::beside(&PathBuf::from("/tmp/fc.sock")));
+        let logger = cfg.logger_json().unwrap();
+        assert_eq!(logger["log_path"], "/tmp/fc.log");
+        assert_eq!(logger["level"], "Warning");
+        assert_eq!(logger["show_level"], true);
+        assert_eq!(
+            cfg.metrics_json().unwrap()["metrics_path"],
+            "/tmp/fc.metrics"
+        );
+    }
 }
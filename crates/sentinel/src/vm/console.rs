@@ -0,0 +1,394 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::SentinelError;
+use crate::registry::VmRegistry;
+
+/// Console file being written.
+const CURRENT: &str = "console.log";
+
+/// Console file rotated out of the way when `CURRENT` fills up.
+const PREVIOUS: &str = "console.log.1";
+
+/// Longest console line handed on in one piece. A longer line is flushed
+/// in chunks of this size, so a guest writing without newlines cannot grow
+/// the sentinel's buffer.
+pub const MAX_CONSOLE_LINE: usize = 4096;
+
+/// How often expired console directories are swept.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Directory holding the console log of the VM running `task_id`.
+///
+/// # Errors
+///
+/// Returns `SentinelError::Vm` if `task_id` is not a single plain path
+/// component, so a task id from the bus cannot escape `root`.
+pub fn vm_dir(root: &Path, task_id: &str) -> Result<PathBuf, SentinelError> {
+    let mut components = Path::new(task_id).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(root.join(task_id)),
+        _ => Err(SentinelError::Vm(format!(
+            "task id {task_id:?} is not usable as a console directory"
+        ))),
+    }
+}
+
+/// Size-capped serial console log for one VM.
+///
+/// Lines are appended to `console.log`. Once it holds half of `max_bytes`
+/// it is renamed to `console.log.1`, replacing the previous one, so the two
+/// files together never exceed `max_bytes` and always hold the most recent
+/// output. Writes are unbuffered: a reader sees every line as soon as it is
+/// appended, even if the sentinel then crashes.
+pub struct ConsoleLog {
+    dir: PathBuf,
+    segment_bytes: u64,
+    file: File,
+    written: u64,
+}
+
+impl ConsoleLog {
+    /// Create (or reopen, after a requeue to this host) the console log of
+    /// `task_id` under `root`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Vm` for an unusable task id, or
+    /// `SentinelError::Io` if the directory or file cannot be created.
+    pub fn create(root: &Path, task_id: &str, max_bytes: u64) -> Result<Self, SentinelError> {
+        let dir = vm_dir(root, task_id)?;
+        std::fs::create_dir_all(&dir)?;
+        let file = append(&dir.join(CURRENT))?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            dir,
+            segment_bytes: (max_bytes / 2).max(1),
+            file,
+            written,
+        })
+    }
+
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Append one console line, rotating first if it would overflow the
+    /// current file. A line longer than a whole file is cut short.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if the line cannot be written.
+    pub fn append(&mut self, line: &str) -> Result<(), SentinelError> {
+        let limit = usize::try_from(self.segment_bytes - 1).unwrap_or(usize::MAX);
+        let line = &line.as_bytes()[..line.len().min(limit)];
+        let len = line.len() as u64 + 1;
+        if self.written > 0 && self.written + len > self.segment_bytes {
+            self.rotate()?;
+        }
+        let mut out = Vec::with_capacity(line.len() + 1);
+        out.extend_from_slice(line);
+        out.push(b'\n');
+        self.file.write_all(&out)?;
+        self.written += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), SentinelError> {
+        std::fs::rename(self.dir.join(CURRENT), self.dir.join(PREVIOUS))?;
+        self.file = append(&self.dir.join(CURRENT))?;
+        self.written = 0;
+        Ok(())
+    }
+
+    /// The last `bytes` of console output; see [`read_tail`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if the log cannot be read.
+    pub fn tail(&self, bytes: u64) -> Result<String, SentinelError> {
+        read_tail(&self.dir, bytes)
+    }
+}
+
+fn append(path: &Path) -> Result<File, SentinelError> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+/// The last `bytes` of the console log in `dir`, oldest first, starting at
+/// a line boundary. Empty if the VM never wrote to its console or its log
+/// has been swept.
+///
+/// # Errors
+///
+/// Returns `SentinelError::Io` if a log file exists but cannot be read.
+pub fn read_tail(dir: &Path, bytes: u64) -> Result<String, SentinelError> {
+    let mut tail = Vec::new();
+    let mut wanted = bytes;
+    let mut cut = false;
+    for name in [CURRENT, PREVIOUS] {
+        if wanted == 0 {
+            break;
+        }
+        let (mut chunk, whole) = read_last(&dir.join(name), wanted)?;
+        wanted -= chunk.len() as u64;
+        cut = !whole;
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+    }
+    if cut && let Some(newline) = tail.iter().position(|&b| b == b'\n') {
+        // Started mid-line: drop the partial first line.
+        tail.drain(..=newline);
+    }
+    Ok(String::from_utf8_lossy(&tail).into_owned())
+}
+
+/// The last `lines` lines within the last `bytes` of the console log in
+/// `dir`, for VMs no longer in the registry.
+///
+/// # Errors
+///
+/// Returns `SentinelError::Io` if a log file exists but cannot be read.
+pub fn tail_lines(dir: &Path, bytes: u64, lines: usize) -> Result<Vec<String>, SentinelError> {
+    let tail = read_tail(dir, bytes)?;
+    let all: Vec<&str> = tail.lines().collect();
+    Ok(all[all.len().saturating_sub(lines)..]
+        .iter()
+        .map(|l| (*l).to_string())
+        .collect())
+}
+
+/// The last `bytes` of `path`, and whether that is the whole file.
+fn read_last(path: &Path, bytes: u64) -> Result<(Vec<u8>, bool), SentinelError> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), true)),
+        Err(e) => return Err(e.into()),
+    };
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(bytes)))?;
+    let mut out = Vec::new();
+    file.read_to_end(&mut out)?;
+    Ok((out, len <= bytes))
+}
+
+/// Copy a Firecracker process's stdout (the guest's `ttyS0`) into `log`
+/// line by line until it closes, handing each line to `on_line` as well,
+/// e.g. [`VmEntry::push_console`](crate::registry::VmEntry::push_console).
+/// Returns the log for reading its tail once the process has exited.
+///
+/// A write failure is logged once and capture carries on in memory only,
+/// so a full disk never blocks the guest on a console pipe. Lines longer
+/// than [`MAX_CONSOLE_LINE`] are split.
+///
+/// # Errors
+///
+/// Returns `SentinelError::Io` if reading the console fails.
+pub async fn capture<R: AsyncRead + Unpin>(
+    output: R,
+    mut log: ConsoleLog,
+    mut on_line: impl FnMut(&str),
+) -> Result<ConsoleLog, SentinelError> {
+    let mut reader = BufReader::new(output);
+    let mut buf = Vec::new();
+    let mut write_failed = false;
+    loop {
+        buf.clear();
+        let read = (&mut reader)
+            .take(MAX_CONSOLE_LINE as u64)
+            .read_until(b'\n', &mut buf)
+            .await?;
+        if read == 0 {
+            return Ok(log);
+        }
+        let text = String::from_utf8_lossy(&buf);
+        let line = text.trim_end_matches(['\n', '\r']);
+        if !write_failed && let Err(e) = log.append(line) {
+            tracing::warn!(dir = %log.dir.display(), error = %e, "console log write failed");
+            write_failed = true;
+        }
+        on_line(line);
+    }
+}
+
+/// Remove console directories under `root` untouched for `retention`,
+/// skipping any that `keep` names (VMs still running). Returns how many
+/// were removed.
+///
+/// # Errors
+///
+/// Returns `SentinelError::Io` if `root` exists but cannot be listed.
+pub fn sweep(
+    root: &Path,
+    retention: Duration,
+    keep: impl Fn(&str) -> bool,
+) -> Result<usize, SentinelError> {
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let cutoff = SystemTime::now()
+        .checked_sub(retention)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let mut removed = 0;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        if !entry.file_type()?.is_dir() || keep(&name.to_string_lossy()) {
+            continue;
+        }
+        let dir = entry.path();
+        if last_written(&dir).is_some_and(|at| at >= cutoff) {
+            continue;
+        }
+        match std::fs::remove_dir_all(&dir) {
+            Ok(()) => removed += 1,
+            Err(e) => tracing::warn!(dir = %dir.display(), error = %e, "console sweep failed"),
+        }
+    }
+    Ok(removed)
+}
+
+/// Latest modification time of the directory or any file in it.
+fn last_written(dir: &Path) -> Option<SystemTime> {
+    let own = std::fs::metadata(dir).and_then(|m| m.modified()).ok();
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok()?.metadata().ok()?.modified().ok())
+        .chain(own)
+        .max()
+}
+
+/// Sweep `root` every hour until `token` is cancelled, keeping logs of
+/// VMs still in `registry`. Failures are logged, not fatal.
+pub async fn sweep_loop(
+    root: PathBuf,
+    retention: Duration,
+    registry: Arc<VmRegistry>,
+    token: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        tokio::select! {
+            () = token.cancelled() => return,
+            _ = ticker.tick() => {}
+        }
+        match sweep(&root, retention, |task_id| registry.find(task_id).is_some()) {
+            Ok(0) => {}
+            Ok(removed) => tracing::info!(removed, "expired console logs removed"),
+            Err(e) => tracing::warn!(dir = %root.display(), error = %e, "console sweep failed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn task_id_must_be_one_component() {
+        let root = Path::new("/c");
+        assert_eq!(vm_dir(root, "t1").unwrap(), PathBuf::from("/c/t1"));
+        for bad in ["", "..", ".", "a/b", "/abs"] {
+            assert!(vm_dir(root, bad).is_err(), "{bad:?} accepted");
+        }
+    }
+
+    #[test]
+    fn log_rotates_within_cap() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut log = ConsoleLog::create(tmp.path(), "t1", 64).unwrap();
+        for i in 0..20 {
+            log.append(&format!("line {i:02}")).unwrap();
+        }
+        let on_disk: u64 = [CURRENT, PREVIOUS]
+            .iter()
+            .map(|f| std::fs::metadata(log.dir().join(f)).unwrap().len())
+            .sum();
+        assert!(on_disk <= 64);
+
+        let tail = log.tail(64).unwrap();
+        assert!(tail.ends_with("line 19\n"));
+        assert!(!tail.contains("line 00"));
+        assert_eq!(log.tail(20).unwrap(), "line 18\nline 19\n");
+    }
+
+    #[test]
+    fn oversized_line_is_cut() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut log = ConsoleLog::create(tmp.path(), "t1", 16).unwrap();
+        log.append(&"x".repeat(100)).unwrap();
+        assert_eq!(log.tail(100).unwrap(), format!("{}\n", "x".repeat(7)));
+    }
+
+    #[test]
+    fn missing_log_has_empty_tail() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(read_tail(&tmp.path().join("gone"), 1024).unwrap(), "");
+        assert!(
+            tail_lines(&tmp.path().join("gone"), 1024, 10)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn capture_writes_file_and_forwards_lines() {
+        let tmp = tempfile::tempdir().unwrap();
+        let log = ConsoleLog::create(tmp.path(), "t1", 4096).unwrap();
+        let console: &[u8] = b"[0.0] Linux version 6.1\r\nKernel panic - not syncing\npartial";
+        let mut seen = Vec::new();
+        let log = capture(console, log, |l| seen.push(l.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(seen.len(), 3);
+        assert_eq!(seen[0], "[0.0] Linux version 6.1");
+        assert_eq!(
+            tail_lines(log.dir(), 4096, 2).unwrap(),
+            ["Kernel panic - not syncing", "partial"]
+        );
+    }
+
+    #[tokio::test]
+    async fn capture_splits_overlong_lines() {
+        let tmp = tempfile::tempdir().unwrap();
+        let log = ConsoleLog::create(tmp.path(), "t1", 1 << 20).unwrap();
+        let console = format!("{}\nok\n", "x".repeat(MAX_CONSOLE_LINE * 2 + 10));
+        let mut seen = Vec::new();
+        capture(console.as_bytes(), log, |l| seen.push(l.len()))
+            .await
+            .unwrap();
+        assert_eq!(seen, [MAX_CONSOLE_LINE, MAX_CONSOLE_LINE, 10, 2]);
+    }
+
+    #[test]
+    fn sweep_removes_only_expired_and_unkept() {
+        let tmp = tempfile::tempdir().unwrap();
+        for task in ["old", "running"] {
+            ConsoleLog::create(tmp.path(), task, 1024)
+                .unwrap()
+                .append("boot")
+                .unwrap();
+        }
+        assert_eq!(
+            sweep(tmp.path(), Duration::from_secs(3600), |_| false).unwrap(),
+            0
+        );
+        let removed = sweep(tmp.path(), Duration::ZERO, |t| t == "running").unwrap();
+        assert_eq!(removed, 1);
+        assert!(!tmp.path().join("old").exists());
+        assert!(tmp.path().join("running").exists());
+        assert_eq!(
+            sweep(&tmp.path().join("none"), Duration::ZERO, |_| false).unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,451 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use gbe_state_store::StateStore;
+use serde::{Deserialize, Serialize};
+
+use crate::claim::{extend_timeout, now_millis};
+use crate::config::{ExtensionPolicy, VmProfile};
+use crate::error::SentinelError;
+use crate::vsock::protocol::SentinelMessage;
+
+use super::lifecycle::VmState;
+
+/// A bounded stretch of a VM's life.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Overlay, tap and Firecracker boot, through the operative connecting.
+    Provision,
+    /// The operative working on the task.
+    Run,
+    /// Gathering results once the operative has reported.
+    Collect,
+    /// Releasing the VM, tap and overlay.
+    Teardown,
+}
+
+impl Phase {
+    /// Failure reason recorded when this phase overruns.
+    #[must_use]
+    pub fn timeout_reason(self) -> &'static str {
+        match self {
+            Self::Provision => "provision_timeout",
+            Self::Run => "run_timeout",
+            Self::Collect => "collect_timeout",
+            Self::Teardown => "teardown_timeout",
+        }
+    }
+
+    /// State to move to when this phase overruns. A run that overruns is a
+    /// task timeout; any other phase overrunning is a failure with its own
+    /// reason. Teardown has nowhere to fail to and is only force-killed.
+    #[must_use]
+    pub fn timeout_state(self) -> Option<VmState> {
+        match self {
+            Self::Run => Some(VmState::Timeout),
+            Self::Provision | Self::Collect => {
+                Some(VmState::Failed(self.timeout_reason().to_string()))
+            }
+            Self::Teardown => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Provision => "provision",
+            Self::Run => "run",
+            Self::Collect => "collect",
+            Self::Teardown => "teardown",
+        })
+    }
+}
+
+/// How long each phase may take, from a profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseDeadlines {
+    pub provision: Duration,
+    pub run: Duration,
+    pub collect: Duration,
+    pub teardown: Duration,
+}
+
+impl PhaseDeadlines {
+    #[must_use]
+    pub fn for_profile(profile: &VmProfile) -> Self {
+        Self {
+            provision: Duration::from_secs(profile.provision_timeout_sec),
+            run: Duration::from_secs(profile.timeout_sec),
+            collect: Duration::from_secs(profile.collect_timeout_sec),
+            teardown: Duration::from_secs(profile.teardown_timeout_sec),
+        }
+    }
+
+    #[must_use]
+    pub fn limit(&self, phase: Phase) -> Duration {
+        match phase {
+            Phase::Provision => self.provision,
+            Phase::Run => self.run,
+            Phase::Collect => self.collect,
+            Phase::Teardown => self.teardown,
+        }
+    }
+
+    /// Run `work` under `phase`'s deadline. On expiry `work` is dropped,
+    /// abandoning whatever call it was blocked in; the caller escalates.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::DeadlineExceeded` on expiry, or whatever
+    /// `work` returns.
+    pub async fn within<T>(
+        &self,
+        phase: Phase,
+        work: impl Future<Output = Result<T, SentinelError>>,
+    ) -> Result<T, SentinelError> {
+        let limit = self.limit(phase);
+        tokio::time::timeout(limit, work)
+            .await
+            .map_err(|_| SentinelError::DeadlineExceeded {
+                phase,
+                limit_secs: limit.as_secs(),
+            })?
+    }
+}
+
+/// Outcome of an operative's request for more run time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extension {
+    /// Granted, possibly less than asked.
+    Granted {
+        additional_sec: u64,
+    },
+    Denied(&'static str),
+}
+
+impl std::fmt::Display for Extension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Granted { additional_sec } => write!(f, "granted {additional_sec}s"),
+            Self::Denied(reason) => write!(f, "denied: {reason}"),
+        }
+    }
+}
+
+/// The run phase's deadline, as the task's `timeout_at` in unix millis.
+/// The operative may push it back within the profile's
+/// [`ExtensionPolicy`].
+#[derive(Debug, Clone)]
+pub struct RunDeadline {
+    timeout_at: u64,
+    base_sec: u64,
+    policy: Option<ExtensionPolicy>,
+    granted_sec: u64,
+    requests: u32,
+}
+
+impl RunDeadline {
+    /// Deadline for a run under `profile` that must end by `timeout_at`.
+    #[must_use]
+    pub fn for_profile(profile: &VmProfile, timeout_at: u64) -> Self {
+        Self {
+            timeout_at,
+            base_sec: profile.timeout_sec,
+            policy: profile.extension_policy,
+            granted_sec: 0,
+            requests: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn timeout_at(&self) -> u64 {
+        self.timeout_at
+    }
+
+    /// `timeout_sec` plus everything granted so far.
+    #[must_use]
+    pub fn limit_secs(&self) -> u64 {
+        self.base_sec.saturating_add(self.granted_sec)
+    }
+
+    /// Time left before the deadline.
+    #[must_use]
+    pub fn remaining(&self) -> Duration {
+        Duration::from_millis(self.timeout_at.saturating_sub(now_millis()))
+    }
+
+    /// The error to fail the run with once [`remaining`](Self::remaining)
+    /// runs out.
+    #[must_use]
+    pub fn exceeded(&self) -> SentinelError {
+        SentinelError::DeadlineExceeded {
+            phase: Phase::Run,
+            limit_secs: self.limit_secs(),
+        }
+    }
+
+    /// Decide a request for `additional_sec` more. Grants are capped at
+    /// what is left of `max_total_sec`. Every request counts toward
+    /// `max_requests`, granted or not.
+    pub fn request(&mut self, additional_sec: u64) -> Extension {
+        let Some(policy) = self.policy else {
+            return Extension::Denied("extensions not allowed for this profile");
+        };
+        if self.requests >= policy.max_requests {
+            return Extension::Denied("extension request limit reached");
+        }
+        self.requests += 1;
+        let granted = additional_sec.min(policy.max_total_sec.saturating_sub(self.granted_sec));
+        if granted == 0 {
+            return Extension::Denied("extension budget exhausted");
+        }
+        self.granted_sec += granted;
+        self.timeout_at = self.timeout_at.saturating_add(granted.saturating_mul(1000));
+        Extension::Granted {
+            additional_sec: granted,
+        }
+    }
+
+    /// Like [`request`](Self::request), recording a grant as the task's
+    /// `timeout_at` so the watcher agrees. A grant that cannot be recorded
+    /// because the task is no longer held is withdrawn.
+    ///
+    /// The daemon's [`TaskRunner`] answers `extend_deadline` through this;
+    /// `gbe-sentinel run-local` has no state store and uses `request`.
+    ///
+    /// [`TaskRunner`]: crate::runner::TaskRunner
+    ///
+    /// # Errors
+    ///
+    /// Returns a store error on I/O failure; the grant is withdrawn.
+    pub async fn request_for_task(
+        &mut self,
+        additional_sec: u64,
+        store: &Arc<dyn StateStore>,
+        state_key: &str,
+        host_id: &str,
+        vm_cid: u32,
+    ) -> Result<Extension, SentinelError> {
+        let extension = self.request(additional_sec);
+        let Extension::Granted { additional_sec } = extension else {
+            return Ok(extension);
+        };
+        match extend_timeout(store, state_key, host_id, vm_cid, self.timeout_at).await {
+            Ok(true) => Ok(extension),
+            Ok(false) => {
+                self.withdraw(additional_sec);
+                Ok(Extension::Denied("task no longer held"))
+            }
+            Err(e) => {
+                self.withdraw(additional_sec);
+                Err(e)
+            }
+        }
+    }
+
+    fn withdraw(&mut self, additional_sec: u64) {
+        self.granted_sec -= additional_sec;
+        self.timeout_at = self
+            .timeout_at
+            .saturating_sub(additional_sec.saturating_mul(1000));
+    }
+
+    /// Answer to send the operative for task `id`.
+    #[must_use]
+    pub fn reply(&self, id: &str, extension: Extension) -> SentinelMessage {
+        let (granted, additional_sec, reason) = match extension {
+            Extension::Granted { additional_sec } => (true, additional_sec, None),
+            Extension::Denied(reason) => (false, 0, Some(reason.to_string())),
+        };
+        SentinelMessage::ExtendDeadlineResult {
+            id: id.to_string(),
+            granted,
+            additional_sec,
+            timeout_at: self.timeout_at,
+            reason,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MemoryStore;
+
+    fn deadlines() -> PhaseDeadlines {
+        let profile: VmProfile = serde_json::from_value(serde_json::json!({
+            "vcpus": 1,
+            "mem_mb": 128,
+            "rootfs": "base.ext4",
+            "provision_timeout_sec": 5,
+        }))
+        .unwrap();
+        PhaseDeadlines::for_profile(&profile)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn overrun_reports_phase() {
+        let err = deadlines()
+            .within(Phase::Provision, async {
+                tokio::time::sleep(Duration::from_secs(6)).await;
+                Ok(())
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SentinelError::DeadlineExceeded {
+                phase: Phase::Provision,
+                limit_secs: 5
+            }
+        ));
+        assert_eq!(err.to_string(), "provision deadline of 5s exceeded");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn work_within_deadline_passes_through() {
+        let deadlines = deadlines();
+        assert_eq!(
+            deadlines
+                .within(Phase::Collect, async { Ok(7) })
+                .await
+                .unwrap(),
+            7
+        );
+        let err = deadlines
+            .within::<()>(Phase::Run, async {
+                Err(SentinelError::Vsock("gone".into()))
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SentinelError::Vsock(_)));
+    }
+
+    #[test]
+    fn each_phase_has_its_own_reason() {
+        assert_eq!(deadlines().limit(Phase::Run).as_secs(), 300);
+        assert_eq!(Phase::Run.timeout_state(), Some(VmState::Timeout));
+        assert_eq!(
+            Phase::Provision.timeout_state(),
+            Some(VmState::Failed("provision_timeout".into()))
+        );
+        assert_eq!(
+            Phase::Collect.timeout_state(),
+            Some(VmState::Failed("collect_timeout".into()))
+        );
+        assert_eq!(Phase::Teardown.timeout_state(), None);
+        assert_eq!(Phase::Teardown.timeout_reason(), "teardown_timeout");
+    }
+
+    fn run_deadline(policy: Option<ExtensionPolicy>) -> RunDeadline {
+        let mut profile: VmProfile = serde_json::from_value(serde_json::json!({
+            "vcpus": 1,
+            "mem_mb": 128,
+            "rootfs": "base.ext4",
+            "timeout_sec": 60,
+        }))
+        .unwrap();
+        profile.extension_policy = policy;
+        RunDeadline::for_profile(&profile, 100_000)
+    }
+
+    #[test]
+    fn extensions_follow_policy() {
+        let mut deadline = run_deadline(Some(ExtensionPolicy {
+            max_total_sec: 90,
+            max_requests: 3,
+        }));
+        assert_eq!(
+            deadline.request(60),
+            Extension::Granted { additional_sec: 60 }
+        );
+        assert_eq!(
+            deadline.request(60),
+            Extension::Granted { additional_sec: 30 }
+        );
+        assert_eq!(
+            deadline.request(10),
+            Extension::Denied("extension budget exhausted")
+        );
+        assert_eq!(
+            deadline.request(10),
+            Extension::Denied("extension request limit reached")
+        );
+        assert_eq!(deadline.timeout_at(), 190_000);
+        assert_eq!(deadline.limit_secs(), 150);
+        assert!(matches!(
+            deadline.exceeded(),
+            SentinelError::DeadlineExceeded {
+                phase: Phase::Run,
+                limit_secs: 150
+            }
+        ));
+    }
+
+    #[test]
+    fn huge_grant_saturates() {
+        let mut deadline = run_deadline(Some(ExtensionPolicy {
+            max_total_sec: u64::MAX,
+            max_requests: 1,
+        }));
+        assert_eq!(
+            deadline.request(u64::MAX),
+            Extension::Granted {
+                additional_sec: u64::MAX
+            }
+        );
+        assert_eq!(deadline.timeout_at(), u64::MAX);
+        assert_eq!(deadline.limit_secs(), u64::MAX);
+    }
+
+    #[test]
+    fn no_policy_denies_and_reply_carries_reason() {
+        let mut deadline = run_deadline(None);
+        let denied = deadline.request(60);
+        assert_eq!(
+            denied,
+            Extension::Denied("extensions not allowed for this profile")
+        );
+        let reply = serde_json::to_value(deadline.reply("t1", denied)).unwrap();
+        assert_eq!(reply["granted"], false);
+        assert_eq!(reply["timeout_at"], 100_000);
+        assert_eq!(reply["reason"], "extensions not allowed for this profile");
+    }
+
+    #[tokio::test]
+    async fn grant_recorded_in_state_store() {
+        let mem = Arc::new(MemoryStore::default().with(
+            "k",
+            &[
+                ("state", "running"),
+                ("worker", "h1:3"),
+                ("timeout_at", "100000"),
+            ],
+        ));
+        let store: Arc<dyn StateStore> = Arc::clone(&mem) as _;
+        let policy = Some(ExtensionPolicy {
+            max_total_sec: 600,
+            max_requests: 5,
+        });
+
+        let mut deadline = run_deadline(policy);
+        let granted = deadline
+            .request_for_task(30, &store, "k", "h1", 3)
+            .await
+            .unwrap();
+        assert_eq!(granted, Extension::Granted { additional_sec: 30 });
+        assert_eq!(mem.field("k", "timeout_at").as_deref(), Some("130000"));
+
+        let mut other = run_deadline(policy);
+        let denied = other
+            .request_for_task(30, &store, "k", "h1", 4)
+            .await
+            .unwrap();
+        assert_eq!(denied, Extension::Denied("task no longer held"));
+        assert_eq!(other.timeout_at(), 100_000);
+        assert_eq!(mem.field("k", "timeout_at").as_deref(), Some("130000"));
+    }
+}
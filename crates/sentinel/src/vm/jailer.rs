@@ -0,0 +1,382 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use super::cgroup::VmCgroup;
+use super::config::FirecrackerConfig;
+use super::telemetry::Fifos;
+use crate::config::JailerConfig;
+use crate::error::SentinelError;
+
+/// Firecracker's API socket, inside the chroot.
+pub const API_SOCKET: &str = "/run/firecracker.socket";
+
+/// Where files linked or created for the VM appear inside the chroot.
+const KERNEL: &str = "/vmlinux";
+const INITRD: &str = "/initrd";
+const ROOTFS: &str = "/rootfs.ext4";
+const VSOCK: &str = "/v.sock";
+const LOG_FIFO: &str = "/firecracker.log";
+const METRICS_FIFO: &str = "/firecracker.metrics";
+
+const NETNS_DIR: &str = "/var/run/netns";
+
+/// One VM's jailer chroot: `{chroot_base}/{exec name}/{id}`, with the
+/// VM's files under its `root/`.
+///
+/// Paths in a [`confine`](Self::confine)d `FirecrackerConfig` are as
+/// Firecracker sees them from inside the chroot; the sentinel reaches them
+/// through [`host_path`](Self::host_path).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Jail {
+    pub id: String,
+    pub jailer_bin: PathBuf,
+    pub exec_file: PathBuf,
+    pub chroot_base: PathBuf,
+    pub uid: u32,
+    pub gid: u32,
+    /// Network namespace name, under `/var/run/netns`.
+    pub netns: Option<String>,
+}
+
+impl Jail {
+    /// The jail for the VM with vsock CID `cid`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Config` if `firecracker_bin` has no file name
+    /// or the per-VM uid/gid overflows.
+    pub fn for_vm(
+        config: &JailerConfig,
+        firecracker_bin: &Path,
+        cid: u32,
+    ) -> Result<Self, SentinelError> {
+        if firecracker_bin.file_name().is_none() {
+            return Err(SentinelError::Config(format!(
+                "firecracker_bin: no file name: {}",
+                firecracker_bin.display()
+            )));
+        }
+        let offset = |base: u32, field: &str| {
+            base.checked_add(cid).ok_or_else(|| {
+                SentinelError::Config(format!("jailer.{field}: overflows for cid {cid}"))
+            })
+        };
+        Ok(Self {
+            id: format!("vm-{cid}"),
+            jailer_bin: config.bin.clone(),
+            exec_file: firecracker_bin.to_path_buf(),
+            chroot_base: config.chroot_base.clone(),
+            uid: offset(config.uid_base, "uid_base")?,
+            gid: offset(config.gid_base, "gid_base")?,
+            netns: config.netns.then(|| format!("gbe-vm-{cid}")),
+        })
+    }
+
+    /// The jail directory, removed whole on teardown.
+    #[must_use]
+    pub fn dir(&self) -> PathBuf {
+        let exec_name = self.exec_file.file_name().unwrap_or_default();
+        self.chroot_base.join(exec_name).join(&self.id)
+    }
+
+    /// The chroot Firecracker runs in.
+    #[must_use]
+    pub fn root(&self) -> PathBuf {
+        self.dir().join("root")
+    }
+
+    /// Host path of `inside`, a path as seen from inside the chroot.
+    #[must_use]
+    pub fn host_path(&self, inside: &Path) -> PathBuf {
+        self.root().join(inside.strip_prefix("/").unwrap_or(inside))
+    }
+
+    /// Host path of Firecracker's API socket.
+    #[must_use]
+    pub fn api_socket(&self) -> PathBuf {
+        self.host_path(Path::new(API_SOCKET))
+    }
+
+    #[must_use]
+    pub fn netns_path(&self) -> Option<PathBuf> {
+        self.netns
+            .as_ref()
+            .map(|name| Path::new(NETNS_DIR).join(name))
+    }
+
+    /// Host paths of telemetry FIFOs named in a confined config.
+    #[must_use]
+    pub fn host_fifos(&self, fifos: &Fifos) -> Fifos {
+        Fifos {
+            log: self.host_path(&fifos.log),
+            metrics: self.host_path(&fifos.metrics),
+        }
+    }
+
+    /// Lay out the chroot for `fc` and return it rewritten to in-chroot
+    /// paths.
+    ///
+    /// The kernel, initrd and rootfs are hard-linked in. The rootfs (the
+    /// VM's own overlay) and the telemetry FIFOs are handed to the jail's
+    /// uid; the kernel and initrd are shared and must be world-readable.
+    /// The network namespace is created if configured.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Vm` if a file cannot be linked (for example,
+    /// across filesystems) or the namespace cannot be created, and
+    /// `SentinelError::Io` for other filesystem failures.
+    pub async fn confine(
+        &self,
+        mut fc: FirecrackerConfig,
+    ) -> Result<FirecrackerConfig, SentinelError> {
+        let root = self.root();
+        tokio::fs::create_dir_all(&root).await?;
+
+        fc.kernel_path = self.link(&fc.kernel_path, KERNEL).await?;
+        if let Some(initrd) = &fc.initrd_path {
+            fc.initrd_path = Some(self.link(initrd, INITRD).await?);
+        }
+        fc.rootfs_path = self.link(&fc.rootfs_path, ROOTFS).await?;
+        self.chown(ROOTFS)?;
+        fc.socket_path = PathBuf::from(VSOCK);
+
+        if fc.telemetry.is_some() {
+            let inside = Fifos {
+                log: PathBuf::from(LOG_FIFO),
+                metrics: PathBuf::from(METRICS_FIFO),
+            };
+            self.host_fifos(&inside).create().await?;
+            self.chown(LOG_FIFO)?;
+            self.chown(METRICS_FIFO)?;
+            fc.telemetry = Some(inside);
+        }
+        fc.jail = Some(self.clone());
+
+        if let Some(name) = &self.netns {
+            ip_netns("add", name).await?;
+        }
+        Ok(fc)
+    }
+
+    /// Hard-link `source` to `inside`, replacing a leftover link.
+    async fn link(&self, source: &Path, inside: &str) -> Result<PathBuf, SentinelError> {
+        let dest = self.host_path(Path::new(inside));
+        match tokio::fs::remove_file(&dest).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        tokio::fs::hard_link(source, &dest).await.map_err(|e| {
+            SentinelError::Vm(format!(
+                "link {} into jail {}: {e}",
+                source.display(),
+                self.id
+            ))
+        })?;
+        Ok(PathBuf::from(inside))
+    }
+
+    fn chown(&self, inside: &str) -> Result<(), SentinelError> {
+        std::os::unix::fs::chown(
+            self.host_path(Path::new(inside)),
+            Some(self.uid),
+            Some(self.gid),
+        )?;
+        Ok(())
+    }
+
+    /// The `jailer` invocation that starts Firecracker in this jail, in
+    /// `cgroup` if given; the jailer creates it and applies its limits.
+    #[must_use]
+    pub fn command(&self, cgroup: Option<&VmCgroup>) -> Command {
+        let mut cmd = Command::new(&self.jailer_bin);
+        cmd.arg("--id")
+            .arg(&self.id)
+            .arg("--exec-file")
+            .arg(&self.exec_file)
+            .arg("--uid")
+            .arg(self.uid.to_string())
+            .arg("--gid")
+            .arg(self.gid.to_string())
+            .arg("--chroot-base-dir")
+            .arg(&self.chroot_base)
+            .arg("--cgroup-version")
+            .arg("2");
+        if let Some(cgroup) = cgroup {
+            cmd.arg("--parent-cgroup").arg(&cgroup.parent);
+            for (file, value) in cgroup.limits.files() {
+                cmd.arg("--cgroup").arg(format!("{file}={value}"));
+            }
+        }
+        if let Some(netns) = self.netns_path() {
+            cmd.arg("--netns").arg(netns);
+        }
+        cmd.arg("--").arg("--api-sock").arg(API_SOCKET);
+        cmd
+    }
+
+    /// Remove the whole jail directory and the network namespace. Either
+    /// being gone already is not an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if the directory cannot be removed, or
+    /// `SentinelError::Vm` if the namespace cannot be deleted.
+    pub async fn remove(&self) -> Result<(), SentinelError> {
+        match tokio::fs::remove_dir_all(self.dir()).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        if let (Some(name), Some(path)) = (&self.netns, self.netns_path())
+            && path.exists()
+        {
+            ip_netns("delete", name).await?;
+        }
+        Ok(())
+    }
+}
+
+async fn ip_netns(action: &str, name: &str) -> Result<(), SentinelError> {
+    let output = Command::new("ip")
+        .args(["netns", action, name])
+        .output()
+        .await?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(SentinelError::Vm(format!(
+            "ip netns {action} {name}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::cmdline::KernelCmdline;
+
+    fn jail(base: &Path, netns: bool) -> Jail {
+        let config = JailerConfig {
+            enabled: true,
+            chroot_base: base.to_path_buf(),
+            netns,
+            ..JailerConfig::default()
+        };
+        Jail::for_vm(&config, Path::new("/usr/bin/firecracker"), 3).unwrap()
+    }
+
+    #[test]
+    fn layout_and_ids() {
+        let jail = jail(Path::new("/srv/jailer"), true);
+        assert_eq!(jail.dir(), PathBuf::from("/srv/jailer/firecracker/vm-3"));
+        assert_eq!(
+            jail.api_socket(),
+            PathBuf::from("/srv/jailer/firecracker/vm-3/root/run/firecracker.socket")
+        );
+        assert_eq!(
+            jail.host_path(Path::new("/v.sock")),
+            PathBuf::from("/srv/jailer/firecracker/vm-3/root/v.sock")
+        );
+        assert_eq!((jail.uid, jail.gid), (100_003, 100_003));
+        assert_eq!(
+            jail.netns_path(),
+            Some(PathBuf::from("/var/run/netns/gbe-vm-3"))
+        );
+
+        let overflow = JailerConfig {
+            uid_base: u32::MAX,
+            ..JailerConfig::default()
+        };
+        let err = Jail::for_vm(&overflow, Path::new("/usr/bin/firecracker"), 3).unwrap_err();
+        assert!(err.to_string().contains("jailer.uid_base"));
+    }
+
+    #[test]
+    fn jailer_command_line() {
+        let jail = jail(Path::new("/srv/jailer"), true);
+        let cmd = jail.command(None);
+        assert_eq!(cmd.as_std().get_program(), "/usr/bin/jailer");
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            args.join(" "),
+            "--id vm-3 --exec-file /usr/bin/firecracker --uid 100003 --gid 100003 \
+             --chroot-base-dir /srv/jailer --cgroup-version 2 \
+             --netns /var/run/netns/gbe-vm-3 -- --api-sock /run/firecracker.socket"
+        );
+
+        let profile = serde_json::from_value(serde_json::json!({
+            "vcpus": 1,
+            "mem_mb": 128,
+            "rootfs": "base.ext4",
+        }))
+        .unwrap();
+        let cgroup = VmCgroup::for_vm(&crate::config::CgroupConfig::default(), &profile, 3);
+        let cmd = jail.command(Some(&cgroup));
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        assert!(args.join(" ").contains(
+            "--cgroup-version 2 --parent-cgroup gbe-sentinel \
+             --cgroup cpu.max=110000 100000 --cgroup memory.max=201326592 --netns"
+        ));
+        assert_eq!(cgroup.name, jail.id);
+    }
+
+    #[tokio::test]
+    async fn confine_links_files_and_rewrites_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        let kernel = tmp.path().join("vmlinux");
+        let overlay = tmp.path().join("t1.ext4");
+        std::fs::write(&kernel, b"kernel").unwrap();
+        std::fs::write(&overlay, b"rootfs").unwrap();
+        let mut jail = jail(&tmp.path().join("jails"), false);
+        // Hand files to ourselves so the test needs no privileges.
+        let me = std::fs::metadata(&overlay).unwrap();
+        (jail.uid, jail.gid) = (
+            std::os::unix::fs::MetadataExt::uid(&me),
+            std::os::unix::fs::MetadataExt::gid(&me),
+        );
+
+        let fc = FirecrackerConfig {
+            vcpus: 1,
+            mem_mb: 128,
+            kernel_path: kernel,
+            initrd_path: None,
+            boot_args: KernelCmdline::firecracker_defaults(),
+            rootfs_path: overlay,
+            vsock_cid: 3,
+            socket_path: tmp.path().join("fc-3.sock"),
+            telemetry: None,
+            jail: None,
+            cgroup: None,
+        }
+        .with_telemetry(Fifos::beside(&tmp.path().join("fc-3.sock")));
+        let fc = jail.confine(fc).await.unwrap();
+
+        assert_eq!(fc.kernel_path, PathBuf::from("/vmlinux"));
+        assert_eq!(fc.rootfs_path, PathBuf::from("/rootfs.ext4"));
+        assert_eq!(fc.socket_path, PathBuf::from("/v.sock"));
+        assert_eq!(fc.jail.as_ref(), Some(&jail));
+        let fifos = jail.host_fifos(fc.telemetry.as_ref().unwrap());
+        assert!(fifos.log.starts_with(jail.root()));
+        assert!(fifos.metrics.exists());
+        assert_eq!(
+            std::fs::read(jail.host_path(&fc.kernel_path)).unwrap(),
+            b"kernel"
+        );
+
+        jail.remove().await.unwrap();
+        assert!(!jail.dir().exists());
+        assert!(tmp.path().join("t1.ext4").exists());
+        jail.remove().await.unwrap();
+    }
+}
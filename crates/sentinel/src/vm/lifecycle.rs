@@ -1,14 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+use crate::claim::now_millis;
+use crate::error::SentinelError;
+use crate::trace::TraceContext;
+
 /// VM lifecycle state machine.
 ///
 /// ```text
 /// IDLE → PROVISIONING → RUNNING → COLLECTING → TEARDOWN → IDLE
-///              │             │           │
-///              ▼             ▼           ▼
-///           FAILED        TIMEOUT     FAILED
-///              │             │           │
-///              └──── all ────┴→ TEARDOWN ┘
+///              │             │           │         ▲
+///              ▼             ▼           ▼         │
+///              └──→ FAILED / TIMEOUT ────┴─────────┘
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// Any active state (provisioning, running, collecting) may fail or time
+/// out. Failed and timed-out VMs can only move on to teardown, so their
+/// resources are always released. See [`VmState::can_transition`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum VmState {
     Idle,
     Provisioning,
@@ -19,9 +28,83 @@ pub enum VmState {
     Timeout,
 }
 
+impl std::fmt::Display for VmState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Idle => f.write_str("idle"),
+            Self::Provisioning => f.write_str("provisioning"),
+            Self::Running => f.write_str("running"),
+            Self::Collecting => f.write_str("collecting"),
+            Self::Teardown => f.write_str("teardown"),
+            Self::Failed(reason) => write!(f, "failed: {reason}"),
+            Self::Timeout => f.write_str("timeout"),
+        }
+    }
+}
+
+impl VmState {
+    /// Whether the transition table allows moving from `self` to `next`.
+    /// `Failed` matches regardless of reason.
+    #[must_use]
+    pub fn can_transition(&self, next: &Self) -> bool {
+        use VmState::{Collecting, Failed, Idle, Provisioning, Running, Teardown, Timeout};
+        matches!(
+            (self, next),
+            (Idle, Provisioning)
+                | (Provisioning, Running)
+                | (Running, Collecting)
+                | (Provisioning | Running | Collecting, Failed(_) | Timeout)
+                | (Collecting | Failed(_) | Timeout, Teardown)
+                | (Teardown, Idle)
+        )
+    }
+}
+
+/// A state the VM entered, and when (Unix millis).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateChange {
+    pub state: VmState,
+    pub at_ms: u64,
+}
+
+/// Time spent in each phase, in millis. A phase still in progress counts
+/// up to now; a phase never entered is `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseDurations {
+    pub provision_ms: Option<u64>,
+    pub run_ms: Option<u64>,
+    pub teardown_ms: Option<u64>,
+}
+
+impl PhaseDurations {
+    #[must_use]
+    pub fn from_history(history: &[StateChange], now_ms: u64) -> Self {
+        Self {
+            provision_ms: time_in(history, &VmState::Provisioning, now_ms),
+            run_ms: time_in(history, &VmState::Running, now_ms),
+            teardown_ms: time_in(history, &VmState::Teardown, now_ms),
+        }
+    }
+}
+
+/// Millis from the last time `state` was entered until the next change,
+/// or until `now_ms` if it is still current.
+fn time_in(history: &[StateChange], state: &VmState, now_ms: u64) -> Option<u64> {
+    let i = history.iter().rposition(|c| &c.state == state)?;
+    let end = history.get(i + 1).map_or(now_ms, |c| c.at_ms);
+    Some(end.saturating_sub(history[i].at_ms))
+}
+
 pub struct VmLifecycle {
     pub state: VmState,
     pub task_id: Option<String>,
+    pub trace_id: Option<String>,
+    /// Every state entered, oldest first, starting with `Idle`.
+    pub history: Vec<StateChange>,
+    /// Span covering the whole VM lifetime; parent of every state span.
+    vm_span: tracing::Span,
+    /// Span for the current state, replaced on every transition.
+    state_span: tracing::Span,
 }
 
 impl Default for VmLifecycle {
@@ -36,12 +119,103 @@ impl VmLifecycle {
         Self {
             state: VmState::Idle,
             task_id: None,
+            trace_id: None,
+            history: vec![StateChange {
+                state: VmState::Idle,
+                at_ms: now_millis(),
+            }],
+            vm_span: tracing::Span::none(),
+            state_span: tracing::Span::none(),
+        }
+    }
+
+    /// Lifecycle bound to a task. The VM span is created as a child of the
+    /// caller's current span (normally the task span), so every state span
+    /// lands in the same trace.
+    #[must_use]
+    pub fn for_task(task_id: &str, trace: &TraceContext) -> Self {
+        let vm_span = tracing::info_span!(
+            "vm",
+            task_id,
+            trace_id = trace.trace_id.as_deref().unwrap_or_default(),
+        );
+        Self {
+            state: VmState::Idle,
+            task_id: Some(task_id.to_string()),
+            trace_id: trace.trace_id.clone(),
+            history: vec![StateChange {
+                state: VmState::Idle,
+                at_ms: now_millis(),
+            }],
+            state_span: tracing::info_span!(parent: &vm_span, "vm_state", state = ?VmState::Idle),
+            vm_span,
         }
     }
 
-    pub fn transition(&mut self, next: VmState) {
-        tracing::info!(from = ?self.state, to = ?next, task = ?self.task_id, "vm state transition");
+    /// Span for the current state. Work performed while in this state
+    /// should run inside it (`.instrument(vm.span().clone())`).
+    #[must_use]
+    pub fn span(&self) -> &tracing::Span {
+        &self.state_span
+    }
+
+    /// When the VM last entered `state` (Unix millis), if it ever did.
+    #[must_use]
+    pub fn entered_at(&self, state: &VmState) -> Option<u64> {
+        self.history
+            .iter()
+            .rev()
+            .find(|c| &c.state == state)
+            .map(|c| c.at_ms)
+    }
+
+    /// Provision, run and teardown times so far.
+    #[must_use]
+    pub fn durations(&self) -> PhaseDurations {
+        PhaseDurations::from_history(&self.history, now_millis())
+    }
+
+    /// Move to `next`, recording when it was entered.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::IllegalTransition` if the transition table
+    /// does not allow it; the state is left unchanged.
+    pub fn transition(&mut self, next: VmState) -> Result<(), SentinelError> {
+        if !self.state.can_transition(&next) {
+            return Err(SentinelError::IllegalTransition {
+                from: self.state.clone(),
+                to: next,
+            });
+        }
+        self.vm_span.in_scope(|| {
+            tracing::info!(
+                from = ?self.state,
+                to = ?next,
+                task = ?self.task_id,
+                trace_id = ?self.trace_id,
+                "vm state transition"
+            );
+        });
+        self.state_span = tracing::info_span!(parent: &self.vm_span, "vm_state", state = ?next);
+        self.history.push(StateChange {
+            state: next.clone(),
+            at_ms: now_millis(),
+        });
+        if next == VmState::Idle {
+            let durations = self.durations();
+            self.vm_span.in_scope(|| {
+                tracing::info!(
+                    task = ?self.task_id,
+                    provision_ms = ?durations.provision_ms,
+                    run_ms = ?durations.run_ms,
+                    teardown_ms = ?durations.teardown_ms,
+                    "vm lifecycle complete"
+                );
+            });
+        }
         self.state = next;
+        Ok(())
     }
 }
 
@@ -66,35 +240,36 @@ mod tests {
     fn happy_path_lifecycle() {
         let mut vm = VmLifecycle::new();
         vm.task_id = Some("task-1".into());
-        vm.transition(VmState::Provisioning);
+        vm.transition(VmState::Provisioning).unwrap();
         assert_eq!(vm.state, VmState::Provisioning);
-        vm.transition(VmState::Running);
+        vm.transition(VmState::Running).unwrap();
         assert_eq!(vm.state, VmState::Running);
-        vm.transition(VmState::Collecting);
+        vm.transition(VmState::Collecting).unwrap();
         assert_eq!(vm.state, VmState::Collecting);
-        vm.transition(VmState::Teardown);
+        vm.transition(VmState::Teardown).unwrap();
         assert_eq!(vm.state, VmState::Teardown);
-        vm.transition(VmState::Idle);
+        vm.transition(VmState::Idle).unwrap();
         assert_eq!(vm.state, VmState::Idle);
     }
 
     #[test]
     fn failure_during_provisioning() {
         let mut vm = VmLifecycle::new();
-        vm.transition(VmState::Provisioning);
-        vm.transition(VmState::Failed("disk full".into()));
+        vm.transition(VmState::Provisioning).unwrap();
+        vm.transition(VmState::Failed("disk full".into())).unwrap();
         assert_eq!(vm.state, VmState::Failed("disk full".into()));
-        vm.transition(VmState::Teardown);
+        vm.transition(VmState::Teardown).unwrap();
         assert_eq!(vm.state, VmState::Teardown);
     }
 
     #[test]
     fn timeout_during_running() {
         let mut vm = VmLifecycle::new();
-        vm.transition(VmState::Running);
-        vm.transition(VmState::Timeout);
+        vm.transition(VmState::Provisioning).unwrap();
+        vm.transition(VmState::Running).unwrap();
+        vm.transition(VmState::Timeout).unwrap();
         assert_eq!(vm.state, VmState::Timeout);
-        vm.transition(VmState::Teardown);
+        vm.transition(VmState::Teardown).unwrap();
         assert_eq!(vm.state, VmState::Teardown);
     }
 
@@ -102,11 +277,165 @@ mod tests {
     fn task_id_persists_through_transitions() {
         let mut vm = VmLifecycle::new();
         vm.task_id = Some("task-42".into());
-        vm.transition(VmState::Provisioning);
-        vm.transition(VmState::Running);
+        vm.transition(VmState::Provisioning).unwrap();
+        vm.transition(VmState::Running).unwrap();
         assert_eq!(vm.task_id.as_deref(), Some("task-42"));
     }
 
+    #[test]
+    fn for_task_carries_task_and_trace_id() {
+        let trace = TraceContext::new(Some("trace-abc".into()));
+        let mut vm = VmLifecycle::for_task("task-7", &trace);
+        assert_eq!(vm.state, VmState::Idle);
+        assert_eq!(vm.task_id.as_deref(), Some("task-7"));
+        vm.transition(VmState::Provisioning).unwrap();
+        vm.transition(VmState::Running).unwrap();
+        assert_eq!(vm.trace_id.as_deref(), Some("trace-abc"));
+    }
+
+    #[test]
+    fn history_records_every_state() {
+        let mut vm = VmLifecycle::new();
+        vm.transition(VmState::Provisioning).unwrap();
+        vm.transition(VmState::Failed("disk full".into())).unwrap();
+        let states: Vec<_> = vm.history.iter().map(|c| c.state.clone()).collect();
+        assert_eq!(
+            states,
+            [
+                VmState::Idle,
+                VmState::Provisioning,
+                VmState::Failed("disk full".into())
+            ]
+        );
+        assert!(vm.history.windows(2).all(|w| w[0].at_ms <= w[1].at_ms));
+    }
+
+    fn all_states() -> [VmState; 7] {
+        [
+            VmState::Idle,
+            VmState::Provisioning,
+            VmState::Running,
+            VmState::Collecting,
+            VmState::Teardown,
+            VmState::Failed("oom".into()),
+            VmState::Timeout,
+        ]
+    }
+
+    #[test]
+    fn transition_table_covers_every_edge() {
+        use VmState::{Collecting, Idle, Provisioning, Running, Teardown, Timeout};
+        let failed = VmState::Failed("oom".into());
+        let allowed = [
+            (Idle, Provisioning),
+            (Provisioning, Running),
+            (Provisioning, failed.clone()),
+            (Provisioning, Timeout),
+            (Running, Collecting),
+            (Running, failed.clone()),
+            (Running, Timeout),
+            (Collecting, Teardown),
+            (Collecting, failed.clone()),
+            (Collecting, Timeout),
+            (failed.clone(), Teardown),
+            (Timeout, Teardown),
+            (Teardown, Idle),
+        ];
+        for from in all_states() {
+            for to in all_states() {
+                let expected = allowed.contains(&(from.clone(), to.clone()));
+                assert_eq!(from.can_transition(&to), expected, "{from} -> {to}");
+            }
+        }
+    }
+
+    #[test]
+    fn failed_and_timeout_only_lead_to_teardown() {
+        for from in [VmState::Failed("oom".into()), VmState::Timeout] {
+            let next: Vec<_> = all_states()
+                .into_iter()
+                .filter(|to| from.can_transition(to))
+                .collect();
+            assert_eq!(next, [VmState::Teardown]);
+        }
+    }
+
+    #[test]
+    fn illegal_transition_rejected_and_state_kept() {
+        let mut vm = VmLifecycle::new();
+        let err = vm.transition(VmState::Collecting).unwrap_err();
+        assert!(matches!(
+            err,
+            SentinelError::IllegalTransition {
+                from: VmState::Idle,
+                to: VmState::Collecting
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "illegal vm transition from idle to collecting"
+        );
+        assert_eq!(vm.state, VmState::Idle);
+        assert_eq!(vm.history.len(), 1);
+    }
+
+    #[test]
+    fn entered_at_and_durations() {
+        let history = [
+            StateChange {
+                state: VmState::Idle,
+                at_ms: 1_000,
+            },
+            StateChange {
+                state: VmState::Provisioning,
+                at_ms: 1_000,
+            },
+            StateChange {
+                state: VmState::Running,
+                at_ms: 1_300,
+            },
+            StateChange {
+                state: VmState::Timeout,
+                at_ms: 9_300,
+            },
+            StateChange {
+                state: VmState::Teardown,
+                at_ms: 9_400,
+            },
+        ];
+        assert_eq!(
+            PhaseDurations::from_history(&history, 9_450),
+            PhaseDurations {
+                provision_ms: Some(300),
+                run_ms: Some(8_000),
+                teardown_ms: Some(50),
+            }
+        );
+        assert_eq!(
+            PhaseDurations::from_history(&history[..2], 1_100),
+            PhaseDurations {
+                provision_ms: Some(100),
+                ..PhaseDurations::default()
+            }
+        );
+
+        let mut vm = VmLifecycle::new();
+        assert!(vm.entered_at(&VmState::Running).is_none());
+        vm.transition(VmState::Provisioning).unwrap();
+        assert_eq!(
+            vm.entered_at(&VmState::Provisioning),
+            Some(vm.history[1].at_ms)
+        );
+        assert!(vm.durations().provision_ms.is_some());
+        assert!(vm.durations().run_ms.is_none());
+    }
+
+    #[test]
+    fn vm_state_display() {
+        assert_eq!(VmState::Running.to_string(), "running");
+        assert_eq!(VmState::Failed("oom".into()).to_string(), "failed: oom");
+    }
+
     #[test]
     fn vm_state_equality() {
         assert_eq!(VmState::Idle, VmState::Idle);
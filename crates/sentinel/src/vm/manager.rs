@@ -1,42 +1,1002 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::process::{Child, Command};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use super::config::FirecrackerConfig;
+use super::console::{self, ConsoleLog};
+use super::jailer::Jail;
+use super::supervisor::{self, ProcessExit};
+use super::telemetry::{self, VmTelemetry};
 use crate::error::SentinelError;
 
-/// Firecracker API client — communicates over Unix socket.
+/// How long Firecracker gets to create its API socket once spawned.
+const API_STARTUP: Duration = Duration::from_secs(5);
+
+/// How often the API socket is retried while Firecracker starts.
+const API_RETRY: Duration = Duration::from_millis(10);
+
+/// Longest API response read, status line, headers and body together.
+const MAX_API_RESPONSE: u64 = 64 * 1024;
+
+/// How long Firecracker gets to flush its metrics before teardown kills it.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Files of a snapshot, in its directory.
+const SNAPSHOT_STATE: &str = "vmstate";
+const SNAPSHOT_MEMORY: &str = "memory";
+
+/// Where a jailed Firecracker writes a snapshot, inside its chroot.
+const JAIL_SNAPSHOT_DIR: &str = "/snapshot";
+
+/// How long a SIGKILLed process from an earlier run gets to disappear.
+const KILL_WAIT: Duration = Duration::from_secs(2);
+
+/// Applies one update to a VM's telemetry totals, wherever they are kept.
+type RecordTelemetry = Box<dyn Fn(&mut dyn FnMut(&mut VmTelemetry)) + Send + Sync>;
+
+/// Where a VM's output goes while Firecracker runs.
+pub struct VmOutput {
+    console: ConsoleLog,
+    on_line: Box<dyn FnMut(&str) + Send>,
+    telemetry: RecordTelemetry,
+}
+
+impl VmOutput {
+    /// Serial console into `console`; lines and telemetry go nowhere else.
+    #[must_use]
+    pub fn new(console: ConsoleLog) -> Self {
+        Self {
+            console,
+            on_line: Box::new(|_| {}),
+            telemetry: Box::new(|_| {}),
+        }
+    }
+
+    /// Hand each console line to `f` as well, e.g.
+    /// [`VmEntry::push_console`](crate::registry::VmEntry::push_console).
+    #[must_use]
+    pub fn on_console_line(mut self, f: impl FnMut(&str) + Send + 'static) -> Self {
+        self.on_line = Box::new(f);
+        self
+    }
+
+    /// Apply each telemetry update to `f`'s totals, e.g.
+    /// [`VmEntry::record_telemetry`](crate::registry::VmEntry::record_telemetry).
+    #[must_use]
+    pub fn on_telemetry(
+        mut self,
+        f: impl Fn(&mut dyn FnMut(&mut VmTelemetry)) + Send + Sync + 'static,
+    ) -> Self {
+        self.telemetry = Box::new(f);
+        self
+    }
+}
+
+/// A Firecracker process this manager spawned.
+struct Process {
+    pid: u32,
+    /// Whether Firecracker was given a metrics FIFO to flush at teardown.
+    metrics: bool,
+    /// The jail it runs in, which snapshots must be written inside.
+    jail: Option<Jail>,
+    /// Cancelled by teardown; the supervisor then SIGKILLs the process.
+    kill: CancellationToken,
+    /// Set once the supervisor has reaped the process.
+    exit: watch::Receiver<Option<ProcessExit>>,
+}
+
+/// Spawns Firecracker, drives its API over the Unix socket and owns the
+/// process until teardown.
 ///
 /// Handles: PUT /machine-config, PUT /boot-source, PUT /drives/*, PUT /vsock,
 /// PUT /actions (`InstanceStart`), etc.
-#[allow(dead_code)]
 pub struct VmManager {
-    pub(crate) firecracker_bin: std::path::PathBuf,
+    pub(crate) firecracker_bin: PathBuf,
+    processes: Mutex<HashMap<u32, Process>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
 impl VmManager {
     #[must_use]
-    pub fn new(firecracker_bin: std::path::PathBuf) -> Self {
-        Self { firecracker_bin }
+    pub fn new(firecracker_bin: PathBuf) -> Self {
+        Self {
+            firecracker_bin,
+            processes: Mutex::new(HashMap::new()),
+        }
     }
 
+    /// Spawn Firecracker for `config`, configure it over its API socket and
+    /// start the instance. The process is reaped by
+    /// [`supervise`](supervisor::supervise) until teardown kills it.
+    ///
+    /// Until the process exits, its stdout (the guest's serial console) is
+    /// captured into `output`, and so are its log and metrics if `config`
+    /// has telemetry FIFOs.
+    ///
     /// # Errors
     ///
-    /// Returns `SentinelError::Vm` on spawn or configuration failure.
+    /// Returns `SentinelError::Vm` if Firecracker cannot be spawned, cannot
+    /// be moved into its cgroup, its API does not come up, or it rejects the
+    /// configuration. The process is killed before returning.
     pub async fn create_vm(
         &self,
-        _config: &super::config::FirecrackerConfig,
+        config: &FirecrackerConfig,
+        output: VmOutput,
     ) -> Result<VmHandle, SentinelError> {
-        // TODO: spawn firecracker process, configure via API, start instance
-        Err(SentinelError::Vm("not implemented".into()))
+        let api_socket = config.api_socket();
+        if let Some(dir) = api_socket.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        remove_stale(&api_socket).await?;
+        remove_stale(&config.host_socket_path()).await?;
+        let fifos = config.host_fifos().map(|f| f.open()).transpose()?;
+
+        let mut cmd = match &config.jail {
+            // The jailer applies the cgroup's limits and execs Firecracker
+            // in place, so the pid is Firecracker's.
+            Some(jail) => jail.command(config.cgroup.as_ref()),
+            None => {
+                let mut cmd = Command::new(&self.firecracker_bin);
+                cmd.arg("--api-sock").arg(&api_socket);
+                cmd
+            }
+        };
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                SentinelError::Vm(format!("spawn {}: {e}", self.firecracker_bin.display()))
+            })?;
+        let pid = child
+            .id()
+            .ok_or_else(|| SentinelError::Vm("firecracker exited as it was spawned".into()))?;
+        let handle = VmHandle {
+            cid: config.vsock_cid,
+            pid,
+            socket_path: api_socket,
+        };
+        let VmOutput {
+            console,
+            on_line,
+            telemetry,
+        } = output;
+        let cid = handle.cid;
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(async move {
+                if let Err(e) = console::capture(stdout, console, on_line).await {
+                    tracing::warn!(cid, error = %e, "console capture failed");
+                }
+            });
+        }
+        let exited = CancellationToken::new();
+        if let Some(readers) = fifos {
+            let done = exited.clone();
+            tokio::spawn(async move {
+                telemetry::ingest(cid, readers, &done, telemetry).await;
+            });
+        }
+        let exit = self.supervise(config, pid, child, exited);
+
+        let setup = async {
+            // The jailer places a jailed VM itself.
+            if config.jail.is_none()
+                && let Some(cgroup) = &config.cgroup
+            {
+                cgroup.attach(pid).await?;
+            }
+            configure(config, &handle.socket_path, exit).await
+        };
+        if let Err(e) = setup.await {
+            tracing::warn!(
+                cid = handle.cid,
+                pid,
+                error = %e,
+                "firecracker setup failed, killing it"
+            );
+            if let Err(kill) = self.kill_vm(&handle).await {
+                tracing::error!(cid = handle.cid, pid, error = %kill, "firecracker kill failed");
+            }
+            return Err(e);
+        }
+        tracing::info!(cid = handle.cid, pid, "firecracker started");
+        Ok(handle)
+    }
+
+    /// Reap `child` in the background, SIGKILLing it once teardown cancels
+    /// its kill token, and cancel `exited` once it is gone.
+    fn supervise(
+        &self,
+        config: &FirecrackerConfig,
+        pid: u32,
+        child: Child,
+        exited: CancellationToken,
+    ) -> watch::Receiver<Option<ProcessExit>> {
+        let cid = config.vsock_cid;
+        let kill = CancellationToken::new();
+        let (reaped, exit) = watch::channel(None);
+        let token = kill.clone();
+        tokio::spawn(async move {
+            match supervisor::supervise(child, token).await {
+                Ok(exit) => {
+                    tracing::info!(
+                        cid,
+                        code = ?exit.code,
+                        signal = ?exit.signal,
+                        "firecracker exited"
+                    );
+                    reaped.send_replace(Some(exit));
+                }
+                Err(e) => tracing::error!(cid, error = %e, "firecracker could not be reaped"),
+            }
+            exited.cancel();
+        });
+        lock(&self.processes).insert(
+            cid,
+            Process {
+                pid,
+                metrics: config.metrics_json().is_some(),
+                jail: config.jail.clone(),
+                kill,
+                exit: exit.clone(),
+            },
+        );
+        exit
+    }
+
+    /// How the Firecracker process behind `handle` ended, once it has.
+    /// `None` if this manager did not spawn it, it was already killed by
+    /// teardown, or it could not be reaped.
+    pub async fn exited(&self, handle: &VmHandle) -> Option<ProcessExit> {
+        let exit = lock(&self.processes).get(&handle.cid)?.exit.clone();
+        wait(exit).await
+    }
+
+    /// Kill `handle`'s process if this manager spawned it, and wait until it
+    /// is reaped.
+    async fn reap(&self, handle: &VmHandle) -> Option<ProcessExit> {
+        let process = {
+            let mut processes = lock(&self.processes);
+            if processes.get(&handle.cid)?.pid != handle.pid {
+                return None;
+            }
+            processes.remove(&handle.cid)?
+        };
+        process.kill.cancel();
+        wait(process.exit).await
+    }
+
+    /// Pause the VM and write a full snapshot (`vmstate` and `memory`) into
+    /// `dest_dir`, returning the directory to record for resumption. The VM
+    /// is left paused for teardown. A jailed VM writes into its chroot and
+    /// the files are moved out.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Vm` if this manager did not start the VM or
+    /// Firecracker cannot pause or snapshot it, and `SentinelError::Io` if
+    /// the snapshot cannot be placed in `dest_dir`.
+    pub async fn snapshot_vm(
+        &self,
+        handle: &VmHandle,
+        dest_dir: &Path,
+    ) -> Result<PathBuf, SentinelError> {
+        let jail = match lock(&self.processes).get(&handle.cid) {
+            Some(process) if process.pid == handle.pid => process.jail.clone(),
+            _ => {
+                return Err(SentinelError::Vm(format!(
+                    "vm {} was not started by this sentinel, cannot snapshot it",
+                    handle.cid
+                )));
+            }
+        };
+        tokio::fs::create_dir_all(dest_dir).await?;
+        // A jailed Firecracker can only write inside its chroot, as its uid.
+        let (inside, written) = match &jail {
+            Some(jail) => {
+                let staging = jail.host_path(Path::new(JAIL_SNAPSHOT_DIR));
+                tokio::fs::create_dir_all(&staging).await?;
+                std::os::unix::fs::chown(&staging, Some(jail.uid), Some(jail.gid))?;
+                (PathBuf::from(JAIL_SNAPSHOT_DIR), staging)
+            }
+            None => (dest_dir.to_path_buf(), dest_dir.to_path_buf()),
+        };
+
+        let socket = &handle.socket_path;
+        api(
+            socket,
+            "PATCH",
+            "/vm",
+            &serde_json::json!({ "state": "Paused" }),
+        )
+        .await?;
+        let create = serde_json::json!({
+            "snapshot_type": "Full",
+            "snapshot_path": inside.join(SNAPSHOT_STATE),
+            "mem_file_path": inside.join(SNAPSHOT_MEMORY),
+        });
+        api(socket, "PUT", "/snapshot/create", &create).await?;
+        if jail.is_some() {
+            for file in [SNAPSHOT_STATE, SNAPSHOT_MEMORY] {
+                move_file(&written.join(file), &dest_dir.join(file)).await?;
+            }
+        }
+        tracing::info!(cid = handle.cid, dir = %dest_dir.display(), "vm snapshotted");
+        Ok(dest_dir.to_path_buf())
     }
 
+    /// SIGKILL the Firecracker process without going through its API and
+    /// remove its API socket. A process this manager spawned is reaped by
+    /// its supervisor. One left by an earlier sentinel run is signalled by
+    /// pid and waited on until it is gone, unless the pid now belongs to
+    /// another program.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Vm` if the process cannot be killed or the
+    /// socket cannot be removed.
+    pub async fn kill_vm(&self, handle: &VmHandle) -> Result<(), SentinelError> {
+        if self.reap(handle).await.is_none() {
+            kill_orphan(handle.pid, &self.firecracker_bin).await?;
+        }
+        remove_stale(&handle.socket_path)
+            .await
+            .map_err(|e| SentinelError::Vm(format!("remove {}: {e}", handle.socket_path.display())))
+    }
+
+    /// Ask Firecracker to flush its metrics, so the totals cover the whole
+    /// run, then [`kill_vm`](Self::kill_vm). A failed flush is logged and
+    /// does not stop the kill.
+    ///
     /// # Errors
     ///
     /// Returns `SentinelError::Vm` on cleanup failure.
-    pub async fn destroy_vm(&self, _handle: &VmHandle) -> Result<(), SentinelError> {
-        // TODO: kill firecracker process, cleanup socket
-        Ok(())
+    pub async fn destroy_vm(&self, handle: &VmHandle) -> Result<(), SentinelError> {
+        let flush = lock(&self.processes)
+            .get(&handle.cid)
+            .is_some_and(|p| p.pid == handle.pid && p.metrics);
+        if flush {
+            let action = serde_json::json!({ "action_type": "FlushMetrics" });
+            let flushed = tokio::time::timeout(
+                FLUSH_TIMEOUT,
+                api(&handle.socket_path, "PUT", "/actions", &action),
+            )
+            .await;
+            match flushed {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::warn!(cid = handle.cid, error = %e, "metrics flush failed"),
+                Err(_) => tracing::warn!(cid = handle.cid, "metrics flush timed out"),
+            }
+        }
+        self.kill_vm(handle).await
     }
 }
 
+/// The reaped exit, or `None` if the supervisor gave up on the process.
+async fn wait(mut exit: watch::Receiver<Option<ProcessExit>>) -> Option<ProcessExit> {
+    exit.wait_for(Option::is_some)
+        .await
+        .ok()
+        .and_then(|exit| *exit)
+}
+
+/// Argument zero of `pid`, or `None` once it has exited. Zombies have an
+/// empty command line, so they count as exited.
+async fn argv0(pid: u32) -> Option<PathBuf> {
+    let cmdline = tokio::fs::read(format!("/proc/{pid}/cmdline")).await.ok()?;
+    let argv0 = cmdline
+        .split(|b| *b == 0)
+        .next()
+        .filter(|a| !a.is_empty())?;
+    Some(PathBuf::from(OsStr::from_bytes(argv0)))
+}
+
+/// SIGKILL a Firecracker process this manager did not spawn and wait for it
+/// to go. A pid that has exited, or was reused by a program other than
+/// `bin`, is left alone.
+async fn kill_orphan(pid: u32, bin: &Path) -> Result<(), SentinelError> {
+    let Some(program) = argv0(pid).await else {
+        return Ok(());
+    };
+    if program.file_name() != bin.file_name() {
+        tracing::warn!(
+            pid,
+            program = %program.display(),
+            "pid is no longer firecracker, not killing it"
+        );
+        return Ok(());
+    }
+    let status = Command::new("kill")
+        .args(["-KILL", &pid.to_string()])
+        .status()
+        .await
+        .map_err(|e| SentinelError::Vm(format!("kill {pid}: {e}")))?;
+    let gone = async {
+        while argv0(pid).await.is_some() {
+            tokio::time::sleep(API_RETRY).await;
+        }
+    };
+    if tokio::time::timeout(KILL_WAIT, gone).await.is_err() {
+        return Err(SentinelError::Vm(format!(
+            "firecracker {pid} still running after SIGKILL ({status})"
+        )));
+    }
+    tracing::info!(pid, "killed firecracker from an earlier run");
+    Ok(())
+}
+
+/// Rename `from` to `to`, copying across filesystems (the jail base and the
+/// snapshot directory need not share one).
+async fn move_file(from: &Path, to: &Path) -> Result<(), SentinelError> {
+    match tokio::fs::rename(from, to).await {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            tokio::fs::copy(from, to).await?;
+            tokio::fs::remove_file(from).await?;
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
+
+/// Remove a socket left by an earlier VM with the same path.
+async fn remove_stale(path: &Path) -> Result<(), SentinelError> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Wait for Firecracker's API, send it `config` and start the instance.
+/// The logger and metrics go first, so boot is covered.
+async fn configure(
+    config: &FirecrackerConfig,
+    socket: &Path,
+    exit: watch::Receiver<Option<ProcessExit>>,
+) -> Result<(), SentinelError> {
+    wait_for_api(socket, exit).await?;
+    if let Some(logger) = config.logger_json() {
+        api(socket, "PUT", "/logger", &logger).await?;
+    }
+    if let Some(metrics) = config.metrics_json() {
+        api(socket, "PUT", "/metrics", &metrics).await?;
+    }
+    api(
+        socket,
+        "PUT",
+        "/machine-config",
+        &config.machine_config_json(),
+    )
+    .await?;
+    api(socket, "PUT", "/boot-source", &config.boot_source_json()?).await?;
+    api(socket, "PUT", "/drives/rootfs", &config.rootfs_drive_json()).await?;
+    api(socket, "PUT", "/vsock", &config.vsock_json()).await?;
+    api(
+        socket,
+        "PUT",
+        "/actions",
+        &serde_json::json!({ "action_type": "InstanceStart" }),
+    )
+    .await
+}
+
+/// Retry `socket` until Firecracker listens on it, giving up if the
+/// process exits or [`API_STARTUP`] passes.
+async fn wait_for_api(
+    socket: &Path,
+    exit: watch::Receiver<Option<ProcessExit>>,
+) -> Result<(), SentinelError> {
+    let ready = async {
+        loop {
+            if exit.borrow().is_some() || exit.has_changed().is_err() {
+                return Err(SentinelError::Vm(
+                    "firecracker exited before its API came up".into(),
+                ));
+            }
+            if UnixStream::connect(socket).await.is_ok() {
+                return Ok(());
+            }
+            tokio::time::sleep(API_RETRY).await;
+        }
+    };
+    tokio::time::timeout(API_STARTUP, ready)
+        .await
+        .map_err(|_| {
+            SentinelError::Vm(format!(
+                "firecracker API {} not up after {}s",
+                socket.display(),
+                API_STARTUP.as_secs()
+            ))
+        })?
+}
+
+/// One request to Firecracker's API on `socket`, a connection each.
+/// Firecracker answers 204 on success, otherwise a JSON `fault_message`.
+async fn api(socket: &Path, method: &str, path: &str, body: &Value) -> Result<(), SentinelError> {
+    let failed = |detail: &dyn std::fmt::Display| {
+        SentinelError::Vm(format!("firecracker {method} {path}: {detail}"))
+    };
+    let body = serde_json::to_vec(body)?;
+    let mut stream = UnixStream::connect(socket).await.map_err(|e| failed(&e))?;
+    let head = format!(
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\nAccept: application/json\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+
+    let mut response = BufReader::new(stream.take(MAX_API_RESPONSE));
+    let mut line = String::new();
+    response.read_line(&mut line).await?;
+    let status: u16 = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| failed(&format_args!("malformed response {:?}", line.trim_end())))?;
+    let mut length = 0;
+    loop {
+        line.clear();
+        if response.read_line(&mut line).await? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            length = value.trim().parse().unwrap_or(0);
+        }
+    }
+    if (200..300).contains(&status) {
+        return Ok(());
+    }
+    let mut fault = Vec::new();
+    response.take(length).read_to_end(&mut fault).await?;
+    let message = serde_json::from_slice::<Value>(&fault)
+        .ok()
+        .and_then(|fault| fault["fault_message"].as_str().map(String::from))
+        .unwrap_or_else(|| String::from_utf8_lossy(&fault).trim().to_string());
+    Err(failed(&format_args!("{status} {message}")))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct VmHandle {
     pub cid: u32,
     pub pid: u32,
-    pub socket_path: std::path::PathBuf,
+    /// Host path of the API socket.
+    pub socket_path: PathBuf,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::process::ExitStatusExt;
+    use std::sync::Arc;
+    use tokio::net::UnixListener;
+
+    use crate::vm::cgroup::{CgroupLimits, VmCgroup};
+    use crate::vm::cmdline::KernelCmdline;
+    use crate::vm::jailer::Jail;
+    use crate::vm::telemetry::Fifos;
+
+    /// Stand-in for the Firecracker binary: writes its pid and arguments to
+    /// `{bin}.args`, prints a console line and sleeps until killed.
+    pub(crate) fn fake_firecracker(dir: &Path) -> PathBuf {
+        let bin = dir.join("firecracker");
+        std::fs::write(
+            &bin,
+            "#!/bin/sh\necho \"$$ $*\" > \"$0.args.tmp\"\nmv \"$0.args.tmp\" \"$0.args\"\n\
+             echo 'Linux version 6.1'\nexec sleep 30\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&bin, std::fs::Permissions::from_mode(0o755)).unwrap();
+        bin
+    }
+
+    /// Serve Firecracker's API on `socket` once the fake has started,
+    /// recording `METHOD path body` per request. Requests whose path is
+    /// `reject` get a 400 with a fault message.
+    pub(crate) fn fake_api(
+        bin: &Path,
+        socket: PathBuf,
+        reject: Option<&'static str>,
+    ) -> Arc<Mutex<Vec<String>>> {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&requests);
+        let started = bin.with_extension("args");
+        tokio::spawn(async move {
+            while !started.exists() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            let listener = UnixListener::bind(&socket).unwrap();
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = BufReader::new(stream);
+                let mut head = String::new();
+                if stream.read_line(&mut head).await.unwrap() == 0 {
+                    continue;
+                }
+                let mut length = 0;
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    stream.read_line(&mut line).await.unwrap();
+                    if line.trim_end().is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("Content-Length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).await.unwrap();
+                let target: Vec<&str> = head.split_whitespace().take(2).collect();
+                let response = if reject == Some(target[1]) {
+                    let fault = r#"{"fault_message":"bad vcpu count"}"#;
+                    format!(
+                        "HTTP/1.1 400 Bad Request\r\nContent-Length: {}\r\n\r\n{fault}",
+                        fault.len()
+                    )
+                } else {
+                    "HTTP/1.1 204 No Content\r\n\r\n".to_string()
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+                seen.lock().unwrap().push(format!(
+                    "{} {}",
+                    target.join(" "),
+                    String::from_utf8(body).unwrap()
+                ));
+            }
+        });
+        requests
+    }
+
+    /// Pid and arguments the fake was started with.
+    pub(crate) fn fake_args(bin: &Path) -> (u32, String) {
+        let args = std::fs::read_to_string(bin.with_extension("args")).unwrap();
+        let (pid, args) = args.trim_end().split_once(' ').unwrap();
+        (pid.parse().unwrap(), args.to_string())
+    }
+
+    pub(crate) fn test_config(dir: &Path) -> FirecrackerConfig {
+        FirecrackerConfig {
+            vcpus: 2,
+            mem_mb: 512,
+            kernel_path: PathBuf::from("/opt/vmlinux"),
+            initrd_path: None,
+            boot_args: KernelCmdline::firecracker_defaults(),
+            rootfs_path: PathBuf::from("/o/vm-3.ext4"),
+            vsock_cid: 3,
+            socket_path: dir.join("fc-3.sock"),
+            telemetry: None,
+            jail: None,
+            cgroup: None,
+        }
+    }
+
+    /// Console log under `dir` and the lines handed on from it.
+    pub(crate) fn test_console(dir: &Path) -> (ConsoleLog, Arc<Mutex<Vec<String>>>) {
+        let log = ConsoleLog::create(&dir.join("console"), "task-1", 4096).unwrap();
+        (log, Arc::new(Mutex::new(Vec::new())))
+    }
+
+    fn running(pid: u32) -> bool {
+        Path::new(&format!("/proc/{pid}")).exists()
+    }
+
+    #[tokio::test]
+    async fn create_configures_and_starts_then_teardown_kills() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bin = fake_firecracker(tmp.path());
+        let config = test_config(tmp.path());
+        let requests = fake_api(&bin, config.api_socket(), None);
+        let vms = VmManager::new(bin.clone());
+
+        let (console, lines) = test_console(tmp.path());
+        let seen = Arc::clone(&lines);
+        let output = VmOutput::new(console).on_console_line(move |line| {
+            seen.lock().unwrap().push(line.to_string());
+        });
+        let handle = vms.create_vm(&config, output).await.unwrap();
+        let (pid, args) = fake_args(&bin);
+        assert_eq!(handle.pid, pid);
+        assert_eq!(handle.socket_path, tmp.path().join("fc-3.api.sock"));
+        assert_eq!(args, format!("--api-sock {}", handle.socket_path.display()));
+        let requests = requests.lock().unwrap().clone();
+        let paths: Vec<&str> = requests
+            .iter()
+            .map(|r| r.split(' ').nth(1).unwrap())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "/machine-config",
+                "/boot-source",
+                "/drives/rootfs",
+                "/vsock",
+                "/actions"
+            ]
+        );
+        assert!(requests[0].ends_with(r#"{"mem_size_mib":512,"vcpu_count":2}"#));
+        assert!(requests[4].ends_with(r#"{"action_type":"InstanceStart"}"#));
+
+        for _ in 0..100 {
+            if !lines.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(*lines.lock().unwrap(), ["Linux version 6.1"]);
+        assert_eq!(
+            console::read_tail(&tmp.path().join("console/task-1"), 1024).unwrap(),
+            "Linux version 6.1\n"
+        );
+
+        assert!(running(pid));
+        vms.destroy_vm(&handle).await.unwrap();
+        assert!(!running(pid));
+        assert!(vms.exited(&handle).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn telemetry_fifos_are_configured_flushed_and_read_until_exit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bin = fake_firecracker(tmp.path());
+        let fifos = Fifos::beside(&tmp.path().join("fc-3.sock"));
+        fifos.create().await.unwrap();
+        let config = test_config(tmp.path()).with_telemetry(fifos.clone());
+        let requests = fake_api(&bin, config.api_socket(), None);
+        let vms = VmManager::new(bin);
+        let totals = Arc::new(Mutex::new(VmTelemetry::default()));
+        let (console, _) = test_console(tmp.path());
+        let recorded = Arc::clone(&totals);
+        let output = VmOutput::new(console).on_telemetry(move |f| f(&mut recorded.lock().unwrap()));
+
+        let handle = vms.create_vm(&config, output).await.unwrap();
+        let paths: Vec<String> = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| r.split(' ').nth(1).unwrap().to_string())
+            .collect();
+        assert_eq!(paths[..3], ["/logger", "/metrics", "/machine-config"]);
+
+        for (path, line) in [
+            (&fifos.metrics, r#"{"block":{"write_count":1}}"#),
+            (&fifos.log, "t [fc:main:ERROR] boom"),
+        ] {
+            let mut tx = tokio::net::unix::pipe::OpenOptions::new()
+                .open_sender(path)
+                .unwrap();
+            tx.write_all(format!("{line}\n").as_bytes()).await.unwrap();
+        }
+        vms.destroy_vm(&handle).await.unwrap();
+        assert!(
+            requests
+                .lock()
+                .unwrap()
+                .last()
+                .unwrap()
+                .ends_with(r#"{"action_type":"FlushMetrics"}"#)
+        );
+        for _ in 0..100 {
+            if totals.lock().unwrap().log.errors == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let totals = totals.lock().unwrap().clone();
+        assert_eq!(totals.metrics.block.write_count, 1);
+        assert_eq!(totals.log.errors, 1);
+    }
+
+    #[tokio::test]
+    async fn unjailed_vm_is_moved_into_its_cgroup() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bin = fake_firecracker(tmp.path());
+        let cgroup = VmCgroup {
+            root: tmp.path().join("cgroup"),
+            parent: "gbe-sentinel".into(),
+            name: "vm-3".into(),
+            limits: CgroupLimits {
+                cpu_max: "220000 100000".into(),
+                memory_max: 576 << 20,
+                io_max: None,
+            },
+        };
+        std::fs::create_dir_all(cgroup.path()).unwrap();
+        let config = test_config(tmp.path()).with_cgroup(cgroup.clone());
+        fake_api(&bin, config.api_socket(), None);
+        let vms = VmManager::new(bin);
+
+        let (console, _) = test_console(tmp.path());
+        let handle = vms
+            .create_vm(&config, VmOutput::new(console))
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(cgroup.path().join("cgroup.procs")).unwrap(),
+            handle.pid.to_string()
+        );
+        vms.destroy_vm(&handle).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn jailed_vm_is_started_through_the_jailer() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bin = fake_firecracker(tmp.path());
+        let jail = Jail {
+            id: "vm-3".into(),
+            jailer_bin: bin.clone(),
+            exec_file: PathBuf::from("/usr/bin/firecracker"),
+            chroot_base: tmp.path().join("jails"),
+            uid: 10_003,
+            gid: 10_003,
+            netns: None,
+        };
+        let mut config = test_config(tmp.path());
+        config.socket_path = PathBuf::from("/v.sock");
+        config.jail = Some(jail.clone());
+        fake_api(&bin, config.api_socket(), None);
+        let vms = VmManager::new(PathBuf::from("/usr/bin/firecracker"));
+
+        let (console, _) = test_console(tmp.path());
+        let handle = vms
+            .create_vm(&config, VmOutput::new(console))
+            .await
+            .unwrap();
+        assert_eq!(handle.socket_path, jail.api_socket());
+        let (_, args) = fake_args(&bin);
+        assert!(args.starts_with("--id vm-3 --exec-file /usr/bin/firecracker"));
+        assert!(args.ends_with("-- --api-sock /run/firecracker.socket"));
+        vms.destroy_vm(&handle).await.unwrap();
+        assert!(!handle.socket_path.exists());
+    }
+
+    #[tokio::test]
+    async fn snapshot_pauses_then_writes_into_dest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bin = fake_firecracker(tmp.path());
+        let config = test_config(tmp.path());
+        let requests = fake_api(&bin, config.api_socket(), None);
+        let vms = VmManager::new(bin);
+        let (console, _) = test_console(tmp.path());
+        let handle = vms
+            .create_vm(&config, VmOutput::new(console))
+            .await
+            .unwrap();
+
+        let dest = tmp.path().join("snapshots/t1");
+        assert_eq!(vms.snapshot_vm(&handle, &dest).await.unwrap(), dest);
+        assert!(dest.is_dir());
+        let requests = requests.lock().unwrap().clone();
+        assert_eq!(requests[5], r#"PATCH /vm {"state":"Paused"}"#);
+        let create: Value =
+            serde_json::from_str(requests[6].strip_prefix("PUT /snapshot/create ").unwrap())
+                .unwrap();
+        assert_eq!(create["snapshot_type"], "Full");
+        assert_eq!(
+            create["mem_file_path"],
+            dest.join("memory").to_str().unwrap()
+        );
+
+        vms.destroy_vm(&handle).await.unwrap();
+        let err = vms.snapshot_vm(&handle, &dest).await.unwrap_err();
+        assert!(err.to_string().contains("not started by this sentinel"));
+    }
+
+    #[tokio::test]
+    async fn rejected_config_kills_firecracker() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bin = fake_firecracker(tmp.path());
+        let config = test_config(tmp.path());
+        let requests = fake_api(&bin, config.api_socket(), Some("/boot-source"));
+        let vms = VmManager::new(bin.clone());
+
+        let (console, _) = test_console(tmp.path());
+        let err = vms
+            .create_vm(&config, VmOutput::new(console))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "vm error: firecracker PUT /boot-source: 400 bad vcpu count"
+        );
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert!(!running(fake_args(&bin).0));
+    }
+
+    #[tokio::test]
+    async fn exit_of_its_own_is_reported() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bin = fake_firecracker(tmp.path());
+        let config = test_config(tmp.path());
+        fake_api(&bin, config.api_socket(), None);
+        let vms = VmManager::new(bin);
+
+        let (console, _) = test_console(tmp.path());
+        let handle = vms
+            .create_vm(&config, VmOutput::new(console))
+            .await
+            .unwrap();
+        let status = tokio::process::Command::new("kill")
+            .args(["-TERM", &handle.pid.to_string()])
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+        let exit = vms.exited(&handle).await.unwrap();
+        assert_eq!(exit.signal, Some(15));
+        assert!(!exit.killed_by_sentinel);
+    }
+
+    #[tokio::test]
+    async fn kill_removes_a_process_from_an_earlier_run() {
+        let tmp = tempfile::tempdir().unwrap();
+        let socket = tmp.path().join("fc-3.api.sock");
+        std::fs::write(&socket, "").unwrap();
+        let mut orphan = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let handle = VmHandle {
+            cid: 3,
+            pid: orphan.id(),
+            socket_path: socket.clone(),
+        };
+
+        VmManager::new(PathBuf::from("/usr/bin/sleep"))
+            .kill_vm(&handle)
+            .await
+            .unwrap();
+        assert_eq!(orphan.wait().unwrap().signal(), Some(9));
+        assert!(!socket.exists());
+    }
+
+    #[tokio::test]
+    async fn kill_leaves_a_reused_pid_alone() {
+        let mut other = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let handle = VmHandle {
+            cid: 3,
+            pid: other.id(),
+            socket_path: PathBuf::from("/nonexistent/fc-3.api.sock"),
+        };
+
+        VmManager::new(PathBuf::from("/usr/bin/firecracker"))
+            .kill_vm(&handle)
+            .await
+            .unwrap();
+        assert!(running(other.id()));
+        other.kill().unwrap();
+        other.wait().unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_binary_is_a_vm_error() {
+        let tmp = tempfile::tempdir().unwrap();
+        let vms = VmManager::new(tmp.path().join("nope"));
+        let (console, _) = test_console(tmp.path());
+        let err = vms
+            .create_vm(&test_config(tmp.path()), VmOutput::new(console))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SentinelError::Vm(_)));
+    }
 }
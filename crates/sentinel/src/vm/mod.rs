@@ -1,5 +1,13 @@
+pub mod cgroup;
+pub mod cmdline;
 pub mod config;
+pub mod console;
+pub mod deadline;
+pub mod jailer;
 pub mod lifecycle;
 pub mod manager;
 pub mod network;
 pub mod overlay;
+pub mod supervisor;
+pub mod teardown;
+pub mod telemetry;
@@ -25,6 +25,7 @@ impl NetworkSetup {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct TapDevice {
     pub name: String,
     pub ip: String,
@@ -0,0 +1,246 @@
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Child;
+use tokio_util::sync::CancellationToken;
+
+use crate::claim::now_millis;
+use crate::error::SentinelError;
+
+/// SIGKILL, what `Child::start_kill` sends.
+const SIGKILL: i32 = 9;
+
+/// Console line the guest kernel prints before `panic=1` reboots it.
+const KERNEL_PANIC: &str = "Kernel panic - not syncing";
+
+/// How a Firecracker process ended, as reaped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessExit {
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+    /// The sentinel sent the kill (timeout, operator kill, preemption).
+    pub killed_by_sentinel: bool,
+    pub at_ms: u64,
+}
+
+impl ProcessExit {
+    #[must_use]
+    pub fn from_status(status: ExitStatus, killed_by_sentinel: bool) -> Self {
+        Self {
+            code: status.code(),
+            signal: status.signal(),
+            killed_by_sentinel,
+            at_ms: now_millis(),
+        }
+    }
+}
+
+/// Reap `child`, killing it first if `kill` is cancelled.
+///
+/// A process that exits on its own while the kill is in flight is not
+/// counted as killed by the sentinel.
+///
+/// # Errors
+///
+/// Returns `SentinelError::Io` if the child cannot be waited on.
+pub async fn supervise(
+    mut child: Child,
+    kill: CancellationToken,
+) -> Result<ProcessExit, SentinelError> {
+    tokio::select! {
+        status = child.wait() => return Ok(ProcessExit::from_status(status?, false)),
+        () = kill.cancelled() => {}
+    }
+    if let Err(e) = child.start_kill() {
+        tracing::debug!(error = %e, "firecracker already gone");
+    }
+    let status = child.wait().await?;
+    let killed = status.signal() == Some(SIGKILL);
+    Ok(ProcessExit::from_status(status, killed))
+}
+
+/// What the sentinel knew about the VM when its process exited, beyond
+/// the exit status.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExitEvidence<'a> {
+    /// The operative sent `result` or `error` before the exit.
+    pub result_received: bool,
+    /// The end of the guest's serial console.
+    pub console_tail: &'a str,
+    /// The kernel OOM killer hit the VM's cgroup.
+    pub oom_killed: bool,
+}
+
+/// Why a VM's Firecracker process exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ExitKind {
+    /// The guest rebooted (`reboot -f`) after the operative reported.
+    Clean,
+    /// The guest shut down before the operative reported.
+    NoResult,
+    /// The guest kernel panicked; `panic=1` turns that into a reboot.
+    KernelPanic,
+    /// The OOM killer took the Firecracker process.
+    OomKilled,
+    /// Firecracker itself failed, e.g. a bad config or a KVM error.
+    FirecrackerError { code: i32 },
+    /// The sentinel killed it.
+    Killed,
+    /// A signal the sentinel did not send.
+    Signaled { signal: i32 },
+}
+
+impl ExitKind {
+    /// Classify an exit. The sentinel's own kill wins over everything
+    /// else, then OOM and panic evidence, then the raw status.
+    #[must_use]
+    pub fn classify(exit: &ProcessExit, evidence: &ExitEvidence<'_>) -> Self {
+        if exit.killed_by_sentinel {
+            return Self::Killed;
+        }
+        if evidence.oom_killed {
+            return Self::OomKilled;
+        }
+        if evidence.console_tail.contains(KERNEL_PANIC) {
+            return Self::KernelPanic;
+        }
+        match (exit.code, exit.signal) {
+            (_, Some(signal)) => Self::Signaled { signal },
+            (Some(0), None) if evidence.result_received => Self::Clean,
+            (Some(0), None) => Self::NoResult,
+            (Some(code), None) => Self::FirecrackerError { code },
+            (None, None) => Self::FirecrackerError { code: -1 },
+        }
+    }
+
+    /// Reason recorded on the task and its terminal event, or `None` for a
+    /// clean exit.
+    #[must_use]
+    pub fn failure_reason(self) -> Option<&'static str> {
+        match self {
+            Self::Clean => None,
+            Self::NoResult => Some("guest_exited_without_result"),
+            Self::KernelPanic => Some("guest_kernel_panic"),
+            Self::OomKilled => Some("oom_killed"),
+            Self::FirecrackerError { .. } => Some("firecracker_error"),
+            Self::Killed => Some("killed_by_sentinel"),
+            Self::Signaled { .. } => Some("firecracker_signaled"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::process::Command;
+
+    fn exit(code: Option<i32>, signal: Option<i32>) -> ProcessExit {
+        ProcessExit {
+            code,
+            signal,
+            killed_by_sentinel: false,
+            at_ms: 0,
+        }
+    }
+
+    #[test]
+    fn every_exit_has_a_distinct_reason() {
+        let reported = ExitEvidence {
+            result_received: true,
+            ..ExitEvidence::default()
+        };
+        let cases = [
+            (exit(Some(0), None), reported, ExitKind::Clean),
+            (
+                exit(Some(0), None),
+                ExitEvidence::default(),
+                ExitKind::NoResult,
+            ),
+            (
+                exit(Some(0), None),
+                ExitEvidence {
+                    console_tail: "[ 1.2] Kernel panic - not syncing: VFS",
+                    ..reported
+                },
+                ExitKind::KernelPanic,
+            ),
+            (
+                exit(None, Some(9)),
+                ExitEvidence {
+                    oom_killed: true,
+                    ..reported
+                },
+                ExitKind::OomKilled,
+            ),
+            (
+                exit(Some(1), None),
+                reported,
+                ExitKind::FirecrackerError { code: 1 },
+            ),
+            (
+                ProcessExit {
+                    killed_by_sentinel: true,
+                    ..exit(None, Some(9))
+                },
+                reported,
+                ExitKind::Killed,
+            ),
+            (
+                exit(None, Some(11)),
+                reported,
+                ExitKind::Signaled { signal: 11 },
+            ),
+        ];
+        let mut reasons = Vec::new();
+        for (process, evidence, expected) in cases {
+            let kind = ExitKind::classify(&process, &evidence);
+            assert_eq!(kind, expected);
+            reasons.push(kind.failure_reason());
+        }
+        assert_eq!(reasons[0], None);
+        reasons.sort_unstable();
+        reasons.dedup();
+        assert_eq!(reasons.len(), 7);
+    }
+
+    #[tokio::test]
+    async fn reaps_exit_code() {
+        let child = Command::new("sh").args(["-c", "exit 3"]).spawn().unwrap();
+        let exit = supervise(child, CancellationToken::new()).await.unwrap();
+        assert_eq!((exit.code, exit.signal), (Some(3), None));
+        assert!(!exit.killed_by_sentinel);
+    }
+
+    #[tokio::test]
+    async fn kill_token_kills_and_is_recorded() {
+        let child = Command::new("sleep").arg("30").spawn().unwrap();
+        let kill = CancellationToken::new();
+        let reaper = tokio::spawn(supervise(child, kill.clone()));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        kill.cancel();
+        let exit = reaper.await.unwrap().unwrap();
+        assert_eq!(exit.signal, Some(SIGKILL));
+        assert!(exit.killed_by_sentinel);
+        assert_eq!(
+            ExitKind::classify(&exit, &ExitEvidence::default()),
+            ExitKind::Killed
+        );
+    }
+
+    #[tokio::test]
+    async fn foreign_signal_is_not_ours() {
+        let child = Command::new("sh")
+            .args(["-c", "kill -TERM $$"])
+            .spawn()
+            .unwrap();
+        let exit = supervise(child, CancellationToken::new()).await.unwrap();
+        assert_eq!(exit.signal, Some(15));
+        assert_eq!(
+            ExitKind::classify(&exit, &ExitEvidence::default()),
+            ExitKind::Signaled { signal: 15 }
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::error::SentinelError;
+use crate::registry::VmResources;
+
+use super::deadline::Phase;
+use super::manager::VmManager;
+use super::network::NetworkSetup;
+use super::overlay::OverlayManager;
+
+/// Bound on each release step retried after a force-kill.
+pub const STEP_LIMIT: Duration = Duration::from_secs(5);
+
+/// Releases a VM's host resources.
+///
+/// Every step is attempted even if an earlier one fails, so a stuck
+/// Firecracker process never leaks its overlay or tap device.
+pub struct Teardown {
+    pub vms: VmManager,
+    pub overlays: OverlayManager,
+    pub network: NetworkSetup,
+}
+
+impl Teardown {
+    #[must_use]
+    pub fn new(firecracker_bin: PathBuf, overlay_dir: PathBuf) -> Self {
+        Self {
+            vms: VmManager::new(firecracker_bin),
+            overlays: OverlayManager::new(overlay_dir),
+            network: NetworkSetup,
+        }
+    }
+
+    /// Destroy the VM, then its cgroup, telemetry FIFOs, jail, tap device
+    /// and overlay. Read the cgroup's usage first if it is wanted.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Vm` listing every step that failed.
+    pub async fn release(&self, resources: &VmResources) -> Result<(), SentinelError> {
+        let mut failures = Vec::new();
+        if let Some(handle) = &resources.handle
+            && let Err(e) = self.vms.destroy_vm(handle).await
+        {
+            tracing::warn!(cid = handle.cid, error = %e, "vm destroy failed");
+            failures.push(format!("vm: {e}"));
+        }
+        self.release_host(resources, None, &mut failures).await;
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(SentinelError::Vm(format!(
+                "teardown incomplete: {}",
+                failures.join("; ")
+            )))
+        }
+    }
+
+    /// [`release`](Self::release), bounded by `limit`. If it overruns, the
+    /// Firecracker process is force-killed and the host resources are
+    /// released again, each step bounded by [`STEP_LIMIT`]. Steps that
+    /// already ran find nothing left to remove.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::DeadlineExceeded` on overrun, even if the
+    /// second pass cleaned up, so the caller keeps the VM unfinished; or
+    /// whatever `release` returns.
+    pub async fn release_within(
+        &self,
+        resources: &VmResources,
+        limit: Duration,
+    ) -> Result<(), SentinelError> {
+        if let Ok(released) = tokio::time::timeout(limit, self.release(resources)).await {
+            return released;
+        }
+        self.force_release(resources, limit).await
+    }
+
+    /// Second pass after `release` overran `limit`: force-kill the VM and
+    /// retry every host step under [`STEP_LIMIT`].
+    async fn force_release(
+        &self,
+        resources: &VmResources,
+        limit: Duration,
+    ) -> Result<(), SentinelError> {
+        let mut failures = Vec::new();
+        if let Some(handle) = &resources.handle {
+            tracing::warn!(
+                cid = handle.cid,
+                pid = handle.pid,
+                "teardown hung, force-killing vm"
+            );
+            if let Err(e) = bounded(Some(STEP_LIMIT), self.vms.kill_vm(handle)).await {
+                tracing::error!(cid = handle.cid, error = %e, "force-kill failed");
+                failures.push(format!("kill: {e}"));
+            }
+        }
+        self.release_host(resources, Some(STEP_LIMIT), &mut failures)
+            .await;
+        if !failures.is_empty() {
+            tracing::error!(
+                failures = %failures.join("; "),
+                "teardown incomplete after force-kill"
+            );
+        }
+        Err(SentinelError::DeadlineExceeded {
+            phase: Phase::Teardown,
+            limit_secs: limit.as_secs(),
+        })
+    }
+
+    /// Every release step after the VM itself, each bounded by `step` if
+    /// given. Failures are logged and appended to `failures`.
+    async fn release_host(
+        &self,
+        resources: &VmResources,
+        step: Option<Duration>,
+        failures: &mut Vec<String>,
+    ) {
+        if let Some(cgroup) = &resources.cgroup
+            && let Err(e) = bounded(step, cgroup.remove()).await
+        {
+            tracing::warn!(cgroup = %cgroup.path().display(), error = %e, "cgroup removal failed");
+            failures.push(format!("cgroup {}: {e}", cgroup.name));
+        }
+        if let Some(fifos) = &resources.fifos
+            && let Err(e) = bounded(step, async { fifos.remove() }).await
+        {
+            tracing::warn!(fifo = %fifos.log.display(), error = %e, "fifo removal failed");
+            failures.push(format!("fifos: {e}"));
+        }
+        if let Some(jail) = &resources.jail
+            && let Err(e) = bounded(step, jail.remove()).await
+        {
+            tracing::warn!(jail = %jail.dir().display(), error = %e, "jail removal failed");
+            failures.push(format!("jail {}: {e}", jail.id));
+        }
+        if let Some(tap) = &resources.tap
+            && let Err(e) = bounded(step, self.network.destroy_tap(tap)).await
+        {
+            tracing::warn!(tap = %tap.name, error = %e, "tap destroy failed");
+            failures.push(format!("tap {}: {e}", tap.name));
+        }
+        if let Some(overlay) = &resources.overlay
+            && let Err(e) = bounded(step, self.overlays.destroy(overlay)).await
+        {
+            tracing::warn!(overlay = %overlay.display(), error = %e, "overlay destroy failed");
+            failures.push(format!("overlay {}: {e}", overlay.display()));
+        }
+    }
+}
+
+/// `step`, bounded by `limit` if given.
+async fn bounded(
+    limit: Option<Duration>,
+    step: impl Future<Output = Result<(), SentinelError>>,
+) -> Result<(), SentinelError> {
+    let Some(limit) = limit else {
+        return step.await;
+    };
+    tokio::time::timeout(limit, step)
+        .await
+        .unwrap_or(Err(SentinelError::DeadlineExceeded {
+            phase: Phase::Teardown,
+            limit_secs: limit.as_secs(),
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::telemetry::Fifos;
+
+    #[tokio::test]
+    async fn overrun_still_releases_host_resources() {
+        let tmp = tempfile::tempdir().unwrap();
+        let fifos = Fifos {
+            log: tmp.path().join("vm.log"),
+            metrics: tmp.path().join("vm.metrics"),
+        };
+        std::fs::write(&fifos.log, "").unwrap();
+        std::fs::write(&fifos.metrics, "").unwrap();
+        let teardown = Teardown::new(PathBuf::from("firecracker"), tmp.path().to_path_buf());
+        let resources = VmResources {
+            fifos: Some(fifos.clone()),
+            ..VmResources::default()
+        };
+
+        let result = teardown
+            .force_release(&resources, Duration::from_secs(30))
+            .await;
+        assert!(matches!(
+            result,
+            Err(SentinelError::DeadlineExceeded {
+                phase: Phase::Teardown,
+                limit_secs: 30,
+            })
+        ));
+        assert!(!fifos.log.exists());
+        assert!(!fifos.metrics.exists());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bounded_step_times_out() {
+        let hung = std::future::pending::<Result<(), SentinelError>>();
+        assert!(matches!(
+            bounded(Some(STEP_LIMIT), hung).await,
+            Err(SentinelError::DeadlineExceeded { limit_secs: 5, .. })
+        ));
+        assert!(bounded(None, async { Ok(()) }).await.is_ok());
+    }
+}
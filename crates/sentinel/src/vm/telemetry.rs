@@ -0,0 +1,387 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::unix::pipe;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::SentinelError;
+
+/// Level Firecracker is asked to log at.
+pub const LOG_LEVEL: &str = "Warning";
+
+/// How long to keep reading a FIFO after the VM is done, for lines
+/// Firecracker wrote just before exiting.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Named pipes Firecracker writes its own log and metrics to, configured
+/// with `PUT /logger` and `PUT /metrics`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fifos {
+    pub log: PathBuf,
+    pub metrics: PathBuf,
+}
+
+impl Fifos {
+    /// FIFOs next to the VM's vsock socket: `fc-3.sock` gets `fc-3.log` and
+    /// `fc-3.metrics`.
+    #[must_use]
+    pub fn beside(socket_path: &Path) -> Self {
+        Self {
+            log: socket_path.with_extension("log"),
+            metrics: socket_path.with_extension("metrics"),
+        }
+    }
+
+    /// Create both FIFOs (mode 0600), replacing leftovers from an earlier
+    /// VM with the same path.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Vm` if `mkfifo` fails.
+    pub async fn create(&self) -> Result<(), SentinelError> {
+        self.remove()?;
+        for path in [&self.log, &self.metrics] {
+            let output = tokio::process::Command::new("mkfifo")
+                .arg("-m")
+                .arg("0600")
+                .arg(path)
+                .output()
+                .await?;
+            if !output.status.success() {
+                return Err(SentinelError::Vm(format!(
+                    "mkfifo {}: {}",
+                    path.display(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Open both FIFOs for reading. Firecracker opens them non-blocking for
+    /// writing, which fails unless a reader is already in place, so this
+    /// must happen before `PUT /logger` and `PUT /metrics`.
+    ///
+    /// Each is opened read-write so it reads as empty, not at end of file,
+    /// until Firecracker opens it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if a FIFO cannot be opened.
+    pub fn open(&self) -> Result<FifoReaders, SentinelError> {
+        let open = |path: &Path| {
+            pipe::OpenOptions::new()
+                .read_write(true)
+                .open_receiver(path)
+        };
+        Ok(FifoReaders {
+            log: open(&self.log)?,
+            metrics: open(&self.metrics)?,
+        })
+    }
+
+    /// Remove both FIFOs. Missing ones are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if a FIFO exists but cannot be removed.
+    pub fn remove(&self) -> Result<(), SentinelError> {
+        for path in [&self.log, &self.metrics] {
+            match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read ends of a VM's [`Fifos`], held from before Firecracker is
+/// configured until [`ingest`] has drained them.
+#[derive(Debug)]
+pub struct FifoReaders {
+    log: pipe::Receiver,
+    metrics: pipe::Receiver,
+}
+
+/// vCPU exits by cause.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VcpuExits {
+    #[serde(alias = "exit_io_in")]
+    pub io_in: u64,
+    #[serde(alias = "exit_io_out")]
+    pub io_out: u64,
+    #[serde(alias = "exit_mmio_read")]
+    pub mmio_read: u64,
+    #[serde(alias = "exit_mmio_write")]
+    pub mmio_write: u64,
+    pub failures: u64,
+}
+
+/// Block device I/O, summed over the VM's drives.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockCounters {
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub read_count: u64,
+    pub write_count: u64,
+}
+
+/// Traffic on a net or vsock device.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceCounters {
+    #[serde(alias = "rx_bytes_count")]
+    pub rx_bytes: u64,
+    #[serde(alias = "tx_bytes_count")]
+    pub tx_bytes: u64,
+    #[serde(alias = "rx_packets_count")]
+    pub rx_packets: u64,
+    #[serde(alias = "tx_packets_count")]
+    pub tx_packets: u64,
+}
+
+/// Firecracker's metrics for one VM, totalled over every flush.
+///
+/// Firecracker writes one JSON object per flush, and its counters are
+/// deltas since the previous flush, so each line is added to the totals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FirecrackerMetrics {
+    /// Metrics lines absorbed.
+    pub flushes: u64,
+    pub vcpu: VcpuExits,
+    pub block: BlockCounters,
+    pub net: DeviceCounters,
+    pub vsock: DeviceCounters,
+}
+
+/// The subset of a Firecracker metrics line that is kept.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct MetricsLine {
+    vcpu: VcpuExits,
+    block: BlockCounters,
+    net: DeviceCounters,
+    vsock: DeviceCounters,
+}
+
+impl FirecrackerMetrics {
+    /// Add one metrics line to the totals.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Json` if the line is not a metrics object;
+    /// the totals are unchanged.
+    pub fn absorb(&mut self, line: &str) -> Result<(), SentinelError> {
+        let m: MetricsLine = serde_json::from_str(line)?;
+        self.flushes += 1;
+        let (v, b) = (&mut self.vcpu, &mut self.block);
+        v.io_in += m.vcpu.io_in;
+        v.io_out += m.vcpu.io_out;
+        v.mmio_read += m.vcpu.mmio_read;
+        v.mmio_write += m.vcpu.mmio_write;
+        v.failures += m.vcpu.failures;
+        b.read_bytes += m.block.read_bytes;
+        b.write_bytes += m.block.write_bytes;
+        b.read_count += m.block.read_count;
+        b.write_count += m.block.write_count;
+        for (total, delta) in [(&mut self.net, m.net), (&mut self.vsock, m.vsock)] {
+            total.rx_bytes += delta.rx_bytes;
+            total.tx_bytes += delta.tx_bytes;
+            total.rx_packets += delta.rx_packets;
+            total.tx_packets += delta.tx_packets;
+        }
+        Ok(())
+    }
+}
+
+/// What Firecracker logged about one VM.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FirecrackerLog {
+    pub warnings: u64,
+    pub errors: u64,
+    pub last_error: Option<String>,
+}
+
+impl FirecrackerLog {
+    /// Count one Firecracker log line by level and forward it to tracing.
+    ///
+    /// Lines look like `2024-01-01T00:00:00.000000000 [instance:thread:WARN] msg`;
+    /// the level is whichever `:`-separated field of the bracketed prefix
+    /// names one.
+    pub fn observe(&mut self, cid: u32, line: &str) {
+        let level = line
+            .split_once('[')
+            .and_then(|(_, rest)| rest.split_once(']'))
+            .and_then(|(prefix, _)| {
+                prefix
+                    .split(':')
+                    .find(|f| matches!(*f, "ERROR" | "WARN" | "INFO" | "DEBUG" | "TRACE"))
+            });
+        match level {
+            Some("ERROR") => {
+                tracing::warn!(cid, line, "firecracker error");
+                self.errors += 1;
+                self.last_error = Some(line.to_string());
+            }
+            Some("WARN") => {
+                tracing::info!(cid, line, "firecracker warning");
+                self.warnings += 1;
+            }
+            _ => tracing::debug!(cid, line, "firecracker log"),
+        }
+    }
+}
+
+/// Firecracker's own view of a VM: its metrics and log summary.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VmTelemetry {
+    pub metrics: FirecrackerMetrics,
+    pub log: FirecrackerLog,
+}
+
+/// Read `receiver` line by line until `done` is cancelled (the Firecracker
+/// process has exited), then drain whatever it wrote last.
+///
+/// # Errors
+///
+/// Returns `SentinelError::Io` if the FIFO cannot be read.
+pub async fn follow(
+    receiver: pipe::Receiver,
+    done: &CancellationToken,
+    mut on_line: impl FnMut(&str),
+) -> Result<(), SentinelError> {
+    let mut lines = BufReader::new(receiver).lines();
+    loop {
+        tokio::select! {
+            biased;
+            line = lines.next_line() => match line? {
+                Some(line) => on_line(&line),
+                None => return Ok(()),
+            },
+            () = done.cancelled() => break,
+        }
+    }
+    while let Ok(Some(line)) = tokio::time::timeout(DRAIN_TIMEOUT, lines.next_line())
+        .await
+        .unwrap_or(Ok(None))
+    {
+        on_line(&line);
+    }
+    Ok(())
+}
+
+/// Follow both FIFOs until `done`, feeding each line into `record`.
+/// Unparseable metrics lines are logged and skipped.
+pub async fn ingest(
+    cid: u32,
+    readers: FifoReaders,
+    done: &CancellationToken,
+    record: impl Fn(&mut dyn FnMut(&mut VmTelemetry)),
+) {
+    let metrics = follow(readers.metrics, done, |line| {
+        record(&mut |t| {
+            if let Err(e) = t.metrics.absorb(line) {
+                tracing::debug!(cid, error = %e, "unreadable firecracker metrics line");
+            }
+        });
+    });
+    let log = follow(readers.log, done, |line| {
+        record(&mut |t| t.log.observe(cid, line));
+    });
+    let (metrics, log) = tokio::join!(metrics, log);
+    for (fifo, result) in [("metrics", metrics), ("log", log)] {
+        if let Err(e) = result {
+            tracing::warn!(cid, fifo, error = %e, "firecracker fifo unreadable");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    const METRICS: &str = r#"{"utc_timestamp_ms":1,"vcpu":{"exit_io_in":3,"exit_io_out":4,"exit_mmio_read":5,"exit_mmio_write":6,"failures":0},"block":{"read_bytes":4096,"write_bytes":512,"read_count":2,"write_count":1,"flush_count":0},"net":{"rx_bytes_count":100,"tx_bytes_count":50,"rx_packets_count":2,"tx_packets_count":1},"vsock":{"rx_bytes_count":7,"tx_bytes_count":9,"rx_packets_count":1,"tx_packets_count":1,"conns_added":1},"api_server":{"process_startup_time_us":10}}"#;
+
+    #[test]
+    fn metrics_lines_are_summed() {
+        let mut metrics = FirecrackerMetrics::default();
+        metrics.absorb(METRICS).unwrap();
+        metrics.absorb(METRICS).unwrap();
+        metrics.absorb(r#"{"utc_timestamp_ms":2}"#).unwrap();
+        assert!(metrics.absorb("not json").is_err());
+
+        assert_eq!(metrics.flushes, 3);
+        assert_eq!(metrics.vcpu.io_in, 6);
+        assert_eq!(metrics.vcpu.mmio_write, 12);
+        assert_eq!(metrics.block.read_bytes, 8192);
+        assert_eq!(metrics.net.tx_bytes, 100);
+        assert_eq!(metrics.vsock.rx_bytes, 14);
+        let json = serde_json::to_value(metrics).unwrap();
+        assert_eq!(json["vsock"]["tx_packets"], 2);
+    }
+
+    #[test]
+    fn log_levels_counted() {
+        let mut log = FirecrackerLog::default();
+        for line in [
+            "2024-01-01T00:00:00.000000000 [anonymous-instance:main:INFO] Running Firecracker",
+            "2024-01-01T00:00:00.000000001 [fc:fc_vcpu 0:WARN:src/vmm/src/vstate/vcpu.rs:1] slow",
+            "2024-01-01T00:00:00.000000002 [fc:fc_api:ERROR] Failed to open tap",
+            "no prefix at all",
+        ] {
+            log.observe(3, line);
+        }
+        assert_eq!((log.warnings, log.errors), (1, 1));
+        assert!(log.last_error.unwrap().ends_with("Failed to open tap"));
+    }
+
+    #[test]
+    fn fifos_sit_beside_the_socket() {
+        let fifos = Fifos::beside(Path::new("/run/gbe/fc-3.sock"));
+        assert_eq!(fifos.log, PathBuf::from("/run/gbe/fc-3.log"));
+        assert_eq!(fifos.metrics, PathBuf::from("/run/gbe/fc-3.metrics"));
+    }
+
+    #[tokio::test]
+    async fn ingest_reads_both_fifos_until_done() {
+        let tmp = tempfile::tempdir().unwrap();
+        let fifos = Fifos::beside(&tmp.path().join("fc-3.sock"));
+        fifos.create().await.unwrap();
+        let done = CancellationToken::new();
+        let telemetry = std::sync::Mutex::new(VmTelemetry::default());
+
+        let writer = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            for (path, line) in [
+                (&fifos.metrics, METRICS),
+                (&fifos.log, "t [fc:main:ERROR] boom"),
+            ] {
+                let mut tx = pipe::OpenOptions::new().open_sender(path).unwrap();
+                tx.write_all(format!("{line}\n").as_bytes()).await.unwrap();
+            }
+            done.cancel();
+        };
+        let reader = ingest(3, fifos.open().unwrap(), &done, |f| {
+            f(&mut telemetry.lock().unwrap())
+        });
+        tokio::join!(writer, reader);
+
+        let telemetry = telemetry.into_inner().unwrap();
+        assert_eq!(telemetry.metrics.flushes, 1);
+        assert_eq!(telemetry.metrics.block.write_count, 1);
+        assert_eq!(telemetry.log.errors, 1);
+
+        fifos.remove().unwrap();
+        assert!(!fifos.log.exists());
+        fifos.remove().unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+use std::path::Path;
+use std::time::Duration;
+
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf,
+    WriteHalf,
+};
+use tokio::net::UnixStream;
+
+use super::protocol::{
+    MAX_VSOCK_MESSAGE_SIZE, OperativeMessage, SentinelMessage, parse_operative_message,
+};
+use crate::error::SentinelError;
+
+/// Guest port the operative listens on.
+pub const OPERATIVE_PORT: u32 = 5000;
+
+/// Open a host-initiated vsock connection to `port` in the guest.
+///
+/// Firecracker exposes the guest's vsock as a Unix socket at `uds_path`;
+/// the host writes `CONNECT {port}` and Firecracker answers `OK {host_port}`.
+/// The operative may still be booting, so connection attempts are retried
+/// until `timeout`.
+///
+/// # Errors
+///
+/// Returns `SentinelError::Timeout` if the guest does not accept in time.
+pub async fn connect_guest(
+    uds_path: &Path,
+    port: u32,
+    timeout: Duration,
+) -> Result<UnixStream, SentinelError> {
+    let attempt = async {
+        loop {
+            match handshake(uds_path, port).await {
+                Ok(stream) => return stream,
+                Err(e) => tracing::trace!(error = %e, "guest not ready"),
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    tokio::time::timeout(timeout, attempt).await.map_err(|_| {
+        SentinelError::Timeout(format!(
+            "guest port {port} on {} not reachable",
+            uds_path.display()
+        ))
+    })
+}
+
+async fn handshake(uds_path: &Path, port: u32) -> Result<UnixStream, SentinelError> {
+    let mut stream = UnixStream::connect(uds_path).await?;
+    stream
+        .write_all(format!("CONNECT {port}\n").as_bytes())
+        .await?;
+    // Read byte by byte so nothing past the ack is consumed.
+    let mut ack = Vec::new();
+    loop {
+        let byte = stream.read_u8().await?;
+        if byte == b'\n' {
+            break;
+        }
+        ack.push(byte);
+    }
+    if ack.starts_with(b"OK ") {
+        Ok(stream)
+    } else {
+        Err(SentinelError::Vsock(format!(
+            "connect refused: {}",
+            String::from_utf8_lossy(&ack)
+        )))
+    }
+}
+
+/// JSON-lines channel to an operative, one message per line.
+pub struct OperativeChannel<S> {
+    reader: BufReader<ReadHalf<S>>,
+    writer: WriteHalf<S>,
+    /// Bytes of a line not yet complete, kept across cancelled `recv`s.
+    partial: Vec<u8>,
+}
+
+impl<S: AsyncRead + AsyncWrite> OperativeChannel<S> {
+    pub fn new(stream: S) -> Self {
+        let (read, writer) = tokio::io::split(stream);
+        Self {
+            reader: BufReader::new(read),
+            writer,
+            partial: Vec::new(),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Io` if the stream is closed.
+    pub async fn send(&mut self, msg: &SentinelMessage) -> Result<(), SentinelError> {
+        let mut line = serde_json::to_vec(msg)?;
+        line.push(b'\n');
+        self.writer.write_all(&line).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    /// Next message, or `None` once the operative closes the stream.
+    /// Cancel-safe: a partly read line is kept for the next call, so this
+    /// can race a timer in `select!`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SentinelError::Vsock` if a line is oversized or malformed.
+    pub async fn recv(&mut self) -> Result<Option<OperativeMessage>, SentinelError> {
+        loop {
+            let room = (MAX_VSOCK_MESSAGE_SIZE + 1).saturating_sub(self.partial.len());
+            let read = (&mut self.reader)
+                .take(room as u64)
+                .read_until(b'\n', &mut self.partial)
+                .await?;
+            let complete = self.partial.last() == Some(&b'\n');
+            if read == 0 && self.partial.is_empty() {
+                return Ok(None);
+            }
+            if complete || read == 0 || self.partial.len() > MAX_VSOCK_MESSAGE_SIZE {
+                let mut line = std::mem::take(&mut self.partial);
+                if complete {
+                    line.pop();
+                }
+                return parse_operative_message(&line).map(Some);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UnixListener;
+
+    #[tokio::test]
+    async fn channel_round_trip() {
+        let (host, guest) = tokio::io::duplex(4096);
+        let mut channel = OperativeChannel::new(host);
+        let mut guest = BufReader::new(guest);
+
+        channel
+            .send(&SentinelMessage::ToolResult {
+                id: "t1".into(),
+                call_id: "c1".into(),
+                result: serde_json::json!(1),
+            })
+            .await
+            .unwrap();
+        let mut line = String::new();
+        guest.read_line(&mut line).await.unwrap();
+        assert!(line.contains("\"tool_result\""));
+
+        guest
+            .write_all(b"{\"type\":\"error\",\"id\":\"t1\",\"error\":\"e\",\"exit_code\":2}\n")
+            .await
+            .unwrap();
+        drop(guest);
+        assert!(matches!(
+            channel.recv().await.unwrap(),
+            Some(OperativeMessage::Error { exit_code: 2, .. })
+        ));
+        assert!(channel.recv().await.unwrap().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn recv_keeps_partial_line_when_cancelled() {
+        let (host, mut guest) = tokio::io::duplex(4096);
+        let mut channel = OperativeChannel::new(host);
+        guest.write_all(br#"{"type":"pong","#).await.unwrap();
+        let cancelled = tokio::time::timeout(Duration::from_millis(10), channel.recv()).await;
+        assert!(cancelled.is_err());
+        guest.write_all(b"\"id\":\"t1\"}\n").await.unwrap();
+        assert!(matches!(
+            channel.recv().await.unwrap(),
+            Some(OperativeMessage::Pong { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn connect_guest_performs_handshake() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vsock.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let firecracker = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "CONNECT 5000\n");
+            stream
+                .get_mut()
+                .write_all(b"OK 1073741824\n")
+                .await
+                .unwrap();
+        });
+        connect_guest(&path, OPERATIVE_PORT, Duration::from_secs(5))
+            .await
+            .unwrap();
+        firecracker.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn connect_guest_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = connect_guest(
+            &dir.path().join("missing.sock"),
+            OPERATIVE_PORT,
+            Duration::from_millis(250),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, SentinelError::Timeout(_)));
+    }
+}
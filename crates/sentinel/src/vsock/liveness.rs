@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::error::SentinelError;
+
+/// Failure reason recorded on tasks whose operative went silent.
+pub const OPERATIVE_UNRESPONSIVE: &str = "operative_unresponsive";
+
+/// How long a pinged operative has to answer.
+pub const PONG_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What to do when [`Liveness::check_at`] passes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Silence {
+    /// Silent for `max_silence`: send a ping.
+    Ping,
+    /// The ping went unanswered: fail the task.
+    Unresponsive,
+}
+
+/// Tracks the last message from an operative against the profile's
+/// `max_silence_sec`.
+///
+/// Any message counts as a sign of life, not only a pong. The sequence on
+/// silence is: ping after `max_silence`, then give up `PONG_TIMEOUT` later.
+#[derive(Debug)]
+pub struct Liveness {
+    max_silence: Option<Duration>,
+    last_heard: Instant,
+    pinged_at: Option<Instant>,
+}
+
+impl Liveness {
+    /// `None` disables the check.
+    #[must_use]
+    pub fn new(max_silence: Option<Duration>) -> Self {
+        Self {
+            max_silence,
+            last_heard: Instant::now(),
+            pinged_at: None,
+        }
+    }
+
+    /// Record a message from the operative.
+    pub fn heard(&mut self) {
+        self.last_heard = Instant::now();
+        self.pinged_at = None;
+    }
+
+    /// When to call [`expired`](Self::expired) next, or `None` if the check
+    /// is disabled.
+    #[must_use]
+    pub fn check_at(&self) -> Option<Instant> {
+        let max_silence = self.max_silence?;
+        Some(match self.pinged_at {
+            Some(pinged_at) => pinged_at + PONG_TIMEOUT,
+            None => self.last_heard + max_silence,
+        })
+    }
+
+    /// Called once `check_at` has passed.
+    pub fn expired(&mut self) -> Silence {
+        if self.pinged_at.is_some() {
+            Silence::Unresponsive
+        } else {
+            self.pinged_at = Some(Instant::now());
+            Silence::Ping
+        }
+    }
+
+    /// The error to fail the task with after [`Silence::Unresponsive`].
+    #[must_use]
+    pub fn unresponsive(&self) -> SentinelError {
+        SentinelError::Unresponsive {
+            silent_secs: self.last_heard.elapsed().as_secs(),
+        }
+    }
+
+    /// Sleep until `check_at`, or forever if the check is disabled.
+    pub async fn wait(&self) {
+        match self.check_at() {
+            Some(at) => tokio::time::sleep_until(at).await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn silence_pings_then_gives_up() {
+        let mut liveness = Liveness::new(Some(Duration::from_secs(30)));
+        let start = Instant::now();
+        assert_eq!(liveness.check_at(), Some(start + Duration::from_secs(30)));
+
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        assert_eq!(liveness.expired(), Silence::Ping);
+        assert_eq!(liveness.check_at(), Some(Instant::now() + PONG_TIMEOUT));
+
+        tokio::time::advance(PONG_TIMEOUT).await;
+        assert_eq!(liveness.expired(), Silence::Unresponsive);
+        assert!(matches!(
+            liveness.unresponsive(),
+            SentinelError::Unresponsive { silent_secs: 40 }
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn any_message_resets_silence() {
+        let mut liveness = Liveness::new(Some(Duration::from_secs(30)));
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(liveness.expired(), Silence::Ping);
+        liveness.heard();
+        assert_eq!(
+            liveness.check_at(),
+            Some(Instant::now() + Duration::from_secs(30))
+        );
+        assert!(Liveness::new(None).check_at().is_none());
+    }
+}
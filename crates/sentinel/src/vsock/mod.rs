@@ -1,3 +1,5 @@
+pub mod channel;
 pub mod listener;
+pub mod liveness;
 pub mod protocol;
 pub mod proxy;
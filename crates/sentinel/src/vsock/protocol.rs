@@ -4,7 +4,7 @@ use serde_json::Value;
 use crate::error::SentinelError;
 
 /// Maximum size of a single vsock message in bytes (1 MB).
-const MAX_VSOCK_MESSAGE_SIZE: usize = 1_048_576;
+pub(crate) const MAX_VSOCK_MESSAGE_SIZE: usize = 1_048_576;
 
 /// Messages sent from operative (guest) to sentinel (host) over vsock.
 ///
@@ -38,12 +38,53 @@ pub enum OperativeMessage {
         tool: String,
         params: Value,
     },
+    /// Answer to `Ping`.
+    Pong { id: String },
+    /// One line of task output, relayed live to `gbe.tasks.{task_type}.logs`.
+    /// `ts` is when the guest saw it, in unix millis.
+    Log {
+        id: String,
+        stream: LogStream,
+        line: String,
+        ts: u64,
+    },
+    /// Ask for more run time. Answered with `ExtendDeadlineResult`.
+    ExtendDeadline {
+        id: String,
+        additional_sec: u64,
+        #[serde(default)]
+        reason: String,
+    },
+}
+
+/// Where a log line came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+    /// The guest's serial console, relayed by the sentinel. Operatives send
+    /// `stdout` or `stderr`.
+    Console,
+}
+
+impl LogStream {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+            Self::Console => "console",
+        }
+    }
 }
 
 /// Messages sent from sentinel (host) to operative (guest) over vsock.
 ///
 /// `payload` and `result` use `Value` because task payloads and tool
 /// results vary by type. Size limits are enforced at serialization time.
+/// `trace_id` carries the queue envelope's trace id so the operative can
+/// attach its own spans to the same trace.
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum SentinelMessage {
@@ -51,12 +92,41 @@ pub enum SentinelMessage {
         id: String,
         payload: Value,
         tools: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trace_id: Option<String>,
     },
     ToolResult {
         id: String,
         call_id: String,
         result: Value,
     },
+    /// Liveness probe, sent after the operative has been silent for the
+    /// profile's `max_silence_sec`. Answered with `Pong`.
+    Ping { id: String },
+    /// Answer to `ExtendDeadline`. `additional_sec` is what was granted,
+    /// possibly less than asked; `timeout_at` is the run deadline in unix
+    /// millis either way. `reason` explains a denial.
+    ExtendDeadlineResult {
+        id: String,
+        granted: bool,
+        additional_sec: u64,
+        timeout_at: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+}
+
+impl SentinelMessage {
+    /// Task the message is about.
+    #[must_use]
+    pub fn id(&self) -> &str {
+        match self {
+            Self::Task { id, .. }
+            | Self::ToolResult { id, .. }
+            | Self::Ping { id }
+            | Self::ExtendDeadlineResult { id, .. } => id,
+        }
+    }
 }
 
 /// Deserialize an operative message with size limit enforcement.
@@ -137,6 +207,41 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_extend_deadline_message() {
+        let json =
+            r#"{"type":"extend_deadline","id":"t5","additional_sec":120,"reason":"slow build"}"#;
+        let msg = parse_operative_message(json.as_bytes()).unwrap();
+        assert!(matches!(
+            msg,
+            OperativeMessage::ExtendDeadline { additional_sec: 120, ref reason, .. } if reason == "slow build"
+        ));
+    }
+
+    #[test]
+    fn parse_log_message() {
+        let json = r#"{"type":"log","id":"t1","stream":"stderr","line":"warning: x","ts":17}"#;
+        let msg = parse_operative_message(json.as_bytes()).unwrap();
+        assert!(matches!(
+            msg,
+            OperativeMessage::Log { stream: LogStream::Stderr, ref line, ts: 17, .. } if line == "warning: x"
+        ));
+        let bad = r#"{"type":"log","id":"t1","stream":"stdin","line":"x","ts":0}"#;
+        assert!(parse_operative_message(bad.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn ping_pong_wire_format() {
+        let ping = SentinelMessage::Ping { id: "t1".into() };
+        assert_eq!(ping.id(), "t1");
+        assert_eq!(
+            serde_json::to_value(ping).unwrap(),
+            serde_json::json!({"type": "ping", "id": "t1"})
+        );
+        let answer = parse_operative_message(br#"{"type":"pong","id":"t1"}"#).unwrap();
+        assert!(matches!(answer, OperativeMessage::Pong { ref id } if id == "t1"));
+    }
+
     #[test]
     fn oversized_message_rejected() {
         let big = vec![b' '; MAX_VSOCK_MESSAGE_SIZE + 1];
@@ -176,6 +281,7 @@ mod tests {
             id: "t1".into(),
             payload: serde_json::json!({"cmd": "echo hi"}),
             tools: vec!["grep".into(), "curl".into()],
+            trace_id: None,
         };
         let bytes = serde_json::to_vec(&msg).unwrap();
         let parsed: SentinelMessage = serde_json::from_slice(&bytes).unwrap();
@@ -187,6 +293,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn sentinel_message_task_carries_trace_id() {
+        let msg = SentinelMessage::Task {
+            id: "t1".into(),
+            payload: serde_json::json!({}),
+            tools: vec![],
+            trace_id: Some("trace-abc".into()),
+        };
+        let json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(json["trace_id"], "trace-abc");
+    }
+
+    #[test]
+    fn sentinel_message_task_omits_missing_trace_id() {
+        let msg = SentinelMessage::Task {
+            id: "t1".into(),
+            payload: serde_json::json!({}),
+            tools: vec![],
+            trace_id: None,
+        };
+        let json = serde_json::to_value(&msg).unwrap();
+        assert!(json.get("trace_id").is_none());
+    }
+
     #[test]
     fn sentinel_message_tool_result_round_trip() {
         let msg = SentinelMessage::ToolResult {
@@ -198,4 +328,17 @@ mod tests {
         let parsed: SentinelMessage = serde_json::from_slice(&bytes).unwrap();
         assert!(matches!(parsed, SentinelMessage::ToolResult { .. }));
     }
+    #[test]
+    fn extend_deadline_result_omits_reason_when_granted() {
+        let msg = SentinelMessage::ExtendDeadlineResult {
+            id: "t1".into(),
+            granted: true,
+            additional_sec: 60,
+            timeout_at: 1_000,
+            reason: None,
+        };
+        let json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(json["type"], "extend_deadline_result");
+        assert!(json.get("reason").is_none());
+    }
 }